@@ -0,0 +1,92 @@
+//! Heartbeat of the agents to the API server and the counters it carries, shared
+//! by the firewall and tracepoint agents.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use aya::maps::{MapData, MapError, PerCpuArray};
+use serde::Serialize;
+
+#[derive(Serialize, PartialEq, Eq, Clone, Debug)]
+pub enum AgentKind {
+    Firewall,
+    Tracepoint,
+}
+
+/// Event counters since the agent started. `events_lost` never reached the agent
+/// as the kernel buffer was full, the others are the `ShipperStats`.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AgentMetrics {
+    pub transport: String,
+    pub events_lost: u64,
+    pub events_shipped: u64,
+    pub events_dropped: u64,
+    pub events_spooled: u64,
+}
+
+/// State of the firewall on an interface. `Failed` interfaces could not be
+/// attached to and `Detached` interfaces were removed from the host.
+#[derive(Serialize, PartialEq, Eq, Clone, Debug)]
+pub enum InterfaceState {
+    Attached,
+    Failed,
+    Detached,
+}
+
+/// What the agent reports to the API server, for an interface with the firewall,
+/// the first heartbeat registers it. `error` tells why the interface failed, or
+/// the features missing on an attached interface.
+#[derive(Clone, Debug, Serialize)]
+pub struct AgentHeartbeat {
+    pub hostname: String,
+    pub kind: AgentKind,
+    pub interface: Option<String>,
+    pub version: String,
+    pub kernel: String,
+    pub attach_mode: Option<String>,
+    pub metrics: Option<AgentMetrics>,
+    pub state: Option<InterfaceState>,
+    pub error: Option<String>,
+}
+
+/// Events lost because the kernel buffer was full. The ring buffer programs count
+/// them per CPU, the perf buffers report them when they are read.
+#[derive(Clone)]
+pub enum LostEvents {
+    RingBuf(Arc<PerCpuArray<MapData, u64>>),
+    PerfEventArray(Arc<AtomicU64>),
+}
+
+impl LostEvents {
+    /// Name of the kernel buffer the events are read from.
+    pub fn transport(&self) -> &'static str {
+        match self {
+            Self::RingBuf(_) => "RingBuf",
+            Self::PerfEventArray(_) => "PerfEventArray",
+        }
+    }
+
+    pub fn get(&self) -> Result<u64, MapError> {
+        match self {
+            Self::RingBuf(lost) => Ok(lost.get(&0, 0)?.iter().sum()),
+            Self::PerfEventArray(lost) => Ok(lost.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+fn read_proc(path: &str) -> String {
+    match std::fs::read_to_string(path) {
+        Ok(value) => value.trim().to_string(),
+        Err(_) => "unknown".to_string(),
+    }
+}
+
+pub fn hostname() -> String {
+    read_proc("/proc/sys/kernel/hostname")
+}
+
+pub fn kernel_release() -> String {
+    read_proc("/proc/sys/kernel/osrelease")
+}
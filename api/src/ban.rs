@@ -0,0 +1,578 @@
+use crate::config::{BanConfig, BanThreshold};
+use crate::db::Db;
+use crate::enums::{ban_source::BanSource, direction::Direction, ip_protocol::IpProtocol};
+use crate::models::command_execution::CommandExecutionData;
+use crate::models::firewall_ban::{ip_key, ip_octets, FirewallBan, FirewallBanData};
+use crate::models::firewall_log::FirewallLogData;
+use crate::models::firewall_rule::{FirewallRule, FirewallRuleData, MAX_RULE_TTL};
+use crate::rule_version::RuleVersion;
+use chrono::{Duration, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use surrealdb::Datetime;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// A source to ban for `ttl` seconds with a deny rule for the agents of `layer`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BanRequest {
+    pub ip: Vec<u8>,
+    pub ttl: u64,
+    pub layer: u8,
+    pub threshold: Option<String>,
+    pub reason: String,
+}
+
+/// First address in the arguments of a command, such as the client of
+/// `sshd: invalid user admin from 203.0.113.7 port 52144`. Loopback and
+/// unspecified addresses are skipped.
+pub fn ip_from_args(args: &str) -> Option<IpAddr> {
+    args.split(|c: char| c.is_whitespace() || ",;\"'()[]=@".contains(c))
+        .filter_map(|token| {
+            token
+                .parse::<IpAddr>()
+                .or_else(|_| token.parse::<SocketAddr>().map(|address| address.ip()))
+                .ok()
+        })
+        .find(|ip| !ip.is_loopback() && !ip.is_unspecified())
+}
+
+/// Error of a ban, `Limit` when `max_bans` sources of the address family are
+/// already banned.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BanError {
+    Limit(usize),
+    Db(String),
+}
+
+impl From<String> for BanError {
+    fn from(error: String) -> Self {
+        Self::Db(error)
+    }
+}
+
+impl std::fmt::Display for BanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Limit(max_bans) => write!(
+                f,
+                "[FIREWALL_BAN ERROR] ban: {} sources of the address family are already banned",
+                max_bans
+            ),
+            Self::Db(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Events counted for a threshold and source, as (time of the event, count) pairs.
+type BanEvents = HashMap<(usize, Vec<u8>), VecDeque<(i64, u64)>>;
+
+/// Bans the sources of the events that reach a threshold, fail2ban style, and
+/// lifts the bans once expired. Events are counted per threshold and source over
+/// the time they happened, the counts are kept in memory only. The ingestion
+/// handlers queue the bans, `run` creates them outside of the requests.
+pub struct BanEngine {
+    db: Arc<Db>,
+    rule_version: Arc<RuleVersion>,
+    thresholds: Vec<BanThreshold>,
+    max_bans: usize,
+    events: Mutex<BanEvents>,
+    sender: UnboundedSender<Vec<BanRequest>>,
+    receiver: Mutex<Option<UnboundedReceiver<Vec<BanRequest>>>>,
+}
+
+impl BanEngine {
+    pub fn new(db: Arc<Db>, rule_version: Arc<RuleVersion>, config: &BanConfig) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Self {
+            db,
+            rule_version,
+            thresholds: config.thresholds.clone(),
+            max_bans: config.max_bans,
+            events: Mutex::new(HashMap::new()),
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+
+    /// Counts the logs at their timestamp, as of `now` in seconds, and returns the
+    /// bans of the sources that reached a threshold. Egress logs are about local
+    /// senders and never count.
+    pub fn count_logs(&self, logs: &[FirewallLogData], now: i64) -> Vec<BanRequest> {
+        let mut requests: Vec<BanRequest> = Vec::new();
+        for (index, threshold) in self.thresholds.iter().enumerate() {
+            if threshold.source != BanSource::FirewallLog {
+                continue;
+            }
+            for log in logs.iter().filter(|log| {
+                log.direction == Direction::Ingress
+                    && log.status == threshold.status
+                    && threshold
+                        .protocol
+                        .as_ref()
+                        .is_none_or(|protocol| *protocol == log.protocol)
+                    && threshold
+                        .destination_port
+                        .is_none_or(|port| log.destination_port == Some(port))
+            }) {
+                requests.extend(self.count(
+                    index,
+                    &log.ip,
+                    seconds(&log.timestamp),
+                    now,
+                    log.packets,
+                ));
+            }
+        }
+        requests
+    }
+
+    /// Counts the command executions at their timestamp, as of `now` in seconds,
+    /// and returns the bans of the sources that reached a threshold.
+    pub fn count_commands(&self, commands: &[CommandExecutionData], now: i64) -> Vec<BanRequest> {
+        let mut requests: Vec<BanRequest> = Vec::new();
+        for (index, threshold) in self.thresholds.iter().enumerate() {
+            if threshold.source != BanSource::CommandExecution {
+                continue;
+            }
+            for command in commands.iter().filter(|command| {
+                threshold
+                    .command
+                    .as_ref()
+                    .is_none_or(|name| *name == command.command)
+            }) {
+                if let Some(ip) = ip_from_args(&command.args) {
+                    requests.extend(self.count(
+                        index,
+                        &ip_octets(ip),
+                        seconds(&command.timestamp),
+                        now,
+                        1,
+                    ));
+                }
+            }
+        }
+        requests
+    }
+
+    /// Adds `count` events of the source that happened at `time` to the window of
+    /// the threshold ending at `now`. Events older than the window are skipped and
+    /// events from the future, skewed agent clocks, are counted at `now`. The
+    /// events are forgotten once the threshold is reached, so a source is
+    /// requested to be banned once per `count` events.
+    fn count(
+        &self,
+        index: usize,
+        ip: &[u8],
+        time: i64,
+        now: i64,
+        count: u64,
+    ) -> Option<BanRequest> {
+        let threshold: &BanThreshold = &self.thresholds[index];
+        let start: i64 = now - threshold.window as i64;
+        if time <= start {
+            return None;
+        }
+        let key: (usize, Vec<u8>) = (index, ip.to_vec());
+        let mut events = self.events.lock().unwrap();
+        let window: &mut VecDeque<(i64, u64)> = events.entry(key.clone()).or_default();
+        // Logs arrive out of order, the window is not sorted by time.
+        window.push_back((time.min(now), count));
+        window.retain(|(time, _)| *time > start);
+        let total: u64 = window.iter().map(|(_, count)| count).sum();
+        if total < threshold.count {
+            return None;
+        }
+        events.remove(&key);
+        Some(BanRequest {
+            ip: ip.to_vec(),
+            ttl: threshold.ttl,
+            layer: threshold.layer,
+            threshold: Some(threshold.name.clone()),
+            reason: format!(
+                "{}: {} events in {} seconds",
+                threshold.name, total, threshold.window
+            ),
+        })
+    }
+
+    /// Forgets the events that happened before the window of their threshold.
+    pub fn prune(&self, now: i64) {
+        let mut events = self.events.lock().unwrap();
+        events.retain(|(index, _), window| {
+            let start: i64 = now - self.thresholds[*index].window as i64;
+            window.retain(|(time, _)| *time > start);
+            !window.is_empty()
+        });
+    }
+
+    /// Creates the deny rule of the ban, for every protocol and expiring with it,
+    /// `None` if the source is already banned. Each ban takes a prefix of the
+    /// agents, past `max_bans` bans of the address family the source is not banned
+    /// so that the bans never crowd the other rules out.
+    pub async fn ban(&self, request: BanRequest) -> Result<Option<FirewallBanData>, BanError> {
+        let Some(key) = ip_key(&request.ip) else {
+            return Err(BanError::Db(
+                "[FIREWALL_BAN ERROR] ban: ip must have 4 (IPv4) or 16 (IPv6) octets".to_string(),
+            ));
+        };
+        let bans: FirewallBan = FirewallBan::new(self.db.clone());
+        if bans.get(FirewallBan::record_id(&key)).await?.is_some() {
+            return Ok(None);
+        }
+        if bans.count(request.ip.len()).await? >= self.max_bans {
+            return Err(BanError::Limit(self.max_bans));
+        }
+        let created_at: chrono::DateTime<Utc> = Utc::now();
+        let expires_at: Datetime =
+            Datetime::from(created_at + Duration::seconds(request.ttl.min(MAX_RULE_TTL) as i64));
+        let rule: FirewallRuleData = FirewallRule::new(self.db.clone())
+            .create(FirewallRuleData {
+                ip: request.ip.clone(),
+                cidr: request.ip.len() as u16 * 8,
+                layer: request.layer,
+                protocol: IpProtocol::Any,
+                status: false,
+                expires_at: Some(expires_at.clone()),
+                ..Default::default()
+            })
+            .await
+            .map_err(String::from)?;
+        let keys: Vec<String> = rule.id.iter().map(|id| id.key().to_string()).collect();
+        let data: FirewallBanData = FirewallBanData {
+            id: None,
+            ip: request.ip,
+            rules: keys,
+            threshold: request.threshold,
+            reason: request.reason,
+            created_at: Datetime::from(created_at),
+            expires_at,
+        };
+        let data: FirewallBanData = bans.create(FirewallBan::record_id(&key), data).await?;
+        self.rule_version.bump();
+        Ok(Some(data))
+    }
+
+    /// Bans the sources, a failed ban does not stop the others.
+    pub async fn ban_all(&self, requests: Vec<BanRequest>) {
+        for request in requests {
+            if let Err(error) = self.ban(request).await {
+                log::error!("{}", error);
+            }
+        }
+    }
+
+    /// Queues the bans for `run`, the caller does not wait for them.
+    pub fn queue(&self, requests: Vec<BanRequest>) {
+        if requests.is_empty() {
+            return;
+        }
+        if self.sender.send(requests).is_err() {
+            log::error!("[FIREWALL_BAN ERROR] queue: the ban queue is closed");
+        }
+    }
+
+    /// Creates the queued bans, one batch at a time so a source is never banned
+    /// twice concurrently. Only the first call consumes the queue.
+    pub async fn run(self: Arc<Self>) {
+        let Some(mut receiver) = self.receiver.lock().unwrap().take() else {
+            return;
+        };
+        while let Some(requests) = receiver.recv().await {
+            self.ban_all(requests).await;
+        }
+    }
+
+    /// Lifts the ban of the address and removes its rules, `None` if there is none.
+    pub async fn lift(&self, key: &str) -> Result<Option<FirewallBanData>, String> {
+        let bans: FirewallBan = FirewallBan::new(self.db.clone());
+        let Some(data) = bans.get(FirewallBan::record_id(key)).await? else {
+            return Ok(None);
+        };
+        let rules: FirewallRule = FirewallRule::new(self.db.clone());
+        for rule in data.rules.iter() {
+            let id = FirewallRule::record_id(rule);
+            // A rule removed by hand in the meantime is already gone.
+            if rules.get(id.clone()).await?.is_some() {
+                rules.remove(id).await?;
+            }
+        }
+        let data: Option<FirewallBanData> = bans.remove(FirewallBan::record_id(key)).await?;
+        self.rule_version.bump();
+        Ok(data)
+    }
+
+    /// Lifts the expired bans, returns how many were lifted.
+    pub async fn lift_expired(&self) -> Result<usize, String> {
+        let bans: Vec<FirewallBanData> = FirewallBan::new(self.db.clone()).list(true).await?;
+        let mut lifted: usize = 0;
+        for ban in bans {
+            if let Some(key) = ip_key(&ban.ip)
+                && self.lift(&key).await?.is_some()
+            {
+                lifted += 1;
+            }
+        }
+        Ok(lifted)
+    }
+
+    /// Lifts the expired bans and forgets the old events every `interval` seconds.
+    pub async fn sweep(self: Arc<Self>, interval: u64) {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval.max(1)));
+        loop {
+            interval.tick().await;
+            self.prune(Utc::now().timestamp());
+            if let Err(error) = self.lift_expired().await {
+                log::error!("{}", error);
+            }
+        }
+    }
+}
+
+/// Time of an event in seconds.
+fn seconds(timestamp: &Datetime) -> i64 {
+    timestamp.into_inner_ref().timestamp()
+}
+
+#[cfg(test)]
+mod test_ban_engine {
+    use super::*;
+    use crate::services::test_util::app_state;
+
+    fn threshold(source: BanSource) -> BanThreshold {
+        BanThreshold {
+            name: "scan".to_string(),
+            source,
+            count: 10,
+            window: 60,
+            ttl: 600,
+            status: false,
+            protocol: None,
+            destination_port: None,
+            command: Some("sshd".to_string()),
+            layer: 3,
+        }
+    }
+
+    fn at(time: i64) -> Datetime {
+        Datetime::from(chrono::DateTime::from_timestamp(time, 0).unwrap())
+    }
+
+    fn drop_log(ip: Vec<u8>, packets: u64, time: i64) -> FirewallLogData {
+        FirewallLogData {
+            ip,
+            protocol: IpProtocol::Tcp,
+            packets,
+            timestamp: at(time),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_ip_from_args() {
+        assert_eq!(
+            ip_from_args("sshd: invalid user admin from 203.0.113.7 port 52144"),
+            "203.0.113.7".parse().ok()
+        );
+        assert_eq!(
+            ip_from_args("-R 127.0.0.1 peer=[2001:db8::1]:22"),
+            "2001:db8::1".parse().ok()
+        );
+        assert_eq!(
+            ip_from_args("user@198.51.100.2:22"),
+            "198.51.100.2".parse().ok()
+        );
+        assert_eq!(ip_from_args("-la /tmp"), None);
+    }
+
+    #[actix_web::test]
+    async fn test_count() {
+        let app_state = app_state().await;
+        let config: BanConfig = BanConfig {
+            interval: 10,
+            thresholds: vec![
+                threshold(BanSource::FirewallLog),
+                threshold(BanSource::CommandExecution),
+            ],
+            ..BanConfig::default()
+        };
+        let engine = BanEngine::new(
+            app_state.db.clone(),
+            app_state.rule_version.clone(),
+            &config,
+        );
+
+        let ip: Vec<u8> = vec![10, 0, 0, 1];
+        assert!(
+            engine
+                .count_logs(&[drop_log(ip.clone(), 6, 100)], 100)
+                .is_empty()
+        );
+        assert!(
+            engine
+                .count_logs(&[drop_log(ip.clone(), 6, 161)], 161)
+                .is_empty(),
+            "expected the events before the window to be forgotten"
+        );
+        let requests: Vec<BanRequest> = engine.count_logs(&[drop_log(ip.clone(), 6, 170)], 170);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].ip, vec![10, 0, 0, 1]);
+        assert_eq!(requests[0].threshold, Some("scan".to_string()));
+        assert!(
+            engine
+                .count_logs(&[drop_log(ip.clone(), 6, 171)], 171)
+                .is_empty(),
+            "expected the events to be forgotten once banned"
+        );
+
+        let old: Vec<FirewallLogData> = vec![drop_log(vec![10, 0, 0, 4], 20, 100)];
+        assert!(
+            engine.count_logs(&old, 200).is_empty(),
+            "expected the events older than the window to be skipped"
+        );
+        let late: Vec<FirewallLogData> = vec![
+            drop_log(vec![10, 0, 0, 5], 6, 190),
+            drop_log(vec![10, 0, 0, 5], 6, 150),
+        ];
+        assert_eq!(
+            engine.count_logs(&late, 200).len(),
+            1,
+            "expected the events to be counted at their timestamp"
+        );
+
+        let mut allowed: FirewallLogData = drop_log(vec![10, 0, 0, 2], 20, 200);
+        allowed.status = true;
+        assert!(engine.count_logs(&[allowed], 200).is_empty());
+
+        let mut egress: FirewallLogData = drop_log(vec![10, 0, 0, 3], 20, 200);
+        egress.direction = Direction::Egress;
+        assert!(engine.count_logs(&[egress], 200).is_empty());
+
+        let command: CommandExecutionData = CommandExecutionData {
+            command: "sshd".to_string(),
+            args: "sshd: root [priv] 192.0.2.9".to_string(),
+            timestamp: at(200),
+            ..Default::default()
+        };
+        let commands: Vec<CommandExecutionData> = vec![command; 10];
+        let requests: Vec<BanRequest> = engine.count_commands(&commands, 200);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].ip, vec![192, 0, 2, 9]);
+    }
+
+    #[actix_web::test]
+    async fn test_ban_and_lift() {
+        let app_state = app_state().await;
+        let engine = BanEngine::new(
+            app_state.db.clone(),
+            app_state.rule_version.clone(),
+            &BanConfig::default(),
+        );
+        let version: u64 = app_state.rule_version.current();
+        let request: BanRequest = BanRequest {
+            ip: vec![10, 0, 0, 1],
+            ttl: 1,
+            layer: 3,
+            threshold: None,
+            reason: "manual".to_string(),
+        };
+        let ban = engine.ban(request.clone()).await;
+        assert!(ban.is_ok(), "{:?}", ban.err());
+        let ban: FirewallBanData = ban.unwrap().unwrap();
+        assert_eq!(ban.rules.len(), 1);
+        assert!(app_state.rule_version.current() > version);
+        let rules = FirewallRule::new(app_state.db.clone())
+            .keys()
+            .await
+            .unwrap();
+        assert_eq!(rules.len(), 1);
+
+        let again = engine.ban(request).await;
+        assert!(
+            again.is_ok_and(|data| data.is_none()),
+            "expected a banned source not to be banned again"
+        );
+
+        assert_eq!(engine.lift_expired().await, Ok(0));
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let lifted = engine.lift_expired().await;
+        assert_eq!(lifted, Ok(1));
+        let rules = FirewallRule::new(app_state.db.clone())
+            .keys()
+            .await
+            .unwrap();
+        assert!(
+            rules.is_empty(),
+            "expected the rules of the ban to be removed"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_ban_limit() {
+        let app_state = app_state().await;
+        let config: BanConfig = BanConfig {
+            max_bans: 2,
+            ..BanConfig::default()
+        };
+        let engine = BanEngine::new(
+            app_state.db.clone(),
+            app_state.rule_version.clone(),
+            &config,
+        );
+        let request = |ip: Vec<u8>| BanRequest {
+            ip,
+            ttl: 600,
+            layer: 3,
+            threshold: None,
+            reason: "manual".to_string(),
+        };
+        for ip in [vec![10, 0, 0, 1], vec![10, 0, 0, 2]] {
+            let ban = engine.ban(request(ip)).await;
+            assert!(ban.as_ref().is_ok_and(|ban| ban.is_some()), "{:?}", ban);
+        }
+        assert_eq!(
+            engine.ban(request(vec![10, 0, 0, 3])).await.err(),
+            Some(BanError::Limit(2)),
+            "expected the bans beyond max_bans to be refused"
+        );
+        let ban = engine
+            .ban(request(vec![
+                0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+            ]))
+            .await;
+        assert!(
+            ban.as_ref().is_ok_and(|ban| ban.is_some()),
+            "expected the bans to be limited per address family, {:?}",
+            ban
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_queue() {
+        let app_state = app_state().await;
+        let engine: Arc<BanEngine> = Arc::new(BanEngine::new(
+            app_state.db.clone(),
+            app_state.rule_version.clone(),
+            &BanConfig::default(),
+        ));
+        // Both tasks would otherwise connect at once, each to its own in-memory
+        // database, and only the last client connected is kept.
+        assert_eq!(app_state.db.connect().await, Ok(()));
+        actix_web::rt::spawn(engine.clone().run());
+        engine.queue(vec![BanRequest {
+            ip: vec![10, 0, 0, 1],
+            ttl: 600,
+            layer: 3,
+            threshold: None,
+            reason: "manual".to_string(),
+        }]);
+        let bans: FirewallBan = FirewallBan::new(app_state.db.clone());
+        for _ in 0..50 {
+            if bans.list(false).await.is_ok_and(|bans| !bans.is_empty()) {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("expected the queued ban to be created");
+    }
+}
@@ -1,3 +1,5 @@
+use crate::enums::{ban_source::BanSource, ip_protocol::IpProtocol};
+use ebpf_firewall_common::MAX_RULE_CHAINS;
 use serde::{Deserialize, Serialize};
 use std::{fs::File, io::Read};
 
@@ -38,10 +40,102 @@ pub struct DatabaServerConfig {
     pub database: String,
 }
 
+/// A source is banned once `count` matching events were received from it within
+/// `window` seconds, the ban lasts `ttl` seconds. Firewall logs match on
+/// `status`, dropped packets by default, and optionally on `protocol` and
+/// `destination_port`, each log counting its `packets`. The agents log dropped
+/// packets aggregated by source and rule, without their port, so
+/// `destination_port` requires `status`. Command executions match on `command`
+/// and are counted for the first address found in their arguments.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BanThreshold {
+    pub name: String,
+    pub source: BanSource,
+    pub count: u64,
+    pub window: u64,
+    pub ttl: u64,
+    #[serde(default)]
+    pub status: bool,
+    #[serde(default)]
+    pub protocol: Option<IpProtocol>,
+    #[serde(default)]
+    pub destination_port: Option<u16>,
+    #[serde(default)]
+    pub command: Option<String>,
+    /// Layer of the agents the deny rules are for.
+    #[serde(default = "default_ban_layer")]
+    pub layer: u8,
+}
+
+pub fn default_ban_layer() -> u8 {
+    3
+}
+
+/// Settings of the ban engine, nothing is banned automatically without thresholds.
+/// Expired bans are lifted every `interval` seconds. At most `max_bans` sources of
+/// each address family are banned at once, each ban takes one of the
+/// `MAX_RULE_CHAINS` prefixes the agents hold per family.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BanConfig {
+    #[serde(default = "default_ban_interval")]
+    pub interval: u64,
+    #[serde(default)]
+    pub thresholds: Vec<BanThreshold>,
+    #[serde(default = "default_max_bans")]
+    pub max_bans: usize,
+}
+
+fn default_ban_interval() -> u64 {
+    10
+}
+
+/// Half of the prefixes, the rest is left to the other rules.
+fn default_max_bans() -> usize {
+    MAX_RULE_CHAINS as usize / 2
+}
+
+impl BanConfig {
+    /// Rejects the thresholds that could never be reached and more bans than the
+    /// agents hold prefixes.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_bans > MAX_RULE_CHAINS as usize {
+            return Err(format!(
+                "config error: ban max_bans must not be greater than {}",
+                MAX_RULE_CHAINS
+            ));
+        }
+        for threshold in &self.thresholds {
+            if threshold.source == BanSource::FirewallLog
+                && !threshold.status
+                && threshold.destination_port.is_some()
+            {
+                return Err(format!(
+                    "config error: ban threshold {}: destination_port requires status = true, \
+                    the logs of dropped packets have no port",
+                    threshold.name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for BanConfig {
+    fn default() -> Self {
+        Self {
+            interval: default_ban_interval(),
+            thresholds: Vec::new(),
+            max_bans: default_max_bans(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
     pub http_server: HttpServerConfig,
     pub database_server: DatabaServerConfig,
+    #[serde(default)]
+    pub ban: BanConfig,
 }
 
 impl AppConfig {
@@ -57,6 +151,7 @@ impl AppConfig {
                 if let Ok(_) = file.read_to_string(&mut buf) {
                     match toml::from_str::<AppConfig>(&buf) {
                         Ok(config) => {
+                            config.ban.validate()?;
                             return Ok(config);
                         }
                         Err(error) => {
@@ -81,4 +176,35 @@ mod test_app_config {
         assert!(app_config.is_ok(), "{:?}", app_config.err());
         let _ = app_config.unwrap();
     }
+
+    #[test]
+    fn test_validate_ban_config() {
+        let config = |status: bool| -> BanConfig {
+            toml::from_str(&format!(
+                "[[thresholds]]
+                name = \"ssh-scan\"
+                source = \"FirewallLog\"
+                count = 10
+                window = 60
+                ttl = 3600
+                status = {}
+                destination_port = 22",
+                status
+            ))
+            .unwrap()
+        };
+        assert!(config(true).validate().is_ok());
+        assert!(
+            config(false).validate().is_err(),
+            "expected a port on the dropped packets to be rejected"
+        );
+        let config = BanConfig {
+            max_bans: MAX_RULE_CHAINS as usize + 1,
+            ..BanConfig::default()
+        };
+        assert!(
+            config.validate().is_err(),
+            "expected more bans than MAX_RULE_CHAINS to be rejected"
+        );
+    }
 }
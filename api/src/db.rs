@@ -1,34 +1,49 @@
 use crate::config::DatabaServerConfig;
 use std::sync::{Arc, RwLock};
 use surrealdb::{
-    engine::remote::ws::{Client, Ws},
+    engine::any::{self, Any},
     opt::auth::Root,
     Surreal,
 };
 
+/// Maximum number of records inserted by a single statement of the bulk inserts.
+pub const INSERT_CHUNK_SIZE: usize = 1000;
+
 #[derive(Debug, Clone)]
 pub struct Db {
     config: Arc<DatabaServerConfig>,
-    instance: Arc<RwLock<Surreal<Client>>>,
+    instance: Arc<RwLock<Surreal<Any>>>,
+}
+
+/// Addresses without a scheme are WebSocket servers, `mem://` is an in-memory
+/// database without authentication, available when built with `kv-mem`.
+fn endpoint(address: &str) -> String {
+    if address.contains("://") {
+        address.to_string()
+    } else {
+        format!("ws://{}", address)
+    }
 }
 
 impl Db {
-    pub fn get_client(&self) -> Arc<RwLock<Surreal<Client>>> {
+    pub fn get_client(&self) -> Arc<RwLock<Surreal<Any>>> {
         self.instance.clone()
     }
 
+    /// A handle to the database, taken out of the lock so that it is not held
+    /// across the queries.
+    pub fn client(&self) -> Result<Surreal<Any>, String> {
+        match self.instance.read() {
+            Ok(instance) => Ok(instance.clone()),
+            Err(error) => Err(format!("[DB_ERROR] client: {}", error)),
+        }
+    }
+
     pub async fn test_query(&self) -> Result<bool, String> {
-        match self.get_client().read() {
-            Ok(instance) => {
-                match instance
-                    .query("DEFINE TABLE IF NOT EXISTS test_table")
-                    .await
-                {
-                    Ok(_) => Ok(true),
-                    Err(error) => Err(format!("[DB_ERROR] test_query: {}", error.to_string())),
-                }
-            }
-            Err(error) => Err(format!("db error: {}", error.to_string())),
+        let client: Surreal<Any> = self.client()?;
+        match client.query("DEFINE TABLE IF NOT EXISTS test_table").await {
+            Ok(_) => Ok(true),
+            Err(error) => Err(format!("[DB_ERROR] test_query: {}", error)),
         }
     }
 
@@ -39,41 +54,42 @@ impl Db {
         })
     }
 
+    /// Reconnects when the test query fails. The new client is connected and signed
+    /// in outside the lock, which is only taken to swap it in, a std lock must not
+    /// be held across the awaits.
     pub async fn connect(&self) -> Result<(), String> {
-        match self.test_query().await {
-            Ok(_) => Ok(()),
-            Err(_) => match self.instance.write() {
-                Ok(instance) => {
-                    let db_config: Arc<DatabaServerConfig> = self.config.clone();
-                    let db_address: String = db_config.address.to_owned();
-                    let db_username: &str = &db_config.username.to_owned();
-                    let db_password: &str = &db_config.password.to_owned();
-                    let db_name: String = db_config.database.to_owned();
-                    let db_namespace: String = db_config.namespace.to_owned();
-                    match instance.connect::<Ws>(db_address).await {
-                        Ok(_) => {
-                            if let Err(error) = instance
-                                .signin(Root {
-                                    username: db_username,
-                                    password: db_password,
-                                })
-                                .await
-                            {
-                                return Err(format!("[DB_ERROR] connect: {}", error.to_string()));
-                            }
-                            if let Err(error) = instance.use_ns(db_namespace).await {
-                                return Err(format!("[DB_ERROR] connect: {}", error.to_string()));
-                            }
-                            if let Err(error) = instance.use_db(db_name).await {
-                                return Err(format!("[DB_ERROR] connect: {}", error.to_string()));
-                            }
-                            Ok(())
-                        }
-                        Err(error) => Err(format!("[DB_ERROR] connect: {}", error.to_string())),
-                    }
-                }
-                Err(error) => Err(format!("db error: {}", error.to_string())),
-            },
+        if self.test_query().await.is_ok() {
+            return Ok(());
+        }
+        let db_config: Arc<DatabaServerConfig> = self.config.clone();
+        let db_endpoint: String = endpoint(&db_config.address);
+        let client: Surreal<Any> = match any::connect(db_endpoint.as_str()).await {
+            Ok(client) => client,
+            Err(error) => return Err(format!("[DB_ERROR] connect: {}", error)),
+        };
+        if !db_endpoint.starts_with("mem://")
+            && let Err(error) = client
+                .signin(Root {
+                    username: &db_config.username,
+                    password: &db_config.password,
+                })
+                .await
+        {
+            return Err(format!("[DB_ERROR] connect: {}", error));
+        }
+        if let Err(error) = client
+            .use_ns(db_config.namespace.as_str())
+            .use_db(db_config.database.as_str())
+            .await
+        {
+            return Err(format!("[DB_ERROR] connect: {}", error));
+        }
+        match self.instance.write() {
+            Ok(mut instance) => {
+                *instance = client;
+                Ok(())
+            }
+            Err(error) => Err(format!("[DB_ERROR] connect: {}", error)),
         }
     }
 }
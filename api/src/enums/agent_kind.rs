@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum AgentKind {
+    #[default]
+    Firewall,
+    Tracepoint,
+}
+
+impl std::fmt::Display for AgentKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Firewall => write!(f, "Firewall"),
+            Self::Tracepoint => write!(f, "Tracepoint"),
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+/// Events a ban threshold counts, the firewall logs of the agents or the command
+/// executions of the tracepoint agents.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum BanSource {
+    FirewallLog,
+    CommandExecution,
+}
+
+impl std::fmt::Display for BanSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FirewallLog => write!(f, "FirewallLog"),
+            Self::CommandExecution => write!(f, "CommandExecution"),
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+/// Connection tracking state a rule applies to, `Established` covers replies to
+/// flows initiated by the protected host.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum ConnectionState {
+    #[default]
+    Any,
+    New,
+    Established,
+}
+
+impl std::fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Any => write!(f, "Any"),
+            Self::New => write!(f, "New"),
+            Self::Established => write!(f, "Established"),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+/// State of an agent on its interface. `Failed` interfaces could not be attached
+/// to and `Detached` interfaces were removed from the host.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum InterfaceState {
+    Attached,
+    Failed,
+    Detached,
+}
+
+impl std::fmt::Display for InterfaceState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Attached => write!(f, "Attached"),
+            Self::Failed => write!(f, "Failed"),
+            Self::Detached => write!(f, "Detached"),
+        }
+    }
+}
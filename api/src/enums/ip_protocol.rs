@@ -1,20 +1,5 @@
-use serde::{Deserialize, Serialize};
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub enum IpProtocol {
-    Tcp,
-    Udp,
-    Icmp,
-    Undefined,
-}
-
-impl std::fmt::Display for IpProtocol {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Tcp => write!(f, "Tcp"),
-            Self::Udp => write!(f, "Udp"),
-            Self::Icmp => write!(f, "Icmp"),
-            Self::Undefined => write!(f, "Undefined"),
-        }
-    }
-}
+pub use ebpf_firewall_common::protocol::IpProtocol;
+/// Protocol number the agents use for `Any`, reserved by IANA.
+pub use ebpf_firewall_common::RULE_PROTOCOL_ANY as PROTOCOL_ANY_NUMBER;
+/// Protocol number of ICMPv6, matched by the `Icmp` rules of IPv6 packets.
+pub use ebpf_firewall_common::parse::IP_PROTO_IPV6_ICMP as PROTOCOL_ICMPV6_NUMBER;
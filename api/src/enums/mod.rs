@@ -1 +1,7 @@
+pub mod agent_kind;
+pub mod ban_source;
+pub mod connection_state;
+pub mod direction;
+pub mod interface_state;
 pub mod ip_protocol;
+pub mod rule_action;
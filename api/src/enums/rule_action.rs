@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// What a rule does with the matched packets. `Filter` passes or drops them by
+/// `status`, `RateLimit` drops the traffic of a source prefix beyond the limit
+/// and `SynFlood` only limits the TCP packets opening a connection.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum RuleAction {
+    #[default]
+    Filter,
+    RateLimit,
+    SynFlood,
+}
+
+impl std::fmt::Display for RuleAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Filter => write!(f, "Filter"),
+            Self::RateLimit => write!(f, "RateLimit"),
+            Self::SynFlood => write!(f, "SynFlood"),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use actix_web::error::{InternalError, JsonPayloadError, PathError, QueryPayloadError};
+use actix_web::{HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+/// A rejected field of a request body.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// JSON body of error responses, `fields` lists the rejected fields of a
+/// validation error.
+#[derive(Clone, Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+impl ErrorResponse {
+    fn new(error: &str, message: String, fields: Vec<FieldError>) -> Self {
+        Self {
+            error: error.to_string(),
+            message,
+            fields,
+        }
+    }
+
+    pub fn validation(fields: Vec<FieldError>) -> HttpResponse {
+        HttpResponse::UnprocessableEntity().json(Self::new(
+            "validation",
+            "the request has invalid fields".to_string(),
+            fields,
+        ))
+    }
+
+    pub fn bad_request(message: String) -> HttpResponse {
+        HttpResponse::BadRequest().json(Self::new("bad_request", message, Vec::new()))
+    }
+
+    pub fn not_found(message: String) -> HttpResponse {
+        HttpResponse::NotFound().json(Self::new("not_found", message, Vec::new()))
+    }
+
+    pub fn conflict(message: String) -> HttpResponse {
+        HttpResponse::Conflict().json(Self::new("conflict", message, Vec::new()))
+    }
+
+    pub fn internal(message: String) -> HttpResponse {
+        HttpResponse::InternalServerError().json(Self::new("internal", message, Vec::new()))
+    }
+}
+
+/// Answers malformed JSON bodies with an `ErrorResponse`.
+pub fn json_error_handler(error: JsonPayloadError, _: &HttpRequest) -> actix_web::Error {
+    let response: HttpResponse = ErrorResponse::bad_request(error.to_string());
+    InternalError::from_response(error, response).into()
+}
+
+/// Answers malformed path parameters with an `ErrorResponse`.
+pub fn path_error_handler(error: PathError, _: &HttpRequest) -> actix_web::Error {
+    let response: HttpResponse = ErrorResponse::bad_request(error.to_string());
+    InternalError::from_response(error, response).into()
+}
+
+/// Answers malformed query strings with an `ErrorResponse`.
+pub fn query_error_handler(error: QueryPayloadError, _: &HttpRequest) -> actix_web::Error {
+    let response: HttpResponse = ErrorResponse::bad_request(error.to_string());
+    InternalError::from_response(error, response).into()
+}
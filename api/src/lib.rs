@@ -1,10 +1,16 @@
+pub mod ban;
 pub mod config;
 pub mod db;
 pub mod enums;
+pub mod error;
 pub mod models;
+pub mod rule_sweeper;
+pub mod rule_version;
 pub mod services;
 use std::sync::Arc;
 
 pub struct AppState {
     pub db: Arc<db::Db>,
+    pub rule_version: Arc<rule_version::RuleVersion>,
+    pub bans: Arc<ban::BanEngine>,
 }
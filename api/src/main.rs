@@ -1,8 +1,14 @@
 use actix_cors::Cors;
 use actix_web::{web, App, HttpServer};
-use api::config::{AppConfig, DatabaServerConfig, HttpServerConfig};
+use api::ban::BanEngine;
+use api::config::{AppConfig, BanConfig, DatabaServerConfig, HttpServerConfig};
 use api::db::Db;
-use api::services::{command_execution, firewall_log, firewall_rule, ping};
+use api::models::firewall_rule::FirewallRule;
+use api::rule_sweeper::sweep_rules;
+use api::rule_version::RuleVersion;
+use api::services::{
+    agent, command_execution, firewall_ban, firewall_log, firewall_rule, firewall_stats, ping,
+};
 use api::AppState;
 use clap::Parser;
 use env_logger;
@@ -22,51 +28,52 @@ async fn main() -> Result<(), String> {
     let app_config = AppConfig::load(Some(config_path))?;
     let http_server_config: HttpServerConfig = app_config.http_server;
     let database_server_config: Arc<DatabaServerConfig> = Arc::new(app_config.database_server);
+    let ban_config: BanConfig = app_config.ban;
 
     unsafe {
         env::set_var("RUST_LOG", http_server_config.log_level.to_string());
     }
     env_logger::init();
     let db: Arc<Db> = Arc::new(Db::new(database_server_config).await?);
+    match FirewallRule::new(db.clone()).migrate_protocols().await {
+        Ok(0) => {}
+        Ok(migrated) => log::info!("migrated the protocol of {} firewall rules", migrated),
+        Err(error) => log::error!("{}", error),
+    }
+    let rule_version: Arc<RuleVersion> = Arc::new(RuleVersion::new());
+    let bans: Arc<BanEngine> = Arc::new(BanEngine::new(
+        db.clone(),
+        rule_version.clone(),
+        &ban_config,
+    ));
+    // The database futures are not `Send`, the sweeps run on the main thread.
+    let local = tokio::task::LocalSet::new();
+    local.spawn_local(bans.clone().sweep(ban_config.interval));
+    local.spawn_local(bans.clone().run());
+    local.spawn_local(sweep_rules(db.clone(), rule_version.clone()));
     match HttpServer::new(move || {
         let cors = Cors::default().allow_any_origin();
         App::new()
             .wrap(cors)
-            .app_data(web::Data::new(AppState { db: db.clone() }))
+            .app_data(web::Data::new(AppState {
+                db: db.clone(),
+                rule_version: rule_version.clone(),
+                bans: bans.clone(),
+            }))
             .route("/ping", web::get().to(ping::pong))
-            .service(
-                web::scope("/command-execution")
-                    .route(
-                        "/log",
-                        web::post().to(command_execution::log_command_execution),
-                    )
-                    .route("/list", web::get().to(command_execution::executed_commands))
-                    .route(
-                        "/stats",
-                        web::get().to(command_execution::executed_command_stats),
-                    ),
-            )
-            .service(
-                web::scope("/firewall-rule")
-                    .route(
-                        "/list/{layer}",
-                        web::get().to(firewall_rule::get_firewall_rules),
-                    )
-                    .route(
-                        "/create",
-                        web::post().to(firewall_rule::create_firewall_rule),
-                    ),
-            )
-            .service(
-                web::scope("/firewall-log")
-                    .route("/list", web::get().to(firewall_log::get_firewall_logs))
-                    .route("/create", web::post().to(firewall_log::create_firewall_log)),
-            )
+            .service(command_execution::scope())
+            .service(agent::scope())
+            .service(firewall_rule::scope())
+            .service(firewall_log::scope())
+            .service(firewall_stats::scope())
+            .service(firewall_ban::scope())
     })
     .bind((http_server_config.host.as_str(), http_server_config.port))
     {
         Ok(server) => {
-            let _ = server.workers(http_server_config.workers).run().await;
+            let _ = local
+                .run_until(server.workers(http_server_config.workers).run())
+                .await;
             Ok(())
         }
         Err(error) => Err(format!("[SERVER ERROR] main: {}", error.to_string())),
@@ -0,0 +1,252 @@
+use crate::db::Db;
+use crate::enums::agent_kind::AgentKind;
+use crate::enums::interface_state::InterfaceState;
+use crate::error::FieldError;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use surrealdb::{engine::any::Any, Datetime, RecordId, Surreal};
+
+/// Event counters an agent reports with its heartbeats, since it started.
+/// `events_lost` never reached the agent as its kernel buffer was full,
+/// `events_dropped` were read but never delivered.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AgentMetrics {
+    pub transport: String,
+    pub events_lost: u64,
+    pub events_shipped: u64,
+    pub events_dropped: u64,
+    pub events_spooled: u64,
+}
+
+/// A registered agent, one record per host, kind and interface. `groups` are
+/// assigned through the API and kept across heartbeats, rules and policies can
+/// target the agent by `hostname` or by any of its groups. `state` and `error`
+/// are the last reported state of the agent on its interface.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AgentData {
+    pub id: Option<RecordId>,
+    pub hostname: String,
+    pub kind: AgentKind,
+    pub interface: Option<String>,
+    pub version: String,
+    pub kernel: String,
+    pub attach_mode: Option<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[serde(default)]
+    pub metrics: Option<AgentMetrics>,
+    #[serde(default)]
+    pub state: Option<InterfaceState>,
+    #[serde(default)]
+    pub error: Option<String>,
+    pub last_seen: Datetime,
+}
+
+/// What an agent reports about itself, the first heartbeat registers it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AgentHeartbeat {
+    pub hostname: String,
+    pub kind: AgentKind,
+    #[serde(default)]
+    pub interface: Option<String>,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub kernel: String,
+    #[serde(default)]
+    pub attach_mode: Option<String>,
+    #[serde(default)]
+    pub metrics: Option<AgentMetrics>,
+    #[serde(default)]
+    pub state: Option<InterfaceState>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Fields merged into the agent record on a heartbeat, leaving `groups` untouched.
+#[derive(Clone, Debug, Serialize)]
+struct AgentStatus {
+    hostname: String,
+    kind: AgentKind,
+    interface: Option<String>,
+    version: String,
+    kernel: String,
+    attach_mode: Option<String>,
+    metrics: Option<AgentMetrics>,
+    state: Option<InterfaceState>,
+    error: Option<String>,
+    last_seen: Datetime,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct AgentGroups {
+    groups: Vec<String>,
+}
+
+impl AgentHeartbeat {
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors: Vec<FieldError> = Vec::new();
+        if self.hostname.trim().is_empty() {
+            errors.push(FieldError::new("hostname", "must not be empty"));
+        }
+        if self
+            .interface
+            .as_ref()
+            .is_some_and(|interface| interface.trim().is_empty())
+        {
+            errors.push(FieldError::new("interface", "must not be empty"));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Record key of the agent, such as `firewall:edge-1:eth0`.
+    pub fn key(&self) -> String {
+        let kind: String = self.kind.to_string().to_lowercase();
+        match &self.interface {
+            Some(interface) => format!("{}:{}:{}", kind, self.hostname, interface),
+            None => format!("{}:{}", kind, self.hostname),
+        }
+    }
+}
+
+pub fn validate_groups(groups: &[String]) -> Result<(), Vec<FieldError>> {
+    if groups.iter().any(|group| group.trim().is_empty()) {
+        return Err(vec![FieldError::new("groups", "must not contain empty names")]);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct Agent {
+    db: Arc<Db>,
+}
+
+impl Agent {
+    pub fn new(db: Arc<Db>) -> Self {
+        Self { db: db.clone() }
+    }
+
+    pub fn table() -> String {
+        "agent".to_string()
+    }
+
+    pub fn record_id(key: &str) -> RecordId {
+        RecordId::from_table_key(Self::table(), key)
+    }
+
+    /// Registers the agent or refreshes its status and `last_seen`.
+    pub async fn heartbeat(&self, data: AgentHeartbeat) -> Result<AgentData, String> {
+        self.db.connect().await?;
+        if let Err(errors) = data.validate() {
+            return Err(format!(
+                "[AGENT ERROR] heartbeat: {}",
+                errors
+                    .iter()
+                    .map(|error| error.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ));
+        }
+        let id: RecordId = Self::record_id(&data.key());
+        let status: AgentStatus = AgentStatus {
+            hostname: data.hostname,
+            kind: data.kind,
+            interface: data.interface,
+            version: data.version,
+            kernel: data.kernel,
+            attach_mode: data.attach_mode,
+            metrics: data.metrics,
+            state: data.state,
+            error: data.error,
+            last_seen: Datetime::from(Utc::now()),
+        };
+        let client: Surreal<Any> = self.db.client()?;
+        match client.upsert::<Option<AgentData>>(id).merge(status).await {
+            Ok(Some(data)) => Ok(data),
+            Ok(None) => Err("[AGENT ERROR] heartbeat: value not found".to_string()),
+            Err(error) => Err(format!("[AGENT ERROR] heartbeat: {}", error)),
+        }
+    }
+
+    pub async fn get(&self, id: RecordId) -> Result<Option<AgentData>, String> {
+        self.db.connect().await?;
+        let client: Surreal<Any> = self.db.client()?;
+        match client.select::<Option<AgentData>>(id).await {
+            Ok(data) => Ok(data),
+            Err(error) => Err(format!("[AGENT ERROR] get: {}", error)),
+        }
+    }
+
+    /// Replaces the groups of the agent, `None` if there is no agent with the id.
+    pub async fn set_groups(
+        &self,
+        id: RecordId,
+        groups: Vec<String>,
+    ) -> Result<Option<AgentData>, String> {
+        self.db.connect().await?;
+        if self.get(id.clone()).await?.is_none() {
+            return Ok(None);
+        }
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .update::<Option<AgentData>>(id)
+            .merge(AgentGroups { groups })
+            .await
+        {
+            Ok(data) => Ok(data),
+            Err(error) => Err(format!("[AGENT ERROR] set_groups: {}", error)),
+        }
+    }
+
+    pub async fn remove(&self, id: RecordId) -> Result<Option<AgentData>, String> {
+        self.db.connect().await?;
+        let client: Surreal<Any> = self.db.client()?;
+        match client.delete::<Option<AgentData>>(id).await {
+            Ok(data) => Ok(data),
+            Err(error) => Err(format!("[AGENT ERROR] remove: {}", error)),
+        }
+    }
+
+    pub async fn list(&self) -> Result<Vec<AgentData>, String> {
+        self.db.connect().await?;
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .query("SELECT * FROM type::table($table) ORDER BY hostname ASC, interface ASC;")
+            .bind(("table", Self::table()))
+            .await
+        {
+            Ok(mut response) => match response.take::<Vec<AgentData>>(0) {
+                Ok(data) => Ok(data),
+                Err(error) => Err(format!("[AGENT ERROR] list: {}", error)),
+            },
+            Err(error) => Err(format!("[AGENT ERROR] list: {}", error)),
+        }
+    }
+
+    /// Groups of all records of the host, whatever their kind or interface.
+    pub async fn groups(&self, hostname: &str) -> Result<Vec<String>, String> {
+        self.db.connect().await?;
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .query(
+                "RETURN array::distinct(array::flatten(
+                    (SELECT VALUE groups ?? [] FROM type::table($table) WHERE hostname = $hostname)
+                ));",
+            )
+            .bind(("table", Self::table()))
+            .bind(("hostname", hostname.to_string()))
+            .await
+        {
+            Ok(mut response) => match response.take::<Vec<String>>(0) {
+                Ok(data) => Ok(data),
+                Err(error) => Err(format!("[AGENT ERROR] groups: {}", error)),
+            },
+            Err(error) => Err(format!("[AGENT ERROR] groups: {}", error)),
+        }
+    }
+}
@@ -1,8 +1,9 @@
-use crate::db::Db;
+use crate::db::{Db, INSERT_CHUNK_SIZE};
+use crate::error::FieldError;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use surrealdb::{Datetime, RecordId};
+use surrealdb::{engine::any::Any, Datetime, RecordId, Surreal};
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CommandExecutionData {
     pub id: Option<RecordId>,
@@ -48,6 +49,15 @@ impl Default for CommandExecutionData {
     }
 }
 
+impl CommandExecutionData {
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        if self.command.is_empty() {
+            return Err(vec![FieldError::new("command", "must not be empty")]);
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CommandExecution {
     db: Arc<Db>,
@@ -66,57 +76,63 @@ impl CommandExecution {
         if data.command.is_empty() {
             return Err("[COMMAND_EXECUTION ERROR] create: command must not be empty".to_string());
         }
-        match self.db.get_client().read() {
-            Ok(db_client) => {
-                match db_client
-                    .insert::<Vec<CommandExecutionData>>(Self::table())
-                    .content(data)
-                    .await
-                {
-                    Ok(data) => {
-                        if let Some(value) = data.first() {
-                            return Ok(value.to_owned());
-                        }
-                        return Err("[COMMAND_EXECUTION ERROR] create: value not found".to_string());
-                    }
-                    Err(error) => Err(format!(
-                        "[COMMAND_EXECUTION ERROR] create: {}",
-                        error.to_string()
-                    )),
-                }
-            }
-            Err(error) => Err(format!(
-                "[COMMAND_EXECUTION ERROR] create: {}",
-                error.to_string()
-            )),
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .insert::<Vec<CommandExecutionData>>(Self::table())
+            .content(data)
+            .await
+        {
+            Ok(data) => match data.first() {
+                Some(value) => Ok(value.to_owned()),
+                None => Err("[COMMAND_EXECUTION ERROR] create: value not found".to_string()),
+            },
+            Err(error) => Err(format!("[COMMAND_EXECUTION ERROR] create: {}", error)),
+        }
+    }
+
+    /// Inserts the executions in a single transaction, in statements of up to
+    /// `INSERT_CHUNK_SIZE` executions. Nothing is inserted if one of them is invalid or
+    /// a statement fails, so the whole batch can be sent again.
+    pub async fn create_many(&self, data: Vec<CommandExecutionData>) -> Result<usize, String> {
+        self.db.connect().await?;
+        if let Some(index) = data.iter().position(|item| item.validate().is_err()) {
+            return Err(format!(
+                "[COMMAND_EXECUTION ERROR] create_many: item {} is invalid",
+                index
+            ));
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let db_client: Surreal<Any> = self.db.client()?;
+        let mut query = db_client.query("BEGIN TRANSACTION;");
+        for (index, chunk) in data.chunks(INSERT_CHUNK_SIZE).enumerate() {
+            query = query
+                .query(format!("INSERT INTO {} $chunk_{};", Self::table(), index))
+                .bind((format!("chunk_{}", index), chunk.to_vec()));
+        }
+        match query.query("COMMIT TRANSACTION;").await {
+            Ok(response) => match response.check() {
+                Ok(_) => Ok(data.len()),
+                Err(error) => Err(format!("[COMMAND_EXECUTION ERROR] create_many: {}", error)),
+            },
+            Err(error) => Err(format!("[COMMAND_EXECUTION ERROR] create_many: {}", error)),
         }
     }
 
     pub async fn get_counts(&self) -> Result<Vec<CommandExecutionCountsData>, String> {
         let _ = self.db.connect().await?;
-
-        match self.db.get_client().read() {
-            Ok(db_client) => {
-                match db_client.query("SELECT * FROM (SELECT command, count(command) AS total FROM command_execution GROUP BY command) ORDER BY total NUMERIC DESC LIMIT 20;")
-                    .bind(("table",Self::table()))
-                    .await
-                {
-                    Ok(mut response) => {
-                        match response.take::<Vec<CommandExecutionCountsData>>(0) {
-                            Ok(data) => Ok(data),
-                            Err(error) => Err(format!("[COMMAND_EXECUTION ERROR] get_counts: {}",error.to_string()))
-                        }
-                    }
-                    Err(error) => Err(format!(
-                        "[COMMAND_EXECUTION ERROR] get_counts: {}",
-                        error.to_string()
-                    )),
-                }
-            }
-            Err(error) => Err(format!(
-                "[COMMAND_EXECUTION ERROR] get_counts: {}",
-                error.to_string()
-            )),
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .query("SELECT * FROM (SELECT command, count(command) AS total FROM command_execution GROUP BY command) ORDER BY total NUMERIC DESC LIMIT 20;")
+            .bind(("table", Self::table()))
+            .await
+        {
+            Ok(mut response) => match response.take::<Vec<CommandExecutionCountsData>>(0) {
+                Ok(data) => Ok(data),
+                Err(error) => Err(format!("[COMMAND_EXECUTION ERROR] get_counts: {}", error)),
+            },
+            Err(error) => Err(format!("[COMMAND_EXECUTION ERROR] get_counts: {}", error)),
         }
     }
 
@@ -126,66 +142,56 @@ impl CommandExecution {
         offset: usize,
     ) -> Result<CommandExecutionPaginationData, String> {
         let _ = self.db.connect().await?;
-
-        match self.db.get_client().read() {
-            Ok(db_client) => {
-                match db_client
-                    .query(
-                        r#"
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .query(
+                r#"
                     SELECT count() as total FROM type::table($table) GROUP BY count;
                     SELECT * FROM type::table($table) ORDER BY timestamp DESC LIMIT $limit START $offset;
                 "#,
-                    )
-                    .bind(("table", Self::table()))
-                    .bind(("limit", limit))
-                    .bind(("offset", offset))
-                    .await
+            )
+            .bind(("table", Self::table()))
+            .bind(("limit", limit))
+            .bind(("offset", offset))
+            .await
+        {
+            Ok(mut response) => {
+                let total: usize = match response.take::<Option<CommandExecutionPaginationTotal>>(0)
                 {
-                    Ok(mut response) => {
-                        let total: usize = match response
-                            .take::<Option<CommandExecutionPaginationTotal>>(0)
-                        {
-                            Ok(data) => match data {
-                                Some(item) => item.total,
-                                None => {
-                                    return Err(
-                                        "[COMMAND_EXECUTION ERROR] get_executed_commands: invalid total value".to_string()
-                                    );
-                                }
-                            },
-                            Err(error) => {
-                                return Err(format!(
-                                    "[COMMAND_EXECUTION ERROR] get_executed_commands {}",
-                                    error.to_string()
-                                ));
-                            }
-                        };
-                        let data: Vec<CommandExecutionData> =
-                            match response.take::<Vec<CommandExecutionData>>(1) {
-                                Ok(data) => data,
-                                Err(error) => {
-                                    return Err(format!(
-                                        "[COMMAND_EXECUTION ERROR] get_executed_commands: {}",
-                                        error.to_string()
-                                    ));
-                                }
-                            };
-                        return Ok(CommandExecutionPaginationData {
-                            data,
-                            total,
-                            offset,
-                            limit,
-                        });
+                    Ok(Some(item)) => item.total,
+                    Ok(None) => {
+                        return Err(
+                            "[COMMAND_EXECUTION ERROR] get_executed_commands: invalid total value"
+                                .to_string(),
+                        );
+                    }
+                    Err(error) => {
+                        return Err(format!(
+                            "[COMMAND_EXECUTION ERROR] get_executed_commands {}",
+                            error
+                        ));
                     }
-                    Err(error) => Err(format!(
-                        "[COMMAND_EXECUTION ERROR] get_executed_commands: {}",
-                        error.to_string()
-                    )),
-                }
+                };
+                let data: Vec<CommandExecutionData> =
+                    match response.take::<Vec<CommandExecutionData>>(1) {
+                        Ok(data) => data,
+                        Err(error) => {
+                            return Err(format!(
+                                "[COMMAND_EXECUTION ERROR] get_executed_commands: {}",
+                                error
+                            ));
+                        }
+                    };
+                Ok(CommandExecutionPaginationData {
+                    data,
+                    total,
+                    offset,
+                    limit,
+                })
             }
             Err(error) => Err(format!(
                 "[COMMAND_EXECUTION ERROR] get_executed_commands: {}",
-                error.to_string()
+                error
             )),
         }
     }
@@ -0,0 +1,133 @@
+use crate::db::Db;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use surrealdb::{engine::any::Any, Datetime, RecordId, Surreal};
+
+/// A source denied by the `rules` until `expires_at`, when the ban is lifted and
+/// the rules are removed. The record key is the address, such as `10.0.0.1`.
+/// `threshold` is the name of the threshold that banned the source, `None` for
+/// the bans created through the API.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FirewallBanData {
+    pub id: Option<RecordId>,
+    pub ip: Vec<u8>,
+    pub rules: Vec<String>,
+    #[serde(default)]
+    pub threshold: Option<String>,
+    pub reason: String,
+    pub created_at: Datetime,
+    pub expires_at: Datetime,
+}
+
+/// Textual form of an address of 4 or 16 octets.
+pub fn ip_key(ip: &[u8]) -> Option<String> {
+    match ip.len() {
+        4 => Some(Ipv4Addr::from(<[u8; 4]>::try_from(ip).ok()?).to_string()),
+        16 => Some(Ipv6Addr::from(<[u8; 16]>::try_from(ip).ok()?).to_string()),
+        _ => None,
+    }
+}
+
+/// Octets of an address, 4 for IPv4 and 16 for IPv6.
+pub fn ip_octets(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FirewallBan {
+    db: Arc<Db>,
+}
+
+impl FirewallBan {
+    pub fn new(db: Arc<Db>) -> Self {
+        Self { db: db.clone() }
+    }
+
+    pub fn table() -> String {
+        "firewall_ban".to_string()
+    }
+
+    pub fn record_id(key: &str) -> RecordId {
+        RecordId::from_table_key(Self::table(), key)
+    }
+
+    pub async fn create(
+        &self,
+        id: RecordId,
+        data: FirewallBanData,
+    ) -> Result<FirewallBanData, String> {
+        self.db.connect().await?;
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .create::<Option<FirewallBanData>>(id)
+            .content(data)
+            .await
+        {
+            Ok(Some(data)) => Ok(data),
+            Ok(None) => Err("[FIREWALL_BAN ERROR] create: value not found".to_string()),
+            Err(error) => Err(format!("[FIREWALL_BAN ERROR] create: {}", error)),
+        }
+    }
+
+    pub async fn get(&self, id: RecordId) -> Result<Option<FirewallBanData>, String> {
+        self.db.connect().await?;
+        let client: Surreal<Any> = self.db.client()?;
+        match client.select::<Option<FirewallBanData>>(id).await {
+            Ok(data) => Ok(data),
+            Err(error) => Err(format!("[FIREWALL_BAN ERROR] get: {}", error)),
+        }
+    }
+
+    /// Bans by ascending expiry, with `expired` only the ones past it.
+    pub async fn list(&self, expired: bool) -> Result<Vec<FirewallBanData>, String> {
+        self.db.connect().await?;
+        let query: &str = if expired {
+            "SELECT * FROM type::table($table) WHERE expires_at <= time::now() ORDER BY expires_at ASC;"
+        } else {
+            "SELECT * FROM type::table($table) ORDER BY expires_at ASC;"
+        };
+        let client: Surreal<Any> = self.db.client()?;
+        match client.query(query).bind(("table", Self::table())).await {
+            Ok(mut response) => match response.take::<Vec<FirewallBanData>>(0) {
+                Ok(data) => Ok(data),
+                Err(error) => Err(format!("[FIREWALL_BAN ERROR] list: {}", error)),
+            },
+            Err(error) => Err(format!("[FIREWALL_BAN ERROR] list: {}", error)),
+        }
+    }
+
+    /// Number of unexpired bans of addresses of `octets` octets, 4 for IPv4 and 16
+    /// for IPv6.
+    pub async fn count(&self, octets: usize) -> Result<usize, String> {
+        self.db.connect().await?;
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .query(
+                "SELECT count() FROM type::table($table)
+                    WHERE array::len(ip) = $octets AND expires_at > time::now() GROUP ALL;",
+            )
+            .bind(("table", Self::table()))
+            .bind(("octets", octets))
+            .await
+        {
+            Ok(mut response) => match response.take::<Option<usize>>((0, "count")) {
+                Ok(count) => Ok(count.unwrap_or(0)),
+                Err(error) => Err(format!("[FIREWALL_BAN ERROR] count: {}", error)),
+            },
+            Err(error) => Err(format!("[FIREWALL_BAN ERROR] count: {}", error)),
+        }
+    }
+
+    pub async fn remove(&self, id: RecordId) -> Result<Option<FirewallBanData>, String> {
+        self.db.connect().await?;
+        let client: Surreal<Any> = self.db.client()?;
+        match client.delete::<Option<FirewallBanData>>(id).await {
+            Ok(data) => Ok(data),
+            Err(error) => Err(format!("[FIREWALL_BAN ERROR] remove: {}", error)),
+        }
+    }
+}
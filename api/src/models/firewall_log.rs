@@ -1,28 +1,101 @@
-use crate::db::Db;
-use crate::enums::ip_protocol::IpProtocol;
+use crate::db::{Db, INSERT_CHUNK_SIZE};
+use crate::enums::{direction::Direction, ip_protocol::IpProtocol};
+use crate::error::FieldError;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use surrealdb::{Datetime, RecordId};
+use surrealdb::{engine::any::Any, Datetime, RecordId, Surreal};
+/// `ip` holds 4 octets for an IPv4 source and 16 octets for an IPv6 source.
+/// A log counts the `packets` dropped from `ip` in the `window` seconds before
+/// `timestamp`, a single packet has a `window` of 0. `rule` is the key of the
+/// matched rule, `None` for the default action. `status` is true for allowed
+/// packets, which are logged for rules with `log` set. The destination, `length`
+/// and the `ifindex` of the interface are only known for single packets. `ip`
+/// and `port` of `Egress` logs are the destination the packets were sent to.
+/// ICMP logs of single packets have the `icmp_type` and `icmp_code` rather than
+/// ports.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct FirewallLogData {
     pub id: Option<RecordId>,
-    pub ip: [u8; 4],
+    pub ip: Vec<u8>,
     pub protocol: IpProtocol,
     pub port: Option<u16>,
     pub status: bool,
     pub timestamp: Datetime,
+    #[serde(default = "default_packets")]
+    pub packets: u64,
+    #[serde(default)]
+    pub bytes: u64,
+    #[serde(default)]
+    pub window: u64,
+    #[serde(default)]
+    pub rule: Option<String>,
+    #[serde(default)]
+    pub destination_ip: Option<Vec<u8>>,
+    #[serde(default)]
+    pub destination_port: Option<u16>,
+    #[serde(default)]
+    pub length: Option<u32>,
+    #[serde(default)]
+    pub ifindex: Option<u32>,
+    #[serde(default)]
+    pub direction: Direction,
+    #[serde(default)]
+    pub icmp_type: Option<u8>,
+    #[serde(default)]
+    pub icmp_code: Option<u8>,
 }
+
+pub fn default_packets() -> u64 {
+    1
+}
+
 impl Default for FirewallLogData {
     fn default() -> Self {
         Self {
             id: None,
-            ip: [0; 4],
+            ip: Vec::new(),
             port: None,
             protocol: IpProtocol::Undefined,
             status: false,
             timestamp: Datetime::from(Utc::now()),
+            packets: default_packets(),
+            bytes: 0,
+            window: 0,
+            rule: None,
+            destination_ip: None,
+            destination_port: None,
+            length: None,
+            ifindex: None,
+            direction: Direction::Ingress,
+            icmp_type: None,
+            icmp_code: None,
+        }
+    }
+}
+
+impl FirewallLogData {
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        if self.ip.len() != 4 && self.ip.len() != 16 {
+            return Err(vec![FieldError::new(
+                "ip",
+                "must have 4 (IPv4) or 16 (IPv6) octets",
+            )]);
+        }
+        if self
+            .destination_ip
+            .as_ref()
+            .is_some_and(|ip| ip.len() != self.ip.len())
+        {
+            return Err(vec![FieldError::new(
+                "destination_ip",
+                "must have as many octets as ip",
+            )]);
+        }
+        if self.packets == 0 {
+            return Err(vec![FieldError::new("packets", "must be at least 1")]);
         }
+        Ok(())
     }
 }
 
@@ -42,53 +115,71 @@ impl FirewallLog {
 
     pub async fn create(&self, data: FirewallLogData) -> Result<FirewallLogData, String> {
         let _ = self.db.connect().await?;
-        match self.db.get_client().read() {
-            Ok(client) => {
-                match client
-                    .insert::<Vec<FirewallLogData>>(Self::table())
-                    .content(data)
-                    .await
-                {
-                    Ok(data) => {
-                        if let Some(value) = data.first() {
-                            return Ok(value.to_owned());
-                        }
-                        return Err("[FIREWALL_LOG ERROR] create: value not found".to_string());
-                    }
-                    Err(error) => {
-                        return Err(format!("[FIREWALL_LOG ERROR] create {}", error.to_string()))
-                    }
-                }
-            }
-            Err(error) => Err(format!("[FIREWALL_LOG ERROR] create {}", error.to_string())),
+        if data.ip.len() != 4 && data.ip.len() != 16 {
+            return Err(
+                "[FIREWALL_LOG ERROR] create: ip must have 4 (IPv4) or 16 (IPv6) octets"
+                    .to_string(),
+            );
+        }
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .insert::<Vec<FirewallLogData>>(Self::table())
+            .content(data)
+            .await
+        {
+            Ok(data) => match data.first() {
+                Some(value) => Ok(value.to_owned()),
+                None => Err("[FIREWALL_LOG ERROR] create: value not found".to_string()),
+            },
+            Err(error) => Err(format!("[FIREWALL_LOG ERROR] create {}", error)),
+        }
+    }
+
+    /// Inserts the logs in a single transaction, in statements of up to
+    /// `INSERT_CHUNK_SIZE` logs. Nothing is inserted if one of them is invalid or
+    /// a statement fails, so the whole batch can be sent again.
+    pub async fn create_many(&self, data: Vec<FirewallLogData>) -> Result<usize, String> {
+        self.db.connect().await?;
+        if let Some(index) = data.iter().position(|item| item.validate().is_err()) {
+            return Err(format!(
+                "[FIREWALL_LOG ERROR] create_many: item {} is invalid",
+                index
+            ));
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let client: Surreal<Any> = self.db.client()?;
+        let mut query = client.query("BEGIN TRANSACTION;");
+        for (index, chunk) in data.chunks(INSERT_CHUNK_SIZE).enumerate() {
+            query = query
+                .query(format!("INSERT INTO {} $chunk_{};", Self::table(), index))
+                .bind((format!("chunk_{}", index), chunk.to_vec()));
+        }
+        match query.query("COMMIT TRANSACTION;").await {
+            Ok(response) => match response.check() {
+                Ok(_) => Ok(data.len()),
+                Err(error) => Err(format!("[FIREWALL_LOG ERROR] create_many: {}", error)),
+            },
+            Err(error) => Err(format!("[FIREWALL_LOG ERROR] create_many: {}", error)),
         }
     }
 
     pub async fn list(&self, status: bool, limit: usize) -> Result<Vec<FirewallLogData>, String> {
         let _ = self.db.connect().await?;
-
-        match self.db.get_client().read() {
-            Ok(db_client) => {
-                match db_client
-                    .query("SELECT * FROM type::table($table) WHERE status=$status LIMIT $limit;")
-                    .bind(("table", Self::table()))
-                    .bind(("status", status))
-                    .bind(("limit", limit))
-                    .await
-                {
-                    Ok(mut response) => match response.take::<Vec<FirewallLogData>>(0) {
-                        Ok(data) => Ok(data),
-                        Err(error) => {
-                            Err(format!("[FIREWALL_LOG ERROR]  list: {}", error.to_string()))
-                        }
-                    },
-                    Err(error) => Err(format!("[FIREWALL_LOG ERROR] list: {}", error.to_string())),
-                }
-            }
-            Err(error) => Err(format!(
-                "[FIREWALL_LOG ERROR] get_counts: {}",
-                error.to_string()
-            )),
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .query("SELECT * FROM type::table($table) WHERE status=$status LIMIT $limit;")
+            .bind(("table", Self::table()))
+            .bind(("status", status))
+            .bind(("limit", limit))
+            .await
+        {
+            Ok(mut response) => match response.take::<Vec<FirewallLogData>>(0) {
+                Ok(data) => Ok(data),
+                Err(error) => Err(format!("[FIREWALL_LOG ERROR] list: {}", error)),
+            },
+            Err(error) => Err(format!("[FIREWALL_LOG ERROR] list: {}", error)),
         }
     }
 }
@@ -110,11 +201,12 @@ mod test_firewall_log {
         let api = FirewallLog::new(db.clone());
         let data: FirewallLogData = FirewallLogData {
             id: None,
-            ip: [192, 168, 211, 128],
+            ip: vec![192, 168, 211, 128],
             status: false,
             protocol: IpProtocol::Tcp,
             port: Some(3000),
             timestamp: Datetime::from(Utc::now()),
+            ..Default::default()
         };
         let result = api.create(data).await;
         assert!(result.is_ok(), "{:?}", result.err());
@@ -124,24 +216,76 @@ mod test_firewall_log {
         assert!(data.len() > 0, "expected atleast 1 record");
         let data: FirewallLogData = FirewallLogData {
             id: None,
-            ip: [192, 168, 211, 1],
+            ip: vec![192, 168, 211, 1],
             status: false,
             protocol: IpProtocol::Tcp,
             port: Some(2000),
             timestamp: Datetime::from(Utc::now()),
+            ..Default::default()
         };
         let result = api.create(data).await;
         assert!(result.is_ok(), "{:?}", result.err());
 
         let data: FirewallLogData = FirewallLogData {
             id: None,
-            ip: [192, 168, 211, 1],
+            ip: vec![192, 168, 211, 1],
             status: false,
             protocol: IpProtocol::Icmp,
             port: None,
             timestamp: Datetime::from(Utc::now()),
+            ..Default::default()
         };
         let result = api.create(data).await;
         assert!(result.is_ok(), "{:?}", result.err());
+
+        let data: FirewallLogData = FirewallLogData {
+            id: None,
+            ip: vec![0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            status: false,
+            protocol: IpProtocol::Tcp,
+            port: Some(443),
+            timestamp: Datetime::from(Utc::now()),
+            ..Default::default()
+        };
+        let result = api.create(data).await;
+        assert!(result.is_ok(), "{:?}", result.err());
+    }
+
+    #[tokio::test]
+    async fn test_create_many() {
+        let app_state = crate::services::test_util::app_state().await;
+        let api = FirewallLog::new(app_state.db.clone());
+        let log = |port: u16| FirewallLogData {
+            ip: vec![10, 0, 0, 1],
+            protocol: IpProtocol::Tcp,
+            port: Some(port),
+            timestamp: Datetime::from(Utc::now()),
+            ..Default::default()
+        };
+        let mut data: Vec<FirewallLogData> = (0..INSERT_CHUNK_SIZE as u16).map(log).collect();
+        data[0].id = Some(RecordId::from_table_key(FirewallLog::table(), "duplicate"));
+        // The second chunk fails on the id of the first one.
+        data.push(FirewallLogData {
+            id: data[0].id.clone(),
+            ..log(0)
+        });
+        let result = api.create_many(data).await;
+        assert!(
+            result.is_err(),
+            "expected the duplicate id to fail the batch"
+        );
+        let logs = api.list(false, 9999).await.unwrap();
+        assert!(
+            logs.is_empty(),
+            "expected the first chunk to be rolled back, found {} logs",
+            logs.len()
+        );
+
+        let data: Vec<FirewallLogData> = (0..INSERT_CHUNK_SIZE as u16 + 1).map(log).collect();
+        assert_eq!(api.create_many(data).await, Ok(INSERT_CHUNK_SIZE + 1));
+        assert_eq!(
+            api.list(false, 9999).await.unwrap().len(),
+            INSERT_CHUNK_SIZE + 1
+        );
     }
 }
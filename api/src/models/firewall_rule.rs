@@ -1,30 +1,532 @@
 use crate::db::Db;
-use crate::enums::ip_protocol::IpProtocol;
+use crate::enums::{
+    connection_state::ConnectionState,
+    direction::Direction,
+    ip_protocol::{IpProtocol, PROTOCOL_ANY_NUMBER, PROTOCOL_ICMPV6_NUMBER},
+    rule_action::RuleAction,
+};
+use crate::error::FieldError;
+use crate::models::agent::{validate_groups, Agent};
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use ebpf_firewall_common::{MAX_RULE_CHAIN_DEPTH, MAX_RULE_CHAINS, MAX_RULES_PER_CHAIN};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use surrealdb::RecordId;
+use surrealdb::{engine::any::Any, Datetime, RecordId, Surreal};
+/// `ip` holds 4 octets for an IPv4 rule and 16 octets for an IPv6 rule.
+/// `ip`/`cidr` and `from_port`/`to_port` match the remote end, the source of
+/// `Ingress` and the destination of `Egress` packets, the `destination_*` fields
+/// optionally match the local address and port.
+/// Rules of the same prefix are evaluated by the agents by ascending `priority`.
+/// `connection_state` restricts the rule to new flows or replies to local flows.
+/// `protocol` is an IP protocol, by name or number, or `Any` for every protocol.
+/// ICMPv6 is matched by `Icmp` rules, its number 58 is rejected.
+/// `vlan_id` restricts the rule to the frames of a VLAN, by their outermost tag.
+/// `icmp_type` and `icmp_code` restrict `Icmp` rules to a message type and code,
+/// numbered as in ICMP for IPv4 rules and as in ICMPv6 for IPv6 rules.
+/// `log` has the agents log every packet the rule matches, not only sampled drops.
+/// `rate_limit` holds the limits of the `RateLimit` and `SynFlood` actions.
+/// A rule with `expires_at` is removed once expired, a rule with a `schedule` is
+/// only served to the agents within it. `expires_in` is the number of seconds
+/// left before `expires_at`, only set in list responses.
+/// `layer` is the OSI layer of the agents the rule is for. `agents` and `groups`
+/// narrow it down to the named agents and the members of the agent groups, when
+/// both are empty the rule is for all agents. `interfaces` narrows it down to the
+/// named interfaces, empty means all of them.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct FirewallRuleData {
     pub id: Option<RecordId>,
-    pub ip: [u8; 4],
+    pub ip: Vec<u8>,
     pub protocol: IpProtocol,
     pub cidr: u16,
     pub layer: u8,
     pub from_port: Option<u16>,
     pub to_port: Option<u16>,
     pub status: bool,
+    #[serde(default)]
+    pub priority: u32,
+    #[serde(default)]
+    pub direction: Direction,
+    #[serde(default)]
+    pub destination_ip: Option<Vec<u8>>,
+    #[serde(default)]
+    pub destination_cidr: Option<u16>,
+    #[serde(default)]
+    pub destination_from_port: Option<u16>,
+    #[serde(default)]
+    pub destination_to_port: Option<u16>,
+    #[serde(default)]
+    pub connection_state: ConnectionState,
+    #[serde(default)]
+    pub vlan_id: Option<u16>,
+    #[serde(default)]
+    pub icmp_type: Option<u8>,
+    #[serde(default)]
+    pub icmp_code: Option<u8>,
+    #[serde(default)]
+    pub log: bool,
+    #[serde(default)]
+    pub action: RuleAction,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    #[serde(default)]
+    pub expires_at: Option<Datetime>,
+    #[serde(default)]
+    pub schedule: Option<RuleSchedule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<i64>,
+    #[serde(default)]
+    pub agents: Vec<String>,
+    #[serde(default)]
+    pub interfaces: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+/// Limits applied to each source prefix of `prefix_len` bits, the full address
+/// when not set. `pps` is in packets and `bps` in bits per second, a source may
+/// send a burst of `burst_ms` milliseconds of traffic at the full rate at once.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct RateLimit {
+    #[serde(default)]
+    pub pps: Option<u32>,
+    #[serde(default)]
+    pub bps: Option<u64>,
+    #[serde(default = "default_burst_ms")]
+    pub burst_ms: u32,
+    #[serde(default)]
+    pub prefix_len: Option<u8>,
+}
+
+fn default_burst_ms() -> u32 {
+    1000
+}
+
+/// Longest lifetime of a rule created with a ttl, ten years in seconds.
+pub const MAX_RULE_TTL: u64 = 10 * 365 * 24 * 3600;
+
+/// Weekly window a rule is active in, in UTC. `from` and `to` are `HH:MM` times,
+/// a window ending before it starts ends the next day. `days` are the days the
+/// window starts on, every day when empty.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct RuleSchedule {
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    pub from: String,
+    pub to: String,
+}
+
+fn parse_time(value: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(value, "%H:%M").ok()
+}
+
+impl RuleSchedule {
+    pub fn validate(&self, errors: &mut Vec<FieldError>) {
+        let from: Option<NaiveTime> = parse_time(&self.from);
+        if from.is_none() {
+            errors.push(FieldError::new("schedule.from", "must be a HH:MM time"));
+        }
+        let to: Option<NaiveTime> = parse_time(&self.to);
+        if to.is_none() {
+            errors.push(FieldError::new("schedule.to", "must be a HH:MM time"));
+        }
+        if from.is_some() && from == to {
+            errors.push(FieldError::new("schedule.to", "must differ from schedule.from"));
+        }
+    }
+
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        let (Some(from), Some(to)) = (parse_time(&self.from), parse_time(&self.to)) else {
+            return false;
+        };
+        let starts_on = |day: Weekday| self.days.is_empty() || self.days.contains(&day);
+        let time: NaiveTime = now.time();
+        let today: Weekday = now.weekday();
+        if from < to {
+            starts_on(today) && from <= time && time < to
+        } else {
+            (starts_on(today) && from <= time) || (starts_on(today.pred()) && time < to)
+        }
+    }
+}
+
+/// Agent and interface a rule list is requested for, a missing value does not
+/// filter the rules. Rules outside their schedule are only listed with `inactive`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FirewallRuleScope {
+    pub agent: Option<String>,
+    pub interface: Option<String>,
+    #[serde(default)]
+    pub inactive: bool,
 }
 impl Default for FirewallRuleData {
     fn default() -> Self {
         Self {
             id: None,
-            ip: [0; 4],
+            ip: Vec::new(),
             cidr: 0,
             layer: 4,
             protocol: IpProtocol::Undefined,
             from_port: None,
             to_port: None,
             status: false,
+            priority: 0,
+            direction: Direction::Ingress,
+            destination_ip: None,
+            destination_cidr: None,
+            destination_from_port: None,
+            destination_to_port: None,
+            connection_state: ConnectionState::Any,
+            vlan_id: None,
+            icmp_type: None,
+            icmp_code: None,
+            log: false,
+            action: RuleAction::Filter,
+            rate_limit: None,
+            expires_at: None,
+            schedule: None,
+            expires_in: None,
+            agents: Vec::new(),
+            interfaces: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+}
+
+/// Largest prefix length for an address of the given number of octets.
+fn max_cidr(ip: &[u8]) -> Option<u16> {
+    match ip.len() {
+        4 => Some(32),
+        16 => Some(128),
+        _ => None,
+    }
+}
+
+/// Check that the bits of `ip` beyond the prefix length are zero.
+fn host_bits_zero(ip: &[u8], cidr: u16) -> bool {
+    ip.iter().enumerate().all(|(index, octet)| {
+        let start: u16 = index as u16 * 8;
+        if cidr >= start + 8 {
+            true
+        } else if cidr <= start {
+            *octet == 0
+        } else {
+            octet & (0xffu8 >> (cidr - start)) == 0
+        }
+    })
+}
+
+/// Check if the prefix `ip`/`cidr` contains `other`/`other_cidr`, or is equal to it.
+fn prefix_contains(ip: &[u8], cidr: u16, other: &[u8], other_cidr: u16) -> bool {
+    ip.len() == other.len()
+        && cidr <= other_cidr
+        && ip
+            .iter()
+            .zip(other)
+            .enumerate()
+            .all(|(index, (octet, other))| {
+                let start: u16 = index as u16 * 8;
+                let bits: u16 = cidr.saturating_sub(start).min(8);
+                let mask: u8 = (0xffu16 << (8 - bits)) as u8;
+                octet & mask == other & mask
+            })
+}
+
+/// Number of rules the agents evaluate for the rule, one per named interface.
+fn chain_len(data: &FirewallRuleData) -> usize {
+    data.interfaces.len().max(1)
+}
+
+/// Check that the rule fits in the rule chain of its prefix once the agents load
+/// `others`, the other rules of its layer. A prefix holds at most
+/// `MAX_RULES_PER_CHAIN` rules and is nested in fewer than `MAX_RULE_CHAIN_DEPTH`
+/// prefixes, the agents reject the whole rule set otherwise. An address family
+/// holds at most `MAX_RULE_CHAINS` distinct prefixes, the agents skip the longest
+/// ones beyond it. The rules of every agent are counted.
+fn validate_chain(data: &FirewallRuleData, others: &[FirewallRuleData]) -> Vec<FieldError> {
+    let mut errors: Vec<FieldError> = Vec::new();
+    let rules: usize = chain_len(data)
+        + others
+            .iter()
+            .filter(|other| {
+                other.cidr == data.cidr
+                    && prefix_contains(&data.ip, data.cidr, &other.ip, other.cidr)
+            })
+            .map(chain_len)
+            .sum::<usize>();
+    if rules > MAX_RULES_PER_CHAIN {
+        errors.push(FieldError::new(
+            "cidr",
+            &format!(
+                "must not have more than {} rules in the layer, it would have {}",
+                MAX_RULES_PER_CHAIN, rules
+            ),
+        ));
+    }
+    let mut prefixes: Vec<(&[u8], u16)> = Vec::new();
+    for (ip, cidr) in others
+        .iter()
+        .map(|other| (other.ip.as_slice(), other.cidr))
+        .chain([(data.ip.as_slice(), data.cidr)])
+    {
+        if !prefixes.iter().any(|(other, other_cidr)| {
+            *other_cidr == cidr && prefix_contains(other, cidr, ip, cidr)
+        }) {
+            prefixes.push((ip, cidr));
+        }
+    }
+    let family: usize = prefixes
+        .iter()
+        .filter(|(ip, _)| ip.len() == data.ip.len())
+        .count();
+    if family > MAX_RULE_CHAINS as usize {
+        errors.push(FieldError::new(
+            "cidr",
+            &format!(
+                "must not have more than {} prefixes of the address family in the layer, it would have {}",
+                MAX_RULE_CHAINS, family
+            ),
+        ));
+    }
+    // The chains evaluated for the addresses of the prefixes within the rule's one.
+    let depth: usize = prefixes
+        .iter()
+        .filter(|(ip, cidr)| prefix_contains(&data.ip, data.cidr, ip, *cidr))
+        .map(|(ip, cidr)| {
+            prefixes
+                .iter()
+                .filter(|(outer_ip, outer_cidr)| prefix_contains(outer_ip, *outer_cidr, ip, *cidr))
+                .count()
+        })
+        .max()
+        .unwrap_or(0);
+    if depth > MAX_RULE_CHAIN_DEPTH {
+        errors.push(FieldError::new(
+            "cidr",
+            &format!(
+                "must not nest more than {} prefixes in the layer, it would nest {}",
+                MAX_RULE_CHAIN_DEPTH, depth
+            ),
+        ));
+    }
+    errors
+}
+
+/// Check a port pair, `to_port` requires `from_port` and must not be below it.
+fn validate_ports(
+    errors: &mut Vec<FieldError>,
+    to_port_field: &str,
+    from_port: Option<u16>,
+    to_port: Option<u16>,
+) {
+    match (from_port, to_port) {
+        (None, Some(_)) => errors.push(FieldError::new(
+            to_port_field,
+            "requires the matching from port",
+        )),
+        (Some(from_port), Some(to_port)) if from_port > to_port => errors.push(FieldError::new(
+            to_port_field,
+            "must not be less than the matching from port",
+        )),
+        _ => {}
+    }
+}
+
+/// Error of a rule write, `Invalid` lists the rejected fields of the rule and
+/// `Db` is a database error.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FirewallRuleError {
+    Invalid(Vec<FieldError>),
+    Db(String),
+}
+
+impl From<String> for FirewallRuleError {
+    fn from(error: String) -> Self {
+        Self::Db(error)
+    }
+}
+
+impl From<FirewallRuleError> for String {
+    fn from(error: FirewallRuleError) -> Self {
+        match error {
+            FirewallRuleError::Invalid(errors) => format!(
+                "[FIREWALL_RULE ERROR] validate: {}",
+                errors
+                    .iter()
+                    .map(|error| error.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            FirewallRuleError::Db(error) => error,
+        }
+    }
+}
+
+impl FirewallRuleData {
+    /// Checks the rule, every rejected field is reported.
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors: Vec<FieldError> = Vec::new();
+        if !(1..=7).contains(&self.layer) {
+            errors.push(FieldError::new("layer", "must be an OSI layer from 1 to 7"));
+        }
+        if self.agents.iter().any(|agent| agent.trim().is_empty()) {
+            errors.push(FieldError::new("agents", "must not contain empty names"));
+        }
+        if self.interfaces.iter().any(|interface| interface.trim().is_empty()) {
+            errors.push(FieldError::new("interfaces", "must not contain empty names"));
+        }
+        if let Err(group_errors) = validate_groups(&self.groups) {
+            errors.extend(group_errors);
+        }
+        if self.protocol == IpProtocol::Undefined {
+            errors.push(FieldError::new("protocol", "must not be Undefined"));
+        }
+        if self.protocol.number() == Some(PROTOCOL_ANY_NUMBER) {
+            errors.push(FieldError::new(
+                "protocol",
+                &format!("{} is reserved, use Any", PROTOCOL_ANY_NUMBER),
+            ));
+        }
+        if self.protocol.number() == Some(PROTOCOL_ICMPV6_NUMBER) {
+            errors.push(FieldError::new(
+                "protocol",
+                &format!(
+                    "{} is ICMPv6, which the agents match as Icmp, use Icmp",
+                    PROTOCOL_ICMPV6_NUMBER
+                ),
+            ));
+        }
+        match max_cidr(&self.ip) {
+            Some(max_cidr) if self.cidr > max_cidr => errors.push(FieldError::new(
+                "cidr",
+                &format!("must not be greater than {}", max_cidr),
+            )),
+            Some(_) if !host_bits_zero(&self.ip, self.cidr) => errors.push(FieldError::new(
+                "ip",
+                "host bits beyond cidr must be zero",
+            )),
+            Some(_) => {}
+            None => errors.push(FieldError::new(
+                "ip",
+                "must have 4 (IPv4) or 16 (IPv6) octets",
+            )),
+        }
+        if let Some(destination_ip) = &self.destination_ip {
+            match max_cidr(destination_ip) {
+                _ if destination_ip.len() != self.ip.len() => errors.push(FieldError::new(
+                    "destination_ip",
+                    "must be of the same address family as ip",
+                )),
+                Some(max_cidr) if self.destination_cidr.unwrap_or(0) > max_cidr => {
+                    errors.push(FieldError::new(
+                        "destination_cidr",
+                        &format!("must not be greater than {}", max_cidr),
+                    ))
+                }
+                Some(max_cidr)
+                    if !host_bits_zero(destination_ip, self.destination_cidr.unwrap_or(max_cidr)) =>
+                {
+                    errors.push(FieldError::new(
+                        "destination_ip",
+                        "host bits beyond destination_cidr must be zero",
+                    ))
+                }
+                _ => {}
+            }
+        } else if self.destination_cidr.is_some() {
+            errors.push(FieldError::new("destination_cidr", "requires destination_ip"));
+        }
+        validate_ports(&mut errors, "to_port", self.from_port, self.to_port);
+        validate_ports(
+            &mut errors,
+            "destination_to_port",
+            self.destination_from_port,
+            self.destination_to_port,
+        );
+        let has_ports: bool = self.from_port.is_some()
+            || self.to_port.is_some()
+            || self.destination_from_port.is_some()
+            || self.destination_to_port.is_some();
+        if has_ports && self.protocol != IpProtocol::Tcp && self.protocol != IpProtocol::Udp {
+            errors.push(FieldError::new(
+                "protocol",
+                "ports are only supported for Tcp and Udp",
+            ));
+        }
+        if self.vlan_id.is_some_and(|vlan_id| !(1..=4094).contains(&vlan_id)) {
+            errors.push(FieldError::new("vlan_id", "must be from 1 to 4094"));
+        }
+        if (self.icmp_type.is_some() || self.icmp_code.is_some())
+            && self.protocol != IpProtocol::Icmp
+        {
+            errors.push(FieldError::new(
+                "protocol",
+                "icmp type and code are only supported for Icmp",
+            ));
+        }
+        if self.icmp_code.is_some() && self.icmp_type.is_none() {
+            errors.push(FieldError::new("icmp_code", "requires icmp_type"));
+        }
+        self.validate_rate_limit(&mut errors);
+        if self
+            .expires_at
+            .as_ref()
+            .is_some_and(|expires_at| *expires_at <= Datetime::from(Utc::now()))
+        {
+            errors.push(FieldError::new("expires_at", "must be in the future"));
+        }
+        if let Some(schedule) = &self.schedule {
+            schedule.validate(&mut errors);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Rate limit rules need a limit and pass the traffic within it.
+    fn validate_rate_limit(&self, errors: &mut Vec<FieldError>) {
+        let Some(rate_limit) = &self.rate_limit else {
+            if self.action != RuleAction::Filter {
+                errors.push(FieldError::new(
+                    "rate_limit",
+                    &format!("is required for {} rules", self.action),
+                ));
+            }
+            return;
+        };
+        if self.action == RuleAction::Filter {
+            errors.push(FieldError::new(
+                "rate_limit",
+                "is only supported for RateLimit and SynFlood rules",
+            ));
+            return;
+        }
+        if !self.status {
+            errors.push(FieldError::new(
+                "status",
+                "must be true for rate limited rules, which allow the traffic within the limit",
+            ));
+        }
+        if self.action == RuleAction::SynFlood && self.protocol != IpProtocol::Tcp {
+            errors.push(FieldError::new("protocol", "must be Tcp for SynFlood rules"));
+        }
+        if rate_limit.pps.unwrap_or(0) == 0 && rate_limit.bps.unwrap_or(0) == 0 {
+            errors.push(FieldError::new(
+                "rate_limit",
+                "requires a pps or bps limit above 0",
+            ));
+        }
+        if rate_limit.burst_ms == 0 {
+            errors.push(FieldError::new("rate_limit.burst_ms", "must be above 0"));
+        }
+        if let (Some(prefix_len), Some(max_cidr)) = (rate_limit.prefix_len, max_cidr(&self.ip))
+            && prefix_len as u16 > max_cidr
+        {
+            errors.push(FieldError::new(
+                "rate_limit.prefix_len",
+                &format!("must not be greater than {}", max_cidr),
+            ));
         }
     }
 }
@@ -43,88 +545,259 @@ impl FirewallRule {
         "firewall_rule".to_string()
     }
 
-    pub async fn create(&self, data: FirewallRuleData) -> Result<FirewallRuleData, String> {
+    pub async fn create(
+        &self,
+        mut data: FirewallRuleData,
+    ) -> Result<FirewallRuleData, FirewallRuleError> {
         let _ = self.db.connect().await?;
-        match self.db.get_client().read() {
-            Ok(client) => {
-                match client
-                    .insert::<Vec<FirewallRuleData>>(Self::table())
-                    .content(data)
-                    .await
-                {
-                    Ok(data) => {
-                        if let Some(value) = data.first() {
-                            return Ok(value.to_owned());
-                        }
-                        return Err("[FIREWALL_RULE ERROR] create: value not found".to_string());
-                    }
-                    Err(error) => {
-                        return Err(format!(
-                            "[FIREWALL_RULE ERROR] create {}",
-                            error.to_string()
-                        ))
-                    }
+        self.validate(&data, None).await?;
+        data.expires_in = None;
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .insert::<Vec<FirewallRuleData>>(Self::table())
+            .content(data)
+            .await
+        {
+            Ok(data) => match data.first() {
+                Some(value) => Ok(value.to_owned()),
+                None => Err("[FIREWALL_RULE ERROR] create: value not found"
+                    .to_string()
+                    .into()),
+            },
+            Err(error) => Err(format!("[FIREWALL_RULE ERROR] create {}", error).into()),
+        }
+    }
+
+    /// Checks the rule, then that it fits in the rule chain of its prefix among the
+    /// unexpired rules of its layer, see `validate_chain`. `id` is the rule being
+    /// replaced, left out of the count.
+    async fn validate(
+        &self,
+        data: &FirewallRuleData,
+        id: Option<&RecordId>,
+    ) -> Result<(), FirewallRuleError> {
+        data.validate().map_err(FirewallRuleError::Invalid)?;
+        let client: Surreal<Any> = self.db.client()?;
+        let mut others: Vec<FirewallRuleData> = match client
+            .query(
+                "SELECT * FROM type::table($table)
+                    WHERE layer=$layer AND (expires_at = NONE OR expires_at > time::now());",
+            )
+            .bind(("table", Self::table()))
+            .bind(("layer", data.layer))
+            .await
+        {
+            Ok(mut response) => match response.take::<Vec<FirewallRuleData>>(0) {
+                Ok(others) => others,
+                Err(error) => {
+                    return Err(format!("[FIREWALL_RULE ERROR] validate: {}", error).into());
                 }
-            }
-            Err(error) => Err(format!(
-                "[FIREWALL_RULE ERROR] create {}",
-                error.to_string()
-            )),
+            },
+            Err(error) => return Err(format!("[FIREWALL_RULE ERROR] validate: {}", error).into()),
+        };
+        others.retain(|other| other.id.as_ref() != id);
+        let errors: Vec<FieldError> = validate_chain(data, &others);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(FirewallRuleError::Invalid(errors))
+        }
+    }
+
+    pub fn record_id(key: &str) -> RecordId {
+        RecordId::from_table_key(Self::table(), key)
+    }
+
+    pub async fn get(&self, id: RecordId) -> Result<Option<FirewallRuleData>, String> {
+        self.db.connect().await?;
+        let client: Surreal<Any> = self.db.client()?;
+        match client.select::<Option<FirewallRuleData>>(id).await {
+            Ok(data) => Ok(data),
+            Err(error) => Err(format!("[FIREWALL_RULE ERROR] get {}", error)),
+        }
+    }
+
+    /// Replaces the rule, `None` if there is no rule with the id.
+    pub async fn update(
+        &self,
+        id: RecordId,
+        mut data: FirewallRuleData,
+    ) -> Result<Option<FirewallRuleData>, FirewallRuleError> {
+        self.db.connect().await?;
+        self.validate(&data, Some(&id)).await?;
+        if self.get(id.clone()).await?.is_none() {
+            return Ok(None);
+        }
+        data.id = Some(id.clone());
+        data.expires_in = None;
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .update::<Option<FirewallRuleData>>(id)
+            .content(data)
+            .await
+        {
+            Ok(data) => Ok(data),
+            Err(error) => Err(format!("[FIREWALL_RULE ERROR] update {}", error).into()),
         }
     }
 
     pub async fn remove(&self, id: RecordId) -> Result<FirewallRuleData, String> {
         let _ = self.db.connect().await?;
-        match self.db.get_client().read() {
-            Ok(client) => match client.delete::<Option<FirewallRuleData>>(id).await {
-                Ok(data) => {
-                    if let Some(value) = data {
-                        return Ok(value.to_owned());
+        let client: Surreal<Any> = self.db.client()?;
+        match client.delete::<Option<FirewallRuleData>>(id).await {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => Err("[FIREWALL_RULE ERROR] remove: data not found".to_string()),
+            Err(error) => Err(format!("[FIREWALL_RULE ERROR] remove {}", error)),
+        }
+    }
+
+    /// Rules of the layer, rules scoped to agents or interfaces are only listed
+    /// when the scope names one of them. Rules scoped to groups are listed for the
+    /// registered agents of the groups. Expired rules are never listed.
+    pub async fn list(
+        &self,
+        layer: u8,
+        scope: &FirewallRuleScope,
+    ) -> Result<Vec<FirewallRuleData>, String> {
+        let _ = self.db.connect().await?;
+        let groups: Vec<String> = match &scope.agent {
+            Some(agent) => Agent::new(self.db.clone()).groups(agent).await?,
+            None => Vec::new(),
+        };
+        let mut query: String = "SELECT *, IF expires_at != NONE THEN
+                duration::secs(expires_at - time::now()) END AS expires_in
+            FROM type::table($table)
+            WHERE layer=$layer AND (expires_at = NONE OR expires_at > time::now())"
+            .to_string();
+        if scope.agent.is_some() {
+            query.push_str(
+                " AND ((array::len(agents ?? []) = 0 AND array::len(groups ?? []) = 0)
+                    OR $agent IN agents
+                    OR array::len(array::intersect(groups ?? [], $groups)) > 0)",
+            );
+        }
+        if scope.interface.is_some() {
+            query.push_str(" AND (interfaces = NONE OR interfaces = [] OR $interface IN interfaces)");
+        }
+        query.push_str(" ORDER BY priority ASC;");
+
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .query(query)
+            .bind(("table", Self::table()))
+            .bind(("layer", layer))
+            .bind(("agent", scope.agent.clone()))
+            .bind(("groups", groups))
+            .bind(("interface", scope.interface.clone()))
+            .await
+        {
+            Ok(mut response) => match response.take::<Vec<FirewallRuleData>>(0) {
+                Ok(mut data) => {
+                    if !scope.inactive {
+                        let now: DateTime<Utc> = Utc::now();
+                        data.retain(|item| {
+                            item.schedule
+                                .as_ref()
+                                .is_none_or(|schedule| schedule.is_active(now))
+                        });
                     }
-                    return Err("[FIREWALL_RULE ERROR] remove: data not found".to_string());
+                    Ok(data)
                 }
+                Err(error) => Err(format!("[FIREWALL_RULE ERROR] list: {}", error)),
+            },
+            Err(error) => Err(format!("[FIREWALL_RULE ERROR] list: {}", error)),
+        }
+    }
 
-                Err(error) => {
-                    return Err(format!(
-                        "[FIREWALL_RULE ERROR] remove {}",
-                        error.to_string()
-                    ))
-                }
+    /// Removes the expired rules and returns them.
+    pub async fn purge_expired(&self) -> Result<Vec<FirewallRuleData>, String> {
+        self.db.connect().await?;
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .query(
+                "DELETE type::table($table)
+                    WHERE expires_at != NONE AND expires_at <= time::now() RETURN BEFORE;",
+            )
+            .bind(("table", Self::table()))
+            .await
+        {
+            Ok(mut response) => match response.take::<Vec<FirewallRuleData>>(0) {
+                Ok(data) => Ok(data),
+                Err(error) => Err(format!("[FIREWALL_RULE ERROR] purge_expired: {}", error)),
             },
-            Err(error) => Err(format!(
-                "[FIREWALL_RULE ERROR] remove {}",
-                error.to_string()
-            )),
+            Err(error) => Err(format!("[FIREWALL_RULE ERROR] purge_expired: {}", error)),
         }
     }
 
-    pub async fn list(&self, layer: u8) -> Result<Vec<FirewallRuleData>, String> {
-        let _ = self.db.connect().await?;
+    /// Record keys of the rules with a schedule active at `now`.
+    pub async fn scheduled(&self, now: DateTime<Utc>) -> Result<Vec<String>, String> {
+        self.db.connect().await?;
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .query("SELECT * FROM type::table($table) WHERE schedule != NONE;")
+            .bind(("table", Self::table()))
+            .await
+        {
+            Ok(mut response) => match response.take::<Vec<FirewallRuleData>>(0) {
+                Ok(data) => Ok(data
+                    .into_iter()
+                    .filter(|item| {
+                        item.schedule
+                            .as_ref()
+                            .is_some_and(|schedule| schedule.is_active(now))
+                    })
+                    .filter_map(|item| item.id.map(|id| id.key().to_string()))
+                    .collect()),
+                Err(error) => Err(format!("[FIREWALL_RULE ERROR] scheduled: {}", error)),
+            },
+            Err(error) => Err(format!("[FIREWALL_RULE ERROR] scheduled: {}", error)),
+        }
+    }
 
-        match self.db.get_client().read() {
-            Ok(db_client) => {
-                match db_client
-                    .query("SELECT * FROM type::table($table) WHERE layer=$layer;")
-                    .bind(("table", Self::table()))
-                    .bind(("layer", layer))
-                    .await
-                {
-                    Ok(mut response) => match response.take::<Vec<FirewallRuleData>>(0) {
-                        Ok(data) => Ok(data),
-                        Err(error) => Err(format!(
-                            "[FIREWALL_RULE ERROR]  list: {}",
-                            error.to_string()
-                        )),
-                    },
-                    Err(error) => Err(format!("[FIREWALL_RULE ERROR] list: {}", error.to_string())),
-                }
-            }
+    /// Migrates the rules stored before protocols were numbers: `Undefined`
+    /// rules were enforced as `Tcp` and are stored as such. Returns the number
+    /// of migrated rules.
+    pub async fn migrate_protocols(&self) -> Result<usize, String> {
+        self.db.connect().await?;
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .query(
+                "UPDATE type::table($table) SET protocol = 'Tcp'
+                    WHERE protocol = 'Undefined' RETURN AFTER;",
+            )
+            .bind(("table", Self::table()))
+            .await
+        {
+            Ok(mut response) => match response.take::<Vec<FirewallRuleData>>(0) {
+                Ok(data) => Ok(data.len()),
+                Err(error) => Err(format!(
+                    "[FIREWALL_RULE ERROR] migrate_protocols: {}",
+                    error
+                )),
+            },
             Err(error) => Err(format!(
-                "[FIREWALL_RULE ERROR] get_counts: {}",
-                error.to_string()
+                "[FIREWALL_RULE ERROR] migrate_protocols: {}",
+                error
             )),
         }
     }
+
+    /// Record keys of all rules, whatever their layer or scope.
+    pub async fn keys(&self) -> Result<Vec<String>, String> {
+        self.db.connect().await?;
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .query("SELECT VALUE <string> record::id(id) FROM type::table($table);")
+            .bind(("table", Self::table()))
+            .await
+        {
+            Ok(mut response) => match response.take::<Vec<String>>(0) {
+                Ok(data) => Ok(data),
+                Err(error) => Err(format!("[FIREWALL_RULE ERROR] keys: {}", error)),
+            },
+            Err(error) => Err(format!("[FIREWALL_RULE ERROR] keys: {}", error)),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -143,48 +816,521 @@ mod test_firewall_rule {
         let api = FirewallRule::new(db.clone());
         let data: FirewallRuleData = FirewallRuleData {
             id: None,
-            ip: [192, 168, 211, 128],
+            ip: vec![192, 168, 211, 128],
             cidr: 32,
             layer: 4,
             status: false,
             protocol: IpProtocol::Tcp,
             from_port: Some(2000),
             to_port: Some(3000),
+            ..Default::default()
         };
         let result = api.create(data).await;
         assert!(result.is_ok(), "{:?}", result.err());
         let created = result.unwrap();
-        let result = api.list(4).await;
+        let result = api.list(4, &FirewallRuleScope::default()).await;
         assert!(result.is_ok(), "{:?}", result.err());
         let data = result.unwrap();
         assert!(data.len() > 0, "expected atleast 1 record");
-        let removed = api.remove(created.id.unwrap()).await;
+        let id = created.id.clone().unwrap();
+        let result = api.get(id.clone()).await;
+        assert!(result.is_ok(), "{:?}", result.err());
+        assert!(result.unwrap().is_some(), "expected the created rule");
+        let result = api
+            .update(
+                id.clone(),
+                FirewallRuleData {
+                    status: true,
+                    to_port: Some(4000),
+                    ..created.clone()
+                },
+            )
+            .await;
+        assert!(result.is_ok(), "{:?}", result.err());
+        let updated = result.unwrap();
+        assert!(updated.is_some(), "expected the updated rule");
+        let updated = updated.unwrap();
+        assert_eq!(updated.id, Some(id.clone()));
+        assert!(updated.status);
+        assert_eq!(updated.to_port, Some(4000));
+        let result = api
+            .update(
+                id.clone(),
+                FirewallRuleData {
+                    protocol: IpProtocol::Undefined,
+                    ..created.clone()
+                },
+            )
+            .await;
+        assert!(result.is_err(), "expected an invalid update to be rejected");
+        let removed = api.remove(id.clone()).await;
         assert!(removed.is_ok(), "{:?}", removed);
+        let result = api.get(id.clone()).await;
+        assert!(result.is_ok(), "{:?}", result.err());
+        assert!(result.unwrap().is_none(), "expected the rule to be removed");
+        let result = api.update(id, created).await;
+        assert!(result.is_ok(), "{:?}", result.err());
+        assert!(result.unwrap().is_none(), "expected no rule to update");
 
         let data: FirewallRuleData = FirewallRuleData {
             id: None,
-            ip: [192, 168, 211, 1],
+            ip: vec![192, 168, 211, 1],
             cidr: 32,
             layer: 4,
             status: false,
             protocol: IpProtocol::Tcp,
             from_port: Some(2000),
             to_port: Some(3000),
+            ..Default::default()
         };
         let result = api.create(data).await;
         assert!(result.is_ok(), "{:?}", result.err());
 
         let data: FirewallRuleData = FirewallRuleData {
             id: None,
-            ip: [192, 168, 211, 1],
+            ip: vec![192, 168, 211, 1],
             cidr: 32,
             layer: 3,
             status: false,
             protocol: IpProtocol::Icmp,
             from_port: None,
             to_port: None,
+            ..Default::default()
+        };
+        let result = api.create(data).await;
+        assert!(result.is_ok(), "{:?}", result.err());
+
+        let data: FirewallRuleData = FirewallRuleData {
+            id: None,
+            ip: vec![0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            cidr: 32,
+            layer: 3,
+            status: false,
+            protocol: IpProtocol::Tcp,
+            from_port: Some(22),
+            to_port: None,
+            ..Default::default()
+        };
+        let result = api.create(data).await;
+        assert!(result.is_ok(), "{:?}", result.err());
+
+        let data: FirewallRuleData = FirewallRuleData {
+            id: None,
+            ip: vec![192, 168, 211, 1],
+            cidr: 64,
+            layer: 3,
+            status: false,
+            protocol: IpProtocol::Tcp,
+            from_port: None,
+            to_port: None,
+            ..Default::default()
+        };
+        let result = api.create(data).await;
+        assert!(result.is_err(), "expected cidr 64 to be rejected for IPv4");
+
+        let data: FirewallRuleData = FirewallRuleData {
+            id: None,
+            ip: vec![192, 168, 211],
+            cidr: 24,
+            layer: 3,
+            status: false,
+            protocol: IpProtocol::Tcp,
+            from_port: None,
+            to_port: None,
+            ..Default::default()
+        };
+        let result = api.create(data).await;
+        assert!(result.is_err(), "expected 3 octet ip to be rejected");
+
+        let data: FirewallRuleData = FirewallRuleData {
+            ip: vec![0, 0, 0, 0],
+            cidr: 0,
+            layer: 3,
+            status: false,
+            protocol: IpProtocol::Tcp,
+            destination_from_port: Some(22),
+            ..Default::default()
+        };
+        let result = api.create(data).await;
+        assert!(result.is_ok(), "{:?}", result.err());
+
+        let data: FirewallRuleData = FirewallRuleData {
+            ip: vec![10, 0, 0, 0],
+            cidr: 8,
+            layer: 3,
+            status: false,
+            protocol: IpProtocol::Udp,
+            destination_ip: Some(vec![192, 168, 211, 0]),
+            destination_cidr: Some(24),
+            destination_from_port: Some(5000),
+            destination_to_port: Some(6000),
+            ..Default::default()
+        };
+        let result = api.create(data).await;
+        assert!(result.is_ok(), "{:?}", result.err());
+
+        let data: FirewallRuleData = FirewallRuleData {
+            ip: vec![10, 0, 0, 0],
+            cidr: 8,
+            layer: 3,
+            status: true,
+            priority: 1,
+            protocol: IpProtocol::Udp,
+            destination_from_port: Some(53),
+            ..Default::default()
+        };
+        let result = api.create(data).await;
+        assert!(result.is_ok(), "{:?}", result.err());
+
+        let data: FirewallRuleData = FirewallRuleData {
+            ip: vec![0, 0, 0, 0],
+            cidr: 0,
+            layer: 3,
+            status: true,
+            protocol: IpProtocol::Tcp,
+            connection_state: ConnectionState::Established,
+            ..Default::default()
+        };
+        let result = api.create(data).await;
+        assert!(result.is_ok(), "{:?}", result.err());
+        assert_eq!(
+            result.unwrap().connection_state,
+            ConnectionState::Established
+        );
+        let data: FirewallRuleData = FirewallRuleData {
+            ip: vec![172, 16, 0, 0],
+            cidr: 12,
+            layer: 3,
+            status: false,
+            protocol: IpProtocol::Tcp,
+            agents: vec!["agent-a".to_string()],
+            interfaces: vec!["eth1".to_string()],
+            ..Default::default()
         };
         let result = api.create(data).await;
         assert!(result.is_ok(), "{:?}", result.err());
+        let is_scoped = |item: &FirewallRuleData| item.agents.contains(&"agent-a".to_string());
+        let scope = FirewallRuleScope {
+            agent: Some("agent-a".to_string()),
+            interface: Some("eth1".to_string()),
+            ..Default::default()
+        };
+        let result = api.list(3, &scope).await;
+        assert!(result.is_ok(), "{:?}", result.err());
+        assert!(result.unwrap().iter().any(is_scoped));
+        let scope = FirewallRuleScope {
+            agent: Some("agent-b".to_string()),
+            interface: Some("eth1".to_string()),
+            ..Default::default()
+        };
+        let result = api.list(3, &scope).await;
+        assert!(result.is_ok(), "{:?}", result.err());
+        assert!(!result.unwrap().iter().any(is_scoped));
+
+        let result = api.list(3, &FirewallRuleScope::default()).await;
+        assert!(result.is_ok(), "{:?}", result.err());
+        let data = result.unwrap();
+        assert!(
+            data.windows(2).all(|items| items[0].priority <= items[1].priority),
+            "expected rules to be ordered by priority"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let data: FirewallRuleData = FirewallRuleData {
+            ip: vec![10, 0, 0, 0],
+            cidr: 8,
+            protocol: IpProtocol::Tcp,
+            destination_from_port: Some(22),
+            ..Default::default()
+        };
+        assert!(data.validate().is_ok(), "{:?}", data.validate().err());
+
+        let invalid: Vec<FirewallRuleData> = vec![
+            FirewallRuleData {
+                destination_ip: Some(vec![0; 16]),
+                ..data.clone()
+            },
+            FirewallRuleData {
+                destination_cidr: Some(24),
+                ..data.clone()
+            },
+            FirewallRuleData {
+                destination_ip: Some(vec![192, 168, 0, 0]),
+                destination_cidr: Some(33),
+                ..data.clone()
+            },
+            FirewallRuleData {
+                destination_from_port: Some(3000),
+                destination_to_port: Some(2000),
+                ..data.clone()
+            },
+            FirewallRuleData {
+                destination_from_port: None,
+                destination_to_port: Some(2000),
+                ..data.clone()
+            },
+            FirewallRuleData {
+                protocol: IpProtocol::Icmp,
+                ..data.clone()
+            },
+            FirewallRuleData {
+                protocol: IpProtocol::Undefined,
+                destination_from_port: None,
+                ..data.clone()
+            },
+            FirewallRuleData {
+                layer: 0,
+                ..data.clone()
+            },
+            FirewallRuleData {
+                agents: vec!["".to_string()],
+                ..data.clone()
+            },
+            FirewallRuleData {
+                ip: vec![10, 0, 0, 1],
+                ..data.clone()
+            },
+            FirewallRuleData {
+                destination_ip: Some(vec![192, 168, 1, 1]),
+                destination_cidr: Some(24),
+                ..data.clone()
+            },
+            FirewallRuleData {
+                from_port: Some(3000),
+                to_port: Some(2000),
+                ..data.clone()
+            },
+            FirewallRuleData {
+                protocol: IpProtocol::Icmp,
+                from_port: Some(8),
+                destination_from_port: None,
+                ..data.clone()
+            },
+            FirewallRuleData {
+                expires_at: Some(Datetime::from(Utc::now())),
+                ..data.clone()
+            },
+            FirewallRuleData {
+                vlan_id: Some(4095),
+                ..data.clone()
+            },
+            FirewallRuleData {
+                protocol: IpProtocol::Any,
+                ..data.clone()
+            },
+            FirewallRuleData {
+                protocol: IpProtocol::Other(255),
+                destination_from_port: None,
+                ..data.clone()
+            },
+            FirewallRuleData {
+                icmp_type: Some(8),
+                ..data.clone()
+            },
+            FirewallRuleData {
+                protocol: IpProtocol::Icmp,
+                destination_from_port: None,
+                icmp_code: Some(4),
+                ..data.clone()
+            },
+            FirewallRuleData {
+                schedule: Some(RuleSchedule {
+                    days: Vec::new(),
+                    from: "8:00am".to_string(),
+                    to: "18:00".to_string(),
+                }),
+                ..data.clone()
+            },
+            FirewallRuleData {
+                schedule: Some(RuleSchedule {
+                    days: Vec::new(),
+                    from: "18:00".to_string(),
+                    to: "18:00".to_string(),
+                }),
+                ..data.clone()
+            },
+        ];
+        for item in invalid {
+            assert!(item.validate().is_err(), "expected {:?} to be invalid", item);
+        }
+
+        let data: FirewallRuleData = FirewallRuleData {
+            ip: vec![0x20, 0x01, 0x0d, 0xb8, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            cidr: 33,
+            protocol: IpProtocol::Udp,
+            from_port: Some(1000),
+            to_port: Some(2000),
+            ..Default::default()
+        };
+        assert!(data.validate().is_ok(), "{:?}", data.validate().err());
+        for protocol in [IpProtocol::Any, IpProtocol::Other(47)] {
+            let data: FirewallRuleData = FirewallRuleData {
+                protocol,
+                from_port: None,
+                to_port: None,
+                ..data.clone()
+            };
+            assert!(data.validate().is_ok(), "{:?}", data.validate().err());
+        }
+        let icmpv6: FirewallRuleData = FirewallRuleData {
+            protocol: IpProtocol::Other(58),
+            from_port: None,
+            to_port: None,
+            ..data.clone()
+        };
+        let errors = icmpv6.validate().unwrap_err();
+        let fields: Vec<&str> = errors.iter().map(|error| error.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            vec!["protocol"],
+            "expected ICMPv6 by number to be rejected for Icmp"
+        );
+        let data: FirewallRuleData = FirewallRuleData {
+            cidr: 32,
+            protocol: IpProtocol::Undefined,
+            from_port: None,
+            to_port: Some(2000),
+            ..data
+        };
+        let errors = data.validate().unwrap_err();
+        let fields: Vec<&str> = errors.iter().map(|error| error.field.as_str()).collect();
+        assert_eq!(fields, vec!["protocol", "ip", "to_port", "protocol"]);
+    }
+
+    #[actix_web::test]
+    async fn test_migrate_protocols() {
+        let app_state = crate::services::test_util::app_state().await;
+        let api = FirewallRule::new(app_state.db.clone());
+        let rule: FirewallRuleData = FirewallRuleData {
+            ip: vec![10, 0, 0, 0],
+            cidr: 8,
+            layer: 3,
+            protocol: IpProtocol::Other(47),
+            ..Default::default()
+        };
+        let result = api.create(rule.clone()).await;
+        assert!(result.is_ok(), "{:?}", result.err());
+        let result = api
+            .create(FirewallRuleData {
+                protocol: IpProtocol::Other(253),
+                ..rule.clone()
+            })
+            .await;
+        assert!(result.is_ok(), "{:?}", result.err());
+        // Rules stored before protocols were validated, bypassing `create`.
+        let client: Surreal<Any> = app_state.db.client().unwrap();
+        let inserted = client
+            .insert::<Vec<FirewallRuleData>>(FirewallRule::table())
+            .content(FirewallRuleData {
+                protocol: IpProtocol::Undefined,
+                ..rule
+            })
+            .await;
+        assert!(inserted.is_ok(), "{:?}", inserted.err());
+
+        assert_eq!(api.migrate_protocols().await, Ok(1));
+        assert_eq!(api.migrate_protocols().await, Ok(0));
+        let mut protocols: Vec<String> = api
+            .list(3, &FirewallRuleScope::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| item.protocol.to_string())
+            .collect();
+        protocols.sort();
+        assert_eq!(protocols, vec!["253", "Gre", "Tcp"]);
+    }
+
+    #[test]
+    fn test_schedule() {
+        let at = |value: &str| {
+            DateTime::parse_from_rfc3339(value)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        let office: RuleSchedule = RuleSchedule {
+            days: vec![Weekday::Mon, Weekday::Tue],
+            from: "08:00".to_string(),
+            to: "18:00".to_string(),
+        };
+        // 2024-01-01 is a Monday.
+        assert!(office.is_active(at("2024-01-01T08:00:00Z")));
+        assert!(!office.is_active(at("2024-01-01T18:00:00Z")));
+        assert!(!office.is_active(at("2024-01-03T12:00:00Z")));
+
+        let night: RuleSchedule = RuleSchedule {
+            days: vec![Weekday::Fri],
+            from: "22:00".to_string(),
+            to: "06:00".to_string(),
+        };
+        assert!(night.is_active(at("2024-01-05T23:00:00Z")));
+        assert!(
+            night.is_active(at("2024-01-06T05:59:00Z")),
+            "expected the window to end the next day"
+        );
+        assert!(!night.is_active(at("2024-01-06T23:00:00Z")));
+        assert!(!night.is_active(at("2024-01-05T05:00:00Z")));
+    }
+
+    #[test]
+    fn test_validate_chain() {
+        let rule = |ip: Vec<u8>, cidr: u16| FirewallRuleData {
+            ip,
+            cidr,
+            protocol: IpProtocol::Tcp,
+            ..Default::default()
+        };
+        let data: FirewallRuleData = rule(vec![10, 0, 0, 0], 8);
+        let mut others: Vec<FirewallRuleData> = (1..MAX_RULES_PER_CHAIN)
+            .map(|_| rule(vec![10, 0, 0, 0], 8))
+            .collect();
+        others.push(rule(vec![10, 0, 0, 0], 16));
+        others.push(rule(vec![0; 16], 8));
+        assert!(validate_chain(&data, &others).is_empty());
+
+        others.push(FirewallRuleData {
+            interfaces: vec!["eth0".to_string(), "eth1".to_string()],
+            ..rule(vec![10, 0, 0, 0], 8)
+        });
+        let fields: Vec<String> = validate_chain(&data, &others)
+            .into_iter()
+            .map(|error| error.field)
+            .collect();
+        assert_eq!(
+            fields,
+            vec!["cidr"],
+            "expected the rules beyond MAX_RULES_PER_CHAIN to be rejected"
+        );
+
+        // The prefixes of 10.0.0.0 from /0 to /6, nesting 10.0.0.0/8 in 7 prefixes.
+        let others: Vec<FirewallRuleData> = (0..MAX_RULE_CHAIN_DEPTH as u16 - 1)
+            .map(|cidr| rule(vec![10 & (0xffu16 << (8 - cidr)) as u8, 0, 0, 0], cidr))
+            .collect();
+        assert!(validate_chain(&rule(vec![10, 0, 0, 0], 8), &others).is_empty());
+        assert!(validate_chain(&rule(vec![10, 0, 0, 0], 16), &others).is_empty());
+        let nested: Vec<FirewallRuleData> =
+            [others.clone(), vec![rule(vec![10, 0, 0, 0], 16)]].concat();
+        assert_eq!(
+            validate_chain(&rule(vec![10, 0, 0, 0], 8), &nested).len(),
+            1,
+            "expected a prefix nesting more than MAX_RULE_CHAIN_DEPTH prefixes to be rejected"
+        );
+
+        let others: Vec<FirewallRuleData> = (0..MAX_RULE_CHAINS)
+            .map(|index| rule(vec![10, 0, (index >> 8) as u8, index as u8], 32))
+            .collect();
+        assert!(validate_chain(&rule(vec![10, 0, 0, 1], 32), &others).is_empty());
+        assert!(validate_chain(&rule(vec![0; 16], 128), &others).is_empty());
+        let fields: Vec<String> = validate_chain(&rule(vec![10, 1, 0, 0], 32), &others)
+            .into_iter()
+            .map(|error| error.field)
+            .collect();
+        assert_eq!(
+            fields,
+            vec!["cidr"],
+            "expected a prefix beyond MAX_RULE_CHAINS in the address family to be rejected"
+        );
     }
 }
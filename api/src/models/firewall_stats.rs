@@ -0,0 +1,286 @@
+use crate::db::Db;
+use crate::enums::ip_protocol::IpProtocol;
+use crate::error::FieldError;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use surrealdb::{engine::any::Any, Datetime, RecordId, Surreal};
+
+/// Packets and bytes passed and dropped by a rule or of a protocol.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct TrafficStats {
+    #[serde(default)]
+    pub passed_packets: u64,
+    #[serde(default)]
+    pub passed_bytes: u64,
+    #[serde(default)]
+    pub dropped_packets: u64,
+    #[serde(default)]
+    pub dropped_bytes: u64,
+}
+
+impl TrafficStats {
+    pub fn add(&mut self, other: &TrafficStats) {
+        self.passed_packets += other.passed_packets;
+        self.passed_bytes += other.passed_bytes;
+        self.dropped_packets += other.dropped_packets;
+        self.dropped_bytes += other.dropped_bytes;
+    }
+}
+
+/// Traffic of a rule, `rule` is the key of its record and `None` for the
+/// default action.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RuleStats {
+    #[serde(default)]
+    pub rule: Option<String>,
+    pub traffic: TrafficStats,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ProtocolStats {
+    pub protocol: IpProtocol,
+    pub traffic: TrafficStats,
+}
+
+/// Traffic counters of an agent and interface since the agent started, each
+/// report replaces the previous one. `parse_errors` is the traffic of the packets
+/// the agent could not parse, given the default action.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FirewallStatsReport {
+    pub agent: String,
+    #[serde(default)]
+    pub interface: Option<String>,
+    #[serde(default)]
+    pub rules: Vec<RuleStats>,
+    #[serde(default)]
+    pub protocols: Vec<ProtocolStats>,
+    #[serde(default)]
+    pub parse_errors: TrafficStats,
+}
+
+impl FirewallStatsReport {
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors: Vec<FieldError> = Vec::new();
+        if self.agent.trim().is_empty() {
+            errors.push(FieldError::new("agent", "must not be empty"));
+        }
+        if self
+            .interface
+            .as_ref()
+            .is_some_and(|interface| interface.trim().is_empty())
+        {
+            errors.push(FieldError::new("interface", "must not be empty"));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Record key of the report, such as `edge-1:eth0`.
+    pub fn key(&self) -> String {
+        match &self.interface {
+            Some(interface) => format!("{}:{}", self.agent, interface),
+            None => self.agent.clone(),
+        }
+    }
+}
+
+/// The latest report of an agent and interface.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FirewallStatsData {
+    pub id: Option<RecordId>,
+    pub agent: String,
+    pub interface: Option<String>,
+    #[serde(default)]
+    pub rules: Vec<RuleStats>,
+    #[serde(default)]
+    pub protocols: Vec<ProtocolStats>,
+    #[serde(default)]
+    pub parse_errors: TrafficStats,
+    pub updated_at: Datetime,
+}
+
+/// Traffic of an agent and interface, as part of the stats of a rule.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AgentTrafficStats {
+    pub agent: String,
+    pub interface: Option<String>,
+    pub traffic: TrafficStats,
+    pub updated_at: Datetime,
+}
+
+/// Traffic of a rule summed over the agents, `agents` lists the ones it matched on.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FirewallRuleStats {
+    pub rule: String,
+    pub traffic: TrafficStats,
+    pub agents: Vec<AgentTrafficStats>,
+}
+
+/// Traffic of all agents. `rules` has every rule, the ones that never matched
+/// have no traffic, `default_action` is the traffic no rule matched and
+/// `parse_errors` the traffic the agents could not parse.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FirewallStatsSummary {
+    pub agents: usize,
+    pub traffic: TrafficStats,
+    pub default_action: TrafficStats,
+    pub rules: Vec<RuleStats>,
+    pub protocols: Vec<ProtocolStats>,
+    pub parse_errors: TrafficStats,
+}
+
+impl FirewallStatsSummary {
+    /// Sums the reports over the agents, rules missing from `rule_keys` were
+    /// removed since and are left out.
+    pub fn new(reports: &[FirewallStatsData], rule_keys: &[String]) -> Self {
+        let mut summary: FirewallStatsSummary = FirewallStatsSummary {
+            agents: reports.len(),
+            traffic: TrafficStats::default(),
+            default_action: TrafficStats::default(),
+            rules: rule_keys
+                .iter()
+                .map(|key| RuleStats {
+                    rule: Some(key.clone()),
+                    traffic: TrafficStats::default(),
+                })
+                .collect(),
+            protocols: Vec::new(),
+            parse_errors: TrafficStats::default(),
+        };
+        for report in reports {
+            summary.parse_errors.add(&report.parse_errors);
+            for item in report.rules.iter() {
+                summary.traffic.add(&item.traffic);
+                match &item.rule {
+                    Some(key) => {
+                        if let Some(stats) = summary
+                            .rules
+                            .iter_mut()
+                            .find(|stats| stats.rule.as_ref() == Some(key))
+                        {
+                            stats.traffic.add(&item.traffic);
+                        }
+                    }
+                    None => summary.default_action.add(&item.traffic),
+                }
+            }
+            for item in report.protocols.iter() {
+                match summary
+                    .protocols
+                    .iter_mut()
+                    .find(|stats| stats.protocol == item.protocol)
+                {
+                    Some(stats) => stats.traffic.add(&item.traffic),
+                    None => summary.protocols.push(item.clone()),
+                }
+            }
+        }
+        summary
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FirewallStats {
+    db: Arc<Db>,
+}
+
+impl FirewallStats {
+    pub fn new(db: Arc<Db>) -> Self {
+        Self { db: db.clone() }
+    }
+
+    pub fn table() -> String {
+        "firewall_stats".to_string()
+    }
+
+    pub fn record_id(key: &str) -> RecordId {
+        RecordId::from_table_key(Self::table(), key)
+    }
+
+    /// Replaces the report of the agent and interface.
+    pub async fn report(&self, data: FirewallStatsReport) -> Result<FirewallStatsData, String> {
+        self.db.connect().await?;
+        if let Err(errors) = data.validate() {
+            return Err(format!(
+                "[FIREWALL_STATS ERROR] report: {}",
+                errors
+                    .iter()
+                    .map(|error| error.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ));
+        }
+        let id: RecordId = Self::record_id(&data.key());
+        let data: FirewallStatsData = FirewallStatsData {
+            id: None,
+            agent: data.agent,
+            interface: data.interface,
+            rules: data.rules,
+            protocols: data.protocols,
+            parse_errors: data.parse_errors,
+            updated_at: Datetime::from(Utc::now()),
+        };
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .upsert::<Option<FirewallStatsData>>(id)
+            .content(data)
+            .await
+        {
+            Ok(Some(data)) => Ok(data),
+            Ok(None) => Err("[FIREWALL_STATS ERROR] report: value not found".to_string()),
+            Err(error) => Err(format!("[FIREWALL_STATS ERROR] report: {}", error)),
+        }
+    }
+
+    pub async fn list(&self) -> Result<Vec<FirewallStatsData>, String> {
+        self.db.connect().await?;
+        let client: Surreal<Any> = self.db.client()?;
+        match client
+            .query("SELECT * FROM type::table($table) ORDER BY agent ASC, interface ASC;")
+            .bind(("table", Self::table()))
+            .await
+        {
+            Ok(mut response) => match response.take::<Vec<FirewallStatsData>>(0) {
+                Ok(data) => Ok(data),
+                Err(error) => Err(format!("[FIREWALL_STATS ERROR] list: {}", error)),
+            },
+            Err(error) => Err(format!("[FIREWALL_STATS ERROR] list: {}", error)),
+        }
+    }
+
+    /// Traffic of the rule with the record key over the agents that reported it.
+    pub async fn rule(&self, key: &str) -> Result<FirewallRuleStats, String> {
+        let mut stats: FirewallRuleStats = FirewallRuleStats {
+            rule: key.to_string(),
+            traffic: TrafficStats::default(),
+            agents: Vec::new(),
+        };
+        for report in self.list().await? {
+            let mut traffic: Option<TrafficStats> = None;
+            for item in report.rules.iter() {
+                if item.rule.as_deref() == Some(key) {
+                    traffic.get_or_insert_default().add(&item.traffic);
+                }
+            }
+            if let Some(traffic) = traffic {
+                stats.traffic.add(&traffic);
+                stats.agents.push(AgentTrafficStats {
+                    agent: report.agent,
+                    interface: report.interface,
+                    traffic,
+                    updated_at: report.updated_at,
+                });
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Traffic of all agents, with every rule of `rule_keys`.
+    pub async fn summary(&self, rule_keys: &[String]) -> Result<FirewallStatsSummary, String> {
+        Ok(FirewallStatsSummary::new(&self.list().await?, rule_keys))
+    }
+}
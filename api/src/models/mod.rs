@@ -1,3 +1,6 @@
+pub mod agent;
 pub mod command_execution;
+pub mod firewall_ban;
 pub mod firewall_log;
 pub mod firewall_rule;
+pub mod firewall_stats;
@@ -0,0 +1,107 @@
+use crate::db::Db;
+use crate::models::firewall_rule::FirewallRule;
+use crate::rule_version::RuleVersion;
+use chrono::Utc;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Interval of the checks for expired rules and schedules opening or closing.
+pub const RULE_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Removes the expired rules. Returns true when the rules served to the agents
+/// changed since the previous sweep, `active` holds the keys of the scheduled
+/// rules active at that sweep.
+pub async fn sweep(api: &FirewallRule, active: &mut Vec<String>) -> Result<bool, String> {
+    let purged = api.purge_expired().await?;
+    let mut scheduled: Vec<String> = api.scheduled(Utc::now()).await?;
+    scheduled.sort();
+    let changed: bool = !purged.is_empty() || scheduled != *active;
+    *active = scheduled;
+    Ok(changed)
+}
+
+/// Sweeps the rules every `RULE_SWEEP_INTERVAL` and bumps the rule version on a
+/// change, so the agents fetch their rules again.
+pub async fn sweep_rules(db: Arc<Db>, rule_version: Arc<RuleVersion>) {
+    let api: FirewallRule = FirewallRule::new(db);
+    let mut active: Vec<String> = Vec::new();
+    let mut interval = tokio::time::interval(RULE_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        match sweep(&api, &mut active).await {
+            Ok(true) => {
+                rule_version.bump();
+            }
+            Ok(false) => {}
+            Err(error) => log::error!("{}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_rule_sweeper {
+    use super::*;
+    use crate::enums::ip_protocol::IpProtocol;
+    use crate::models::firewall_rule::{FirewallRuleData, FirewallRuleScope, RuleSchedule};
+    use crate::services::test_util::app_state;
+    use surrealdb::Datetime;
+
+    #[actix_web::test]
+    async fn test_sweep() {
+        let app_state = app_state().await;
+        let api = FirewallRule::new(app_state.db.clone());
+        let rule: FirewallRuleData = FirewallRuleData {
+            ip: vec![10, 0, 0, 1],
+            cidr: 32,
+            layer: 3,
+            protocol: IpProtocol::Tcp,
+            ..Default::default()
+        };
+        let expiring = api
+            .create(FirewallRuleData {
+                expires_at: Some(Datetime::from(Utc::now() + chrono::Duration::seconds(1))),
+                ..rule.clone()
+            })
+            .await;
+        assert!(expiring.is_ok(), "{:?}", expiring.err());
+        let scheduled = api
+            .create(FirewallRuleData {
+                schedule: Some(RuleSchedule {
+                    days: Vec::new(),
+                    from: (Utc::now() - chrono::Duration::hours(1))
+                        .format("%H:%M")
+                        .to_string(),
+                    to: (Utc::now() + chrono::Duration::hours(1))
+                        .format("%H:%M")
+                        .to_string(),
+                }),
+                ..rule
+            })
+            .await;
+        assert!(scheduled.is_ok(), "{:?}", scheduled.err());
+
+        let rules = api.list(3, &FirewallRuleScope::default()).await.unwrap();
+        assert_eq!(rules.len(), 2);
+        assert!(
+            rules
+                .iter()
+                .any(|item| item.expires_in.is_some_and(|value| value <= 1)),
+            "expected the remaining lifetime to be listed"
+        );
+
+        let mut active: Vec<String> = Vec::new();
+        assert_eq!(sweep(&api, &mut active).await, Ok(true));
+        assert_eq!(active.len(), 1, "expected the scheduled rule to be active");
+        assert_eq!(sweep(&api, &mut active).await, Ok(false));
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let rules = api.list(3, &FirewallRuleScope::default()).await.unwrap();
+        assert_eq!(rules.len(), 1, "expected the expired rule not to be listed");
+        assert_eq!(sweep(&api, &mut active).await, Ok(true));
+        assert_eq!(
+            api.keys().await.unwrap().len(),
+            1,
+            "expected the expired rule to be removed"
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// Version of the firewall rule set, bumped on every rule change and broadcast to
+/// the agents subscribed to `/firewall-rule/stream/{layer}`.
+/// It starts at the startup time in milliseconds so a restarted server never
+/// reuses a version an agent has already applied.
+pub struct RuleVersion {
+    version: AtomicU64,
+    sender: broadcast::Sender<u64>,
+}
+
+impl RuleVersion {
+    pub fn new() -> Self {
+        let version: u64 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|value| value.as_millis() as u64)
+            .unwrap_or(0);
+        let (sender, _) = broadcast::channel(16);
+        Self {
+            version: AtomicU64::new(version),
+            sender,
+        }
+    }
+
+    pub fn current(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    /// Bumps the version and notifies the subscribers, returns the new version.
+    pub fn bump(&self) -> u64 {
+        let version: u64 = self.version.fetch_add(1, Ordering::SeqCst) + 1;
+        // No subscriber is not an error.
+        let _ = self.sender.send(version);
+        version
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<u64> {
+        self.sender.subscribe()
+    }
+
+    /// Value of the `ETag` header for the version.
+    pub fn etag(version: u64) -> String {
+        format!("\"{}\"", version)
+    }
+}
+
+impl Default for RuleVersion {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test_rule_version {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bump() {
+        let rule_version = RuleVersion::new();
+        let mut receiver = rule_version.subscribe();
+        let version = rule_version.current();
+        assert_eq!(rule_version.bump(), version + 1);
+        assert_eq!(rule_version.current(), version + 1);
+        let result = receiver.recv().await;
+        assert_eq!(result.ok(), Some(version + 1));
+        assert_eq!(RuleVersion::etag(7), "\"7\"");
+    }
+}
@@ -0,0 +1,280 @@
+use crate::error::{json_error_handler, path_error_handler, ErrorResponse};
+use crate::models::agent::{validate_groups, Agent, AgentHeartbeat};
+use crate::AppState;
+use actix_web::{web, HttpResponse, Responder, Scope};
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct AgentGroupsForm {
+    pub groups: Vec<String>,
+}
+
+fn not_found(key: &str) -> HttpResponse {
+    ErrorResponse::not_found(format!("agent {} not found", key))
+}
+
+/// Routes of the `/agent` scope.
+pub fn scope() -> Scope {
+    web::scope("/agent")
+        .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+        .app_data(web::PathConfig::default().error_handler(path_error_handler))
+        .route("/heartbeat", web::post().to(agent_heartbeat))
+        .route("/list", web::get().to(get_agents))
+        .route("/{id}", web::get().to(get_agent))
+        .route("/{id}", web::delete().to(delete_agent))
+        .route("/{id}/groups", web::put().to(update_agent_groups))
+}
+
+/// Registers the agent on its first heartbeat, later ones refresh its status.
+pub async fn agent_heartbeat(
+    form: web::Json<AgentHeartbeat>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let data = form.into_inner();
+    if let Err(errors) = data.validate() {
+        return ErrorResponse::validation(errors);
+    }
+    let api = Agent::new(app_state.db.clone());
+    match api.heartbeat(data).await {
+        Ok(data) => HttpResponse::Ok().json(data),
+        Err(error) => ErrorResponse::internal(error),
+    }
+}
+
+pub async fn get_agents(app_state: web::Data<AppState>) -> impl Responder {
+    let api = Agent::new(app_state.db.clone());
+    match api.list().await {
+        Ok(data) => HttpResponse::Ok().json(data),
+        Err(error) => ErrorResponse::internal(error),
+    }
+}
+
+pub async fn get_agent(path: web::Path<String>, app_state: web::Data<AppState>) -> impl Responder {
+    let key = path.into_inner();
+    let api = Agent::new(app_state.db.clone());
+    match api.get(Agent::record_id(&key)).await {
+        Ok(Some(data)) => HttpResponse::Ok().json(data),
+        Ok(None) => not_found(&key),
+        Err(error) => ErrorResponse::internal(error),
+    }
+}
+
+/// Replaces the groups of the agent. Rules targeting groups may now apply to a
+/// different set of agents, so the rule set version is bumped.
+pub async fn update_agent_groups(
+    path: web::Path<String>,
+    form: web::Json<AgentGroupsForm>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let key = path.into_inner();
+    let form = form.into_inner();
+    if let Err(errors) = validate_groups(&form.groups) {
+        return ErrorResponse::validation(errors);
+    }
+    let api = Agent::new(app_state.db.clone());
+    match api.set_groups(Agent::record_id(&key), form.groups).await {
+        Ok(Some(data)) => {
+            app_state.rule_version.bump();
+            HttpResponse::Ok().json(data)
+        }
+        Ok(None) => not_found(&key),
+        Err(error) => ErrorResponse::internal(error),
+    }
+}
+
+pub async fn delete_agent(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let key = path.into_inner();
+    let api = Agent::new(app_state.db.clone());
+    match api.remove(Agent::record_id(&key)).await {
+        Ok(Some(data)) => {
+            app_state.rule_version.bump();
+            HttpResponse::Ok().json(data)
+        }
+        Ok(None) => not_found(&key),
+        Err(error) => ErrorResponse::internal(error),
+    }
+}
+
+#[cfg(test)]
+mod test_agent_service {
+    use super::*;
+    use crate::enums::agent_kind::AgentKind;
+    use crate::enums::interface_state::InterfaceState;
+    use crate::models::agent::AgentData;
+    use crate::models::firewall_rule::FirewallRuleData;
+    use crate::services::firewall_rule;
+    use crate::services::test_util::app_state;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use serde_json::json;
+
+    #[actix_web::test]
+    async fn test_agent_handlers() {
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state().await)
+                .service(scope())
+                .service(firewall_rule::scope()),
+        )
+        .await;
+
+        let heartbeat = json!({
+            "hostname": "edge-1",
+            "kind": "Firewall",
+            "interface": "eth0",
+            "version": "0.1.0",
+            "kernel": "6.1.0",
+            "attach_mode": "Default"
+        });
+        let request = test::TestRequest::post()
+            .uri("/agent/heartbeat")
+            .set_json(heartbeat.clone())
+            .to_request();
+        let registered: AgentData = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(registered.kind, AgentKind::Firewall);
+        assert!(registered.groups.is_empty());
+        let key = "firewall:edge-1:eth0";
+        assert_eq!(registered.id, Some(Agent::record_id(key)));
+
+        let request = test::TestRequest::put()
+            .uri(&format!("/agent/{}/groups", key))
+            .set_json(json!({ "groups": ["edge"] }))
+            .to_request();
+        let data: AgentData = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(data.groups, vec!["edge".to_string()]);
+
+        let mut heartbeat = heartbeat;
+        heartbeat["metrics"] = json!({
+            "transport": "RingBuf",
+            "events_lost": 3,
+            "events_shipped": 120,
+            "events_dropped": 0,
+            "events_spooled": 0
+        });
+        let request = test::TestRequest::post()
+            .uri("/agent/heartbeat")
+            .set_json(heartbeat)
+            .to_request();
+        let data: AgentData = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(data.id, registered.id, "expected the agent to be updated");
+        assert_eq!(data.groups, vec!["edge".to_string()], "expected the groups to be kept");
+        assert_eq!(data.metrics.map(|metrics| metrics.events_lost), Some(3));
+
+        let request = test::TestRequest::post()
+            .uri("/agent/heartbeat")
+            .set_json(json!({
+                "hostname": "edge-1",
+                "kind": "Firewall",
+                "interface": "eth1",
+                "state": "Failed",
+                "error": "failed to attach the XDP program"
+            }))
+            .to_request();
+        let data: AgentData = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(data.state, Some(InterfaceState::Failed));
+        assert!(data.error.is_some());
+        let request = test::TestRequest::post()
+            .uri("/agent/heartbeat")
+            .set_json(json!({
+                "hostname": "edge-1",
+                "kind": "Firewall",
+                "interface": "eth1",
+                "state": "Attached"
+            }))
+            .to_request();
+        let data: AgentData = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(data.state, Some(InterfaceState::Attached));
+        assert_eq!(data.error, None, "expected the error to be cleared");
+        let request = test::TestRequest::delete()
+            .uri("/agent/firewall:edge-1:eth1")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = test::TestRequest::post()
+            .uri("/agent/heartbeat")
+            .set_json(json!({ "hostname": "build-1", "kind": "Tracepoint", "kernel": "5.15.0" }))
+            .to_request();
+        let data: AgentData = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(data.interface, None);
+
+        let request = test::TestRequest::get().uri("/agent/list").to_request();
+        let data: Vec<AgentData> = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(data.len(), 2);
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-rule/create")
+            .set_json(json!({
+                "ip": [10, 0, 0, 0],
+                "protocol": "Tcp",
+                "cidr": 8,
+                "layer": 3,
+                "from_port": null,
+                "to_port": null,
+                "status": false,
+                "groups": ["edge"]
+            }))
+            .to_request();
+        let created: FirewallRuleData = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(created.groups, vec!["edge".to_string()]);
+
+        let request = test::TestRequest::get()
+            .uri("/firewall-rule/list/3?agent=edge-1")
+            .to_request();
+        let data: Vec<FirewallRuleData> = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(data.len(), 1, "expected the group rule for a member");
+
+        let request = test::TestRequest::get()
+            .uri("/firewall-rule/list/3?agent=edge-2")
+            .to_request();
+        let data: Vec<FirewallRuleData> = test::call_and_read_body_json(&app, request).await;
+        assert!(data.is_empty(), "expected no group rule for other agents");
+
+        let request = test::TestRequest::delete()
+            .uri(&format!("/agent/{}", key))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = test::TestRequest::get()
+            .uri("/firewall-rule/list/3?agent=edge-1")
+            .to_request();
+        let data: Vec<FirewallRuleData> = test::call_and_read_body_json(&app, request).await;
+        assert!(data.is_empty(), "expected no group rule for a removed agent");
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/agent/{}", key))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_agent_validation() {
+        let app = test::init_service(App::new().app_data(app_state().await).service(scope())).await;
+
+        let request = test::TestRequest::post()
+            .uri("/agent/heartbeat")
+            .set_json(json!({ "hostname": " ", "kind": "Firewall", "interface": "" }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let request = test::TestRequest::put()
+            .uri("/agent/firewall:missing/groups")
+            .set_json(json!({ "groups": ["edge"] }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let request = test::TestRequest::put()
+            .uri("/agent/firewall:missing/groups")
+            .set_json(json!({ "groups": [""] }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::error::{ErrorResponse, FieldError};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Size limit of bulk request bodies, agents send batches of hundreds of events
+/// and replay tools tens of thousands.
+pub const BULK_PAYLOAD_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Content type of bodies with one JSON object per line.
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Response of the bulk ingestion endpoints, `errors` lists the fields of the
+/// rejected items prefixed with their index as in `[3].ip`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BulkResponse {
+    pub inserted: usize,
+    pub rejected: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+/// Items of a bulk request body, the ones that could not be parsed or are invalid
+/// are left out and reported in `errors`.
+#[derive(Clone, Debug)]
+pub struct BulkItems<T> {
+    pub items: Vec<T>,
+    pub rejected: usize,
+    pub errors: Vec<FieldError>,
+}
+
+impl<T> BulkItems<T> {
+    /// Parses a JSON array or, with the `application/x-ndjson` content type, one
+    /// JSON object per line, blank lines are skipped. Array items are indexed in
+    /// the order they appear and lines by their position, blank lines included, so
+    /// the index is the line number counted from 0. The request is rejected when
+    /// the body itself is malformed.
+    pub fn parse<F: DeserializeOwned>(
+        request: &HttpRequest,
+        body: &[u8],
+        convert: impl Fn(F) -> T,
+        validate: impl Fn(&T) -> Result<(), Vec<FieldError>>,
+    ) -> Result<Self, HttpResponse> {
+        let mut bulk: BulkItems<T> = BulkItems {
+            items: Vec::new(),
+            rejected: 0,
+            errors: Vec::new(),
+        };
+        let mut push = |index: usize, item: Result<F, serde_json::Error>| {
+            match item.map(&convert) {
+                Ok(item) => match validate(&item) {
+                    Ok(()) => bulk.items.push(item),
+                    Err(errors) => bulk.reject(index, errors),
+                },
+                Err(error) => bulk.reject(index, vec![FieldError::new("", &error.to_string())]),
+            }
+        };
+        match request.content_type() {
+            NDJSON_CONTENT_TYPE => {
+                body.split(|byte| *byte == b'\n')
+                    .map(|line| line.trim_ascii())
+                    .enumerate()
+                    .filter(|(_, line)| !line.is_empty())
+                    .for_each(|(index, line)| push(index, serde_json::from_slice::<F>(line)));
+            }
+            "application/json" | "" => match serde_json::from_slice::<Vec<Value>>(body) {
+                Ok(values) => values
+                    .into_iter()
+                    .enumerate()
+                    .for_each(|(index, value)| push(index, serde_json::from_value::<F>(value))),
+                Err(error) => {
+                    return Err(ErrorResponse::bad_request(format!(
+                        "expected a JSON array: {}",
+                        error
+                    )));
+                }
+            },
+            content_type => {
+                return Err(ErrorResponse::bad_request(format!(
+                    "unsupported content type {}, expected application/json or {}",
+                    content_type, NDJSON_CONTENT_TYPE
+                )));
+            }
+        }
+        Ok(bulk)
+    }
+
+    fn reject(&mut self, index: usize, errors: Vec<FieldError>) {
+        self.rejected += 1;
+        self.errors.extend(errors.into_iter().map(|error| FieldError {
+            field: if error.field.is_empty() {
+                format!("[{}]", index)
+            } else {
+                format!("[{}].{}", index, error.field)
+            },
+            message: error.message,
+        }));
+    }
+
+    /// Answers with the number of inserted items and the rejected ones, a body
+    /// whose items were all rejected is a validation error.
+    pub fn response(self, inserted: usize) -> HttpResponse {
+        if inserted == 0 && self.rejected > 0 {
+            return ErrorResponse::validation(self.errors);
+        }
+        HttpResponse::Ok().json(BulkResponse {
+            inserted,
+            rejected: self.rejected,
+            errors: self.errors,
+        })
+    }
+}
@@ -1,7 +1,11 @@
+use crate::error::ErrorResponse;
 use crate::models::command_execution::{CommandExecution, CommandExecutionData};
+use crate::services::bulk::{BulkItems, BULK_PAYLOAD_LIMIT};
 use crate::AppState;
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder, Scope};
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
+use surrealdb::Datetime;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct CommandDataForm {
@@ -11,6 +15,22 @@ pub struct CommandDataForm {
     pub pid: u32,
     pub gid: u32,
     pub uid: u32,
+    /// Time of the execution, the time of the request when missing.
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+fn form_data(form: CommandDataForm) -> CommandExecutionData {
+    CommandExecutionData {
+        command: form.command,
+        args: form.args,
+        tgid: form.tgid,
+        gid: form.gid,
+        pid: form.pid,
+        uid: form.uid,
+        timestamp: Datetime::from(form.timestamp.unwrap_or_else(Utc::now)),
+        ..Default::default()
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -18,6 +38,19 @@ pub struct ExecutedCommandsRequest {
     pub offset: usize,
     pub limit: usize,
 }
+
+/// Routes of the `/command-execution` scope.
+pub fn scope() -> Scope {
+    web::scope("/command-execution")
+        .route("/log", web::post().to(log_command_execution))
+        .service(
+            web::resource("/bulk")
+                .app_data(web::PayloadConfig::new(BULK_PAYLOAD_LIMIT))
+                .route(web::post().to(log_command_executions)),
+        )
+        .route("/list", web::get().to(executed_commands))
+        .route("/stats", web::get().to(executed_command_stats))
+}
 pub async fn executed_commands(
     query: web::Query<ExecutedCommandsRequest>,
     app_state: web::Data<AppState>,
@@ -42,20 +75,105 @@ pub async fn log_command_execution(
     app_state: web::Data<AppState>,
 ) -> impl Responder {
     let api = CommandExecution::new(app_state.db.clone());
-    let form_data = json_data.into_inner();
-    match api
-        .create(CommandExecutionData {
-            command: form_data.command,
-            args: form_data.args,
-            tgid: form_data.tgid,
-            gid: form_data.gid,
-            pid: form_data.pid,
-            uid: form_data.uid,
-            ..Default::default()
-        })
-        .await
-    {
-        Ok(data) => HttpResponse::Ok().json(data),
+    match api.create(form_data(json_data.into_inner())).await {
+        Ok(data) => {
+            let bans = app_state
+                .bans
+                .count_commands(std::slice::from_ref(&data), Utc::now().timestamp());
+            app_state.bans.queue(bans);
+            HttpResponse::Ok().json(data)
+        }
         Err(error) => HttpResponse::BadRequest().body(error),
     }
 }
+
+/// Inserts a JSON array or NDJSON stream of executions, as sent by the agents and
+/// replay tools. Invalid executions are reported by index, the valid ones are
+/// inserted.
+pub async fn log_command_executions(
+    request: HttpRequest,
+    body: web::Bytes,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let bulk = match BulkItems::parse(
+        &request,
+        &body,
+        form_data,
+        CommandExecutionData::validate,
+    ) {
+        Ok(bulk) => bulk,
+        Err(response) => return response,
+    };
+    let api = CommandExecution::new(app_state.db.clone());
+    match api.create_many(bulk.items.clone()).await {
+        Ok(inserted) => {
+            let bans = app_state.bans.count_commands(&bulk.items, Utc::now().timestamp());
+            app_state.bans.queue(bans);
+            bulk.response(inserted)
+        }
+        Err(error) => ErrorResponse::internal(error),
+    }
+}
+
+#[cfg(test)]
+mod test_command_execution_service {
+    use super::*;
+    use crate::services::bulk::{BulkResponse, NDJSON_CONTENT_TYPE};
+    use crate::services::test_util::app_state;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use serde_json::{json, Value};
+
+    #[actix_web::test]
+    async fn test_log_command_executions() {
+        let app_state = app_state().await;
+        let app = test::init_service(App::new().app_data(app_state.clone()).service(scope())).await;
+
+        let request = test::TestRequest::post()
+            .uri("/command-execution/bulk")
+            .set_json(json!([
+                { "command": "ls", "args": "-la", "tgid": 1, "pid": 1, "gid": 0, "uid": 0 },
+                { "command": "id", "args": "", "tgid": 2, "pid": 2, "gid": 0, "uid": 0 }
+            ]))
+            .to_request();
+        let response: BulkResponse = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(response.inserted, 2);
+        assert_eq!(response.rejected, 0);
+
+        let request = test::TestRequest::post()
+            .uri("/command-execution/bulk")
+            .insert_header(("content-type", NDJSON_CONTENT_TYPE))
+            .set_payload(concat!(
+                "{\"command\": \"ps\", \"args\": \"aux\", \"tgid\": 4, \"pid\": 4, \"gid\": 0, \"uid\": 0}\n",
+                "{\"command\": \"\", \"args\": \"\", \"tgid\": 5, \"pid\": 5, \"gid\": 0, \"uid\": 0}\n",
+            ))
+            .to_request();
+        let response: BulkResponse = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(response.inserted, 1);
+        assert_eq!(response.errors[0].field, "[1].command");
+
+        let request = test::TestRequest::post()
+            .uri("/command-execution/bulk")
+            .set_json(json!([
+                { "command": "", "args": "", "tgid": 3, "pid": 3, "gid": 0, "uid": 0 }
+            ]))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["fields"][0]["field"], "[0].command");
+
+        let request = test::TestRequest::post()
+            .uri("/command-execution/bulk")
+            .insert_header(("content-type", "application/json"))
+            .set_payload("[{")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let api = CommandExecution::new(app_state.db.clone());
+        let data = api.get_executed_commands(10, 0).await;
+        assert!(data.is_ok(), "{:?}", data.err());
+        assert_eq!(data.unwrap().total, 3);
+    }
+}
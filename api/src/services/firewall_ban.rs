@@ -0,0 +1,158 @@
+use crate::ban::{BanError, BanRequest};
+use crate::config::default_ban_layer;
+use crate::error::{json_error_handler, ErrorResponse, FieldError};
+use crate::models::firewall_ban::{ip_key, FirewallBan};
+use crate::models::firewall_rule::MAX_RULE_TTL;
+use crate::AppState;
+use actix_web::{web, HttpResponse, Responder, Scope};
+use serde::Deserialize;
+
+/// A ban of `ip` for `ttl` seconds, denied for the agents of `layer`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct FirewallBanForm {
+    pub ip: Vec<u8>,
+    pub ttl: u64,
+    pub reason: String,
+    #[serde(default = "default_ban_layer")]
+    pub layer: u8,
+}
+
+impl FirewallBanForm {
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors: Vec<FieldError> = Vec::new();
+        if ip_key(&self.ip).is_none() {
+            errors.push(FieldError::new(
+                "ip",
+                "must have 4 (IPv4) or 16 (IPv6) octets",
+            ));
+        }
+        if self.ttl == 0 || self.ttl > MAX_RULE_TTL {
+            errors.push(FieldError::new(
+                "ttl",
+                &format!("must be from 1 to {}", MAX_RULE_TTL),
+            ));
+        }
+        if self.reason.trim().is_empty() {
+            errors.push(FieldError::new("reason", "must not be empty"));
+        }
+        if !(1..=7).contains(&self.layer) {
+            errors.push(FieldError::new("layer", "must be an OSI layer from 1 to 7"));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Routes of the `/firewall-ban` scope, bans are keyed by address as in
+/// `/firewall-ban/10.0.0.1`.
+pub fn scope() -> Scope {
+    web::scope("/firewall-ban")
+        .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+        .route("/list", web::get().to(get_firewall_bans))
+        .route("/create", web::post().to(create_firewall_ban))
+        .route("/{ip}", web::delete().to(delete_firewall_ban))
+}
+
+pub async fn get_firewall_bans(app_state: web::Data<AppState>) -> impl Responder {
+    let api = FirewallBan::new(app_state.db.clone());
+    match api.list(false).await {
+        Ok(data) => HttpResponse::Ok().json(data),
+        Err(error) => ErrorResponse::internal(error),
+    }
+}
+
+/// Bans a source by hand, a source is banned at most once at a time.
+pub async fn create_firewall_ban(
+    form: web::Json<FirewallBanForm>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let form = form.into_inner();
+    if let Err(errors) = form.validate() {
+        return ErrorResponse::validation(errors);
+    }
+    let request: BanRequest = BanRequest {
+        ip: form.ip,
+        ttl: form.ttl,
+        layer: form.layer,
+        threshold: None,
+        reason: form.reason,
+    };
+    match app_state.bans.ban(request).await {
+        Ok(Some(data)) => HttpResponse::Ok().json(data),
+        Ok(None) => ErrorResponse::conflict("the source is already banned".to_string()),
+        Err(error @ BanError::Limit(_)) => ErrorResponse::conflict(error.to_string()),
+        Err(error) => ErrorResponse::internal(error.to_string()),
+    }
+}
+
+/// Lifts the ban before it expires.
+pub async fn delete_firewall_ban(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let key = path.into_inner();
+    match app_state.bans.lift(&key).await {
+        Ok(Some(data)) => HttpResponse::Ok().json(data),
+        Ok(None) => ErrorResponse::not_found(format!("firewall ban {} not found", key)),
+        Err(error) => ErrorResponse::internal(error),
+    }
+}
+
+#[cfg(test)]
+mod test_firewall_ban_service {
+    use super::*;
+    use crate::models::firewall_ban::FirewallBanData;
+    use crate::services::test_util::app_state;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use serde_json::json;
+
+    #[actix_web::test]
+    async fn test_firewall_ban_handlers() {
+        let app = test::init_service(App::new().app_data(app_state().await).service(scope())).await;
+
+        let ban = json!({ "ip": [10, 0, 0, 1], "ttl": 600, "reason": "port scan" });
+        let request = test::TestRequest::post()
+            .uri("/firewall-ban/create")
+            .set_json(ban.clone())
+            .to_request();
+        let created: FirewallBanData = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(created.rules.len(), 1);
+        assert_eq!(created.threshold, None);
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-ban/create")
+            .set_json(ban)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-ban/create")
+            .set_json(json!({ "ip": [10, 0, 0], "ttl": 0, "reason": "" }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let request = test::TestRequest::get()
+            .uri("/firewall-ban/list")
+            .to_request();
+        let bans: Vec<FirewallBanData> = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(bans.len(), 1);
+
+        let request = test::TestRequest::delete()
+            .uri("/firewall-ban/10.0.0.1")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = test::TestRequest::delete()
+            .uri("/firewall-ban/10.0.0.1")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -1,8 +1,12 @@
-use crate::enums::ip_protocol::IpProtocol;
-use crate::models::firewall_log::{FirewallLog, FirewallLogData};
+use crate::enums::{direction::Direction, ip_protocol::IpProtocol};
+use crate::error::ErrorResponse;
+use crate::models::firewall_log::{default_packets, FirewallLog, FirewallLogData};
+use crate::services::bulk::{BulkItems, BULK_PAYLOAD_LIMIT};
 use crate::AppState;
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder, Scope};
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
+use surrealdb::Datetime;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct GetFirewallLogsFilter {
@@ -12,11 +16,73 @@ pub struct GetFirewallLogsFilter {
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct FirewallLogForm {
-    pub ip: [u8; 4],
+    pub ip: Vec<u8>,
     pub protocol: IpProtocol,
     pub port: Option<u16>,
     pub status: bool,
+    /// Time of the event, the time of the request when missing.
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Packets dropped in the `window` seconds before `timestamp`, aggregated by
+    /// the agent. A single packet when missing.
+    #[serde(default = "default_packets")]
+    pub packets: u64,
+    #[serde(default)]
+    pub bytes: u64,
+    #[serde(default)]
+    pub window: u64,
+    #[serde(default)]
+    pub rule: Option<String>,
+    #[serde(default)]
+    pub destination_ip: Option<Vec<u8>>,
+    #[serde(default)]
+    pub destination_port: Option<u16>,
+    #[serde(default)]
+    pub length: Option<u32>,
+    #[serde(default)]
+    pub ifindex: Option<u32>,
+    #[serde(default)]
+    pub direction: Direction,
+    #[serde(default)]
+    pub icmp_type: Option<u8>,
+    #[serde(default)]
+    pub icmp_code: Option<u8>,
 }
+
+fn form_data(form: FirewallLogForm) -> FirewallLogData {
+    FirewallLogData {
+        ip: form.ip,
+        port: form.port,
+        protocol: form.protocol,
+        status: form.status,
+        timestamp: Datetime::from(form.timestamp.unwrap_or_else(Utc::now)),
+        packets: form.packets,
+        bytes: form.bytes,
+        window: form.window,
+        rule: form.rule,
+        destination_ip: form.destination_ip,
+        destination_port: form.destination_port,
+        length: form.length,
+        ifindex: form.ifindex,
+        direction: form.direction,
+        icmp_type: form.icmp_type,
+        icmp_code: form.icmp_code,
+        ..Default::default()
+    }
+}
+
+/// Routes of the `/firewall-log` scope.
+pub fn scope() -> Scope {
+    web::scope("/firewall-log")
+        .route("/list", web::get().to(get_firewall_logs))
+        .route("/create", web::post().to(create_firewall_log))
+        .service(
+            web::resource("/bulk")
+                .app_data(web::PayloadConfig::new(BULK_PAYLOAD_LIMIT))
+                .route(web::post().to(create_firewall_logs)),
+        )
+}
+
 pub async fn get_firewall_logs(
     query: web::Query<GetFirewallLogsFilter>,
     app_state: web::Data<AppState>,
@@ -32,17 +98,208 @@ pub async fn create_firewall_log(
     form: web::Json<FirewallLogForm>,
     app_state: web::Data<AppState>,
 ) -> impl Responder {
-    let form = form.into_inner();
-    let data = FirewallLogData {
-        ip: form.ip,
-        port: form.port,
-        protocol: form.protocol,
-        status: form.status,
-        ..Default::default()
-    };
+    let data = form_data(form.into_inner());
     let api = FirewallLog::new(app_state.db.clone());
     match api.create(data).await {
-        Ok(data) => HttpResponse::Ok().json(data),
+        Ok(data) => {
+            let bans = app_state
+                .bans
+                .count_logs(std::slice::from_ref(&data), Utc::now().timestamp());
+            app_state.bans.queue(bans);
+            HttpResponse::Ok().json(data)
+        }
         Err(error) => HttpResponse::BadRequest().body(error),
     }
 }
+
+/// Inserts a JSON array or NDJSON stream of logs, as sent by the agents and
+/// replay tools. Invalid logs are reported by index, the valid ones are inserted.
+pub async fn create_firewall_logs(
+    request: HttpRequest,
+    body: web::Bytes,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let bulk = match BulkItems::parse(
+        &request,
+        &body,
+        form_data,
+        FirewallLogData::validate,
+    ) {
+        Ok(bulk) => bulk,
+        Err(response) => return response,
+    };
+    let api = FirewallLog::new(app_state.db.clone());
+    match api.create_many(bulk.items.clone()).await {
+        Ok(inserted) => {
+            let bans = app_state.bans.count_logs(&bulk.items, Utc::now().timestamp());
+            app_state.bans.queue(bans);
+            bulk.response(inserted)
+        }
+        Err(error) => ErrorResponse::internal(error),
+    }
+}
+
+#[cfg(test)]
+mod test_firewall_log_service {
+    use super::*;
+    use crate::services::bulk::{BulkResponse, NDJSON_CONTENT_TYPE};
+    use crate::services::test_util::app_state;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use serde_json::{json, Value};
+
+    #[actix_web::test]
+    async fn test_create_firewall_logs() {
+        let app_state = app_state().await;
+        let app = test::init_service(App::new().app_data(app_state.clone()).service(scope())).await;
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-log/bulk")
+            .set_json(json!([
+                {
+                    "ip": [10, 0, 0, 1],
+                    "protocol": "Tcp",
+                    "port": 22,
+                    "status": false,
+                    "timestamp": "2025-01-01T00:00:00Z"
+                },
+                {
+                    "ip": [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+                    "protocol": "Icmp",
+                    "port": null,
+                    "status": false
+                }
+            ]))
+            .to_request();
+        let response: BulkResponse = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(response.inserted, 2);
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-log/bulk")
+            .set_json(json!([
+                { "ip": [10, 0, 0, 2], "protocol": "Udp", "port": 53, "status": false },
+                { "ip": [10, 0, 0], "protocol": "Udp", "port": 53, "status": false }
+            ]))
+            .to_request();
+        let response: BulkResponse = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(response.inserted, 1, "expected the valid log to be inserted");
+        assert_eq!(response.rejected, 1);
+        assert_eq!(response.errors[0].field, "[1].ip");
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-log/bulk")
+            .insert_header(("content-type", NDJSON_CONTENT_TYPE))
+            .set_payload(concat!(
+                "{\"ip\": [10, 0, 0, 3], \"protocol\": \"Tcp\", \"port\": 80, \"status\": false}\n",
+                "\n",
+                "{\"ip\": [10, 0, 0, 4], \"protocol\": \"Tcp\"\n",
+                "{\"ip\": [10, 0, 0, 5], \"protocol\": \"Tcp\", \"port\": 80, \"status\": false}\r\n",
+            ))
+            .to_request();
+        let response: BulkResponse = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(response.inserted, 2);
+        assert_eq!(response.rejected, 1);
+        assert_eq!(
+            response.errors[0].field, "[2]",
+            "expected the index of the line, blank lines included"
+        );
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-log/bulk")
+            .set_json(json!([{ "ip": [], "protocol": "Tcp", "port": 80, "status": false }]))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["fields"][0]["field"], "[0].ip");
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-log/bulk")
+            .insert_header(("content-type", "text/plain"))
+            .set_payload("[]")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-log/bulk")
+            .set_json(json!([
+                {
+                    "ip": [10, 0, 0, 6],
+                    "protocol": "Tcp",
+                    "port": null,
+                    "status": false,
+                    "packets": 1200,
+                    "bytes": 72000,
+                    "window": 10,
+                    "rule": "abc"
+                },
+                { "ip": [10, 0, 0, 7], "protocol": "Tcp", "port": null, "status": false, "packets": 0 }
+            ]))
+            .to_request();
+        let response: BulkResponse = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(response.inserted, 1);
+        assert_eq!(response.errors[0].field, "[1].packets");
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-log/bulk")
+            .set_json(json!([
+                {
+                    "ip": [10, 0, 0, 8],
+                    "protocol": "Tcp",
+                    "port": 51000,
+                    "status": true,
+                    "rule": "abc",
+                    "destination_ip": [10, 0, 0, 254],
+                    "destination_port": 443,
+                    "length": 74,
+                    "ifindex": 2,
+                    "direction": "Egress"
+                },
+                {
+                    "ip": [10, 0, 0, 9],
+                    "protocol": "Tcp",
+                    "port": 51000,
+                    "status": true,
+                    "destination_ip": [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]
+                }
+            ]))
+            .to_request();
+        let response: BulkResponse = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(response.inserted, 1);
+        assert_eq!(response.errors[0].field, "[1].destination_ip");
+
+        let api = FirewallLog::new(app_state.db.clone());
+        let data = api.list(true, 100).await;
+        assert!(data.is_ok(), "{:?}", data.err());
+        let data = data.unwrap();
+        assert_eq!(data.len(), 1, "expected the allowed log to be listed apart");
+        assert_eq!(data[0].destination_ip, Some(vec![10, 0, 0, 254]));
+        assert_eq!(data[0].destination_port, Some(443));
+        assert_eq!((data[0].length, data[0].ifindex), (Some(74), Some(2)));
+        assert_eq!(data[0].direction, Direction::Egress);
+
+        let api = FirewallLog::new(app_state.db.clone());
+        let data = api.list(false, 100).await;
+        assert!(data.is_ok(), "{:?}", data.err());
+        let data = data.unwrap();
+        assert_eq!(data.len(), 6, "expected only the valid logs to be inserted");
+        assert!(
+            data.iter()
+                .filter(|item| item.ip != vec![10, 0, 0, 6])
+                .all(|item| item.packets == 1 && item.window == 0),
+            "expected a log without counts to be a single packet"
+        );
+        let aggregate = data.iter().find(|item| item.ip == vec![10, 0, 0, 6]).unwrap();
+        assert_eq!(
+            (aggregate.packets, aggregate.bytes, aggregate.window),
+            (1200, 72000, 10)
+        );
+        assert_eq!(aggregate.rule, Some("abc".to_string()));
+        assert!(
+            data.iter()
+                .any(|item| item.timestamp.to_string().contains("2025-01-01T00:00:00")),
+            "expected the submitted timestamp to be kept"
+        );
+    }
+}
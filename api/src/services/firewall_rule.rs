@@ -1,48 +1,895 @@
-use crate::enums::ip_protocol::IpProtocol;
-use crate::models::firewall_rule::{FirewallRule, FirewallRuleData};
+use crate::enums::{
+    connection_state::ConnectionState, direction::Direction, ip_protocol::IpProtocol,
+    rule_action::RuleAction,
+};
+use crate::error::{
+    json_error_handler, path_error_handler, query_error_handler, ErrorResponse, FieldError,
+};
+use crate::models::firewall_rule::{
+    FirewallRule, FirewallRuleData, FirewallRuleError, FirewallRuleScope, RateLimit, RuleSchedule,
+    MAX_RULE_TTL,
+};
+use crate::models::firewall_stats::FirewallStats;
+use crate::rule_version::RuleVersion;
 use crate::AppState;
-use actix_web::{web, HttpResponse, Responder};
-use serde::Deserialize;
+use actix_web::http::header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH};
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse, Responder, Scope};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::time::Duration;
+use surrealdb::Datetime;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Interval of the keep-alive comments sent on an idle rule stream.
+const STREAM_KEEP_ALIVE: Duration = Duration::from_secs(15);
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct FirewallRuleForm {
-    pub ip: [u8; 4],
+    pub ip: Vec<u8>,
     pub protocol: IpProtocol,
     pub cidr: u16,
     pub layer: u8,
     pub from_port: Option<u16>,
     pub to_port: Option<u16>,
     pub status: bool,
+    #[serde(default)]
+    pub priority: u32,
+    #[serde(default)]
+    pub direction: Direction,
+    pub destination_ip: Option<Vec<u8>>,
+    pub destination_cidr: Option<u16>,
+    pub destination_from_port: Option<u16>,
+    pub destination_to_port: Option<u16>,
+    #[serde(default)]
+    pub connection_state: ConnectionState,
+    pub vlan_id: Option<u16>,
+    pub icmp_type: Option<u8>,
+    pub icmp_code: Option<u8>,
+    /// Logs every packet the rule matches, allowed ones included.
+    #[serde(default)]
+    pub log: bool,
+    #[serde(default)]
+    pub action: RuleAction,
+    pub rate_limit: Option<RateLimit>,
+    /// Time the rule is removed at, or `ttl` seconds from now.
+    pub expires_at: Option<DateTime<Utc>>,
+    pub ttl: Option<u64>,
+    pub schedule: Option<RuleSchedule>,
+    #[serde(default)]
+    pub agents: Vec<String>,
+    #[serde(default)]
+    pub interfaces: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+/// Lets a PATCH tell a field set to `null` (`Some(None)`) from a missing one (`None`).
+fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Fields of a PATCH, missing fields are left unchanged.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct FirewallRulePatchForm {
+    pub ip: Option<Vec<u8>>,
+    pub protocol: Option<IpProtocol>,
+    pub cidr: Option<u16>,
+    pub layer: Option<u8>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub from_port: Option<Option<u16>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub to_port: Option<Option<u16>>,
+    pub status: Option<bool>,
+    pub priority: Option<u32>,
+    pub direction: Option<Direction>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub destination_ip: Option<Option<Vec<u8>>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub destination_cidr: Option<Option<u16>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub destination_from_port: Option<Option<u16>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub destination_to_port: Option<Option<u16>>,
+    pub connection_state: Option<ConnectionState>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub vlan_id: Option<Option<u16>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub icmp_type: Option<Option<u8>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub icmp_code: Option<Option<u8>>,
+    pub log: Option<bool>,
+    pub action: Option<RuleAction>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub rate_limit: Option<Option<RateLimit>>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    pub ttl: Option<u64>,
+    #[serde(default, deserialize_with = "double_option")]
+    pub schedule: Option<Option<RuleSchedule>>,
+    pub agents: Option<Vec<String>>,
+    pub interfaces: Option<Vec<String>>,
+    pub groups: Option<Vec<String>>,
+}
+
+impl FirewallRulePatchForm {
+    pub fn apply(self, data: &mut FirewallRuleData) {
+        if let Some(value) = self.ip {
+            data.ip = value;
+        }
+        if let Some(value) = self.protocol {
+            data.protocol = value;
+        }
+        if let Some(value) = self.cidr {
+            data.cidr = value;
+        }
+        if let Some(value) = self.layer {
+            data.layer = value;
+        }
+        if let Some(value) = self.from_port {
+            data.from_port = value;
+        }
+        if let Some(value) = self.to_port {
+            data.to_port = value;
+        }
+        if let Some(value) = self.status {
+            data.status = value;
+        }
+        if let Some(value) = self.priority {
+            data.priority = value;
+        }
+        if let Some(value) = self.direction {
+            data.direction = value;
+        }
+        if let Some(value) = self.destination_ip {
+            data.destination_ip = value;
+        }
+        if let Some(value) = self.destination_cidr {
+            data.destination_cidr = value;
+        }
+        if let Some(value) = self.destination_from_port {
+            data.destination_from_port = value;
+        }
+        if let Some(value) = self.destination_to_port {
+            data.destination_to_port = value;
+        }
+        if let Some(value) = self.connection_state {
+            data.connection_state = value;
+        }
+        if let Some(value) = self.vlan_id {
+            data.vlan_id = value;
+        }
+        if let Some(value) = self.icmp_type {
+            data.icmp_type = value;
+        }
+        if let Some(value) = self.icmp_code {
+            data.icmp_code = value;
+        }
+        if let Some(value) = self.log {
+            data.log = value;
+        }
+        if let Some(value) = self.action {
+            data.action = value;
+        }
+        if let Some(value) = self.rate_limit {
+            data.rate_limit = value;
+        }
+        if let Some(value) = self.expires_at {
+            data.expires_at = value.map(Datetime::from);
+        }
+        if let Some(value) = self.ttl {
+            data.expires_at = Some(ttl_expiry(value));
+        }
+        if let Some(value) = self.schedule {
+            data.schedule = value;
+        }
+        if let Some(value) = self.agents {
+            data.agents = value;
+        }
+        if let Some(value) = self.interfaces {
+            data.interfaces = value;
+        }
+        if let Some(value) = self.groups {
+            data.groups = value;
+        }
+    }
+}
+
+/// Expiry of a rule `ttl` seconds from now.
+fn ttl_expiry(ttl: u64) -> Datetime {
+    Datetime::from(Utc::now() + chrono::Duration::seconds(ttl.min(MAX_RULE_TTL) as i64))
+}
+
+/// Responds to a failed rule write, rejected fields are a validation error.
+fn rule_error(error: FirewallRuleError) -> HttpResponse {
+    match error {
+        FirewallRuleError::Invalid(errors) => ErrorResponse::validation(errors),
+        FirewallRuleError::Db(error) => ErrorResponse::internal(error),
+    }
+}
+
+/// `expires_at` and `ttl` set the same field, only one of them may be submitted.
+fn validate_expiry(expires_at: bool, ttl: Option<u64>) -> Result<(), Vec<FieldError>> {
+    let mut errors: Vec<FieldError> = Vec::new();
+    if expires_at && ttl.is_some() {
+        errors.push(FieldError::new("ttl", "must not be set with expires_at"));
+    }
+    if ttl.is_some_and(|ttl| ttl > MAX_RULE_TTL) {
+        errors.push(FieldError::new(
+            "ttl",
+            &format!("must not be greater than {}", MAX_RULE_TTL),
+        ));
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn form_data(form: FirewallRuleForm) -> FirewallRuleData {
+    FirewallRuleData {
+        ip: form.ip,
+        cidr: form.cidr,
+        layer: form.layer,
+        protocol: form.protocol,
+        from_port: form.from_port,
+        to_port: form.to_port,
+        status: form.status,
+        priority: form.priority,
+        direction: form.direction,
+        destination_ip: form.destination_ip,
+        destination_cidr: form.destination_cidr,
+        destination_from_port: form.destination_from_port,
+        destination_to_port: form.destination_to_port,
+        connection_state: form.connection_state,
+        vlan_id: form.vlan_id,
+        icmp_type: form.icmp_type,
+        icmp_code: form.icmp_code,
+        log: form.log,
+        action: form.action,
+        rate_limit: form.rate_limit,
+        expires_at: form
+            .ttl
+            .map(ttl_expiry)
+            .or(form.expires_at.map(Datetime::from)),
+        schedule: form.schedule,
+        expires_in: None,
+        agents: form.agents,
+        interfaces: form.interfaces,
+        groups: form.groups,
+        id: None,
+    }
+}
+
+fn not_found(key: &str) -> HttpResponse {
+    ErrorResponse::not_found(format!("firewall rule {} not found", key))
+}
+
+/// Routes of the `/firewall-rule` scope.
+pub fn scope() -> Scope {
+    web::scope("/firewall-rule")
+        .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+        .app_data(web::PathConfig::default().error_handler(path_error_handler))
+        .app_data(web::QueryConfig::default().error_handler(query_error_handler))
+        .route("/list/{layer}", web::get().to(get_firewall_rules))
+        .route("/stream/{layer}", web::get().to(stream_firewall_rules))
+        .route("/create", web::post().to(create_firewall_rule))
+        .route("/{id}", web::get().to(get_firewall_rule))
+        .route("/{id}", web::put().to(update_firewall_rule))
+        .route("/{id}", web::patch().to(patch_firewall_rule))
+        .route("/{id}", web::delete().to(delete_firewall_rule))
+        .route("/{id}/stats", web::get().to(get_firewall_rule_stats))
+}
+
+/// Rules of a layer at the given rule set version, sent as `rules` stream events.
+#[derive(Clone, Debug, Serialize)]
+pub struct FirewallRuleSet {
+    pub version: u64,
+    pub rules: Vec<FirewallRuleData>,
 }
+
+/// The `ETag` is the rule set version, a matching `If-None-Match` gets a 304.
+/// Agents pass their name and interface as `agent` and `interface` query parameters,
+/// `inactive=true` lists the rules outside their schedule too.
 pub async fn get_firewall_rules(
     path: web::Path<u8>,
+    query: web::Query<FirewallRuleScope>,
+    request: HttpRequest,
     app_state: web::Data<AppState>,
 ) -> impl Responder {
     let layer = path.into_inner();
+    let scope = query.into_inner();
+    // Read before listing, a change made meanwhile is picked up by the next request.
+    let etag: String = RuleVersion::etag(app_state.rule_version.current());
+    let not_modified: bool = request
+        .headers()
+        .get(IF_NONE_MATCH)
+        .is_some_and(|value| value.as_bytes() == etag.as_bytes());
+    if not_modified {
+        return HttpResponse::NotModified()
+            .insert_header((ETAG, etag))
+            .finish();
+    }
     let api = FirewallRule::new(app_state.db.clone());
-    match api.list(layer).await {
-        Ok(data) => HttpResponse::Ok().json(data),
-        Err(error) => HttpResponse::BadRequest().body(error),
+    match api.list(layer, &scope).await {
+        Ok(data) => HttpResponse::Ok().insert_header((ETAG, etag)).json(data),
+        Err(error) => ErrorResponse::internal(error),
     }
 }
 
+/// Server-sent events stream of the rules of a layer. The current rules are sent on
+/// connect and again after every change, idle streams get a keep-alive comment.
+pub async fn stream_firewall_rules(
+    path: web::Path<u8>,
+    query: web::Query<FirewallRuleScope>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let layer = path.into_inner();
+    let scope = query.into_inner();
+    let db = app_state.db.clone();
+    let rule_version = app_state.rule_version.clone();
+    // Subscribe before reading the version so no change is missed.
+    let receiver = rule_version.subscribe();
+    let version: u64 = rule_version.current();
+    let stream = futures_util::stream::unfold(
+        (receiver, Some(version)),
+        move |(mut receiver, pending)| {
+            let db = db.clone();
+            let rule_version = rule_version.clone();
+            let scope = scope.clone();
+            async move {
+                let version: u64 = match pending {
+                    Some(version) => version,
+                    None => match tokio::time::timeout(STREAM_KEEP_ALIVE, receiver.recv()).await {
+                        Ok(Ok(version)) => version,
+                        // Only the latest version matters to the agents.
+                        Ok(Err(RecvError::Lagged(_))) => rule_version.current(),
+                        Ok(Err(RecvError::Closed)) => return None,
+                        Err(_) => {
+                            let keep_alive: Result<Bytes, actix_web::Error> =
+                                Ok(Bytes::from_static(b": keep-alive\n\n"));
+                            return Some((keep_alive, (receiver, None)));
+                        }
+                    },
+                };
+                let api = FirewallRule::new(db);
+                let event: Result<Bytes, actix_web::Error> = match api.list(layer, &scope).await {
+                    Ok(rules) => match serde_json::to_string(&FirewallRuleSet { version, rules }) {
+                        Ok(data) => Ok(Bytes::from(format!(
+                            "event: rules\nid: {}\ndata: {}\n\n",
+                            version, data
+                        ))),
+                        Err(error) => Err(actix_web::error::ErrorInternalServerError(error)),
+                    },
+                    Err(error) => Err(actix_web::error::ErrorInternalServerError(error)),
+                };
+                Some((event, (receiver, None)))
+            }
+        },
+    );
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(stream)
+}
+
 pub async fn create_firewall_rule(
     form: web::Json<FirewallRuleForm>,
     app_state: web::Data<AppState>,
 ) -> impl Responder {
     let form = form.into_inner();
-    let data = FirewallRuleData {
-        ip: form.ip,
-        cidr: form.cidr,
-        protocol: form.protocol,
-        from_port: form.from_port,
-        to_port: form.to_port,
-        status: form.status,
-        ..Default::default()
-    };
+    if let Err(errors) = validate_expiry(form.expires_at.is_some(), form.ttl) {
+        return ErrorResponse::validation(errors);
+    }
+    let data = form_data(form);
     let api = FirewallRule::new(app_state.db.clone());
     match api.create(data).await {
+        Ok(data) => {
+            app_state.rule_version.bump();
+            HttpResponse::Ok().json(data)
+        }
+        Err(error) => rule_error(error),
+    }
+}
+
+pub async fn get_firewall_rule(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let key = path.into_inner();
+    let api = FirewallRule::new(app_state.db.clone());
+    match api.get(FirewallRule::record_id(&key)).await {
+        Ok(Some(data)) => HttpResponse::Ok().json(data),
+        Ok(None) => not_found(&key),
+        Err(error) => ErrorResponse::internal(error),
+    }
+}
+
+/// Replaces the rule with the submitted one.
+pub async fn update_firewall_rule(
+    path: web::Path<String>,
+    form: web::Json<FirewallRuleForm>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let key = path.into_inner();
+    let form = form.into_inner();
+    if let Err(errors) = validate_expiry(form.expires_at.is_some(), form.ttl) {
+        return ErrorResponse::validation(errors);
+    }
+    let data = form_data(form);
+    let api = FirewallRule::new(app_state.db.clone());
+    match api.update(FirewallRule::record_id(&key), data).await {
+        Ok(Some(data)) => {
+            app_state.rule_version.bump();
+            HttpResponse::Ok().json(data)
+        }
+        Ok(None) => not_found(&key),
+        Err(error) => rule_error(error),
+    }
+}
+
+/// Changes the submitted fields of the rule, the result must still be a valid rule.
+pub async fn patch_firewall_rule(
+    path: web::Path<String>,
+    form: web::Json<FirewallRulePatchForm>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let key = path.into_inner();
+    let id = FirewallRule::record_id(&key);
+    let api = FirewallRule::new(app_state.db.clone());
+    let mut data = match api.get(id.clone()).await {
+        Ok(Some(data)) => data,
+        Ok(None) => return not_found(&key),
+        Err(error) => return ErrorResponse::internal(error),
+    };
+    let form = form.into_inner();
+    if let Err(errors) = validate_expiry(
+        form.expires_at.as_ref().is_some_and(|value| value.is_some()),
+        form.ttl,
+    ) {
+        return ErrorResponse::validation(errors);
+    }
+    form.apply(&mut data);
+    match api.update(id, data).await {
+        Ok(Some(data)) => {
+            app_state.rule_version.bump();
+            HttpResponse::Ok().json(data)
+        }
+        Ok(None) => not_found(&key),
+        Err(error) => rule_error(error),
+    }
+}
+
+pub async fn delete_firewall_rule(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let key = path.into_inner();
+    let id = FirewallRule::record_id(&key);
+    let api = FirewallRule::new(app_state.db.clone());
+    match api.get(id.clone()).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return not_found(&key),
+        Err(error) => return ErrorResponse::internal(error),
+    }
+    match api.remove(id).await {
+        Ok(data) => {
+            app_state.rule_version.bump();
+            HttpResponse::Ok().json(data)
+        }
+        Err(error) => ErrorResponse::internal(error),
+    }
+}
+
+/// Traffic the rule passed or dropped over the agents, as last reported by each.
+pub async fn get_firewall_rule_stats(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let key = path.into_inner();
+    let api = FirewallRule::new(app_state.db.clone());
+    match api.get(FirewallRule::record_id(&key)).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return not_found(&key),
+        Err(error) => return ErrorResponse::internal(error),
+    }
+    let api = FirewallStats::new(app_state.db.clone());
+    match api.rule(&key).await {
         Ok(data) => HttpResponse::Ok().json(data),
-        Err(error) => HttpResponse::BadRequest().body(error),
+        Err(error) => ErrorResponse::internal(error),
+    }
+}
+
+#[cfg(test)]
+mod test_firewall_rule_service {
+    use super::*;
+    use crate::services::test_util::app_state;
+    use actix_web::http::StatusCode;
+    use ebpf_firewall_common::MAX_RULES_PER_CHAIN;
+    use actix_web::{test, App};
+    use serde_json::{json, Value};
+
+    fn record_key(data: &FirewallRuleData) -> String {
+        data.id.as_ref().unwrap().key().to_string()
+    }
+
+    #[actix_web::test]
+    async fn test_firewall_rule_handlers() {
+        let app = test::init_service(App::new().app_data(app_state().await).service(scope())).await;
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-rule/create")
+            .set_json(json!({
+                "ip": [10, 0, 0, 0],
+                "protocol": "Tcp",
+                "cidr": 8,
+                "layer": 3,
+                "from_port": 22,
+                "to_port": null,
+                "status": false
+            }))
+            .to_request();
+        let created: FirewallRuleData = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(created.layer, 3, "expected the submitted layer to be stored");
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-rule/create")
+            .set_json(json!({
+                "ip": [192, 168, 1, 0],
+                "protocol": "Udp",
+                "cidr": 24,
+                "layer": 3,
+                "from_port": null,
+                "to_port": null,
+                "status": false,
+                "agents": ["agent-a"],
+                "interfaces": ["eth1"]
+            }))
+            .to_request();
+        let scoped: FirewallRuleData = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(scoped.agents, vec!["agent-a".to_string()]);
+
+        let request = test::TestRequest::get()
+            .uri("/firewall-rule/list/3?agent=agent-a&interface=eth1")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers().get(ETAG).unwrap().clone();
+        let data: Vec<FirewallRuleData> = test::read_body_json(response).await;
+        assert_eq!(data.len(), 2);
+
+        let request = test::TestRequest::get()
+            .uri("/firewall-rule/list/3?agent=agent-b&interface=eth1")
+            .to_request();
+        let data: Vec<FirewallRuleData> = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(data.len(), 1, "expected the agent-a rule to be filtered out");
+        assert_eq!(data[0].id, created.id);
+
+        let request = test::TestRequest::get()
+            .uri("/firewall-rule/list/4")
+            .to_request();
+        let data: Vec<FirewallRuleData> = test::call_and_read_body_json(&app, request).await;
+        assert!(data.is_empty(), "expected no layer 4 rules");
+
+        let request = test::TestRequest::get()
+            .uri("/firewall-rule/list/3")
+            .insert_header((IF_NONE_MATCH, etag.clone()))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let key = record_key(&created);
+        let request = test::TestRequest::patch()
+            .uri(&format!("/firewall-rule/{}", key))
+            .set_json(json!({ "status": true, "to_port": 2222, "log": true }))
+            .to_request();
+        let patched: FirewallRuleData = test::call_and_read_body_json(&app, request).await;
+        assert!(patched.status);
+        assert!(patched.log);
+        assert_eq!(patched.from_port, Some(22));
+        assert_eq!(patched.to_port, Some(2222));
+        assert_eq!(patched.layer, 3);
+
+        let request = test::TestRequest::get()
+            .uri("/firewall-rule/list/3")
+            .insert_header((IF_NONE_MATCH, etag))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK, "expected a new version");
+
+        let request = test::TestRequest::put()
+            .uri(&format!("/firewall-rule/{}", key))
+            .set_json(json!({
+                "ip": [10, 1, 0, 0],
+                "protocol": "Icmp",
+                "cidr": 16,
+                "layer": 3,
+                "from_port": null,
+                "to_port": null,
+                "status": true
+            }))
+            .to_request();
+        let updated: FirewallRuleData = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(updated.ip, vec![10, 1, 0, 0]);
+        assert_eq!(updated.from_port, None);
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/firewall-rule/{}", key))
+            .to_request();
+        let data: FirewallRuleData = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(data.protocol, IpProtocol::Icmp);
+
+        let request = test::TestRequest::delete()
+            .uri(&format!("/firewall-rule/{}", key))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/firewall-rule/{}", key))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["error"], "not_found");
+
+        let request = test::TestRequest::delete()
+            .uri(&format!("/firewall-rule/{}", key))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_firewall_rule_validation() {
+        let app = test::init_service(App::new().app_data(app_state().await).service(scope())).await;
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-rule/create")
+            .set_json(json!({
+                "ip": [10, 0, 0, 1],
+                "protocol": "Undefined",
+                "cidr": 8,
+                "layer": 0,
+                "from_port": 3000,
+                "to_port": 2000,
+                "status": false
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["error"], "validation");
+        let fields: Vec<&str> = body["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(fields, vec!["layer", "protocol", "ip", "to_port", "protocol"]);
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-rule/create")
+            .set_json(json!({
+                "ip": [10, 0, 0, 1],
+                "protocol": "Udp",
+                "cidr": 32,
+                "layer": 4,
+                "status": false,
+                "action": "SynFlood",
+                "rate_limit": { "pps": 0, "burst_ms": 0, "prefix_len": 33 }
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = test::read_body_json(response).await;
+        let fields: Vec<&str> = body["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                "status",
+                "protocol",
+                "rate_limit",
+                "rate_limit.burst_ms",
+                "rate_limit.prefix_len"
+            ]
+        );
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-rule/create")
+            .set_json(json!({
+                "ip": [10, 0, 0, 1],
+                "protocol": "Tcp",
+                "cidr": 32,
+                "layer": 4,
+                "status": true,
+                "action": "RateLimit"
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["fields"][0]["field"], "rate_limit");
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-rule/create")
+            .set_json(json!({
+                "ip": [10, 0, 0, 1],
+                "protocol": "Tcp",
+                "cidr": 32,
+                "layer": 4,
+                "status": true,
+                "rate_limit": { "pps": 100 }
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-rule/create")
+            .set_json(json!({
+                "ip": [10, 0, 0, 0],
+                "protocol": "Tcp",
+                "cidr": 8,
+                "layer": 4,
+                "status": true,
+                "action": "SynFlood",
+                "rate_limit": { "pps": 100, "prefix_len": 24 }
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["action"], "SynFlood");
+        assert_eq!(body["rate_limit"]["burst_ms"], 1000);
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-rule/create")
+            .insert_header(("content-type", "application/json"))
+            .set_payload("{\"ip\": ")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["error"], "bad_request");
+
+        let request = test::TestRequest::get()
+            .uri("/firewall-rule/list/layer")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let request = test::TestRequest::patch()
+            .uri("/firewall-rule/missing")
+            .set_json(json!({ "status": true }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_firewall_rule_expiry() {
+        let app = test::init_service(App::new().app_data(app_state().await).service(scope())).await;
+        let rule = json!({
+            "ip": [10, 0, 0, 1],
+            "protocol": "Tcp",
+            "cidr": 32,
+            "layer": 3,
+            "status": false
+        });
+
+        let mut expiring = rule.clone();
+        expiring["ttl"] = json!(3600);
+        let request = test::TestRequest::post()
+            .uri("/firewall-rule/create")
+            .set_json(expiring.clone())
+            .to_request();
+        let created: FirewallRuleData = test::call_and_read_body_json(&app, request).await;
+        assert!(created.expires_at.is_some());
+
+        expiring["expires_at"] = json!("2100-01-01T00:00:00Z");
+        let request = test::TestRequest::post()
+            .uri("/firewall-rule/create")
+            .set_json(expiring)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["fields"][0]["field"], "ttl");
+
+        let mut scheduled = rule.clone();
+        let tomorrow = (Utc::now() + chrono::Duration::days(1)).format("%a").to_string();
+        scheduled["schedule"] = json!({ "days": [tomorrow], "from": "00:00", "to": "23:00" });
+        let request = test::TestRequest::post()
+            .uri("/firewall-rule/create")
+            .set_json(scheduled)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = test::TestRequest::get()
+            .uri("/firewall-rule/list/3")
+            .to_request();
+        let data: Vec<FirewallRuleData> = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(data.len(), 1, "expected the inactive rule not to be listed");
+        assert!(
+            data[0].expires_in.is_some_and(|value| value > 3500 && value <= 3600),
+            "expected the remaining lifetime, got {:?}",
+            data[0].expires_in
+        );
+
+        let request = test::TestRequest::get()
+            .uri("/firewall-rule/list/3?inactive=true")
+            .to_request();
+        let data: Vec<FirewallRuleData> = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(data.len(), 2);
+
+        let request = test::TestRequest::patch()
+            .uri(&format!("/firewall-rule/{}", created.id.unwrap().key()))
+            .set_json(json!({ "expires_at": null }))
+            .to_request();
+        let patched: FirewallRuleData = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(patched.expires_at, None);
+    }
+
+    #[actix_web::test]
+    async fn test_firewall_rule_chain() {
+        let app = test::init_service(App::new().app_data(app_state().await).service(scope())).await;
+        let rule = json!({
+            "ip": [10, 0, 0, 0],
+            "protocol": "Tcp",
+            "cidr": 8,
+            "layer": 3,
+            "status": false
+        });
+        let mut created: Vec<FirewallRuleData> = Vec::new();
+        for _ in 0..MAX_RULES_PER_CHAIN {
+            let request = test::TestRequest::post()
+                .uri("/firewall-rule/create")
+                .set_json(&rule)
+                .to_request();
+            created.push(test::call_and_read_body_json(&app, request).await);
+        }
+
+        let request = test::TestRequest::post()
+            .uri("/firewall-rule/create")
+            .set_json(&rule)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(
+            response.status(),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "expected a rule beyond MAX_RULES_PER_CHAIN on the prefix to be rejected"
+        );
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["fields"][0]["field"], "cidr");
+
+        let request = test::TestRequest::put()
+            .uri(&format!("/firewall-rule/{}", record_key(&created[0])))
+            .set_json(&rule)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(
+            response.status(),
+            StatusCode::OK,
+            "expected the replaced rule not to be counted"
+        );
+
+        let request = test::TestRequest::patch()
+            .uri(&format!("/firewall-rule/{}", record_key(&created[0])))
+            .set_json(json!({ "interfaces": ["eth0", "eth1"] }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     }
 }
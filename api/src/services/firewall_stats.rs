@@ -0,0 +1,179 @@
+use crate::error::{json_error_handler, ErrorResponse};
+use crate::models::firewall_rule::FirewallRule;
+use crate::models::firewall_stats::{FirewallStats, FirewallStatsReport};
+use crate::AppState;
+use actix_web::{web, HttpResponse, Responder, Scope};
+
+/// Routes of the `/firewall` scope.
+pub fn scope() -> Scope {
+    web::scope("/firewall")
+        .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+        .route("/stats", web::post().to(report_firewall_stats))
+        .route("/stats", web::get().to(get_firewall_stats))
+}
+
+/// Replaces the traffic counters of the reporting agent and interface.
+pub async fn report_firewall_stats(
+    form: web::Json<FirewallStatsReport>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let data = form.into_inner();
+    if let Err(errors) = data.validate() {
+        return ErrorResponse::validation(errors);
+    }
+    let api = FirewallStats::new(app_state.db.clone());
+    match api.report(data).await {
+        Ok(data) => HttpResponse::Ok().json(data),
+        Err(error) => ErrorResponse::internal(error),
+    }
+}
+
+/// Traffic of all agents per rule and protocol, rules without traffic are dead.
+pub async fn get_firewall_stats(app_state: web::Data<AppState>) -> impl Responder {
+    let rule_keys = match FirewallRule::new(app_state.db.clone()).keys().await {
+        Ok(data) => data,
+        Err(error) => return ErrorResponse::internal(error),
+    };
+    let api = FirewallStats::new(app_state.db.clone());
+    match api.summary(&rule_keys).await {
+        Ok(data) => HttpResponse::Ok().json(data),
+        Err(error) => ErrorResponse::internal(error),
+    }
+}
+
+#[cfg(test)]
+mod test_firewall_stats_service {
+    use super::*;
+    use crate::enums::ip_protocol::IpProtocol;
+    use crate::models::firewall_rule::FirewallRuleData;
+    use crate::models::firewall_stats::{FirewallRuleStats, FirewallStatsSummary, TrafficStats};
+    use crate::services::firewall_rule;
+    use crate::services::test_util::app_state;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use serde_json::{json, Value};
+
+    fn traffic(passed_packets: u64, dropped_packets: u64) -> Value {
+        json!({
+            "passed_packets": passed_packets,
+            "passed_bytes": passed_packets * 100,
+            "dropped_packets": dropped_packets,
+            "dropped_bytes": dropped_packets * 100
+        })
+    }
+
+    #[actix_web::test]
+    async fn test_firewall_stats_handlers() {
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state().await)
+                .service(scope())
+                .service(firewall_rule::scope()),
+        )
+        .await;
+
+        let mut keys: Vec<String> = Vec::new();
+        for ip in [[10, 0, 0, 0], [192, 168, 0, 0]] {
+            let request = test::TestRequest::post()
+                .uri("/firewall-rule/create")
+                .set_json(json!({
+                    "ip": ip,
+                    "protocol": "Tcp",
+                    "cidr": 16,
+                    "layer": 3,
+                    "from_port": null,
+                    "to_port": null,
+                    "status": false
+                }))
+                .to_request();
+            let created: FirewallRuleData = test::call_and_read_body_json(&app, request).await;
+            keys.push(created.id.unwrap().key().to_string());
+        }
+        let (active, dead) = (keys[0].clone(), keys[1].clone());
+
+        for (agent, dropped_packets) in [("edge-1", 40), ("edge-2", 2)] {
+            for _ in 0..2 {
+                let request = test::TestRequest::post()
+                    .uri("/firewall/stats")
+                    .set_json(json!({
+                        "agent": agent,
+                        "interface": "eth0",
+                        "rules": [
+                            { "rule": active, "traffic": traffic(0, dropped_packets) },
+                            { "rule": "removed", "traffic": traffic(0, 7) },
+                            { "rule": null, "traffic": traffic(100, 0) }
+                        ],
+                        "protocols": [
+                            { "protocol": "Tcp", "traffic": traffic(100, dropped_packets + 7) }
+                        ],
+                        "parse_errors": traffic(1, 3)
+                    }))
+                    .to_request();
+                let response = test::call_service(&app, request).await;
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+        }
+
+        let request = test::TestRequest::get().uri("/firewall/stats").to_request();
+        let data: FirewallStatsSummary = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(
+            data.agents, 2,
+            "expected a report to replace the previous one"
+        );
+        assert_eq!(data.default_action.passed_packets, 200);
+        assert_eq!(data.traffic.dropped_packets, 56);
+        assert_eq!(data.rules.len(), 2, "expected removed rules to be left out");
+        let rule = |key: &str| {
+            data.rules
+                .iter()
+                .find(|item| item.rule.as_deref() == Some(key))
+                .unwrap()
+                .traffic
+        };
+        assert_eq!(rule(&active).dropped_packets, 42);
+        assert_eq!(
+            rule(&dead),
+            TrafficStats::default(),
+            "expected no traffic for a dead rule"
+        );
+        assert_eq!(data.protocols.len(), 1);
+        assert_eq!(data.protocols[0].protocol, IpProtocol::Tcp);
+        assert_eq!(data.protocols[0].traffic.dropped_bytes, 5600);
+        assert_eq!(
+            (
+                data.parse_errors.passed_packets,
+                data.parse_errors.dropped_bytes
+            ),
+            (2, 600),
+            "expected the parse errors to be summed over the agents"
+        );
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/firewall-rule/{}/stats", active))
+            .to_request();
+        let data: FirewallRuleStats = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(data.traffic.dropped_packets, 42);
+        assert_eq!(data.agents.len(), 2);
+        assert_eq!(data.agents[0].agent, "edge-1");
+        assert_eq!(data.agents[0].traffic.dropped_bytes, 4000);
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/firewall-rule/{}/stats", dead))
+            .to_request();
+        let data: FirewallRuleStats = test::call_and_read_body_json(&app, request).await;
+        assert!(data.agents.is_empty());
+
+        let request = test::TestRequest::get()
+            .uri("/firewall-rule/removed/stats")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let request = test::TestRequest::post()
+            .uri("/firewall/stats")
+            .set_json(json!({ "agent": " ", "interface": "" }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
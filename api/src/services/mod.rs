@@ -1,4 +1,44 @@
+pub mod agent;
+pub mod bulk;
 pub mod command_execution;
+pub mod firewall_ban;
 pub mod firewall_log;
 pub mod firewall_rule;
+pub mod firewall_stats;
 pub mod ping;
+
+#[cfg(test)]
+pub(crate) mod test_util {
+    use crate::ban::BanEngine;
+    use crate::config::{BanConfig, DatabaServerConfig};
+    use crate::db::Db;
+    use crate::rule_version::RuleVersion;
+    use crate::AppState;
+    use actix_web::web;
+    use std::sync::Arc;
+
+    /// State backed by a fresh in-memory database.
+    pub async fn app_state() -> web::Data<AppState> {
+        let config = DatabaServerConfig {
+            address: "mem://".to_string(),
+            username: String::new(),
+            password: String::new(),
+            namespace: "arise_test".to_string(),
+            database: "arise".to_string(),
+        };
+        let db = Db::new(Arc::new(config)).await;
+        assert!(db.is_ok(), "{:?}", db.err());
+        let db: Arc<Db> = Arc::new(db.unwrap());
+        let rule_version: Arc<RuleVersion> = Arc::new(RuleVersion::new());
+        let bans: Arc<BanEngine> = Arc::new(BanEngine::new(
+            db.clone(),
+            rule_version.clone(),
+            &BanConfig::default(),
+        ));
+        web::Data::new(AppState {
+            db,
+            rule_version,
+            bans,
+        })
+    }
+}
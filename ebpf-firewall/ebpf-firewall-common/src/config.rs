@@ -0,0 +1,14 @@
+/// Settings pushed by the agent for each interface, keyed by its index in
+/// `FIREWALL_CONFIG`. `default_action` applies to packets not matched by any rule. One in
+/// `log_sample_rate` dropped packets is logged on its own, none when 0. Tunnel
+/// packets are also matched by the packet they carry when `decapsulate` is 1.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct FirewallConfig {
+    pub default_action: u8,
+    pub decapsulate: u8,
+    pub _padding: [u8; 2],
+    pub log_sample_rate: u32,
+}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for FirewallConfig {}
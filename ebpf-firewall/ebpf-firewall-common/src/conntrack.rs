@@ -0,0 +1,67 @@
+use core::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use crate::protocol::IpProtocol;
+
+/// A flow as seen from this host, the key of `FIREWALL_CONNTRACK`. IPv4 addresses
+/// are stored in the first 4 octets. ICMP echo flows use the echo identifier as
+/// `local_port` and 0 as `remote_port`, protocols without ports use 0 for both.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ConntrackKey {
+    pub local_ip: [u8; 16],
+    pub remote_ip: [u8; 16],
+    pub local_port: u16,
+    pub remote_port: u16,
+    pub protocol: u8,
+    pub ip_version: u8,
+    pub _padding: [u8; 2],
+}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for ConntrackKey {}
+
+/// `last_seen` is the `bpf_ktime_get_ns` time of the latest packet of the flow, in
+/// `CLOCK_MONOTONIC` nanoseconds.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ConntrackEntry {
+    pub last_seen: u64,
+    pub packets_out: u64,
+    pub packets_in: u64,
+}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for ConntrackEntry {}
+
+impl ConntrackKey {
+    fn address(&self, ip: [u8; 16]) -> IpAddr {
+        if self.ip_version == 6 {
+            IpAddr::V6(Ipv6Addr::from(ip))
+        } else {
+            IpAddr::V4(Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]))
+        }
+    }
+
+    pub fn local_address(&self) -> IpAddr {
+        self.address(self.local_ip)
+    }
+
+    pub fn remote_address(&self) -> IpAddr {
+        self.address(self.remote_ip)
+    }
+}
+
+impl fmt::Display for ConntrackKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} port {} -> {} port {}",
+            IpProtocol::from_number(self.protocol),
+            self.local_address(),
+            self.local_port,
+            self.remote_address(),
+            self.remote_port
+        )
+    }
+}
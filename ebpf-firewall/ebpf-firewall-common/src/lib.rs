@@ -1 +1,62 @@
 #![no_std]
+
+pub mod config;
+pub mod conntrack;
+pub mod log;
+pub mod parse;
+pub mod protocol;
+pub mod rule;
+pub mod stats;
+
+/// Maximum number of rules sharing the same prefix, evaluated in priority order.
+pub const MAX_RULES_PER_CHAIN: usize = 16;
+/// Maximum number of distinct prefixes per address family.
+pub const MAX_RULE_CHAINS: u32 = 1024;
+/// Maximum number of nested prefixes whose chains are evaluated for a packet, the
+/// chain of the longest prefix containing the address then those of its parents.
+pub const MAX_RULE_CHAIN_DEPTH: usize = 8;
+/// `RuleChain::parent` of the chains without a containing prefix.
+pub const NO_PARENT_CHAIN: u32 = u32::MAX;
+/// Rule id of the packets handled by the default action, rule ids start at 1.
+pub const DEFAULT_RULE_ID: u32 = 0;
+/// Maximum number of sources with drop counters, the least recently dropped are
+/// evicted first.
+pub const MAX_DROP_ENTRIES: u32 = 16384;
+/// Maximum number of rules with traffic counters, the least recently matched are
+/// evicted first.
+pub const MAX_RULE_STATS: u32 = 16384;
+/// Maximum number of source prefixes with a rate limit state, the least recently
+/// seen are evicted first.
+pub const MAX_RATE_LIMIT_ENTRIES: u32 = 65536;
+/// `Rule::action` values. Filter rules pass or drop by `status`, rate limit rules
+/// drop the traffic of a source prefix beyond the limit and SYN flood rules only
+/// limit the TCP packets opening a connection.
+pub const RULE_ACTION_FILTER: u8 = 0;
+pub const RULE_ACTION_RATE_LIMIT: u8 = 1;
+pub const RULE_ACTION_SYN_FLOOD: u8 = 2;
+/// `Rule::protocol` of the rules matching every protocol, 255 is reserved by IANA.
+pub const RULE_PROTOCOL_ANY: u8 = 255;
+/// Maximum number of interfaces with settings in `FIREWALL_CONFIG`.
+pub const MAX_INTERFACES: u32 = 256;
+/// Maximum number of exempted prefixes per address family, over all the interfaces.
+pub const MAX_EXEMPTIONS: u32 = 1024;
+/// `FirewallConfig::default_action` values, a zeroed config allows traffic.
+pub const DEFAULT_ACTION_ALLOW: u8 = 0;
+pub const DEFAULT_ACTION_DENY: u8 = 1;
+/// `Rule::connection_state` values, `NEW` matches packets without a tracked flow
+/// and `ESTABLISHED` replies to (or errors related to) flows initiated locally.
+pub const CONNECTION_STATE_ANY: u8 = 0;
+pub const CONNECTION_STATE_NEW: u8 = 1;
+pub const CONNECTION_STATE_ESTABLISHED: u8 = 2;
+/// Maximum number of flows tracked, the least recently used are evicted first.
+pub const MAX_CONNTRACK_ENTRIES: u32 = 65536;
+/// Idle time after which a tracked flow no longer matches as established.
+pub const CONNTRACK_TCP_TIMEOUT_NS: u64 = 3600 * 1_000_000_000;
+pub const CONNTRACK_UDP_TIMEOUT_NS: u64 = 120 * 1_000_000_000;
+pub const CONNTRACK_OTHER_TIMEOUT_NS: u64 = 30 * 1_000_000_000;
+/// Index in `FIREWALL_INGRESS_PROGRAMS` and `FIREWALL_EGRESS_PROGRAMS` of the program
+/// evaluating the packets carried by tunnels.
+pub const PROGRAM_TUNNEL: u32 = 0;
+/// Size of the ring buffers carrying events to the agent, a power of 2 multiple of
+/// the page size.
+pub const LOG_RING_BUF_SIZE: u32 = 1 << 20;
@@ -0,0 +1,77 @@
+/// A logged packet, `ip` and `port` are the remote end, the source of ingress and
+/// the destination of egress packets. IPv4 addresses are stored in the first 4
+/// octets. `timestamp` is the `bpf_ktime_get_ns` time, in `CLOCK_MONOTONIC`
+/// nanoseconds, `length` the size of the frame and `ifindex` the receiving or
+/// sending interface. ICMP packets have no ports, their type and code are set
+/// instead.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "user", derive(bytemuck::Pod, bytemuck::Zeroable))]
+pub struct FirewallLog {
+    pub ip: [u8; 16],
+    pub destination_ip: [u8; 16],
+    pub timestamp: u64,
+    pub rule_id: u32,
+    pub length: u32,
+    pub ifindex: u32,
+    pub port: u16,
+    pub destination_port: u16,
+    pub protocol: u8,
+    pub status: u8,
+    pub ip_version: u8,
+    pub direction: u8,
+    pub icmp_type: u8,
+    pub icmp_code: u8,
+    pub _padding: [u8; 2],
+}
+
+impl FirewallLog {
+    /// Remote address octets, 4 for IPv4 and 16 for IPv6.
+    pub fn source_ip(&self) -> &[u8] {
+        octets(&self.ip, self.ip_version)
+    }
+
+    pub fn destination_ip(&self) -> &[u8] {
+        octets(&self.destination_ip, self.ip_version)
+    }
+}
+
+/// Remote address, protocol, rule and direction the dropped packets are counted
+/// for in `FIREWALL_DROPS`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DropKey {
+    pub ip: [u8; 16],
+    pub rule_id: u32,
+    pub protocol: u8,
+    pub ip_version: u8,
+    pub direction: u8,
+    pub _padding: [u8; 1],
+}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for DropKey {}
+
+impl DropKey {
+    /// Remote address octets, 4 for IPv4 and 16 for IPv6.
+    pub fn source_ip(&self) -> &[u8] {
+        octets(&self.ip, self.ip_version)
+    }
+}
+
+/// Packets and bytes dropped for a `DropKey`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DropCount {
+    pub packets: u64,
+    pub bytes: u64,
+}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for DropCount {}
+
+fn octets(ip: &[u8; 16], ip_version: u8) -> &[u8] {
+    if ip_version == 6 {
+        ip
+    } else {
+        &ip[..4]
+    }
+}
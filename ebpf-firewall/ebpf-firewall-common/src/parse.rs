@@ -0,0 +1,1086 @@
+//! Ethernet, VLAN, IP and transport header parsing shared by the eBPF programs and
+//! the userspace tests. The packet is read through `PacketBytes`, every read is
+//! bounds checked and every loop bounded so the verifier accepts the programs.
+//! The header parsers are inlined and the offsets past the variable length headers
+//! go through `PacketBytes::opaque`, keeping the verified instructions in bounds.
+//! GRE, VXLAN and Geneve packets are optionally decapsulated, a single level deep,
+//! once the outer packet has been evaluated.
+
+use crate::CONNECTION_STATE_NEW;
+
+pub const ETH_HDR_LEN: usize = 14;
+pub const VLAN_HDR_LEN: usize = 4;
+pub const IPV4_HDR_MIN_LEN: usize = 20;
+pub const IPV6_HDR_LEN: usize = 40;
+pub const IPV6_FRAG_HDR_LEN: usize = 8;
+pub const UDP_HDR_LEN: usize = 8;
+/// GRE header without the optional checksum, key and sequence number fields.
+pub const GRE_HDR_LEN: usize = 4;
+pub const VXLAN_HDR_LEN: usize = 8;
+/// Geneve header without its variable length options.
+pub const GENEVE_HDR_LEN: usize = 8;
+
+pub const ETHER_TYPE_IPV4: u16 = 0x0800;
+pub const ETHER_TYPE_IPV6: u16 = 0x86dd;
+/// 802.1Q customer and 802.1ad (QinQ) service tags.
+pub const ETHER_TYPE_VLAN: u16 = 0x8100;
+pub const ETHER_TYPE_QINQ: u16 = 0x88a8;
+/// Ethernet frames carried by GRE and Geneve.
+pub const ETHER_TYPE_TEB: u16 = 0x6558;
+
+pub const IP_PROTO_HOPOPT: u8 = 0;
+pub const IP_PROTO_ICMP: u8 = 1;
+pub const IP_PROTO_TCP: u8 = 6;
+pub const IP_PROTO_UDP: u8 = 17;
+pub const IP_PROTO_IPV6_ROUTE: u8 = 43;
+pub const IP_PROTO_IPV6_FRAG: u8 = 44;
+pub const IP_PROTO_GRE: u8 = 47;
+pub const IP_PROTO_AH: u8 = 51;
+pub const IP_PROTO_IPV6_ICMP: u8 = 58;
+pub const IP_PROTO_IPV6_OPTS: u8 = 60;
+
+pub const VXLAN_PORT: u16 = 4789;
+pub const GENEVE_PORT: u16 = 6081;
+
+/// Upper bound on the VLAN tags skipped, a QinQ frame carries two.
+pub const MAX_VLAN_TAGS: usize = 2;
+
+/// VLAN ID of untagged frames, outside of the 12 bits of a tag.
+const NO_VLAN: u16 = u16::MAX;
+
+/// Headers are only looked for in the first 4 KiB of a packet, bounding their
+/// offsets for the verifier.
+pub const MAX_HEADER_OFFSET: usize = 4096;
+
+/// Upper bound on the IPv6 extension headers walked before giving up on
+/// finding the transport header, the verifier needs the loop to be bounded.
+pub const MAX_IPV6_EXT_HDRS: usize = 8;
+
+const TCP_FLAG_SYN: u8 = 0x02;
+const TCP_FLAG_ACK: u8 = 0x10;
+
+/// GRE checksum, key and sequence number present flags, each adds 4 bytes.
+const GRE_OPTIONAL_FIELDS: [u16; 3] = [0x8000, 0x2000, 0x1000];
+/// The legacy routing flag and the version, only version 0 without routing is
+/// decapsulated.
+const GRE_ROUTING_AND_VERSION: u16 = 0x4007;
+
+/// Why a packet could not be parsed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// A header extends beyond the end of the packet.
+    Truncated,
+    /// The IPv4 header length is below the minimum of 20 bytes.
+    InvalidHeaderLength,
+    /// The frame carries more than `MAX_VLAN_TAGS` VLAN tags.
+    TooManyVlanTags,
+    /// The IPv6 header chain has more than `MAX_IPV6_EXT_HDRS` extension headers.
+    TooManyExtensionHeaders,
+}
+
+/// Read access to the bytes of a packet.
+pub trait PacketBytes {
+    /// The `N` bytes at `offset`, `None` when they are beyond the end of the packet.
+    fn read<const N: usize>(&self, offset: usize) -> Option<[u8; N]>;
+
+    /// `offset` through a value the verifier cannot follow, the eBPF programs
+    /// round-trip it through a map.
+    fn opaque(&self, offset: usize) -> usize {
+        offset
+    }
+}
+
+impl PacketBytes for [u8] {
+    fn read<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        self.get(offset..offset.checked_add(N)?)?.try_into().ok()
+    }
+}
+
+/// Addresses and ports of a parsed packet, IPv4 addresses are stored in the
+/// first 4 octets. Ports are `None` for ICMP and non-first fragments, the ICMP
+/// type, code and echo identifier are only set for ICMP. `transport_offset` is the
+/// offset of the header after the IP headers. `vlan_id` is the ID of the
+/// outermost VLAN tag, `first_fragment` is false for the IP fragments past the
+/// first. `connection_state` is left for the programs to fill in.
+///
+/// The optional headers are stored as plain values with a presence flag: the
+/// payload of a `None` is left uninitialized by LLVM, and the verifier rejects
+/// the programs reading it when the packet is copied or spilled to the stack.
+pub struct Packet {
+    pub protocol: u8,
+    pub ip_version: u8,
+    pub source_ip: [u8; 16],
+    pub destination_ip: [u8; 16],
+    pub transport_offset: usize,
+    pub connection_state: u8,
+    /// TCP SYN without ACK, opening a connection.
+    pub tcp_syn: bool,
+    pub first_fragment: bool,
+    source_port: u16,
+    destination_port: u16,
+    has_ports: bool,
+    icmp_type: u8,
+    icmp_code: u8,
+    icmp_id: u16,
+    has_icmp: bool,
+    vlan_id: u16,
+}
+
+impl Packet {
+    pub fn source_port(&self) -> Option<u16> {
+        self.has_ports.then_some(self.source_port)
+    }
+
+    pub fn destination_port(&self) -> Option<u16> {
+        self.has_ports.then_some(self.destination_port)
+    }
+
+    pub fn icmp_type(&self) -> Option<u8> {
+        self.has_icmp.then_some(self.icmp_type)
+    }
+
+    pub fn icmp_code(&self) -> Option<u8> {
+        self.has_icmp.then_some(self.icmp_code)
+    }
+
+    pub fn icmp_id(&self) -> Option<u16> {
+        self.has_icmp.then_some(self.icmp_id)
+    }
+
+    pub fn vlan_id(&self) -> Option<u16> {
+        (self.vlan_id != NO_VLAN).then_some(self.vlan_id)
+    }
+
+    /// Swap the source and the destination, giving the packet as seen from the
+    /// other end.
+    pub fn reverse(&mut self) {
+        core::mem::swap(&mut self.source_ip, &mut self.destination_ip);
+        core::mem::swap(&mut self.source_port, &mut self.destination_port);
+    }
+}
+
+fn read<const N: usize, B: PacketBytes + ?Sized>(
+    bytes: &B,
+    offset: usize,
+) -> Result<[u8; N], ParseError> {
+    bytes.read(offset).ok_or(ParseError::Truncated)
+}
+
+/// `offset` past a variable length header, the headers after `MAX_HEADER_OFFSET`
+/// are not looked at.
+fn header_offset<B: PacketBytes + ?Sized>(bytes: &B, offset: usize) -> Result<usize, ParseError> {
+    let offset: usize = bytes.opaque(offset);
+    if offset > MAX_HEADER_OFFSET {
+        return Err(ParseError::Truncated);
+    }
+    Ok(offset)
+}
+
+fn read_u8<B: PacketBytes + ?Sized>(bytes: &B, offset: usize) -> Result<u8, ParseError> {
+    read::<1, B>(bytes, offset).map(|value| value[0])
+}
+
+fn read_u16<B: PacketBytes + ?Sized>(bytes: &B, offset: usize) -> Result<u16, ParseError> {
+    read::<2, B>(bytes, offset).map(u16::from_be_bytes)
+}
+
+/// Skip the VLAN tags after the Ethernet addresses of the frame at `offset`.
+/// Returns the EtherType of the payload, its offset and the ID of the first tag,
+/// `NO_VLAN` for untagged frames. Frames with more tags than `MAX_VLAN_TAGS` are
+/// an error, their payload is never looked at.
+fn parse_ethernet<B: PacketBytes + ?Sized>(
+    bytes: &B,
+    offset: usize,
+) -> Result<(u16, usize, u16), ParseError> {
+    let mut ether_type: u16 = read_u16(bytes, offset + ETH_HDR_LEN - 2)?;
+    let mut offset: usize = offset + ETH_HDR_LEN;
+    let mut vlan_id: u16 = NO_VLAN;
+    for index in 0..MAX_VLAN_TAGS {
+        if ether_type != ETHER_TYPE_VLAN && ether_type != ETHER_TYPE_QINQ {
+            break;
+        }
+        if index == 0 {
+            vlan_id = read_u16(bytes, offset)? & 0x0fff;
+        }
+        ether_type = read_u16(bytes, offset + 2)?;
+        offset += VLAN_HDR_LEN;
+    }
+    if ether_type == ETHER_TYPE_VLAN || ether_type == ETHER_TYPE_QINQ {
+        return Err(ParseError::TooManyVlanTags);
+    }
+    Ok((ether_type, offset, vlan_id))
+}
+
+/// Parse the IP and transport headers, `None` for non IP packets. Tunnels are
+/// parsed as they are, see `decapsulate` for the packet they carry.
+pub fn parse_packet<B: PacketBytes + ?Sized>(bytes: &B) -> Result<Option<Packet>, ParseError> {
+    let (ether_type, offset, vlan_id) = parse_ethernet(bytes, 0)?;
+    let Some(mut packet) = parse_ip(bytes, ether_type, offset)? else {
+        return Ok(None);
+    };
+    packet.vlan_id = vlan_id;
+    Ok(Some(packet))
+}
+
+/// Replace the GRE, VXLAN or Geneve `packet` with the packet it carries, the VLAN
+/// ID stays the one of the outer frame. False for other packets and for a tunnel
+/// payload that cannot be parsed, as sent to a tunnel port by other traffic,
+/// leaving `packet` as it is.
+pub fn decapsulate<B: PacketBytes + ?Sized>(bytes: &B, packet: &mut Packet) -> bool {
+    let Ok(Some((ether_type, offset))) = tunnel_payload(bytes, packet) else {
+        return false;
+    };
+    let Ok(Some(mut inner)) = parse_ip(bytes, ether_type, offset) else {
+        return false;
+    };
+    inner.vlan_id = packet.vlan_id;
+    *packet = inner;
+    true
+}
+
+/// Status of `packet` by `status`, then with `decapsulate` by `tunnel_status`, see
+/// `evaluate_tunnel`. The outer packet is evaluated first, a denied one is dropped
+/// whatever it carries, so the rules of the tunnel endpoints are not bypassed by
+/// the inner source.
+pub fn evaluate<E>(
+    packet: &mut Packet,
+    decapsulate: bool,
+    status: impl FnOnce(&mut Packet) -> Result<bool, E>,
+    tunnel_status: impl FnOnce(&mut Packet) -> Result<bool, E>,
+) -> Result<bool, E> {
+    if !status(packet)? {
+        return Ok(false);
+    }
+    if decapsulate {
+        return tunnel_status(packet);
+    }
+    Ok(true)
+}
+
+/// Status by `status` of the packet `decapsulate` replaces the allowed `packet`
+/// with, allowed when it is not a tunnel.
+pub fn evaluate_tunnel<E>(
+    packet: &mut Packet,
+    decapsulate: impl FnOnce(&mut Packet) -> bool,
+    status: impl FnOnce(&mut Packet) -> Result<bool, E>,
+) -> Result<bool, E> {
+    if !decapsulate(packet) {
+        return Ok(true);
+    }
+    status(packet)
+}
+
+#[inline(always)]
+fn parse_ip<B: PacketBytes + ?Sized>(
+    bytes: &B,
+    ether_type: u16,
+    offset: usize,
+) -> Result<Option<Packet>, ParseError> {
+    match ether_type {
+        ETHER_TYPE_IPV4 => parse_ipv4(bytes, offset).map(Some),
+        ETHER_TYPE_IPV6 => parse_ipv6(bytes, offset).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// EtherType and offset of the payload of a GRE, VXLAN or Geneve packet, `None`
+/// for other packets. Ethernet payloads are skipped to the EtherType they carry.
+fn tunnel_payload<B: PacketBytes + ?Sized>(
+    bytes: &B,
+    packet: &Packet,
+) -> Result<Option<(u16, usize)>, ParseError> {
+    if !packet.first_fragment {
+        return Ok(None);
+    }
+    // Bounded again, the packet may have been read back from a map.
+    let offset: usize = header_offset(bytes, packet.transport_offset)?;
+    let (protocol_type, payload): (u16, usize) = if packet.protocol == IP_PROTO_GRE {
+        let flags: u16 = read_u16(bytes, offset)?;
+        if flags & GRE_ROUTING_AND_VERSION != 0 {
+            return Ok(None);
+        }
+        let mut header_len: usize = GRE_HDR_LEN;
+        for flag in GRE_OPTIONAL_FIELDS {
+            if flags & flag != 0 {
+                header_len += 4;
+            }
+        }
+        (read_u16(bytes, offset + 2)?, offset + header_len)
+    } else if packet.protocol != IP_PROTO_UDP {
+        return Ok(None);
+    } else if packet.destination_port() == Some(VXLAN_PORT) {
+        (ETHER_TYPE_TEB, offset + UDP_HDR_LEN + VXLAN_HDR_LEN)
+    } else if packet.destination_port() == Some(GENEVE_PORT) {
+        let header: [u8; 4] = read(bytes, offset + UDP_HDR_LEN)?;
+        let options_len: usize = (header[0] & 0x3f) as usize * 4;
+        (
+            u16::from_be_bytes([header[2], header[3]]),
+            offset + UDP_HDR_LEN + GENEVE_HDR_LEN + options_len,
+        )
+    } else {
+        return Ok(None);
+    };
+    let (ether_type, offset): (u16, usize) = if protocol_type == ETHER_TYPE_TEB {
+        let (ether_type, offset, _) = parse_ethernet(bytes, payload)?;
+        (ether_type, offset)
+    } else {
+        (protocol_type, payload)
+    };
+    Ok(Some((ether_type, header_offset(bytes, offset)?)))
+}
+
+fn new_packet(protocol: u8, ip_version: u8, transport_offset: usize) -> Packet {
+    Packet {
+        protocol,
+        ip_version,
+        source_ip: [0; 16],
+        destination_ip: [0; 16],
+        transport_offset,
+        connection_state: CONNECTION_STATE_NEW,
+        tcp_syn: false,
+        first_fragment: true,
+        source_port: 0,
+        destination_port: 0,
+        has_ports: false,
+        icmp_type: 0,
+        icmp_code: 0,
+        icmp_id: 0,
+        has_icmp: false,
+        vlan_id: NO_VLAN,
+    }
+}
+
+/// Read the ports, the TCP flags and the ICMP type, code and echo identifier.
+fn parse_transport<B: PacketBytes + ?Sized>(
+    bytes: &B,
+    packet: &mut Packet,
+) -> Result<(), ParseError> {
+    let offset: usize = packet.transport_offset;
+    if packet.protocol == IP_PROTO_TCP || packet.protocol == IP_PROTO_UDP {
+        packet.source_port = read_u16(bytes, offset)?;
+        packet.destination_port = read_u16(bytes, offset + 2)?;
+        packet.has_ports = true;
+        if packet.protocol == IP_PROTO_TCP {
+            let flags: u8 = read_u8(bytes, offset + 13)?;
+            packet.tcp_syn = flags & TCP_FLAG_SYN != 0 && flags & TCP_FLAG_ACK == 0;
+        }
+    } else if packet.protocol == IP_PROTO_ICMP || packet.protocol == IP_PROTO_IPV6_ICMP {
+        let [icmp_type, icmp_code]: [u8; 2] = read(bytes, offset)?;
+        packet.icmp_type = icmp_type;
+        packet.icmp_code = icmp_code;
+        packet.icmp_id = read_u16(bytes, offset + 4)?;
+        packet.has_icmp = true;
+    }
+    Ok(())
+}
+
+/// The header length comes from IHL, options are skipped. Only the first
+/// fragment carries the transport header.
+#[inline(always)]
+fn parse_ipv4<B: PacketBytes + ?Sized>(bytes: &B, offset: usize) -> Result<Packet, ParseError> {
+    let header: [u8; IPV4_HDR_MIN_LEN] = read(bytes, offset)?;
+    let header_len: usize = (header[0] & 0x0f) as usize * 4;
+    if header_len < IPV4_HDR_MIN_LEN {
+        return Err(ParseError::InvalidHeaderLength);
+    }
+    let mut packet: Packet = new_packet(header[9], 4, offset + header_len);
+    packet.source_ip[..4].copy_from_slice(&header[12..16]);
+    packet.destination_ip[..4].copy_from_slice(&header[16..20]);
+    packet.first_fragment = u16::from_be_bytes([header[6], header[7]]) & 0x1fff == 0;
+    if packet.first_fragment {
+        parse_transport(bytes, &mut packet)?;
+    }
+    Ok(packet)
+}
+
+/// Extension headers with the length in 8-byte units after the first 8 bytes.
+#[inline(always)]
+fn is_options_hdr(next_hdr: u8) -> bool {
+    next_hdr == IP_PROTO_HOPOPT || next_hdr == IP_PROTO_IPV6_ROUTE || next_hdr == IP_PROTO_IPV6_OPTS
+}
+
+/// Walk the IPv6 extension header chain from `offset` until the upper-layer
+/// header is found. Returns the upper-layer protocol, its offset and whether the
+/// packet is the first fragment (only the first fragment carries the transport
+/// header). A chain still going after `MAX_IPV6_EXT_HDRS` headers is an error.
+#[inline(always)]
+fn ipv6_upper_layer<B: PacketBytes + ?Sized>(
+    bytes: &B,
+    next_hdr: u8,
+    offset: usize,
+) -> Result<(u8, usize, bool), ParseError> {
+    let mut next_hdr: u8 = next_hdr;
+    let mut offset: usize = offset;
+    let mut first_fragment: bool = true;
+    for _ in 0..MAX_IPV6_EXT_HDRS {
+        if is_options_hdr(next_hdr) {
+            let ext_hdr: [u8; 2] = read(bytes, offset)?;
+            next_hdr = ext_hdr[0];
+            offset += (ext_hdr[1] as usize + 1) * 8;
+        } else if next_hdr == IP_PROTO_AH {
+            let ext_hdr: [u8; 2] = read(bytes, offset)?;
+            next_hdr = ext_hdr[0];
+            offset += (ext_hdr[1] as usize + 2) * 4;
+        } else if next_hdr == IP_PROTO_IPV6_FRAG {
+            let frag_hdr: [u8; 4] = read(bytes, offset)?;
+            next_hdr = frag_hdr[0];
+            if u16::from_be_bytes([frag_hdr[2], frag_hdr[3]]) & 0xfff8 != 0 {
+                first_fragment = false;
+            }
+            offset += IPV6_FRAG_HDR_LEN;
+        } else {
+            break;
+        }
+        offset = header_offset(bytes, offset)?;
+    }
+    if is_options_hdr(next_hdr) || next_hdr == IP_PROTO_AH || next_hdr == IP_PROTO_IPV6_FRAG {
+        return Err(ParseError::TooManyExtensionHeaders);
+    }
+    Ok((next_hdr, offset, first_fragment))
+}
+
+/// ICMPv6 is reported as ICMP, so it is matched by the same `Icmp` rules.
+#[inline(always)]
+fn parse_ipv6<B: PacketBytes + ?Sized>(bytes: &B, offset: usize) -> Result<Packet, ParseError> {
+    let next_hdr: u8 = read_u8(bytes, offset + 6)?;
+    let source_ip: [u8; 16] = read(bytes, offset + 8)?;
+    let destination_ip: [u8; 16] = read(bytes, offset + 24)?;
+    let (next_hdr, transport_offset, first_fragment) =
+        ipv6_upper_layer(bytes, next_hdr, offset + IPV6_HDR_LEN)?;
+    let mut packet: Packet = new_packet(next_hdr, 6, transport_offset);
+    packet.source_ip = source_ip;
+    packet.destination_ip = destination_ip;
+    packet.first_fragment = first_fragment;
+    if first_fragment {
+        parse_transport(bytes, &mut packet)?;
+    }
+    if next_hdr == IP_PROTO_IPV6_ICMP {
+        packet.protocol = IP_PROTO_ICMP;
+    }
+    Ok(packet)
+}
+
+#[cfg(test)]
+mod test_parse {
+    extern crate std;
+
+    use std::{cell::RefCell, vec::Vec};
+
+    use super::*;
+    use crate::rule::Rule;
+
+    const SOURCE_V4: [u8; 4] = [10, 0, 0, 1];
+    const DESTINATION_V4: [u8; 4] = [10, 0, 0, 254];
+    const INNER_SOURCE_V4: [u8; 4] = [192, 168, 0, 1];
+
+    fn destination_port_rule(port: u16) -> Rule {
+        Rule {
+            destination_from_port: port,
+            has_destination_from_port: 1,
+            ..Rule::default()
+        }
+    }
+
+    fn ethernet(tags: &[u16], ether_type: u16) -> Vec<u8> {
+        let mut frame: Vec<u8> = Vec::new();
+        frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1]);
+        for tag in tags {
+            frame.extend_from_slice(&tag.to_be_bytes());
+            frame.extend_from_slice(&[0x00, 0x64]);
+        }
+        frame.extend_from_slice(&ether_type.to_be_bytes());
+        frame
+    }
+
+    fn ipv4(protocol: u8, options: usize, fragment_offset: u16) -> Vec<u8> {
+        let mut header: Vec<u8> = Vec::new();
+        header.push(0x40 | (5 + options / 4) as u8);
+        header.extend_from_slice(&[0, 0, 0, 0, 0]);
+        header.extend_from_slice(&fragment_offset.to_be_bytes());
+        header.extend_from_slice(&[64, protocol, 0, 0]);
+        header.extend_from_slice(&SOURCE_V4);
+        header.extend_from_slice(&DESTINATION_V4);
+        // Record Route and End of Options List padding.
+        header.extend((0..options).map(|index| if index == 0 { 7 } else { 0 }));
+        header
+    }
+
+    fn ipv6(next_hdr: u8) -> Vec<u8> {
+        let mut header: Vec<u8> = std::vec![0x60, 0, 0, 0, 0, 0, next_hdr, 64];
+        header.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        header.extend_from_slice(&[0; 11]);
+        header.push(1);
+        header.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        header.extend_from_slice(&[0; 11]);
+        header.push(2);
+        header
+    }
+
+    fn tcp(source_port: u16, destination_port: u16, flags: u8) -> Vec<u8> {
+        let mut header: Vec<u8> = Vec::new();
+        header.extend_from_slice(&source_port.to_be_bytes());
+        header.extend_from_slice(&destination_port.to_be_bytes());
+        header.extend_from_slice(&[0; 9]);
+        header.push(flags);
+        header.extend_from_slice(&[0; 6]);
+        header
+    }
+
+    fn udp(source_port: u16, destination_port: u16) -> Vec<u8> {
+        let mut header: Vec<u8> = Vec::new();
+        header.extend_from_slice(&source_port.to_be_bytes());
+        header.extend_from_slice(&destination_port.to_be_bytes());
+        header.extend_from_slice(&[0, 8, 0, 0]);
+        header
+    }
+
+    fn icmp_echo(icmp_type: u8, id: u16) -> Vec<u8> {
+        let mut header: Vec<u8> = std::vec![icmp_type, 0, 0, 0];
+        header.extend_from_slice(&id.to_be_bytes());
+        header.extend_from_slice(&[0, 1]);
+        header
+    }
+
+    fn fixture(parts: &[Vec<u8>]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    fn parse(frame: &[u8]) -> Packet {
+        parse_packet(frame).unwrap().unwrap()
+    }
+
+    /// The packet carried by the tunnel of `frame`, the outer packet when there is none.
+    fn decapsulated(frame: &[u8]) -> Packet {
+        let mut packet: Packet = parse(frame);
+        decapsulate(frame, &mut packet);
+        packet
+    }
+
+    /// The IPv4 header of a packet carried by a tunnel.
+    fn inner_ipv4(protocol: u8) -> Vec<u8> {
+        let mut header: Vec<u8> = ipv4(protocol, 0, 0);
+        header[12..16].copy_from_slice(&INNER_SOURCE_V4);
+        header
+    }
+
+    #[test]
+    fn test_ipv4_tcp() {
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_TCP, 0, 0),
+            tcp(51000, 443, TCP_FLAG_SYN),
+        ]);
+        let packet: Packet = parse(&frame);
+        assert_eq!((packet.ip_version, packet.protocol), (4, IP_PROTO_TCP));
+        assert_eq!(packet.source_ip[..4], SOURCE_V4);
+        assert_eq!(packet.destination_ip[..4], DESTINATION_V4);
+        assert_eq!(
+            (packet.source_port(), packet.destination_port()),
+            (Some(51000), Some(443))
+        );
+        assert_eq!(packet.transport_offset, ETH_HDR_LEN + IPV4_HDR_MIN_LEN);
+        assert!(packet.tcp_syn);
+
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_TCP, 0, 0),
+            tcp(51000, 443, TCP_FLAG_SYN | TCP_FLAG_ACK),
+        ]);
+        assert!(!parse(&frame).tcp_syn, "expected a SYN-ACK not to open");
+    }
+
+    #[test]
+    fn test_ipv4_udp() {
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_UDP, 0, 0),
+            udp(5353, 53),
+        ]);
+        let mut packet: Packet = parse(&frame);
+        assert_eq!(
+            (packet.source_port(), packet.destination_port()),
+            (Some(5353), Some(53)),
+            "expected the ports after the IPv4 header"
+        );
+
+        packet.reverse();
+        assert_eq!(packet.source_ip[..4], DESTINATION_V4);
+        assert_eq!(packet.destination_ip[..4], SOURCE_V4);
+        assert_eq!(
+            (packet.source_port(), packet.destination_port()),
+            (Some(53), Some(5353))
+        );
+    }
+
+    #[test]
+    fn test_ipv4_options() {
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_TCP, 8, 0),
+            tcp(51000, 22, 0),
+        ]);
+        let packet: Packet = parse(&frame);
+        assert_eq!(packet.transport_offset, ETH_HDR_LEN + IPV4_HDR_MIN_LEN + 8);
+        assert_eq!(
+            (packet.source_port(), packet.destination_port()),
+            (Some(51000), Some(22))
+        );
+
+        let mut frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_TCP, 0, 0),
+            tcp(51000, 22, 0),
+        ]);
+        frame[ETH_HDR_LEN] = 0x44;
+        assert_eq!(
+            parse_packet(frame.as_slice()).err(),
+            Some(ParseError::InvalidHeaderLength)
+        );
+    }
+
+    #[test]
+    fn test_ipv4_fragments() {
+        // More fragments set, offset 0: the first fragment carries the ports.
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_UDP, 0, 0x2000),
+            udp(5353, 53),
+        ]);
+        let packet: Packet = parse(&frame);
+        assert_eq!(packet.destination_port(), Some(53));
+        assert!(destination_port_rule(53).ports_match(&packet));
+
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_UDP, 0, 0x00b9),
+            std::vec![0xff; 4],
+        ]);
+        let packet: Packet = parse(&frame);
+        assert_eq!(packet.protocol, IP_PROTO_UDP);
+        assert_eq!(
+            (packet.source_port(), packet.destination_port()),
+            (None, None),
+            "expected no ports for a non-first fragment"
+        );
+        assert!(
+            !destination_port_rule(53).ports_match(&packet),
+            "expected a non-first fragment not to match a rule with ports"
+        );
+        assert!(Rule::default().ports_match(&packet));
+    }
+
+    #[test]
+    fn test_ipv4_icmp() {
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_ICMP, 0, 0),
+            icmp_echo(8, 0x1234),
+        ]);
+        let packet: Packet = parse(&frame);
+        assert_eq!(packet.protocol, IP_PROTO_ICMP);
+        assert_eq!((packet.icmp_type(), packet.icmp_code()), (Some(8), Some(0)));
+        assert_eq!(packet.icmp_id(), Some(0x1234));
+        assert_eq!(
+            (packet.source_port(), packet.destination_port()),
+            (None, None)
+        );
+
+        // Destination Unreachable, Fragmentation Needed.
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_ICMP, 0, 0),
+            std::vec![3, 4, 0, 0, 0, 0, 0x05, 0xdc],
+        ]);
+        let packet: Packet = parse(&frame);
+        assert_eq!((packet.icmp_type(), packet.icmp_code()), (Some(3), Some(4)));
+    }
+
+    #[test]
+    fn test_vlan() {
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[ETHER_TYPE_VLAN], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_UDP, 0, 0),
+            udp(5353, 53),
+        ]);
+        let packet: Packet = parse(&frame);
+        assert_eq!(packet.source_ip[..4], SOURCE_V4);
+        assert_eq!(packet.destination_port(), Some(53));
+        assert_eq!(packet.vlan_id(), Some(100));
+        assert_eq!(
+            packet.transport_offset,
+            ETH_HDR_LEN + VLAN_HDR_LEN + IPV4_HDR_MIN_LEN
+        );
+
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[ETHER_TYPE_QINQ, ETHER_TYPE_VLAN], ETHER_TYPE_IPV6),
+            ipv6(IP_PROTO_TCP),
+            tcp(51000, 443, TCP_FLAG_SYN),
+        ]);
+        let packet: Packet = parse(&frame);
+        assert_eq!(packet.ip_version, 6);
+        assert_eq!(packet.destination_port(), Some(443));
+
+        let frame: Vec<u8> = fixture(&[
+            ethernet(
+                &[ETHER_TYPE_QINQ, ETHER_TYPE_VLAN, ETHER_TYPE_VLAN],
+                ETHER_TYPE_IPV4,
+            ),
+            ipv4(IP_PROTO_UDP, 0, 0),
+        ]);
+        assert_eq!(
+            parse_packet(frame.as_slice()).err(),
+            Some(ParseError::TooManyVlanTags),
+            "expected more tags than MAX_VLAN_TAGS to be an error"
+        );
+    }
+
+    #[test]
+    fn test_gre() {
+        // Key present, IPv4 payload.
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_GRE, 0, 0),
+            std::vec![0x20, 0x00, 0x08, 0x00, 0, 0, 0, 1],
+            inner_ipv4(IP_PROTO_TCP),
+            tcp(51000, 22, TCP_FLAG_SYN),
+        ]);
+        let packet: Packet = decapsulated(&frame);
+        assert_eq!(packet.source_ip[..4], INNER_SOURCE_V4);
+        assert_eq!(packet.protocol, IP_PROTO_TCP);
+        assert_eq!(packet.destination_port(), Some(22));
+        assert!(packet.tcp_syn);
+
+        let packet: Packet = parse(&frame);
+        assert_eq!(packet.source_ip[..4], SOURCE_V4);
+        assert_eq!(
+            packet.protocol, IP_PROTO_GRE,
+            "expected the tunnel without decapsulation"
+        );
+
+        // Transparent Ethernet bridging, the inner frame is tagged.
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[ETHER_TYPE_VLAN], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_GRE, 0, 0),
+            std::vec![0x00, 0x00, 0x65, 0x58],
+            ethernet(&[ETHER_TYPE_VLAN], ETHER_TYPE_IPV6),
+            ipv6(IP_PROTO_UDP),
+            udp(5353, 53),
+        ]);
+        let packet: Packet = decapsulated(&frame);
+        assert_eq!(packet.ip_version, 6);
+        assert_eq!(packet.destination_port(), Some(53));
+        assert_eq!(packet.vlan_id(), Some(100));
+
+        // GRE version 1 (PPTP) is left alone.
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_GRE, 0, 0),
+            std::vec![0x00, 0x01, 0x88, 0x0b],
+        ]);
+        assert_eq!(decapsulated(&frame).protocol, IP_PROTO_GRE);
+    }
+
+    #[test]
+    fn test_vxlan() {
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_UDP, 0, 0),
+            udp(50000, VXLAN_PORT),
+            std::vec![0x08, 0, 0, 0, 0, 0, 0x64, 0],
+            ethernet(&[], ETHER_TYPE_IPV4),
+            inner_ipv4(IP_PROTO_UDP),
+            udp(5353, 53),
+        ]);
+        let packet: Packet = decapsulated(&frame);
+        assert_eq!(packet.source_ip[..4], INNER_SOURCE_V4);
+        assert_eq!(
+            (packet.source_port(), packet.destination_port()),
+            (Some(5353), Some(53))
+        );
+
+        let packet: Packet = parse(&frame);
+        assert_eq!(packet.destination_port(), Some(VXLAN_PORT));
+
+        let mut frame: Vec<u8> = frame;
+        frame.truncate(frame.len() - 10);
+        let packet: Packet = decapsulated(&frame);
+        assert_eq!(
+            packet.source_ip[..4],
+            SOURCE_V4,
+            "expected the outer packet when the inner one is truncated"
+        );
+        assert_eq!(
+            (packet.protocol, packet.destination_port()),
+            (IP_PROTO_UDP, Some(VXLAN_PORT))
+        );
+
+        // Other traffic to the VXLAN port, shorter than a VXLAN header.
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_UDP, 0, 0),
+            udp(50000, VXLAN_PORT),
+            std::vec![0xde, 0xad],
+        ]);
+        let packet: Packet = decapsulated(&frame);
+        assert_eq!(packet.source_ip[..4], SOURCE_V4);
+        assert_eq!(
+            (packet.source_port(), packet.destination_port()),
+            (Some(50000), Some(VXLAN_PORT)),
+            "expected a payload that is not a tunnel to leave the outer packet"
+        );
+    }
+
+    #[test]
+    fn test_evaluate_tunnel() {
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_UDP, 0, 0),
+            udp(50000, VXLAN_PORT),
+            std::vec![0x08, 0, 0, 0, 0, 0, 0x64, 0],
+            ethernet(&[], ETHER_TYPE_IPV4),
+            inner_ipv4(IP_PROTO_TCP),
+            tcp(51000, 22, TCP_FLAG_SYN),
+        ]);
+        // Drops the packets of `denied`, records the sources evaluated.
+        let evaluate_with = |denied: [u8; 4]| {
+            let mut packet: Packet = parse(&frame);
+            let sources: RefCell<Vec<[u8; 4]>> = RefCell::new(Vec::new());
+            let status = |packet: &mut Packet| -> Result<bool, ()> {
+                let source: [u8; 4] = packet.source_ip[..4].try_into().unwrap();
+                sources.borrow_mut().push(source);
+                Ok(source != denied)
+            };
+            let status: bool = evaluate(&mut packet, true, status, |packet| {
+                evaluate_tunnel(
+                    packet,
+                    |packet| decapsulate(frame.as_slice(), packet),
+                    status,
+                )
+            })
+            .unwrap();
+            (status, sources.into_inner())
+        };
+
+        assert_eq!(
+            evaluate_with(SOURCE_V4),
+            (false, std::vec![SOURCE_V4]),
+            "expected a denied tunnel endpoint to drop the packet whatever it carries"
+        );
+        assert_eq!(
+            evaluate_with(INNER_SOURCE_V4),
+            (false, std::vec![SOURCE_V4, INNER_SOURCE_V4])
+        );
+        assert_eq!(
+            evaluate_with([0; 4]),
+            (true, std::vec![SOURCE_V4, INNER_SOURCE_V4])
+        );
+
+        let mut frame: Vec<u8> = frame.clone();
+        frame.truncate(frame.len() - 30);
+        let mut packet: Packet = parse(&frame);
+        let status: Result<bool, ()> = evaluate_tunnel(
+            &mut packet,
+            |packet| decapsulate(frame.as_slice(), packet),
+            |_| Ok(false),
+        );
+        assert_eq!(
+            (status, packet.source_ip[..4].try_into().unwrap()),
+            (Ok(true), SOURCE_V4),
+            "expected the allowed outer packet when the payload is not a tunnel"
+        );
+
+        // Drops the packets to the VXLAN port, records the sources evaluated.
+        let evaluate_fragment = |fragment_offset: u16| {
+            let frame: Vec<u8> = fixture(&[
+                ethernet(&[], ETHER_TYPE_IPV4),
+                ipv4(IP_PROTO_UDP, 0, fragment_offset),
+                udp(50000, VXLAN_PORT),
+                std::vec![0x08, 0, 0, 0, 0, 0, 0x64, 0],
+                ethernet(&[], ETHER_TYPE_IPV4),
+                inner_ipv4(IP_PROTO_TCP),
+                tcp(51000, 22, TCP_FLAG_SYN),
+            ]);
+            let mut packet: Packet = parse(&frame);
+            let sources: RefCell<Vec<[u8; 4]>> = RefCell::new(Vec::new());
+            let status = |packet: &mut Packet| -> Result<bool, ()> {
+                sources
+                    .borrow_mut()
+                    .push(packet.source_ip[..4].try_into().unwrap());
+                Ok(!destination_port_rule(VXLAN_PORT).ports_match(packet))
+            };
+            let status: bool = evaluate(&mut packet, true, status, |packet| {
+                evaluate_tunnel(
+                    packet,
+                    |packet| decapsulate(frame.as_slice(), packet),
+                    status,
+                )
+            })
+            .unwrap();
+            (status, sources.into_inner())
+        };
+        assert_eq!(evaluate_fragment(0x2000), (false, std::vec![SOURCE_V4]));
+        assert_eq!(
+            evaluate_fragment(0x00b9),
+            (true, std::vec![SOURCE_V4]),
+            "expected a non-first fragment to skip the rules with ports and not be decapsulated"
+        );
+    }
+
+    #[test]
+    fn test_geneve() {
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_UDP, 0, 0),
+            udp(50000, GENEVE_PORT),
+            // One 4 byte option.
+            std::vec![0x01, 0, 0x65, 0x58, 0, 0, 0x64, 0],
+            std::vec![0x01, 0x02, 0x03, 0x00],
+            ethernet(&[], ETHER_TYPE_IPV6),
+            ipv6(IP_PROTO_TCP),
+            tcp(51000, 443, TCP_FLAG_SYN),
+        ]);
+        let packet: Packet = decapsulated(&frame);
+        assert_eq!(packet.ip_version, 6);
+        assert_eq!(packet.source_ip[15], 1);
+        assert_eq!(packet.destination_port(), Some(443));
+
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_UDP, 0, 0),
+            udp(50000, GENEVE_PORT),
+            std::vec![0x00, 0, 0x08, 0x00, 0, 0, 0x64, 0],
+            inner_ipv4(IP_PROTO_ICMP),
+            icmp_echo(8, 1),
+        ]);
+        let packet: Packet = decapsulated(&frame);
+        assert_eq!(packet.source_ip[..4], INNER_SOURCE_V4);
+        assert_eq!(packet.icmp_type(), Some(8));
+    }
+
+    #[test]
+    fn test_ipv6() {
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV6),
+            ipv6(IP_PROTO_UDP),
+            udp(5353, 53),
+        ]);
+        let packet: Packet = parse(&frame);
+        assert_eq!((packet.ip_version, packet.protocol), (6, IP_PROTO_UDP));
+        assert_eq!(packet.source_ip[15], 1);
+        assert_eq!(packet.destination_ip[15], 2);
+        assert_eq!(packet.destination_port(), Some(53));
+
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV6),
+            ipv6(IP_PROTO_IPV6_ICMP),
+            icmp_echo(128, 7),
+        ]);
+        let packet: Packet = parse(&frame);
+        assert_eq!(packet.protocol, IP_PROTO_ICMP);
+        assert_eq!((packet.icmp_type(), packet.icmp_id()), (Some(128), Some(7)));
+    }
+
+    #[test]
+    fn test_ipv6_extension_headers() {
+        let hop_by_hop: Vec<u8> = std::vec![IP_PROTO_IPV6_FRAG, 0, 1, 4, 0, 0, 0, 0];
+        let first_fragment: Vec<u8> = std::vec![IP_PROTO_TCP, 0, 0x00, 0x01, 0, 0, 0, 1];
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV6),
+            ipv6(IP_PROTO_HOPOPT),
+            hop_by_hop.clone(),
+            first_fragment,
+            tcp(51000, 443, 0),
+        ]);
+        let packet: Packet = parse(&frame);
+        assert_eq!(packet.protocol, IP_PROTO_TCP);
+        assert_eq!(packet.transport_offset, ETH_HDR_LEN + IPV6_HDR_LEN + 16);
+        assert_eq!(packet.destination_port(), Some(443));
+
+        let fragment: Vec<u8> = std::vec![IP_PROTO_TCP, 0, 0x00, 0xb9, 0, 0, 0, 1];
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV6),
+            ipv6(IP_PROTO_HOPOPT),
+            hop_by_hop,
+            fragment,
+            std::vec![0xff; 4],
+        ]);
+        let packet: Packet = parse(&frame);
+        assert_eq!(packet.protocol, IP_PROTO_TCP);
+        assert_eq!(
+            (packet.source_port(), packet.destination_port()),
+            (None, None),
+            "expected no ports for a non-first fragment"
+        );
+        assert!(!destination_port_rule(443).ports_match(&packet));
+
+        // Two 2 KiB hop-by-hop headers put the transport header past MAX_HEADER_OFFSET.
+        let mut hop_by_hop: Vec<u8> = std::vec![0; 2048];
+        hop_by_hop[..2].copy_from_slice(&[IP_PROTO_HOPOPT, 255]);
+        let mut last_hop_by_hop: Vec<u8> = hop_by_hop.clone();
+        last_hop_by_hop[0] = IP_PROTO_TCP;
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV6),
+            ipv6(IP_PROTO_HOPOPT),
+            hop_by_hop,
+            last_hop_by_hop,
+            tcp(51000, 443, 0),
+        ]);
+        assert_eq!(
+            parse_packet(frame.as_slice()).err(),
+            Some(ParseError::Truncated),
+            "expected the headers past MAX_HEADER_OFFSET not to be parsed"
+        );
+
+        let destination_options = |next_hdr: u8| std::vec![next_hdr, 0, 1, 4, 0, 0, 0, 0];
+        let mut parts: Vec<Vec<u8>> =
+            std::vec![ethernet(&[], ETHER_TYPE_IPV6), ipv6(IP_PROTO_IPV6_OPTS)];
+        parts.extend((1..MAX_IPV6_EXT_HDRS).map(|_| destination_options(IP_PROTO_IPV6_OPTS)));
+        parts.push(destination_options(IP_PROTO_TCP));
+        parts.push(tcp(51000, 22, TCP_FLAG_SYN));
+        let packet: Packet = parse(&fixture(&parts));
+        assert_eq!(packet.protocol, IP_PROTO_TCP);
+        assert_eq!(
+            packet.destination_port(),
+            Some(22),
+            "expected MAX_IPV6_EXT_HDRS extension headers to be walked"
+        );
+
+        let mut parts: Vec<Vec<u8>> =
+            std::vec![ethernet(&[], ETHER_TYPE_IPV6), ipv6(IP_PROTO_IPV6_OPTS)];
+        parts.extend((0..MAX_IPV6_EXT_HDRS).map(|_| destination_options(IP_PROTO_IPV6_OPTS)));
+        parts.push(destination_options(IP_PROTO_TCP));
+        parts.push(tcp(51000, 22, TCP_FLAG_SYN));
+        assert_eq!(
+            parse_packet(fixture(&parts).as_slice()).err(),
+            Some(ParseError::TooManyExtensionHeaders),
+            "expected more extension headers than MAX_IPV6_EXT_HDRS to be an error"
+        );
+    }
+
+    #[test]
+    fn test_truncated_and_other() {
+        let frame: Vec<u8> = ethernet(&[], 0x0806);
+        assert!(
+            parse_packet(frame.as_slice()).unwrap().is_none(),
+            "expected ARP not to be parsed"
+        );
+
+        let frame: Vec<u8> = fixture(&[ethernet(&[], ETHER_TYPE_IPV4), std::vec![0x45; 10]]);
+        assert_eq!(
+            parse_packet(frame.as_slice()).err(),
+            Some(ParseError::Truncated)
+        );
+
+        let frame: Vec<u8> = fixture(&[
+            ethernet(&[], ETHER_TYPE_IPV4),
+            ipv4(IP_PROTO_TCP, 0, 0),
+            std::vec![0; 4],
+        ]);
+        assert_eq!(
+            parse_packet(frame.as_slice()).err(),
+            Some(ParseError::Truncated),
+            "expected a truncated TCP header to be an error"
+        );
+        assert!(parse_packet(&[0u8; 8][..]).is_err());
+    }
+}
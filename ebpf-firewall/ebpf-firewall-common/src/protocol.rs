@@ -0,0 +1,189 @@
+//! IP protocols of the rules, logs and stats exchanged by the agent and the API
+//! server. Serialized with the `serde` feature.
+
+use core::fmt;
+
+use crate::parse::{IP_PROTO_ICMP, IP_PROTO_TCP, IP_PROTO_UDP};
+
+/// An IP protocol number. `Any` matches every protocol in rules and `Undefined`
+/// is the protocol of records without one. Protocols with a name are written
+/// by name, as in `"Tcp"` or `"Gre"`, the others by number, both are read.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum IpProtocol {
+    Tcp,
+    Udp,
+    Icmp,
+    Any,
+    Other(u8),
+    Undefined,
+}
+
+/// Names of the protocols without a variant.
+const PROTOCOL_NAMES: [(&str, u8); 6] = [
+    ("Igmp", 2),
+    ("Gre", 47),
+    ("Esp", 50),
+    ("Ah", 51),
+    ("Sctp", 132),
+    ("UdpLite", 136),
+];
+
+impl IpProtocol {
+    /// The protocol of a protocol number, named variants are used when they exist.
+    pub fn from_number(number: u8) -> Self {
+        match number {
+            IP_PROTO_TCP => Self::Tcp,
+            IP_PROTO_UDP => Self::Udp,
+            IP_PROTO_ICMP => Self::Icmp,
+            number => Self::Other(number),
+        }
+    }
+
+    /// The protocol number, `None` for `Any` and `Undefined`.
+    pub fn number(&self) -> Option<u8> {
+        match self {
+            Self::Tcp => Some(IP_PROTO_TCP),
+            Self::Udp => Some(IP_PROTO_UDP),
+            Self::Icmp => Some(IP_PROTO_ICMP),
+            Self::Other(number) => Some(*number),
+            Self::Any | Self::Undefined => None,
+        }
+    }
+
+    fn name(&self) -> Option<&'static str> {
+        match self {
+            Self::Tcp => Some("Tcp"),
+            Self::Udp => Some("Udp"),
+            Self::Icmp => Some("Icmp"),
+            Self::Any => Some("Any"),
+            Self::Undefined => Some("Undefined"),
+            Self::Other(number) => PROTOCOL_NAMES
+                .iter()
+                .find(|(_, value)| value == number)
+                .map(|(name, _)| *name),
+        }
+    }
+
+    /// Parses a protocol name, ignoring case, or a protocol number.
+    pub fn parse(value: &str) -> Option<Self> {
+        if let Ok(number) = value.parse::<u8>() {
+            return Some(Self::from_number(number));
+        }
+        [Self::Tcp, Self::Udp, Self::Icmp, Self::Any, Self::Undefined]
+            .into_iter()
+            .find(|protocol| {
+                protocol
+                    .name()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(value))
+            })
+            .or_else(|| {
+                PROTOCOL_NAMES
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(value))
+                    .map(|(_, number)| Self::from_number(*number))
+            })
+    }
+}
+
+impl fmt::Display for IpProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.name(), self) {
+            (Some(name), _) => write!(f, "{}", name),
+            (None, Self::Other(number)) => write!(f, "{}", number),
+            (None, _) => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serialization {
+    use core::fmt;
+
+    use serde::{
+        de::{self, Visitor},
+        Deserialize, Deserializer, Serialize, Serializer,
+    };
+
+    use super::IpProtocol;
+
+    impl Serialize for IpProtocol {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match (self.name(), self) {
+                (None, Self::Other(number)) => serializer.serialize_u8(*number),
+                _ => serializer.collect_str(self),
+            }
+        }
+    }
+
+    struct IpProtocolVisitor;
+
+    impl Visitor<'_> for IpProtocolVisitor {
+        type Value = IpProtocol;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a protocol name or a protocol number from 0 to 255")
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<IpProtocol, E> {
+            u8::try_from(value)
+                .map(IpProtocol::from_number)
+                .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(value), &self))
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<IpProtocol, E> {
+            u8::try_from(value)
+                .map(IpProtocol::from_number)
+                .map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<IpProtocol, E> {
+            IpProtocol::parse(value)
+                .ok_or_else(|| E::invalid_value(de::Unexpected::Str(value), &self))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for IpProtocol {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(IpProtocolVisitor)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod test_protocol {
+    extern crate std;
+
+    use std::{vec, vec::Vec};
+
+    use super::*;
+
+    #[test]
+    fn test_serde() {
+        let protocols: Vec<IpProtocol> = vec![
+            IpProtocol::Tcp,
+            IpProtocol::Any,
+            IpProtocol::Other(47),
+            IpProtocol::Other(253),
+        ];
+        assert_eq!(
+            serde_json::to_string(&protocols).unwrap(),
+            r#"["Tcp","Any","Gre",253]"#
+        );
+
+        let protocols: Vec<IpProtocol> =
+            serde_json::from_str(r#"["udp", "SCTP", 6, "50", 253, "Any"]"#).unwrap();
+        assert_eq!(
+            protocols,
+            vec![
+                IpProtocol::Udp,
+                IpProtocol::Other(132),
+                IpProtocol::Tcp,
+                IpProtocol::Other(50),
+                IpProtocol::Other(253),
+                IpProtocol::Any,
+            ]
+        );
+        assert!(serde_json::from_str::<IpProtocol>("256").is_err());
+        assert!(serde_json::from_str::<IpProtocol>(r#""Ipx""#).is_err());
+    }
+}
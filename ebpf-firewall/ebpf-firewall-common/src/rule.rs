@@ -0,0 +1,188 @@
+use crate::{
+    parse::{Packet, IP_PROTO_TCP},
+    MAX_RULES_PER_CHAIN, NO_PARENT_CHAIN,
+};
+
+/// Rules are keyed by the prefix of the remote address, the source of ingress
+/// and the destination of egress packets. `from_port`/`to_port` match the remote
+/// port and `destination_*` match the local address and port. `destination_prefix_len` of 0 matches
+/// any destination address. `connection_state` restricts the rule to new or
+/// established flows, see `CONNECTION_STATE_ANY`. `log` has every matched packet logged.
+/// `id` identifies the rule in the counters and logs. Rules with a rate limit
+/// `action` allow `pps` packets and `bps` bits per second, 0 for no limit, to
+/// each source prefix of `rate_prefix_len` bits, in bursts of up to `burst_ns`.
+/// `vlan_id` restricts the rule to a VLAN, see `Packet::vlan_id`. `icmp_type` and
+/// `icmp_code` restrict ICMP rules to a message type, and code of that type, in
+/// the numbering of the address family (ICMP or ICMPv6). `protocol` is the IP
+/// protocol number, `RULE_PROTOCOL_ANY` matches every protocol. `ifindex`
+/// restricts the rule to an interface, 0 applies it to every interface. Rules with
+/// ports only match packets with ports, not ICMP nor the fragments past the first.
+///
+/// The rule is a map value shared with the programs, so it has no `Option` nor
+/// `bool` fields: the optional values are plain values with a `has_*` flag, read
+/// through the accessors, and the flags are 0 or 1. The padding is explicit for
+/// the layout to be the same on both sides, and every byte initialized.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rule {
+    pub bps: u64,
+    pub burst_ns: u64,
+    pub destination_ip: [u8; 16],
+    pub id: u32,
+    pub pps: u32,
+    pub ifindex: u32,
+    pub from_port: u16,
+    pub to_port: u16,
+    pub destination_from_port: u16,
+    pub destination_to_port: u16,
+    pub vlan_id: u16,
+    pub status: u8,
+    pub protocol: u8,
+    pub direction: u8,
+    pub destination_prefix_len: u8,
+    pub connection_state: u8,
+    pub log: u8,
+    pub action: u8,
+    pub rate_prefix_len: u8,
+    pub icmp_type: u8,
+    pub icmp_code: u8,
+    pub has_from_port: u8,
+    pub has_to_port: u8,
+    pub has_destination_from_port: u8,
+    pub has_destination_to_port: u8,
+    pub has_vlan_id: u8,
+    pub has_icmp_type: u8,
+    pub has_icmp_code: u8,
+    pub _padding: [u8; 1],
+}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for Rule {}
+
+const _: () = assert!(core::mem::size_of::<Rule>() == 72);
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self {
+            bps: 0,
+            burst_ns: 0,
+            destination_ip: [0; 16],
+            id: 0,
+            pps: 0,
+            ifindex: 0,
+            from_port: 0,
+            to_port: 0,
+            destination_from_port: 0,
+            destination_to_port: 0,
+            vlan_id: 0,
+            status: 1,
+            protocol: IP_PROTO_TCP,
+            direction: 0,
+            destination_prefix_len: 0,
+            connection_state: 0,
+            log: 0,
+            action: 0,
+            rate_prefix_len: 0,
+            icmp_type: 0,
+            icmp_code: 0,
+            has_from_port: 0,
+            has_to_port: 0,
+            has_destination_from_port: 0,
+            has_destination_to_port: 0,
+            has_vlan_id: 0,
+            has_icmp_type: 0,
+            has_icmp_code: 0,
+            _padding: [0; 1],
+        }
+    }
+}
+
+impl Rule {
+    pub fn status(&self) -> bool {
+        self.status != 0
+    }
+
+    pub fn log(&self) -> bool {
+        self.log != 0
+    }
+
+    pub fn from_port(&self) -> Option<u16> {
+        (self.has_from_port != 0).then_some(self.from_port)
+    }
+
+    pub fn to_port(&self) -> Option<u16> {
+        (self.has_to_port != 0).then_some(self.to_port)
+    }
+
+    pub fn destination_from_port(&self) -> Option<u16> {
+        (self.has_destination_from_port != 0).then_some(self.destination_from_port)
+    }
+
+    pub fn destination_to_port(&self) -> Option<u16> {
+        (self.has_destination_to_port != 0).then_some(self.destination_to_port)
+    }
+
+    pub fn vlan_id(&self) -> Option<u16> {
+        (self.has_vlan_id != 0).then_some(self.vlan_id)
+    }
+
+    pub fn icmp_type(&self) -> Option<u8> {
+        (self.has_icmp_type != 0).then_some(self.icmp_type)
+    }
+
+    pub fn icmp_code(&self) -> Option<u8> {
+        (self.has_icmp_code != 0).then_some(self.icmp_code)
+    }
+
+    /// Check the source and destination ports of the packet against the ports of
+    /// the rule, see `check_port`.
+    pub fn ports_match(&self, packet: &Packet) -> bool {
+        check_port(packet.source_port(), self.from_port(), self.to_port())
+            && check_port(
+                packet.destination_port(),
+                self.destination_from_port(),
+                self.destination_to_port(),
+            )
+    }
+}
+
+/// Check if port is in port range or exact
+/// if from_port and to_port is given, it will do port range checking
+/// if only from_port is given, it will do exact port checking
+/// if from_port is not given, any port matches, a packet without port matches none
+/// if match, it will return true else false
+fn check_port(port: Option<u16>, from_port: Option<u16>, to_port: Option<u16>) -> bool {
+    let Some(from_port) = from_port else {
+        return true;
+    };
+    let Some(port) = port else {
+        return false;
+    };
+    if let Some(to_port) = to_port {
+        from_port <= port && to_port >= port
+    } else {
+        from_port == port
+    }
+}
+
+/// Rules of a single prefix, sorted by priority. Only the first `len` rules are set.
+/// `parent` is the index of the chain of the longest prefix containing this one,
+/// evaluated next when no rule matches, `NO_PARENT_CHAIN` when there is none.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RuleChain {
+    pub len: u32,
+    pub parent: u32,
+    pub rules: [Rule; MAX_RULES_PER_CHAIN],
+}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for RuleChain {}
+
+impl Default for RuleChain {
+    fn default() -> Self {
+        Self {
+            len: 0,
+            parent: NO_PARENT_CHAIN,
+            rules: [Rule::default(); MAX_RULES_PER_CHAIN],
+        }
+    }
+}
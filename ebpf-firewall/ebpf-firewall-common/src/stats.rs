@@ -0,0 +1,22 @@
+/// Packets and bytes passed and dropped by a rule or of a protocol, the values of
+/// `FIREWALL_RULE_STATS`, `FIREWALL_PROTOCOL_STATS` and `FIREWALL_PARSE_ERRORS`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TrafficStats {
+    pub passed_packets: u64,
+    pub passed_bytes: u64,
+    pub dropped_packets: u64,
+    pub dropped_bytes: u64,
+}
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for TrafficStats {}
+
+impl TrafficStats {
+    pub fn add(&mut self, other: &TrafficStats) {
+        self.passed_packets += other.passed_packets;
+        self.passed_bytes += other.passed_bytes;
+        self.dropped_packets += other.dropped_packets;
+        self.dropped_bytes += other.dropped_bytes;
+    }
+}
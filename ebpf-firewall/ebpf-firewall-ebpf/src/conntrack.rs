@@ -0,0 +1,219 @@
+use aya_ebpf::{helpers::bpf_ktime_get_ns, macros::map, maps::LruHashMap};
+use ebpf_firewall_common::{
+    conntrack::{ConntrackEntry, ConntrackKey},
+    parse::MAX_HEADER_OFFSET,
+    CONNECTION_STATE_ESTABLISHED, CONNECTION_STATE_NEW, CONNTRACK_OTHER_TIMEOUT_NS,
+    CONNTRACK_TCP_TIMEOUT_NS, CONNTRACK_UDP_TIMEOUT_NS, MAX_CONNTRACK_ENTRIES,
+};
+use network_types::{
+    icmp::IcmpHdr,
+    ip::{IpProto, Ipv4Hdr, Ipv6Hdr},
+};
+
+use crate::{ptr_at, Packet, PacketContext};
+
+/// ICMP and ICMPv6 echo request/reply types.
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// Flows initiated by this host, populated by the egress classifier.
+#[map]
+static FIREWALL_CONNTRACK: LruHashMap<ConntrackKey, ConntrackEntry> =
+    LruHashMap::with_max_entries(MAX_CONNTRACK_ENTRIES, 0);
+
+fn timeout(protocol: u8) -> u64 {
+    if protocol == IpProto::Tcp as u8 {
+        CONNTRACK_TCP_TIMEOUT_NS
+    } else if protocol == IpProto::Udp as u8 {
+        CONNTRACK_UDP_TIMEOUT_NS
+    } else {
+        CONNTRACK_OTHER_TIMEOUT_NS
+    }
+}
+
+fn is_tcp_or_udp(protocol: u8) -> bool {
+    protocol == IpProto::Tcp as u8 || protocol == IpProto::Udp as u8
+}
+
+fn is_echo_request(packet: &Packet) -> bool {
+    match packet.icmp_type() {
+        Some(icmp_type) if packet.ip_version == 6 => icmp_type == ICMPV6_ECHO_REQUEST,
+        Some(icmp_type) => icmp_type == ICMP_ECHO_REQUEST,
+        None => false,
+    }
+}
+
+fn is_echo_reply(packet: &Packet) -> bool {
+    match packet.icmp_type() {
+        Some(icmp_type) if packet.ip_version == 6 => icmp_type == ICMPV6_ECHO_REPLY,
+        Some(icmp_type) => icmp_type == ICMP_ECHO_REPLY,
+        None => false,
+    }
+}
+
+/// Destination Unreachable, Time Exceeded and Parameter Problem (plus Packet Too Big
+/// for ICMPv6) quote the header of the packet that caused them.
+fn is_icmp_error(packet: &Packet) -> bool {
+    match packet.icmp_type() {
+        Some(icmp_type) if packet.ip_version == 6 => (1..=4).contains(&icmp_type),
+        Some(icmp_type) => icmp_type == 3 || icmp_type == 11 || icmp_type == 12,
+        None => false,
+    }
+}
+
+/// Key of the flow a packet leaving this host belongs to, `None` if it is not tracked.
+fn egress_key(packet: &Packet) -> Option<ConntrackKey> {
+    let (local_port, remote_port): (u16, u16) = if is_tcp_or_udp(packet.protocol) {
+        (packet.source_port()?, packet.destination_port()?)
+    } else if packet.protocol == IpProto::Icmp as u8 {
+        if !is_echo_request(packet) {
+            return None;
+        }
+        (packet.icmp_id()?, 0)
+    } else {
+        (0, 0)
+    };
+    Some(ConntrackKey {
+        local_ip: packet.source_ip,
+        remote_ip: packet.destination_ip,
+        local_port,
+        remote_port,
+        protocol: packet.protocol,
+        ip_version: packet.ip_version,
+        _padding: [0; 2],
+    })
+}
+
+/// Key of the flow a packet reaching this host replies to, `None` if it can't be a reply.
+fn ingress_key(packet: &Packet) -> Option<ConntrackKey> {
+    let (local_port, remote_port): (u16, u16) = if is_tcp_or_udp(packet.protocol) {
+        (packet.destination_port()?, packet.source_port()?)
+    } else if packet.protocol == IpProto::Icmp as u8 {
+        if !is_echo_reply(packet) {
+            return None;
+        }
+        (packet.icmp_id()?, 0)
+    } else {
+        (0, 0)
+    };
+    Some(ConntrackKey {
+        local_ip: packet.destination_ip,
+        remote_ip: packet.source_ip,
+        local_port,
+        remote_port,
+        protocol: packet.protocol,
+        ip_version: packet.ip_version,
+        _padding: [0; 2],
+    })
+}
+
+/// Key of the flow quoted by an ICMP error, the quoted packet was sent by this host.
+/// IPv6 extension headers in the quoted packet are not walked.
+fn related_key<C: PacketContext>(ctx: &C, packet: &Packet) -> Result<ConntrackKey, ()> {
+    if packet.transport_offset > MAX_HEADER_OFFSET {
+        return Err(());
+    }
+    let offset: usize = packet.transport_offset + IcmpHdr::LEN;
+    let mut key: ConntrackKey = ConntrackKey {
+        local_ip: [0; 16],
+        remote_ip: [0; 16],
+        local_port: 0,
+        remote_port: 0,
+        protocol: 0,
+        ip_version: packet.ip_version,
+        _padding: [0; 2],
+    };
+    let inner_offset: usize = if packet.ip_version == 6 {
+        let ipv6_hdr: *const Ipv6Hdr = unsafe { ptr_at(ctx, offset)? };
+        key.local_ip = unsafe { (*ipv6_hdr).src_addr };
+        key.remote_ip = unsafe { (*ipv6_hdr).dst_addr };
+        key.protocol = unsafe { (*ipv6_hdr).next_hdr } as u8;
+        if key.protocol == IpProto::Ipv6Icmp as u8 {
+            key.protocol = IpProto::Icmp as u8;
+        }
+        offset + Ipv6Hdr::LEN
+    } else {
+        let ipv4_hdr: *const Ipv4Hdr = unsafe { ptr_at(ctx, offset)? };
+        key.local_ip[..4].copy_from_slice(&unsafe { (*ipv4_hdr).src_addr });
+        key.remote_ip[..4].copy_from_slice(&unsafe { (*ipv4_hdr).dst_addr });
+        key.protocol = unsafe { (*ipv4_hdr).proto } as u8;
+        offset + ((unsafe { (*ipv4_hdr).ihl() } & 0x0f) as usize) * 4
+    };
+    if is_tcp_or_udp(key.protocol) {
+        let ports: *const [u8; 4] = unsafe { ptr_at(ctx, inner_offset)? };
+        let ports: [u8; 4] = unsafe { *ports };
+        key.local_port = u16::from_be_bytes([ports[0], ports[1]]);
+        key.remote_port = u16::from_be_bytes([ports[2], ports[3]]);
+    } else if key.protocol == IpProto::Icmp as u8 {
+        let icmp_hdr: *const IcmpHdr = unsafe { ptr_at(ctx, inner_offset)? };
+        key.local_port = u16::from_be(unsafe { (*icmp_hdr).un.echo.id });
+    }
+    Ok(key)
+}
+
+/// Refresh the flow of the key if it has not timed out, the caller counts the packet.
+fn lookup_flow(key: &ConntrackKey) -> Option<*mut ConntrackEntry> {
+    let entry: *mut ConntrackEntry = FIREWALL_CONNTRACK.get_ptr_mut(key)?;
+    let now: u64 = unsafe { bpf_ktime_get_ns() };
+    if now.saturating_sub(unsafe { (*entry).last_seen }) > timeout(key.protocol) {
+        return None;
+    }
+    unsafe { (*entry).last_seen = now };
+    Some(entry)
+}
+
+/// Record a packet leaving this host, creating its flow if needed.
+#[inline(never)]
+pub fn track_egress(packet: &Packet) {
+    let Some(key) = egress_key(packet) else {
+        return;
+    };
+    if let Some(entry) = FIREWALL_CONNTRACK.get_ptr_mut(&key) {
+        unsafe {
+            (*entry).last_seen = bpf_ktime_get_ns();
+            (*entry).packets_out += 1;
+        }
+        return;
+    }
+    let entry: ConntrackEntry = ConntrackEntry {
+        last_seen: unsafe { bpf_ktime_get_ns() },
+        packets_out: 1,
+        packets_in: 0,
+    };
+    let _ = FIREWALL_CONNTRACK.insert(&key, &entry, 0);
+}
+
+/// Connection state of a packet reaching this host. Replies to tracked flows and
+/// ICMP errors about them are established, everything else is new.
+pub fn connection_state<C: PacketContext>(ctx: &C, packet: &Packet) -> u8 {
+    if let Some(key) = ingress_key(packet) {
+        if let Some(entry) = lookup_flow(&key) {
+            unsafe { (*entry).packets_in += 1 };
+            return CONNECTION_STATE_ESTABLISHED;
+        }
+    } else if is_icmp_error(packet) {
+        if let Ok(key) = related_key(ctx, packet) {
+            if lookup_flow(&key).is_some() {
+                return CONNECTION_STATE_ESTABLISHED;
+            }
+        }
+    }
+    CONNECTION_STATE_NEW
+}
+
+/// Connection state of a packet leaving this host. Packets of tracked flows are
+/// established, the first packet of a flow and the replies to remote flows are new.
+#[inline(never)]
+pub fn egress_connection_state(packet: &Packet) -> u8 {
+    if let Some(key) = egress_key(packet) {
+        if let Some(entry) = unsafe { FIREWALL_CONNTRACK.get(&key) } {
+            let now: u64 = unsafe { bpf_ktime_get_ns() };
+            if now.saturating_sub(entry.last_seen) <= timeout(key.protocol) {
+                return CONNECTION_STATE_ESTABLISHED;
+            }
+        }
+    }
+    CONNECTION_STATE_NEW
+}
@@ -0,0 +1,891 @@
+// The program shared by the ring buffer and perf event array builds, included by
+// their crate roots which pick the `transport` module.
+
+use core::{
+    mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use aya_ebpf::{
+    bindings::{xdp_action, TC_ACT_PIPE, TC_ACT_SHOT},
+    helpers::{bpf_get_prandom_u32, bpf_ktime_get_ns},
+    macros::{classifier, map, xdp},
+    maps::{
+        lpm_trie::Key, Array, HashMap, LpmTrie, LruHashMap, LruPerCpuHashMap, PerCpuArray,
+        ProgramArray,
+    },
+    programs::{TcContext, XdpContext},
+    EbpfContext,
+};
+use aya_log_ebpf::info;
+use ebpf_firewall_common::{
+    config::FirewallConfig,
+    log::{DropCount, DropKey, FirewallLog},
+    parse::{self, Packet, PacketBytes, ParseError, IP_PROTO_ICMP, IP_PROTO_TCP, IP_PROTO_UDP},
+    rule::{Rule, RuleChain},
+    stats::TrafficStats,
+    CONNECTION_STATE_ANY, DEFAULT_ACTION_DENY, DEFAULT_RULE_ID, MAX_DROP_ENTRIES, MAX_EXEMPTIONS,
+    MAX_INTERFACES, MAX_RATE_LIMIT_ENTRIES, MAX_RULES_PER_CHAIN, MAX_RULE_CHAINS,
+    MAX_RULE_CHAIN_DEPTH, MAX_RULE_STATS, PROGRAM_TUNNEL, RULE_ACTION_FILTER,
+    RULE_ACTION_SYN_FLOOD, RULE_PROTOCOL_ANY,
+};
+
+mod conntrack;
+
+/// Rule direction, XDP evaluates the ingress rules and the egress classifier the
+/// egress rules.
+pub const DIRECTION_INGRESS: u8 = 0;
+pub const DIRECTION_EGRESS: u8 = 1;
+
+/// Source prefix and rule the rate limit state of `FIREWALL_RATE_LIMITS` is kept for.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RateLimitKey {
+    pub ip: [u8; 16],
+    pub rule_id: u32,
+    pub ip_version: u8,
+    pub _padding: [u8; 3],
+}
+
+/// Theoretical arrival times of the packet and bit limits, in `bpf_ktime_get_ns`
+/// time. A packet conforms when they are at most the burst ahead of now.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RateLimitState {
+    pub packets_tat: u64,
+    pub bits_tat: u64,
+}
+
+/// Packet bounds, length and interface of the XDP and TC contexts, so parsing and
+/// rule evaluation are shared by both programs.
+pub trait PacketContext: EbpfContext {
+    fn data(&self) -> usize;
+    fn data_end(&self) -> usize;
+    fn length(&self) -> u32;
+    fn ifindex(&self) -> u32;
+}
+
+impl PacketContext for XdpContext {
+    fn data(&self) -> usize {
+        XdpContext::data(self)
+    }
+
+    fn data_end(&self) -> usize {
+        XdpContext::data_end(self)
+    }
+
+    fn length(&self) -> u32 {
+        (XdpContext::data_end(self) - XdpContext::data(self)) as u32
+    }
+
+    fn ifindex(&self) -> u32 {
+        unsafe { (*self.ctx).ingress_ifindex }
+    }
+}
+
+impl PacketContext for TcContext {
+    fn data(&self) -> usize {
+        TcContext::data(self)
+    }
+
+    fn data_end(&self) -> usize {
+        TcContext::data_end(self)
+    }
+
+    fn length(&self) -> u32 {
+        self.len()
+    }
+
+    fn ifindex(&self) -> u32 {
+        unsafe { (*self.skb.skb).ifindex }
+    }
+}
+
+/// ICMPv6 Router Solicitation up to Redirect (133-137), neighbour discovery must keep
+/// working when the default action is deny.
+const ICMPV6_NDP_TYPES: (u8, u8) = (133, 137);
+
+/// Maps a prefix to its index in `FIREWALL_RULE_CHAINS`.
+#[map]
+static FIREWALL_RULES: LpmTrie<[u8; 4], u32> = LpmTrie::with_max_entries(MAX_RULE_CHAINS, 0);
+
+#[map]
+static FIREWALL_RULE_CHAINS: Array<RuleChain> = Array::with_max_entries(MAX_RULE_CHAINS, 0);
+
+/// Maps a prefix to its index in `FIREWALL_RULE_CHAINS_V6`.
+#[map]
+static FIREWALL_RULES_V6: LpmTrie<[u8; 16], u32> = LpmTrie::with_max_entries(MAX_RULE_CHAINS, 0);
+
+#[map]
+static FIREWALL_RULE_CHAINS_V6: Array<RuleChain> = Array::with_max_entries(MAX_RULE_CHAINS, 0);
+
+#[map]
+static FIREWALL_CONFIG: HashMap<u32, FirewallConfig> = HashMap::with_max_entries(MAX_INTERFACES, 0);
+
+/// Sources that are always allowed on an interface regardless of the rules and the
+/// default action, keyed by the interface index in network byte order followed by
+/// the source prefix.
+#[map]
+static FIREWALL_EXEMPTIONS: LpmTrie<[u8; 8], u8> = LpmTrie::with_max_entries(MAX_EXEMPTIONS, 0);
+
+#[map]
+static FIREWALL_EXEMPTIONS_V6: LpmTrie<[u8; 20], u8> = LpmTrie::with_max_entries(MAX_EXEMPTIONS, 0);
+
+/// Drop counters read and logged by the agent at the end of each window.
+#[map]
+static FIREWALL_DROPS: LruPerCpuHashMap<DropKey, DropCount> =
+    LruPerCpuHashMap::with_max_entries(MAX_DROP_ENTRIES, 0);
+
+/// Traffic of each rule id, `DEFAULT_RULE_ID` for the packets of the default action.
+#[map]
+static FIREWALL_RULE_STATS: LruPerCpuHashMap<u32, TrafficStats> =
+    LruPerCpuHashMap::with_max_entries(MAX_RULE_STATS, 0);
+
+/// Traffic of each IP protocol number, counted with the rule stats.
+#[map]
+static FIREWALL_PROTOCOL_STATS: PerCpuArray<TrafficStats> = PerCpuArray::with_max_entries(256, 0);
+
+/// Traffic of the packets the parser rejected, given the default action.
+#[map]
+static FIREWALL_PARSE_ERRORS: PerCpuArray<TrafficStats> = PerCpuArray::with_max_entries(1, 0);
+
+/// Rate limit state of each source prefix matched by a rate limit rule.
+#[map]
+static FIREWALL_RATE_LIMITS: LruHashMap<RateLimitKey, RateLimitState> =
+    LruHashMap::with_max_entries(MAX_RATE_LIMIT_ENTRIES, 0);
+
+/// Programs evaluating the packets carried by tunnels on ingress, see `PROGRAM_TUNNEL`.
+/// A program array only holds programs of one type, egress has its own.
+#[map]
+static FIREWALL_INGRESS_PROGRAMS: ProgramArray = ProgramArray::with_max_entries(1, 0);
+
+/// Programs evaluating the packets carried by tunnels on egress.
+#[map]
+static FIREWALL_EGRESS_PROGRAMS: ProgramArray = ProgramArray::with_max_entries(1, 0);
+
+/// Copy of the rule matched by the packet being processed, see `match_rule_chain`.
+#[map]
+static FIREWALL_MATCHED_RULE: PerCpuArray<Rule> = PerCpuArray::with_max_entries(1, 0);
+
+#[xdp]
+pub fn ebpf_firewall(ctx: XdpContext) -> u32 {
+    match try_ebpf_firewall(ctx) {
+        Ok(ret) => ret,
+        Err(_) => xdp_action::XDP_ABORTED,
+    }
+}
+
+/// Drops the packets denied by the egress rules and records the flows initiated
+/// by this host so their replies match as established. Packets no egress rule
+/// applies to continue to the next classifier, on egress the default action is
+/// only given to the packets the parser rejects. Packets failing to be evaluated
+/// are dropped, as on ingress and in `ebpf_firewall_egress_tunnel`, they would
+/// otherwise escape the egress rules.
+#[classifier]
+pub fn ebpf_firewall_egress(ctx: TcContext) -> i32 {
+    match try_ebpf_firewall_egress(ctx) {
+        Ok(ret) => ret,
+        Err(_) => TC_ACT_SHOT,
+    }
+}
+
+#[inline(always)]
+unsafe fn ptr_at<T, C: PacketContext>(ctx: &C, offset: usize) -> Result<*const T, ()> {
+    let start = ctx.data();
+    let end = ctx.data_end();
+    let len = mem::size_of::<T>();
+
+    if start + offset + len > end {
+        return Err(());
+    }
+
+    Ok((start + offset) as *const T)
+}
+
+/// Check the ICMP type and code of the rule, a rule without them matches any message.
+/// Non-first fragments have no ICMP header and only match rules without a type.
+fn check_icmp(rule: &Rule, packet: &Packet) -> bool {
+    let Some(icmp_type) = rule.icmp_type() else {
+        return true;
+    };
+    packet.icmp_type() == Some(icmp_type)
+        && (rule.icmp_code().is_none() || rule.icmp_code() == packet.icmp_code())
+}
+
+/// The first 64 bits of an address and the last 64 bits, in network order.
+fn address_halves(address: &[u8; 16]) -> (u64, u64) {
+    let address: u128 = u128::from_be_bytes(*address);
+    ((address >> 64) as u64, address as u64)
+}
+
+/// Mask of the first `prefix_len` bits of an address half.
+fn half_mask(prefix_len: u8) -> u64 {
+    match prefix_len {
+        0 => 0,
+        1..=63 => u64::MAX << (64 - prefix_len),
+        _ => u64::MAX,
+    }
+}
+
+/// Check if the first `prefix_len` bits of address and network are equal. The
+/// halves are compared whole, the verifier would follow a loop over the octets
+/// once for each prefix length of every rule.
+fn check_address(address: &[u8; 16], network: &[u8; 16], prefix_len: u8) -> bool {
+    let (address_high, address_low): (u64, u64) = address_halves(address);
+    let (network_high, network_low): (u64, u64) = address_halves(network);
+    (address_high ^ network_high) & half_mask(prefix_len) == 0
+        && (address_low ^ network_low) & half_mask(prefix_len.saturating_sub(64)) == 0
+}
+
+/// Check if the rule of the direction applies to the packet of the interface, seen
+/// from the remote end.
+fn rule_matches(rule: &Rule, packet: &Packet, direction: u8, ifindex: u32) -> bool {
+    rule.direction == direction
+        && (rule.ifindex == 0 || rule.ifindex == ifindex)
+        && (rule.protocol == RULE_PROTOCOL_ANY || packet.protocol == rule.protocol)
+        && rule.ports_match(packet)
+        && check_address(
+            &packet.destination_ip,
+            &rule.destination_ip,
+            rule.destination_prefix_len,
+        )
+        && (rule.connection_state == CONNECTION_STATE_ANY
+            || rule.connection_state == packet.connection_state)
+        && (rule.vlan_id().is_none() || rule.vlan_id() == packet.vlan_id())
+        && check_icmp(rule, packet)
+}
+
+/// Clear the bits of the address beyond the prefix length.
+fn mask_address(address: &[u8; 16], prefix_len: u8) -> [u8; 16] {
+    let mut network: [u8; 16] = [0; 16];
+    let mut remaining: u8 = prefix_len;
+    for i in 0..16 {
+        if remaining == 0 {
+            break;
+        }
+        let bits: u8 = if remaining >= 8 { 8 } else { remaining };
+        network[i] = address[i] & (0xff << (8 - bits));
+        remaining -= bits;
+    }
+    network
+}
+
+/// Check if the packet exceeds the rate limit of its source prefix, following the
+/// generic cell rate algorithm. The state is shared by the CPUs without locking,
+/// concurrent packets of a source may slip through, which is fine for a limit.
+fn is_rate_limited(packet: &Packet, rule: &Rule, length: u32) -> bool {
+    if rule.action == RULE_ACTION_SYN_FLOOD && !packet.tcp_syn {
+        return false;
+    }
+    let key: RateLimitKey = RateLimitKey {
+        ip: mask_address(&packet.source_ip, rule.rate_prefix_len),
+        rule_id: rule.id,
+        ip_version: packet.ip_version,
+        _padding: [0; 3],
+    };
+    let now: u64 = unsafe { bpf_ktime_get_ns() };
+    let packet_cost: u64 = if rule.pps > 0 {
+        1_000_000_000 / rule.pps as u64
+    } else {
+        0
+    };
+    let bit_cost: u64 = (length as u64 * 8_000_000_000)
+        .checked_div(rule.bps)
+        .unwrap_or(0);
+    match FIREWALL_RATE_LIMITS.get_ptr_mut(&key) {
+        Some(state) => unsafe {
+            let packets_tat: u64 = if (*state).packets_tat > now {
+                (*state).packets_tat
+            } else {
+                now
+            };
+            let bits_tat: u64 = if (*state).bits_tat > now {
+                (*state).bits_tat
+            } else {
+                now
+            };
+            if packets_tat - now > rule.burst_ns || bits_tat - now > rule.burst_ns {
+                return true;
+            }
+            (*state).packets_tat = packets_tat + packet_cost;
+            (*state).bits_tat = bits_tat + bit_cost;
+            false
+        },
+        None => {
+            let state: RateLimitState = RateLimitState {
+                packets_tat: now + packet_cost,
+                bits_tat: now + bit_cost,
+            };
+            let _ = FIREWALL_RATE_LIMITS.insert(&key, &state, 0);
+            false
+        }
+    }
+}
+
+/// Copy the first rule of the chain, in priority order, that applies to the packet
+/// into `matched`, the `FIREWALL_MATCHED_RULE` slot, false if none does. The copy
+/// leaves the verifier with the same state whichever rule matched, it would
+/// otherwise follow the rest of the program once for each rule of the chain.
+fn match_rule_chain(
+    chain: &'static RuleChain,
+    packet: &Packet,
+    direction: u8,
+    ifindex: u32,
+    matched: &mut Rule,
+) -> bool {
+    for i in 0..MAX_RULES_PER_CHAIN {
+        if i >= chain.len as usize {
+            break;
+        }
+        let rule: &Rule = &chain.rules[i];
+        if rule_matches(rule, packet, direction, ifindex) {
+            *matched = rule.clone();
+            return true;
+        }
+    }
+    false
+}
+
+/// The `FIREWALL_MATCHED_RULE` slot of this CPU.
+fn matched_rule() -> Result<&'static mut Rule, ()> {
+    let rule: *mut Rule = FIREWALL_MATCHED_RULE.get_ptr_mut(0).ok_or(())?;
+    Ok(unsafe { &mut *rule })
+}
+
+/// Copy the first rule of the chain at `chain_index` that applies to the packet, or
+/// of its parents in turn, see `match_rule_chain`. At most `MAX_RULE_CHAIN_DEPTH`
+/// chains are followed, the agent rejects the rule sets nesting more prefixes.
+fn match_rule_chains(
+    chains: &'static Array<RuleChain>,
+    chain_index: u32,
+    packet: &Packet,
+    direction: u8,
+    ifindex: u32,
+    matched: &mut Rule,
+) -> bool {
+    let mut chain_index: u32 = chain_index;
+    for _ in 0..MAX_RULE_CHAIN_DEPTH {
+        // `NO_PARENT_CHAIN` is beyond the end of the array.
+        let Some(chain) = chains.get(chain_index) else {
+            return false;
+        };
+        if match_rule_chain(chain, packet, direction, ifindex, matched) {
+            return true;
+        }
+        chain_index = chain.parent;
+    }
+    false
+}
+
+/// Find the first matching rule of the longest configured prefix that contains the
+/// IPv4 source, then of the shorter prefixes containing it, longest first.
+fn lookup_rule_v4(packet: &Packet, direction: u8, ifindex: u32, matched: &mut Rule) -> bool {
+    let source_ipv4: [u8; 4] = [
+        packet.source_ip[0],
+        packet.source_ip[1],
+        packet.source_ip[2],
+        packet.source_ip[3],
+    ];
+    let Some(chain_index) = FIREWALL_RULES.get(&Key::new(32, source_ipv4)) else {
+        return false;
+    };
+    match_rule_chains(
+        &FIREWALL_RULE_CHAINS,
+        *chain_index,
+        packet,
+        direction,
+        ifindex,
+        matched,
+    )
+}
+
+/// Find the first matching rule of the longest configured prefix that contains the
+/// IPv6 source, as `lookup_rule_v4` does.
+fn lookup_rule_v6(packet: &Packet, direction: u8, ifindex: u32, matched: &mut Rule) -> bool {
+    let Some(chain_index) = FIREWALL_RULES_V6.get(&Key::new(128, packet.source_ip)) else {
+        return false;
+    };
+    match_rule_chains(
+        &FIREWALL_RULE_CHAINS_V6,
+        *chain_index,
+        packet,
+        direction,
+        ifindex,
+        matched,
+    )
+}
+
+/// Check if the source is in the exemption list of the interface for its address family.
+fn is_exempted(packet: &Packet, ifindex: u32) -> bool {
+    let interface: [u8; 4] = ifindex.to_be_bytes();
+    if packet.ip_version == 6 {
+        let mut data: [u8; 20] = [0; 20];
+        data[..4].copy_from_slice(&interface);
+        data[4..].copy_from_slice(&packet.source_ip);
+        FIREWALL_EXEMPTIONS_V6.get(&Key::new(160, data)).is_some()
+    } else {
+        let mut data: [u8; 8] = [0; 8];
+        data[..4].copy_from_slice(&interface);
+        data[4..].copy_from_slice(&packet.source_ip[..4]);
+        FIREWALL_EXEMPTIONS.get(&Key::new(64, data)).is_some()
+    }
+}
+
+/// Settings of the interface, `None` until the agent configured it.
+fn interface_config(ifindex: u32) -> Option<&'static FirewallConfig> {
+    unsafe { FIREWALL_CONFIG.get(&ifindex) }
+}
+
+/// Status applied when no rule matches, allowed unless the agent configured deny
+/// on the interface.
+fn default_status(ifindex: u32) -> bool {
+    match interface_config(ifindex) {
+        Some(config) => config.default_action != DEFAULT_ACTION_DENY,
+        None => true,
+    }
+}
+
+/// Adds the packet to the drop counters of its source, protocol, rule and direction.
+fn count_drop(packet: &Packet, rule_id: u32, direction: u8, bytes: u64) {
+    let key: DropKey = DropKey {
+        ip: packet.source_ip,
+        rule_id,
+        protocol: packet.protocol,
+        ip_version: packet.ip_version,
+        direction,
+        _padding: [0; 1],
+    };
+    match FIREWALL_DROPS.get_ptr_mut(&key) {
+        Some(count) => unsafe {
+            (*count).packets += 1;
+            (*count).bytes += bytes;
+        },
+        None => {
+            let _ = FIREWALL_DROPS.insert(&key, &DropCount { packets: 1, bytes }, 0);
+        }
+    }
+}
+
+fn add_traffic(stats: *mut TrafficStats, status: bool, bytes: u64) {
+    unsafe {
+        if status {
+            (*stats).passed_packets += 1;
+            (*stats).passed_bytes += bytes;
+        } else {
+            (*stats).dropped_packets += 1;
+            (*stats).dropped_bytes += bytes;
+        }
+    }
+}
+
+/// Adds the packet to the traffic counters of its rule and protocol.
+fn count_traffic(packet: &Packet, rule_id: u32, status: bool, bytes: u64) {
+    match FIREWALL_RULE_STATS.get_ptr_mut(&rule_id) {
+        Some(stats) => add_traffic(stats, status, bytes),
+        None => {
+            let mut stats: TrafficStats = TrafficStats {
+                passed_packets: 0,
+                passed_bytes: 0,
+                dropped_packets: 0,
+                dropped_bytes: 0,
+            };
+            add_traffic(&mut stats, status, bytes);
+            let _ = FIREWALL_RULE_STATS.insert(&rule_id, &stats, 0);
+        }
+    }
+    if let Some(stats) = FIREWALL_PROTOCOL_STATS.get_ptr_mut(packet.protocol as u32) {
+        add_traffic(stats, status, bytes);
+    }
+}
+
+/// Status of a packet the parser rejected, the default action of the interface,
+/// counted in `FIREWALL_PARSE_ERRORS`.
+fn parse_error_status<C: PacketContext>(ctx: &C) -> bool {
+    let status: bool = default_status(ctx.ifindex());
+    if let Some(stats) = FIREWALL_PARSE_ERRORS.get_ptr_mut(0) {
+        add_traffic(stats, status, ctx.length() as u64);
+    }
+    status
+}
+
+/// Check if the dropped packet is sampled to be logged on its own.
+fn is_sampled(ifindex: u32) -> bool {
+    match interface_config(ifindex) {
+        Some(config) if config.log_sample_rate > 0 => {
+            (unsafe { bpf_get_prandom_u32() }) % config.log_sample_rate == 0
+        }
+        _ => false,
+    }
+}
+
+fn status_to_string(status: bool) -> &'static str {
+    if status {
+        "ALLOWED"
+    } else {
+        "DROPPED"
+    }
+}
+
+fn direction_to_string(direction: u8) -> &'static str {
+    if direction == DIRECTION_EGRESS {
+        "Egress"
+    } else {
+        "Ingress"
+    }
+}
+
+/// Name of the common protocols, as written by the agent.
+fn protocol_name(protocol: u8) -> Option<&'static str> {
+    match protocol {
+        IP_PROTO_TCP => Some("Tcp"),
+        IP_PROTO_UDP => Some("Udp"),
+        IP_PROTO_ICMP => Some("Icmp"),
+        _ => None,
+    }
+}
+
+/// Logs the packet through the eBPF logger and sends it to the agent. Not inlined,
+/// the frame of the logger would add to the stack of the programs.
+#[inline(never)]
+fn log_packet<C: PacketContext>(
+    ctx: &C,
+    packet: &Packet,
+    rule_id: u32,
+    status: bool,
+    direction: u8,
+    length: u32,
+) {
+    let source_ip = packet.source_ip;
+    let address: IpAddr = if packet.ip_version == 6 {
+        IpAddr::V6(Ipv6Addr::from(source_ip))
+    } else {
+        IpAddr::V4(Ipv4Addr::new(
+            source_ip[0],
+            source_ip[1],
+            source_ip[2],
+            source_ip[3],
+        ))
+    };
+    // The protocols without a name are logged by number.
+    match protocol_name(packet.protocol) {
+        Some(name) => info!(
+            ctx,
+            "[{}] {} Protocol: {}, IP Address: {:i}, Port:{}",
+            status_to_string(status),
+            direction_to_string(direction),
+            name,
+            address,
+            packet.source_port().unwrap_or(0)
+        ),
+        None => info!(
+            ctx,
+            "[{}] {} Protocol: {}, IP Address: {:i}, Port:{}",
+            status_to_string(status),
+            direction_to_string(direction),
+            packet.protocol,
+            address,
+            packet.source_port().unwrap_or(0)
+        ),
+    }
+    transport::output(
+        ctx,
+        &FirewallLog {
+            ip: source_ip,
+            destination_ip: packet.destination_ip,
+            timestamp: unsafe { bpf_ktime_get_ns() },
+            rule_id,
+            length,
+            ifindex: ctx.ifindex(),
+            port: packet.source_port().unwrap_or(0),
+            destination_port: packet.destination_port().unwrap_or(0),
+            protocol: packet.protocol,
+            status: status as u8,
+            ip_version: packet.ip_version,
+            direction,
+            icmp_type: packet.icmp_type().unwrap_or(0),
+            icmp_code: packet.icmp_code().unwrap_or(0),
+            _padding: [0; 2],
+        },
+    );
+}
+
+/// Check if the matched firewall rule tells source is allowed or denied.
+/// If status is true, its allowed.
+/// If status is false, its denied.
+/// Sources without a matching rule get the default action.
+/// Rate limit rules allow the traffic within their limit and drop the excess.
+/// Traffic is counted per rule and protocol, drops also per source and rule.
+/// Packets of rules with `log` set are all logged, other drops only when sampled.
+fn checked_firewall_rule<C: PacketContext>(
+    ctx: &C,
+    packet: &Packet,
+    rule: Option<&Rule>,
+    direction: u8,
+) -> bool {
+    let length: u32 = ctx.length();
+    let ifindex: u32 = ctx.ifindex();
+    let (status, rule_id, log): (bool, u32, bool) = match rule {
+        Some(rule) if rule.action != RULE_ACTION_FILTER => {
+            (!is_rate_limited(packet, rule, length), rule.id, rule.log())
+        }
+        Some(rule) => (rule.status(), rule.id, rule.log()),
+        None => (default_status(ifindex), DEFAULT_RULE_ID, false),
+    };
+    count_traffic(packet, rule_id, status, length as u64);
+    if !status {
+        count_drop(packet, rule_id, direction, length as u64);
+    }
+    if log || (!status && is_sampled(ifindex)) {
+        log_packet(ctx, packet, rule_id, status, direction, length);
+    }
+    status
+}
+
+/// Packets the parser rejects, truncated or with too many VLAN tags to find their
+/// IP header, get the default action, they would otherwise escape the rules.
+fn try_ebpf_firewall(ctx: XdpContext) -> Result<u32, ()> {
+    let packet: &mut Packet = match parse_packet(&ctx) {
+        Ok(Some(packet)) => packet,
+        Ok(None) => return Ok(xdp_action::XDP_PASS),
+        Err(_) if parse_error_status(&ctx) => return Ok(xdp_action::XDP_PASS),
+        Err(_) => return Ok(xdp_action::XDP_DROP),
+    };
+    let status: bool = parse::evaluate(
+        packet,
+        is_decapsulated(ctx.ifindex()),
+        |packet| ingress_status(&ctx, packet),
+        |_| tail_call(&ctx, &FIREWALL_INGRESS_PROGRAMS),
+    )?;
+    if status {
+        Ok(xdp_action::XDP_PASS)
+    } else {
+        Ok(xdp_action::XDP_DROP)
+    }
+}
+
+/// Evaluates the packet carried by a tunnel, the tunnel itself was allowed by
+/// `ebpf_firewall` and left in `FIREWALL_PACKET`.
+#[xdp]
+pub fn ebpf_firewall_tunnel(ctx: XdpContext) -> u32 {
+    let Some(slot) = FIREWALL_PACKET.get_ptr_mut(0) else {
+        return xdp_action::XDP_ABORTED;
+    };
+    let status: Result<bool, ()> = parse::evaluate_tunnel(
+        unsafe { &mut *slot },
+        |packet| decapsulate_into(&ctx, packet),
+        |packet| ingress_status(&ctx, packet),
+    );
+    match status {
+        Ok(true) => xdp_action::XDP_PASS,
+        Ok(false) => xdp_action::XDP_DROP,
+        Err(_) => xdp_action::XDP_ABORTED,
+    }
+}
+
+/// Status of a packet, or of the packet carried by a tunnel, against the ingress
+/// rules, shared by `ebpf_firewall` and `ebpf_firewall_tunnel`.
+#[inline(never)]
+fn ingress_status(ctx: &XdpContext, packet: &mut Packet) -> Result<bool, ()> {
+    let ifindex: u32 = ctx.ifindex();
+    if is_ndp(packet) || is_exempted(packet, ifindex) {
+        return Ok(true);
+    }
+    packet.connection_state = conntrack::connection_state(ctx, packet);
+    let rule: &mut Rule = matched_rule()?;
+    let matched: bool = if packet.ip_version == 6 {
+        lookup_rule_v6(packet, DIRECTION_INGRESS, ifindex, rule)
+    } else {
+        lookup_rule_v4(packet, DIRECTION_INGRESS, ifindex, rule)
+    };
+    Ok(checked_firewall_rule(
+        ctx,
+        packet,
+        matched.then_some(rule),
+        DIRECTION_INGRESS,
+    ))
+}
+
+/// Only the packets an egress rule applies to are counted and logged, dropped
+/// packets don't open a flow. The rules are looked up with the packet seen from
+/// the remote end, the destination of a packet leaving this host takes the place
+/// of the source. Packets the parser rejects get the default action, as on ingress.
+fn try_ebpf_firewall_egress(ctx: TcContext) -> Result<i32, ()> {
+    let packet: &mut Packet = match parse_packet(&ctx) {
+        Ok(Some(packet)) => packet,
+        Ok(None) => return Ok(TC_ACT_PIPE),
+        Err(_) if parse_error_status(&ctx) => return Ok(TC_ACT_PIPE),
+        Err(_) => return Ok(TC_ACT_SHOT),
+    };
+    let status: bool = parse::evaluate(
+        packet,
+        is_decapsulated(ctx.ifindex()),
+        |packet| egress_status(&ctx, packet),
+        |_| tail_call(&ctx, &FIREWALL_EGRESS_PROGRAMS),
+    )?;
+    if status {
+        Ok(TC_ACT_PIPE)
+    } else {
+        Ok(TC_ACT_SHOT)
+    }
+}
+
+/// Evaluates the packet carried by a tunnel, the tunnel itself was allowed by
+/// `ebpf_firewall_egress` and left in `FIREWALL_PACKET`.
+#[classifier]
+pub fn ebpf_firewall_egress_tunnel(ctx: TcContext) -> i32 {
+    let Some(slot) = FIREWALL_PACKET.get_ptr_mut(0) else {
+        return TC_ACT_SHOT;
+    };
+    let status: Result<bool, ()> = parse::evaluate_tunnel(
+        unsafe { &mut *slot },
+        |packet| decapsulate_into(&ctx, packet),
+        |packet| egress_status(&ctx, packet),
+    );
+    match status {
+        Ok(true) => TC_ACT_PIPE,
+        _ => TC_ACT_SHOT,
+    }
+}
+
+/// Continues with the `PROGRAM_TUNNEL` program of `programs`, the packets carried
+/// by tunnels are evaluated there so each program verifies the rules only once.
+/// Only returns when the program is missing, the packet is then dropped rather
+/// than let through unevaluated.
+fn tail_call<C: EbpfContext>(ctx: &C, programs: &ProgramArray) -> Result<bool, ()> {
+    let _ = unsafe { programs.tail_call(ctx, PROGRAM_TUNNEL) };
+    Ok(false)
+}
+
+/// Status of a packet, or of the packet carried by a tunnel, against the egress
+/// rules. The flows of the tunnel and of the packet it carries are both tracked,
+/// the replies are matched against the outer packet first on ingress too. Always
+/// inlined, the verifier goes past its limit on it as a subprogram, the flow keys
+/// and the reversed packet are kept out of the program frame instead.
+#[inline(always)]
+fn egress_status(ctx: &TcContext, packet: &mut Packet) -> Result<bool, ()> {
+    if !is_ndp(packet) {
+        let connection_state: u8 = conntrack::egress_connection_state(packet);
+        if !egress_rule_status(ctx, packet, connection_state)? {
+            return Ok(false);
+        }
+    }
+    conntrack::track_egress(packet);
+    Ok(true)
+}
+
+/// Status against the egress rules of the packet seen from the remote end, which
+/// is given back as it was. Not inlined, the reversed copy stays in its frame.
+#[inline(never)]
+fn egress_rule_status(
+    ctx: &TcContext,
+    packet: &mut Packet,
+    connection_state: u8,
+) -> Result<bool, ()> {
+    let ifindex: u32 = ctx.ifindex();
+    packet.reverse();
+    if !is_exempted(packet, ifindex) {
+        packet.connection_state = connection_state;
+        let rule: &mut Rule = matched_rule()?;
+        let matched: bool = if packet.ip_version == 6 {
+            lookup_rule_v6(packet, DIRECTION_EGRESS, ifindex, rule)
+        } else {
+            lookup_rule_v4(packet, DIRECTION_EGRESS, ifindex, rule)
+        };
+        if matched && !checked_firewall_rule(ctx, packet, Some(rule), DIRECTION_EGRESS) {
+            return Ok(false);
+        }
+    }
+    packet.reverse();
+    Ok(true)
+}
+
+/// Slot the header offsets go through on their way out of the parser, see
+/// `ContextBytes::opaque`.
+#[map]
+static FIREWALL_SCRATCH: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+/// Reads the packet of an XDP or TC context for the parser.
+struct ContextBytes<'a, C>(&'a C);
+
+impl<C: PacketContext> PacketBytes for ContextBytes<'_, C> {
+    fn read<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        let bytes: *const [u8; N] = unsafe { ptr_at(self.0, offset).ok()? };
+        Some(unsafe { *bytes })
+    }
+
+    /// The verifier knows nothing of a value read back from a map, so the states
+    /// of the extension header and tunnel paths converge on the same offset
+    /// instead of being followed once for each header length.
+    fn opaque(&self, offset: usize) -> usize {
+        let Some(slot) = FIREWALL_SCRATCH.get_ptr_mut(0) else {
+            return usize::MAX;
+        };
+        unsafe {
+            core::ptr::write_volatile(slot, offset as u64);
+            core::ptr::read_volatile(slot) as usize
+        }
+    }
+}
+
+/// Parse the IP and transport headers, `None` for non IP packets.
+fn parse_packet<C: PacketContext>(ctx: &C) -> Result<Option<&'static mut Packet>, ParseError> {
+    let Some(slot) = FIREWALL_PACKET.get_ptr_mut(0) else {
+        return Ok(None);
+    };
+    let packet: &'static mut Packet = unsafe { &mut *slot };
+    parse_into(ctx, packet)?;
+    Ok((packet.ip_version != 0).then_some(packet))
+}
+
+/// The packet being processed, `ip_version` is 0 for non IP packets. The rest
+/// of the program reads it from here whatever path the parser took.
+#[map]
+static FIREWALL_PACKET: PerCpuArray<Packet> = PerCpuArray::with_max_entries(1, 0);
+
+/// Parse the packet into `slot`. Not inlined, its stack frame is released before
+/// the rules are matched.
+#[inline(never)]
+fn parse_into<C: PacketContext>(ctx: &C, slot: &mut Packet) -> Result<(), ParseError> {
+    match parse::parse_packet(&ContextBytes(ctx))? {
+        Some(packet) => *slot = packet,
+        None => slot.ip_version = 0,
+    }
+    Ok(())
+}
+
+/// Tunnels are decapsulated when the agent enabled it on the interface.
+fn is_decapsulated(ifindex: u32) -> bool {
+    match interface_config(ifindex) {
+        Some(config) => config.decapsulate != 0,
+        None => false,
+    }
+}
+
+/// Replace the tunnel in `slot` with the packet it carries, see `parse::decapsulate`.
+/// Not inlined for the same reason as `parse_into`.
+#[inline(never)]
+fn decapsulate_into<C: PacketContext>(ctx: &C, slot: &mut Packet) -> bool {
+    parse::decapsulate(&ContextBytes(ctx), slot)
+}
+
+/// Check if the packet is an ICMPv6 neighbour discovery message.
+fn is_ndp(packet: &Packet) -> bool {
+    match packet.icmp_type() {
+        Some(icmp_type) => {
+            packet.ip_version == 6
+                && icmp_type >= ICMPV6_NDP_TYPES.0
+                && icmp_type <= ICMPV6_NDP_TYPES.1
+        }
+        None => false,
+    }
+}
+
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
+
+#[link_section = "license"]
+#[no_mangle]
+static LICENSE: [u8; 13] = *b"Dual MIT/GPL\0";
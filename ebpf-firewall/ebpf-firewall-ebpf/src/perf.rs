@@ -0,0 +1,10 @@
+#![no_std]
+#![no_main]
+
+//! The firewall reporting dropped packets through a perf event array, loaded on
+//! kernels without BPF ring buffers.
+
+#[path = "transport/perf_event_array.rs"]
+mod transport;
+
+include!("firewall.rs");
@@ -0,0 +1,11 @@
+use aya_ebpf::{macros::map, maps::PerfEventArray, EbpfContext};
+
+use crate::FirewallLog;
+
+/// Lost logs are reported to the reader of each CPU buffer.
+#[map]
+static FIREWALL_LOG: PerfEventArray<FirewallLog> = PerfEventArray::new(0);
+
+pub fn output<C: EbpfContext>(ctx: &C, log: &FirewallLog) {
+    FIREWALL_LOG.output(ctx, log, 0);
+}
@@ -0,0 +1,23 @@
+use aya_ebpf::{
+    macros::map,
+    maps::{PerCpuArray, RingBuf},
+    EbpfContext,
+};
+use ebpf_firewall_common::LOG_RING_BUF_SIZE;
+
+use crate::FirewallLog;
+
+#[map]
+static FIREWALL_LOG: RingBuf = RingBuf::with_byte_size(LOG_RING_BUF_SIZE, 0);
+
+/// Number of logs that did not fit in `FIREWALL_LOG`, per CPU.
+#[map]
+static FIREWALL_LOG_LOST: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+pub fn output<C: EbpfContext>(_ctx: &C, log: &FirewallLog) {
+    if FIREWALL_LOG.output(log, 0).is_err() {
+        if let Some(lost) = FIREWALL_LOG_LOST.get_ptr_mut(0) {
+            unsafe { *lost += 1 };
+        }
+    }
+}
@@ -0,0 +1,3 @@
+pub use agent_common::{
+    hostname, kernel_release, AgentHeartbeat, AgentKind, AgentMetrics, InterfaceState, LostEvents,
+};
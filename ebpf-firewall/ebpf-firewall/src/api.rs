@@ -1,28 +1,99 @@
-use reqwest::{self, ClientBuilder};
+use std::time::Duration;
 
-use crate::{config::ApiServerConfig, log::FirewallLogData, rule::FirewallRuleData};
+use anyhow::anyhow;
+use log_shipper::{Shipper, ShipperConfig};
+use reqwest::{
+    self,
+    header::{ETAG, IF_NONE_MATCH},
+    Client, StatusCode,
+};
+use serde::Serialize;
 
+use crate::{
+    agent::AgentHeartbeat,
+    config::ApiServerConfig,
+    log::FirewallLogData,
+    rule::{FirewallRuleData, FirewallRuleSet},
+    stats::FirewallStatsReport,
+};
+
+/// Time to connect to the API server, for every request including the rule stream.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time for a whole request, the rule stream has none as it is left open.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The server sends a keep-alive every 15 seconds, a longer silence means the
+/// connection is gone.
+const STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Agent and interface the rules are requested for, the API server leaves out
+/// rules scoped to other agents or interfaces.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RuleScope {
+    pub agent: Option<String>,
+    pub interface: Option<String>,
+}
+
+/// Client of the API server, its clones share the connections.
 #[derive(Debug, Clone)]
 pub struct Api {
     base_url: String,
+    scope: RuleScope,
+    client: Client,
 }
 
 impl Api {
-    pub fn new(api_server_config: ApiServerConfig) -> Self {
-        Self {
+    pub fn new(api_server_config: ApiServerConfig) -> Result<Self, anyhow::Error> {
+        let client: Client = Client::builder().connect_timeout(CONNECT_TIMEOUT).build()?;
+        Ok(Self {
             base_url: api_server_config.base_url,
-        }
+            scope: RuleScope::default(),
+            client,
+        })
     }
 
-    pub async fn send_firewall_log(
+    pub fn with_scope(mut self, scope: RuleScope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Starts shipping firewall logs to the bulk endpoint in batches.
+    pub fn ship_firewall_logs(
         &self,
-        data: FirewallLogData,
-    ) -> Result<FirewallLogData, anyhow::Error> {
-        let client = ClientBuilder::new().build()?;
-        let url: String = format!("{}/firewall-log/create", self.base_url);
-        let response = client.post(url).json(&data).send().await?;
-        let data = response.json::<FirewallLogData>().await?;
-        Ok(data)
+        config: ShipperConfig,
+    ) -> Result<Shipper<FirewallLogData>, anyhow::Error> {
+        let url: String = format!("{}/firewall-log/bulk", self.base_url);
+        Ok(Shipper::spawn("firewall-log", url, config)?)
+    }
+
+    /// Registers the agent with the API server or refreshes its status.
+    pub async fn send_heartbeat(&self, data: &AgentHeartbeat) -> Result<(), anyhow::Error> {
+        let url: String = format!("{}/agent/heartbeat", self.base_url);
+        self.client
+            .post(url)
+            .timeout(REQUEST_TIMEOUT)
+            .json(data)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Replaces the traffic counters the API server keeps for the agent and interface.
+    pub async fn send_firewall_stats(
+        &self,
+        data: &FirewallStatsReport,
+    ) -> Result<(), anyhow::Error> {
+        let url: String = format!("{}/firewall/stats", self.base_url);
+        self.client
+            .post(url)
+            .timeout(REQUEST_TIMEOUT)
+            .json(data)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
     }
 
     pub async fn load_firewall_rules(
@@ -30,10 +101,114 @@ impl Api {
         layer: u8,
     ) -> Result<Vec<FirewallRuleData>, anyhow::Error> {
         let url: String = format!("{}/firewall-rule/list/{}", self.base_url, layer);
-        let response = reqwest::get(url).await?;
+        let response = self
+            .client
+            .get(url)
+            .timeout(REQUEST_TIMEOUT)
+            .query(&self.scope)
+            .send()
+            .await?;
         let data = response.json::<Vec<FirewallRuleData>>().await?;
         Ok(data)
     }
+
+    /// Loads the rules unless the rule set is still at `version`, in which case
+    /// `None` is returned. The version is carried by the `ETag` header.
+    pub async fn load_firewall_rules_if_changed(
+        &self,
+        layer: u8,
+        version: Option<u64>,
+    ) -> Result<Option<FirewallRuleSet>, anyhow::Error> {
+        let url: String = format!("{}/firewall-rule/list/{}", self.base_url, layer);
+        let mut request = self
+            .client
+            .get(url)
+            .timeout(REQUEST_TIMEOUT)
+            .query(&self.scope);
+        if let Some(version) = version {
+            request = request.header(IF_NONE_MATCH, format!("\"{}\"", version));
+        }
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+        // A missing version never matches, the next poll loads the rules again.
+        let version: u64 = response
+            .headers()
+            .get(ETAG)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim_matches('"').parse().ok())
+            .unwrap_or(0);
+        let rules = response.json::<Vec<FirewallRuleData>>().await?;
+        Ok(Some(FirewallRuleSet { version, rules }))
+    }
+
+    /// Opens the server-sent events stream of the rules of the layer. The stream
+    /// has no request timeout, a silent server is caught by `STREAM_IDLE_TIMEOUT`.
+    pub async fn subscribe_firewall_rules(
+        &self,
+        layer: u8,
+    ) -> Result<FirewallRuleStream, anyhow::Error> {
+        let url: String = format!("{}/firewall-rule/stream/{}", self.base_url, layer);
+        let response = self
+            .client
+            .get(url)
+            .query(&self.scope)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(FirewallRuleStream {
+            response,
+            buffer: Vec::new(),
+        })
+    }
+}
+
+/// Rule sets pushed by the API server, the current one first and then one per change.
+pub struct FirewallRuleStream {
+    response: reqwest::Response,
+    buffer: Vec<u8>,
+}
+
+impl FirewallRuleStream {
+    /// Waits for the next rule set, `None` once the server closed the stream.
+    pub async fn next(&mut self) -> Result<Option<FirewallRuleSet>, anyhow::Error> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|value| value == b"\n\n") {
+                let event: Vec<u8> = self.buffer.drain(..end + 2).collect();
+                if let Some(rule_set) = parse_rule_event(&String::from_utf8_lossy(&event))? {
+                    return Ok(Some(rule_set));
+                }
+                continue;
+            }
+            match tokio::time::timeout(STREAM_IDLE_TIMEOUT, self.response.chunk()).await {
+                Ok(Ok(Some(chunk))) => self.buffer.extend_from_slice(&chunk),
+                Ok(Ok(None)) => return Ok(None),
+                Ok(Err(error)) => return Err(anyhow!(error.to_string())),
+                Err(_) => return Err(anyhow!("no data on the rule stream")),
+            }
+        }
+    }
+}
+
+/// Parses a server-sent event, only `rules` events carry a rule set.
+fn parse_rule_event(event: &str) -> Result<Option<FirewallRuleSet>, anyhow::Error> {
+    let mut name: &str = "message";
+    let mut data: Vec<&str> = Vec::new();
+    for line in event.lines() {
+        if let Some(value) = line.strip_prefix("event:") {
+            name = value.trim();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.strip_prefix(' ').unwrap_or(value));
+        }
+    }
+    if name != "rules" || data.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str::<FirewallRuleSet>(
+        &data.join("\n"),
+    )?))
 }
 
 #[cfg(test)]
@@ -49,8 +224,23 @@ pub mod test_api {
         assert!(app_config.is_ok(), "{:?}", app_config.err());
         let api_server_config: ApiServerConfig = app_config.unwrap().api_server;
 
-        let api: Api = Api::new(api_server_config);
+        let api: Api = Api::new(api_server_config).unwrap();
         let data = api.load_firewall_rules(3).await;
         assert!(data.is_ok(), "{:?}", data.err());
     }
+
+    #[test]
+    fn test_parse_rule_event() {
+        let result = parse_rule_event(": keep-alive\n\n");
+        assert!(matches!(result, Ok(None)), "comments carry no rule set");
+
+        let result = parse_rule_event(
+            "event: rules\nid: 7\ndata: {\"version\":7,\"rules\":[{\"ip\":[10,0,0,0],\"protocol\":\"Tcp\",\"cidr\":8,\"from_port\":null,\"to_port\":null,\"status\":false}]}\n\n",
+        );
+        assert!(result.is_ok(), "{:?}", result.err());
+        let rule_set = result.unwrap().unwrap();
+        assert_eq!(rule_set.version, 7);
+        assert_eq!(rule_set.rules.len(), 1);
+        assert_eq!(rule_set.rules[0].cidr, 8);
+    }
 }
@@ -0,0 +1,33 @@
+use aya::programs::XdpFlags;
+use serde::{Deserialize, Serialize};
+
+/// How the XDP program is attached, `Skb` works on every driver at the cost of
+/// running after the socket buffer is allocated.
+#[derive(Serialize, PartialEq, Eq, Deserialize, Clone, Debug, Default)]
+pub enum AttachMode {
+    #[default]
+    Default,
+    Skb,
+    Driver,
+    Hardware,
+}
+
+impl std::fmt::Display for AttachMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Default => write!(f, "Default"),
+            Self::Skb => write!(f, "Skb"),
+            Self::Driver => write!(f, "Driver"),
+            Self::Hardware => write!(f, "Hardware"),
+        }
+    }
+}
+
+pub fn get_xdp_flags(attach_mode: AttachMode) -> XdpFlags {
+    match attach_mode {
+        AttachMode::Default => XdpFlags::default(),
+        AttachMode::Skb => XdpFlags::SKB_MODE,
+        AttachMode::Driver => XdpFlags::DRV_MODE,
+        AttachMode::Hardware => XdpFlags::HW_MODE,
+    }
+}
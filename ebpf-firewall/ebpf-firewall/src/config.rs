@@ -1,24 +1,153 @@
 use std::{fs::File, io::Read};
 
 use anyhow::anyhow;
+use log_shipper::ShipperConfig;
 use serde::{Deserialize, Serialize};
 use toml;
 
+use crate::{agent::hostname, attach_mode::AttachMode, policy::DefaultAction};
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ApiServerConfig {
     pub base_url: String,
 }
 
+/// Policy of an interface, the fields left out take the values of `EbpfConfig`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InterfaceConfig {
+    pub name: String,
+    #[serde(default)]
+    pub attach_mode: Option<AttachMode>,
+    #[serde(default)]
+    pub default_action: Option<DefaultAction>,
+    /// Added to the exemptions of `EbpfConfig`.
+    #[serde(default)]
+    pub exemptions: Vec<String>,
+    #[serde(default)]
+    pub decapsulate: Option<bool>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct EbpfConfig {
     pub layer: u8,
-    pub interface: String,
+    /// The interface to attach to, when it is the only one.
+    #[serde(default)]
+    pub interface: Option<String>,
+    /// Interfaces to attach to, each with its own policy.
+    #[serde(default)]
+    pub interfaces: Vec<InterfaceConfig>,
+    /// Attach to every interface but the `exclude_interfaces`, including the ones
+    /// added later. `interfaces` then only set policies. A trailing `*` matches
+    /// a name prefix, as in `veth*`.
+    #[serde(default)]
+    pub all_interfaces: bool,
+    #[serde(default)]
+    pub exclude_interfaces: Vec<String>,
+    /// Seconds between rule polls while the rule stream is down.
     pub fwr_update_duration: u64,
+    /// Action for packets not matched by any rule, `Deny` for an allow-list posture.
+    #[serde(default)]
+    pub default_action: DefaultAction,
+    /// Sources (`ip` or `ip/cidr`) that are never filtered. The API server
+    /// address is always exempted.
+    #[serde(default)]
+    pub exemptions: Vec<String>,
+    /// Name the agent registers and requests its rules with, the host name when not set.
+    #[serde(default)]
+    pub agent: Option<String>,
+    #[serde(default)]
+    pub attach_mode: AttachMode,
+    /// Seconds between heartbeats to the API server.
+    #[serde(default = "default_heartbeat_duration")]
+    pub heartbeat_duration: u64,
+    /// Dropped packets are logged as counts per source and rule every
+    /// `drop_log_window` seconds, plus one in `log_sample_rate` packets on its own.
+    /// 0 disables the sampled logs.
+    #[serde(default)]
+    pub log_sample_rate: u32,
+    #[serde(default = "default_drop_log_window")]
+    pub drop_log_window: u64,
+    /// Seconds between the rule and protocol traffic reports to the API server.
+    #[serde(default = "default_stats_duration")]
+    pub stats_duration: u64,
+    /// Match GRE, VXLAN and Geneve packets by the packet they carry rather than by
+    /// the tunnel. VLAN tags are matched on the frame as received, NICs stripping
+    /// them (`rxvlan` offload) hide them from the firewall.
+    #[serde(default)]
+    pub decapsulate: bool,
+}
+
+fn default_heartbeat_duration() -> u64 {
+    30
+}
+
+fn default_drop_log_window() -> u64 {
+    10
+}
+
+fn default_stats_duration() -> u64 {
+    60
+}
+
+impl EbpfConfig {
+    pub fn agent_name(&self) -> String {
+        match &self.agent {
+            Some(agent) => agent.clone(),
+            None => hostname(),
+        }
+    }
+
+    /// Whether the agent attaches to the interface named `name`.
+    pub fn selects(&self, name: &str) -> bool {
+        if self.all_interfaces {
+            return !self.exclude_interfaces.iter().any(|pattern| {
+                match pattern.strip_suffix('*') {
+                    Some(prefix) => name.starts_with(prefix),
+                    None => name == pattern,
+                }
+            });
+        }
+        self.interface.as_deref() == Some(name)
+            || self
+                .interfaces
+                .iter()
+                .any(|interface| interface.name == name)
+    }
+
+    /// The configuration of the interface named `name`, with its policy applied.
+    pub fn for_interface(&self, name: &str) -> EbpfConfig {
+        let mut config: EbpfConfig = EbpfConfig {
+            interface: Some(name.to_string()),
+            interfaces: Vec::new(),
+            all_interfaces: false,
+            exclude_interfaces: Vec::new(),
+            ..self.clone()
+        };
+        if let Some(policy) = self
+            .interfaces
+            .iter()
+            .find(|interface| interface.name == name)
+        {
+            if let Some(attach_mode) = &policy.attach_mode {
+                config.attach_mode = attach_mode.clone();
+            }
+            if let Some(default_action) = &policy.default_action {
+                config.default_action = default_action.clone();
+            }
+            if let Some(decapsulate) = policy.decapsulate {
+                config.decapsulate = decapsulate;
+            }
+            config.exemptions.extend(policy.exemptions.iter().cloned());
+        }
+        config
+    }
 }
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AppConfig {
     pub api_server: ApiServerConfig,
     pub ebpf: EbpfConfig,
+    #[serde(default)]
+    pub shipper: ShipperConfig,
 }
 
 impl AppConfig {
@@ -39,3 +168,61 @@ impl AppConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod test_config {
+    use super::*;
+
+    fn ebpf_config() -> EbpfConfig {
+        toml::from_str(
+            r#"
+            layer = 3
+            fwr_update_duration = 5
+            exemptions = ["10.0.0.1"]
+            exclude_interfaces = ["lo", "veth*"]
+
+            [[interfaces]]
+            name = "eth1"
+            attach_mode = "Skb"
+            default_action = "Deny"
+            exemptions = ["192.168.0.0/16"]
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_selects() {
+        let mut config: EbpfConfig = ebpf_config();
+        config.interface = Some("eth0".to_string());
+        assert!(config.selects("eth0"));
+        assert!(config.selects("eth1"));
+        assert!(
+            !config.selects("eth2"),
+            "expected only the listed interfaces"
+        );
+
+        config.all_interfaces = true;
+        assert!(config.selects("eth2"));
+        assert!(!config.selects("lo"));
+        assert!(
+            !config.selects("veth1a2b"),
+            "expected the prefix to be excluded"
+        );
+    }
+
+    #[test]
+    fn test_for_interface() {
+        let config: EbpfConfig = ebpf_config();
+        let eth1: EbpfConfig = config.for_interface("eth1");
+        assert_eq!(eth1.interface, Some("eth1".to_string()));
+        assert_eq!(eth1.attach_mode, AttachMode::Skb);
+        assert_eq!(eth1.default_action, DefaultAction::Deny);
+        assert_eq!(eth1.exemptions, vec!["10.0.0.1", "192.168.0.0/16"]);
+
+        let eth2: EbpfConfig = config.for_interface("eth2");
+        assert_eq!(eth2.attach_mode, AttachMode::Default);
+        assert_eq!(eth2.default_action, DefaultAction::Allow);
+        assert_eq!(eth2.exemptions, vec!["10.0.0.1"]);
+    }
+}
@@ -0,0 +1,32 @@
+use ebpf_firewall_common::{
+    CONNECTION_STATE_ANY, CONNECTION_STATE_ESTABLISHED, CONNECTION_STATE_NEW,
+};
+use serde::{Deserialize, Serialize};
+
+/// Connection tracking state a rule applies to. `Established` also covers ICMP
+/// errors related to a tracked flow.
+#[derive(Serialize, PartialEq, Eq, Deserialize, Clone, Debug, Default)]
+pub enum ConnectionState {
+    #[default]
+    Any,
+    New,
+    Established,
+}
+
+impl std::fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Any => write!(f, "Any"),
+            Self::New => write!(f, "New"),
+            Self::Established => write!(f, "Established"),
+        }
+    }
+}
+
+pub fn get_connection_state(state: ConnectionState) -> u8 {
+    match state {
+        ConnectionState::Any => CONNECTION_STATE_ANY,
+        ConnectionState::New => CONNECTION_STATE_NEW,
+        ConnectionState::Established => CONNECTION_STATE_ESTABLISHED,
+    }
+}
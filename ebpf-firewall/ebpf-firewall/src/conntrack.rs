@@ -0,0 +1,11 @@
+pub use ebpf_firewall_common::conntrack::{ConntrackEntry, ConntrackKey};
+
+/// Current `CLOCK_MONOTONIC` time, the clock used by `bpf_ktime_get_ns`.
+pub fn monotonic_now_ns() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64
+}
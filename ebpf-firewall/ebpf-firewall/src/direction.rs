@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, PartialEq, Eq, Deserialize, Clone, Debug, Default)]
+pub enum Direction {
+    #[default]
+    Ingress,
+    Egress,
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ingress => write!(f, "Ingress"),
+            Self::Egress => write!(f, "Egress"),
+        }
+    }
+}
+
+pub fn get_direction(direction: Direction) -> u8 {
+    match direction {
+        Direction::Ingress => 0,
+        Direction::Egress => 1,
+    }
+}
+
+pub fn get_direction_from_u8(direction: u8) -> Direction {
+    match direction {
+        1 => Direction::Egress,
+        _ => Direction::Ingress,
+    }
+}
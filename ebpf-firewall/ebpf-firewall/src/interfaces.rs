@@ -0,0 +1,111 @@
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+use anyhow::anyhow;
+use tokio::io::{unix::AsyncFd, Interest};
+
+/// A network interface of the host, its index changes when it is added again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Interface {
+    pub name: String,
+    pub ifindex: u32,
+}
+
+/// Lists the network interfaces of the host, by name.
+pub fn list_interfaces() -> Result<Vec<Interface>, anyhow::Error> {
+    let mut interfaces: Vec<Interface> = Vec::new();
+    for entry in std::fs::read_dir("/sys/class/net")? {
+        let entry = entry?;
+        // The interface may be gone by now, it is left out.
+        let Ok(ifindex) = std::fs::read_to_string(entry.path().join("ifindex")) else {
+            continue;
+        };
+        interfaces.push(Interface {
+            name: entry.file_name().to_string_lossy().to_string(),
+            ifindex: ifindex
+                .trim()
+                .parse()
+                .map_err(|error| anyhow!("invalid ifindex {}: {}", ifindex.trim(), error))?,
+        });
+    }
+    interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(interfaces)
+}
+
+/// Netlink socket notified of the link changes, interfaces being added, removed
+/// or renamed.
+pub struct LinkMonitor {
+    socket: AsyncFd<OwnedFd>,
+}
+
+impl LinkMonitor {
+    pub fn new() -> Result<Self, anyhow::Error> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(anyhow!("netlink socket: {}", io::Error::last_os_error()));
+        }
+        let socket: OwnedFd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as u16;
+        address.nl_groups = libc::RTMGRP_LINK as u32;
+        let ret = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(anyhow!("netlink bind: {}", io::Error::last_os_error()));
+        }
+        Ok(Self {
+            socket: AsyncFd::with_interest(socket, Interest::READABLE)?,
+        })
+    }
+
+    /// Waits for link changes. The messages are discarded, the interfaces are
+    /// listed again instead.
+    pub async fn changed(&self) -> Result<(), anyhow::Error> {
+        let mut buffer: [u8; 8192] = [0; 8192];
+        loop {
+            let mut guard = self.socket.readable().await?;
+            let mut received: bool = false;
+            loop {
+                let ret = unsafe {
+                    libc::recv(
+                        self.socket.as_raw_fd(),
+                        buffer.as_mut_ptr() as *mut libc::c_void,
+                        buffer.len(),
+                        0,
+                    )
+                };
+                if ret >= 0 {
+                    received = true;
+                    continue;
+                }
+                let error = io::Error::last_os_error();
+                match error.raw_os_error() {
+                    Some(libc::EAGAIN) => {
+                        guard.clear_ready();
+                        break;
+                    }
+                    // Messages were lost, listing the interfaces catches up anyway.
+                    Some(libc::ENOBUFS) => received = true,
+                    Some(libc::EINTR) => {}
+                    _ => return Err(anyhow!("netlink recv: {}", error)),
+                }
+            }
+            if received {
+                return Ok(());
+            }
+        }
+    }
+}
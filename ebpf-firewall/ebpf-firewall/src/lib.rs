@@ -1,6 +1,15 @@
+pub mod agent;
 pub mod api;
+pub mod attach_mode;
 pub mod config;
+pub mod connection_state;
+pub mod conntrack;
+pub mod direction;
+pub mod interfaces;
 pub mod log;
 pub mod maps;
+pub mod policy;
 pub mod protocol;
 pub mod rule;
+pub mod rule_action;
+pub mod stats;
@@ -1,20 +1,30 @@
-use bytemuck::{Pod, Zeroable};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::protocol::IpProtocol;
-#[repr(C)]
-#[derive(Clone, Copy, Debug, Pod, Zeroable)]
-pub struct FirewallLog {
-    pub ip: [u8; 4],
-    pub port: u16,
-    pub protocol: u8,
-    pub status: u8,
-}
-
+use crate::{direction::Direction, protocol::IpProtocol};
+/// A single packet has `packets` 1 and `window` 0, an aggregate counts the
+/// packets dropped from `ip` by `rule` in the `window` seconds before `timestamp`.
+/// `rule` is the key of the rule record, `None` for the default action. The
+/// destination, `length` and `ifindex` are only known for single packets. The
+/// `ip` of `Egress` logs is the destination the packets were sent to. ICMP logs
+/// of single packets have the `icmp_type` and `icmp_code` rather than ports.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct FirewallLogData {
-    pub ip: [u8; 4],
+    pub ip: Vec<u8>,
     pub protocol: IpProtocol,
     pub port: Option<u16>,
     pub status: bool,
+    /// Time the event was read, kept while the event waits in the spool.
+    pub timestamp: DateTime<Utc>,
+    pub packets: u64,
+    pub bytes: u64,
+    pub window: u64,
+    pub rule: Option<String>,
+    pub destination_ip: Option<Vec<u8>>,
+    pub destination_port: Option<u16>,
+    pub length: Option<u32>,
+    pub ifindex: Option<u32>,
+    pub direction: Direction,
+    pub icmp_type: Option<u8>,
+    pub icmp_code: Option<u8>,
 }
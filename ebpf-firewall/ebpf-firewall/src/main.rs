@@ -1,16 +1,38 @@
-use anyhow::Context as _;
-use aya::programs::{Xdp, XdpFlags};
+use anyhow::{anyhow, Context as _};
+use aya::programs::{
+    tc::{self, SchedClassifierLinkId},
+    xdp::XdpLinkId,
+    ProgramFd, SchedClassifier, TcAttachType, Xdp,
+};
 use clap::Parser;
+use log_shipper::{Shipper, ShipperConfig};
 #[rustfmt::skip]
-use log::{debug, warn};
-use aya::maps::{lpm_trie::LpmTrie, HashMap};
+use log::{debug, info, warn};
+use std::{collections::BTreeMap, time::Duration};
+
+use aya::maps::{lpm_trie::LpmTrie, HashMap, MapData, PerCpuHashMap, ProgramArray};
 use ebpf_firewall::{
-    api::Api,
+    agent::{kernel_release, AgentHeartbeat, AgentKind, AgentMetrics, InterfaceState, LostEvents},
+    api::{Api, RuleScope},
+    attach_mode::get_xdp_flags,
     config::{ApiServerConfig, AppConfig, EbpfConfig},
-    maps::{configure_firewall_cidrs, configure_firewall_log, configure_firewall_rules},
-    rule::Rule,
+    conntrack::{ConntrackEntry, ConntrackKey},
+    interfaces::{list_interfaces, Interface, LinkMonitor},
+    log::FirewallLogData,
+    maps::{
+        collect_firewall_drops, configure_firewall_config, configure_firewall_exemptions,
+        configure_firewall_log, log_firewall_conntrack, remove_firewall_config,
+        remove_firewall_exemptions, DropAggregator, FirewallRuleMaps, FirewallStatsMaps,
+    },
+    policy::FirewallConfig,
+    rule::{FirewallRuleData, RuleIds},
+    stats::{FirewallStatsReport, TrafficStats},
 };
-use tokio::signal;
+use ebpf_firewall_common::{
+    log::{DropCount, DropKey},
+    PROGRAM_TUNNEL,
+};
+use tokio::{signal, sync::watch, task::JoinHandle};
 
 #[derive(Debug, Parser)]
 struct Opt {
@@ -29,6 +51,15 @@ async fn main() -> anyhow::Result<()> {
         Err(error) => panic!("{:?}", error.to_string()),
     };
     let ebpf_config: EbpfConfig = app_config.ebpf;
+    let shipper_config: ShipperConfig = app_config.shipper;
+    if !ebpf_config.all_interfaces
+        && ebpf_config.interface.is_none()
+        && ebpf_config.interfaces.is_empty()
+    {
+        return Err(anyhow!(
+            "no interface to attach to, set interface, interfaces or all_interfaces"
+        ));
+    }
     // Bump the memlock rlimit. This is needed for older kernels that don't use the
     // new memcg based accounting, see https://lwn.net/Articles/837122/
     let rlim = libc::rlimit {
@@ -39,74 +70,663 @@ async fn main() -> anyhow::Result<()> {
     if ret != 0 {
         debug!("remove limit on locked memory failed, ret is: {ret}");
     }
-    println!("Interface: {}", ebpf_config.interface);
+    let api_server_config: ApiServerConfig = app_config.api_server;
+    let api: Api = Api::new(api_server_config.clone())?;
+    let firewall_log_shipper = match api.ship_firewall_logs(shipper_config) {
+        Ok(value) => value,
+        Err(error) => panic!("{:?}", error),
+    };
+
+    // A single instance of the programs is attached to every interface, the rules,
+    // counters and tracked flows are shared and the policies are kept per interface.
+    let mut ebpf: aya::Ebpf = load_ebpf()?;
+    let rule_ids: RuleIds = RuleIds::default();
+    let lost_events: LostEvents =
+        configure_firewall_log(&firewall_log_shipper, &rule_ids, &mut ebpf)?;
+    // Opened before the stats task takes the rule counters it shares.
+    let firewall_rule_maps: FirewallRuleMaps = FirewallRuleMaps::new(&mut ebpf, rule_ids.clone())?;
+    spawn_firewall_tasks(
+        &api,
+        &ebpf_config,
+        &mut ebpf,
+        &rule_ids,
+        firewall_log_shipper.clone(),
+    )?;
+    let (attached_interfaces, attached_interfaces_rx) = watch::channel(BTreeMap::new());
+    let rule_api: Api = api.clone().with_scope(RuleScope {
+        agent: Some(ebpf_config.agent_name()),
+        interface: None,
+    });
+    tokio::task::spawn(sync_firewall_rules(
+        rule_api,
+        ebpf_config.layer,
+        ebpf_config.fwr_update_duration,
+        firewall_rule_maps,
+        attached_interfaces_rx,
+        agent_heartbeat(&ebpf_config),
+    ));
+    let firewall: Firewall = Firewall::new(ebpf)?;
+    tokio::task::spawn(watch_interfaces(
+        api,
+        api_server_config,
+        ebpf_config,
+        firewall,
+        attached_interfaces,
+        firewall_log_shipper,
+        lost_events,
+    ));
+
+    let ctrl_c = signal::ctrl_c();
+    println!("Waiting for Ctrl-C...");
+    ctrl_c.await?;
+    println!("Exiting...");
+
+    Ok(())
+}
+
+/// Loads the eBPF programs, attached to the interfaces later on.
+fn load_ebpf() -> Result<aya::Ebpf, anyhow::Error> {
     // This will include your eBPF object file as raw bytes at compile-time and load it at
     // runtime. This approach is recommended for most real-world use cases. If you would
     // like to specify the eBPF program at runtime rather than at compile-time, you can
     // reach for `Bpf::load_file` instead.
-    let layer: u8 = ebpf_config.layer;
-    let iface: String = ebpf_config.interface;
-    let api_server_config: ApiServerConfig = app_config.api_server;
-    let api: Api = Api::new(api_server_config.clone());
+    // Kernels before 5.8 have no BPF ring buffer, the build logging through a
+    // perf event array is loaded instead.
+    let mut ebpf = match aya::Ebpf::load(aya::include_bytes_aligned!(concat!(
+        env!("OUT_DIR"),
+        "/ebpf-firewall"
+    ))) {
+        Ok(value) => value,
+        Err(error) => {
+            warn!("failed to load the ring buffer build, falling back to perf events: {error}");
+            aya::Ebpf::load(aya::include_bytes_aligned!(concat!(
+                env!("OUT_DIR"),
+                "/ebpf-firewall-perf"
+            )))?
+        }
+    };
+    if let Err(e) = aya_log::EbpfLogger::init(&mut ebpf) {
+        // This can happen if you remove all log statements from your eBPF program.
+        warn!("failed to initialize eBPF logger: {e}");
+    }
+    let program: &mut Xdp = ebpf
+        .program_mut("ebpf_firewall")
+        .context("missing the XDP program")?
+        .try_into()?;
+    program.load()?;
+    let program: &mut SchedClassifier = ebpf
+        .program_mut("ebpf_firewall_egress")
+        .context("missing the egress classifier")?
+        .try_into()?;
+    program.load()?;
+
+    // The packets carried by tunnels are evaluated by the programs the attached
+    // ones tail call into, once the tunnel itself was allowed.
+    let program: &mut Xdp = ebpf
+        .program_mut("ebpf_firewall_tunnel")
+        .context("missing the XDP tunnel program")?
+        .try_into()?;
+    program.load()?;
+    let fd: ProgramFd = program.fd()?.try_clone()?;
+    set_tunnel_program(&mut ebpf, "FIREWALL_INGRESS_PROGRAMS", &fd)?;
+    let program: &mut SchedClassifier = ebpf
+        .program_mut("ebpf_firewall_egress_tunnel")
+        .context("missing the egress tunnel classifier")?
+        .try_into()?;
+    program.load()?;
+    let fd: ProgramFd = program.fd()?.try_clone()?;
+    set_tunnel_program(&mut ebpf, "FIREWALL_EGRESS_PROGRAMS", &fd)?;
+    Ok(ebpf)
+}
+
+/// Puts the tunnel program `fd` in the program array `name`.
+fn set_tunnel_program(
+    ebpf: &mut aya::Ebpf,
+    name: &str,
+    fd: &ProgramFd,
+) -> Result<(), anyhow::Error> {
+    let mut programs: ProgramArray<&mut MapData> = ProgramArray::try_from(
+        ebpf.map_mut(name)
+            .with_context(|| format!("missing {name}"))?,
+    )?;
+    programs.set(PROGRAM_TUNNEL, fd, 0)?;
+    Ok(())
+}
+
+/// Starts the tasks reporting the drops, traffic and tracked flows of all the
+/// interfaces.
+fn spawn_firewall_tasks(
+    api: &Api,
+    ebpf_config: &EbpfConfig,
+    ebpf: &mut aya::Ebpf,
+    rule_ids: &RuleIds,
+    firewall_log_shipper: Shipper<FirewallLogData>,
+) -> Result<(), anyhow::Error> {
     let fwr_update_duration = ebpf_config.fwr_update_duration;
+    let drop_log_window = ebpf_config.drop_log_window;
+    let stats_duration = ebpf_config.stats_duration;
+    let firewall_drops: PerCpuHashMap<MapData, DropKey, DropCount> = PerCpuHashMap::try_from(
+        ebpf.take_map("FIREWALL_DROPS")
+            .context("missing FIREWALL_DROPS")?,
+    )?;
+    let mut drop_aggregator: DropAggregator =
+        DropAggregator::new(rule_ids.clone(), drop_log_window);
     tokio::task::spawn(async move {
-        let mut ebpf = aya::Ebpf::load(aya::include_bytes_aligned!(concat!(
-            env!("OUT_DIR"),
-            "/ebpf-firewall"
-        )))
-        .unwrap();
-        if let Err(e) = aya_log::EbpfLogger::init(&mut ebpf) {
-            // This can happen if you remove all log statements from your eBPF program.
-            warn!("failed to initialize eBPF logger: {e}");
-        }
-        let program: &mut Xdp = ebpf
-            .program_mut("ebpf_firewall")
-            .unwrap()
-            .try_into()
-            .unwrap();
-        program.load().unwrap();
-        program.attach(&iface, XdpFlags::default())
-        .context("failed to attach the XDP program with default flags - try changing XdpFlags::default() to XdpFlags::SKB_MODE").unwrap();
+        loop {
+            tokio::time::sleep(Duration::from_secs(drop_log_window)).await;
+            match collect_firewall_drops(&firewall_drops, &mut drop_aggregator) {
+                Ok(logs) => logs
+                    .into_iter()
+                    .for_each(|log| firewall_log_shipper.send(log)),
+                Err(error) => warn!("{:?}", error),
+            }
+        }
+    });
 
-        let firewall_log_map = ebpf.take_map("FIREWALL_LOG").unwrap();
-        if let Err(error) = configure_firewall_log(&api, firewall_log_map).await {
-            panic!("{:?}", error);
+    // The counters are shared by the interfaces, they are reported for the agent.
+    let mut stats_report: FirewallStatsReport = FirewallStatsReport {
+        agent: ebpf_config.agent_name(),
+        interface: None,
+        rules: Vec::new(),
+        protocols: Vec::new(),
+        parse_errors: TrafficStats::default(),
+    };
+    let firewall_stats_maps: FirewallStatsMaps = FirewallStatsMaps::new(ebpf, rule_ids.clone())?;
+    let stats_api: Api = api.clone();
+    tokio::task::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(stats_duration)).await;
+            match (
+                firewall_stats_maps.rule_stats(),
+                firewall_stats_maps.protocol_stats(),
+                firewall_stats_maps.parse_errors(),
+            ) {
+                (Ok(rules), Ok(protocols), Ok(parse_errors)) => {
+                    stats_report.rules = rules;
+                    stats_report.protocols = protocols;
+                    stats_report.parse_errors = parse_errors;
+                }
+                (Err(error), _, _) | (_, Err(error), _) | (_, _, Err(error)) => {
+                    warn!("failed to read the firewall stats: {:?}", error);
+                    continue;
+                }
+            }
+            if let Err(error) = stats_api.send_firewall_stats(&stats_report).await {
+                warn!("failed to send the firewall stats: {:?}", error);
+            }
         }
+    });
+    let firewall_conntrack: HashMap<MapData, ConntrackKey, ConntrackEntry> = HashMap::try_from(
+        ebpf.take_map("FIREWALL_CONNTRACK")
+            .context("missing FIREWALL_CONNTRACK")?,
+    )?;
+    tokio::task::spawn(async move {
         loop {
-            for map in ebpf.maps_mut() {
-                let map_key: &str = map.0;
-                if map_key == "FIREWALL_RULES" {
-                    let mut firewall_rules: LpmTrie<_, [u8; 4], Rule> =
-                        match LpmTrie::try_from(map.1) {
-                            Ok(value) => value,
-                            Err(error) => panic!("{:?}", error),
-                        };
-
-                    if let Err(error) =
-                        configure_firewall_rules(&api, layer, &mut firewall_rules).await
-                    {
-                        panic!("{:?}", error);
-                    }
-                } else if map_key == "FIREWALL_CIDRS" {
-                    let mut firewall_cidrs: HashMap<_, u16, u16> = match HashMap::try_from(map.1) {
-                        Ok(value) => value,
-                        Err(error) => panic!("{:?}", error),
-                    };
-                    if let Err(error) =
-                        configure_firewall_cidrs(&api, layer, &mut firewall_cidrs).await
-                    {
-                        panic!("{:?}", error);
-                    }
+            if let Err(error) = log_firewall_conntrack(&firewall_conntrack) {
+                warn!("{:?}", error);
+            }
+            tokio::time::sleep(Duration::from_secs(fwr_update_duration)).await;
+        }
+    });
+    Ok(())
+}
+
+/// Applies the rules to the maps. A rejected rule set leaves the rules in place
+/// enforced, the error is reported to the API server with the heartbeat of the
+/// agent, and cleared once a rule set is applied again.
+async fn apply_firewall_rules(
+    api: &Api,
+    firewall_rule_maps: &mut FirewallRuleMaps,
+    rules: &[FirewallRuleData],
+    interfaces: &BTreeMap<String, u32>,
+    heartbeat: &mut AgentHeartbeat,
+) {
+    let error: Option<String> = match firewall_rule_maps.configure(rules.to_vec(), interfaces) {
+        Ok(()) => None,
+        Err(error) => {
+            warn!("failed to configure the firewall rules: {:?}", error);
+            Some(format!("firewall rules rejected: {:#}", error))
+        }
+    };
+    if heartbeat.error != error {
+        heartbeat.error = error;
+        if let Err(error) = api.send_heartbeat(heartbeat).await {
+            warn!("failed to send the agent heartbeat: {:?}", error);
+        }
+    }
+}
+
+/// Keeps the rule maps in sync with the rules of the agent. Rule changes are
+/// pushed by the API server, polling only covers the time until the stream is
+/// back. The rules in place stay enforced meanwhile. The rules are applied again
+/// when interfaces are attached or detached, for the rules restricted to them.
+async fn sync_firewall_rules(
+    api: Api,
+    layer: u8,
+    fwr_update_duration: u64,
+    mut firewall_rule_maps: FirewallRuleMaps,
+    mut attached_interfaces: watch::Receiver<BTreeMap<String, u32>>,
+    mut heartbeat: AgentHeartbeat,
+) {
+    let mut rules: Vec<FirewallRuleData> = Vec::new();
+    let mut version: Option<u64> = None;
+    loop {
+        match api.subscribe_firewall_rules(layer).await {
+            Ok(mut stream) => loop {
+                tokio::select! {
+                    next = stream.next() => match next {
+                        Ok(Some(rule_set)) => {
+                            rules = rule_set.rules;
+                            version = Some(rule_set.version);
+                        }
+                        Ok(None) => {
+                            warn!("the firewall rule stream was closed");
+                            break;
+                        }
+                        Err(error) => {
+                            warn!("the firewall rule stream failed: {:?}", error);
+                            break;
+                        }
+                    },
+                    Ok(()) = attached_interfaces.changed() => {}
                 }
+                let interfaces: BTreeMap<String, u32> =
+                    attached_interfaces.borrow_and_update().clone();
+                apply_firewall_rules(
+                    &api,
+                    &mut firewall_rule_maps,
+                    &rules,
+                    &interfaces,
+                    &mut heartbeat,
+                )
+                .await;
+            },
+            Err(error) => warn!("failed to subscribe to the firewall rules: {:?}", error),
+        }
+        match api.load_firewall_rules_if_changed(layer, version).await {
+            Ok(Some(rule_set)) => {
+                rules = rule_set.rules;
+                version = Some(rule_set.version);
             }
-            std::thread::sleep(std::time::Duration::from_secs(fwr_update_duration));
+            Ok(None) => {}
+            Err(error) => warn!("failed to load the firewall rules: {:?}", error),
+        }
+        let interfaces: BTreeMap<String, u32> = attached_interfaces.borrow_and_update().clone();
+        apply_firewall_rules(
+            &api,
+            &mut firewall_rule_maps,
+            &rules,
+            &interfaces,
+            &mut heartbeat,
+        )
+        .await;
+        tokio::time::sleep(Duration::from_secs(fwr_update_duration)).await;
+    }
+}
+
+/// Heartbeat of the agent itself, carrying the errors of the rule sync.
+fn agent_heartbeat(ebpf_config: &EbpfConfig) -> AgentHeartbeat {
+    AgentHeartbeat {
+        hostname: ebpf_config.agent_name(),
+        kind: AgentKind::Firewall,
+        interface: None,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        kernel: kernel_release(),
+        attach_mode: None,
+        metrics: None,
+        state: None,
+        error: None,
+    }
+}
+
+/// Heartbeat of the interface of `ebpf_config`, without metrics.
+fn interface_heartbeat(ebpf_config: &EbpfConfig, state: InterfaceState) -> AgentHeartbeat {
+    AgentHeartbeat {
+        hostname: ebpf_config.agent_name(),
+        kind: AgentKind::Firewall,
+        interface: ebpf_config.interface.clone(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        kernel: kernel_release(),
+        attach_mode: Some(ebpf_config.attach_mode.to_string()),
+        metrics: None,
+        state: Some(state),
+        error: None,
+    }
+}
+
+/// Sends the heartbeat in the background.
+fn spawn_heartbeat(api: &Api, heartbeat: AgentHeartbeat) {
+    let api: Api = api.clone();
+    tokio::task::spawn(async move {
+        if let Err(error) = api.send_heartbeat(&heartbeat).await {
+            warn!("failed to send the agent heartbeat: {:?}", error);
         }
     });
+}
 
-    let ctrl_c = signal::ctrl_c();
-    println!("Waiting for Ctrl-C...");
-    ctrl_c.await?;
-    println!("Exiting...");
+/// Reports an attached interface every `heartbeat_duration` seconds, along with
+/// the event counters of the agent.
+async fn send_heartbeats(
+    api: Api,
+    mut heartbeat: AgentHeartbeat,
+    heartbeat_duration: u64,
+    firewall_log_shipper: Shipper<FirewallLogData>,
+    lost_events: LostEvents,
+) {
+    loop {
+        let stats = firewall_log_shipper.stats();
+        heartbeat.metrics = Some(AgentMetrics {
+            transport: lost_events.transport().to_string(),
+            events_lost: lost_events.get().unwrap_or_else(|error| {
+                warn!("failed to read the lost events: {:?}", error);
+                0
+            }),
+            events_shipped: stats.shipped,
+            events_dropped: stats.dropped,
+            events_spooled: stats.spooled,
+        });
+        if let Err(error) = api.send_heartbeat(&heartbeat).await {
+            warn!("failed to send the agent heartbeat: {:?}", error);
+        }
+        tokio::time::sleep(Duration::from_secs(heartbeat_duration)).await;
+    }
+}
 
-    Ok(())
+/// Links of the programs attached to an interface. `egress_error` tells why the
+/// egress classifier could not be attached.
+struct Attachment {
+    ifindex: u32,
+    xdp_link: XdpLinkId,
+    egress_link: Option<SchedClassifierLinkId>,
+    egress_error: Option<String>,
+}
+
+/// The loaded programs and the maps holding the policy of each interface.
+struct Firewall {
+    ebpf: aya::Ebpf,
+    firewall_config: HashMap<MapData, u32, FirewallConfig>,
+    firewall_exemptions: LpmTrie<MapData, [u8; 8], u8>,
+    firewall_exemptions_v6: LpmTrie<MapData, [u8; 20], u8>,
+}
+
+impl Firewall {
+    fn new(mut ebpf: aya::Ebpf) -> Result<Self, anyhow::Error> {
+        let firewall_config: HashMap<MapData, u32, FirewallConfig> = HashMap::try_from(
+            ebpf.take_map("FIREWALL_CONFIG")
+                .context("missing FIREWALL_CONFIG")?,
+        )?;
+        let firewall_exemptions: LpmTrie<MapData, [u8; 8], u8> = LpmTrie::try_from(
+            ebpf.take_map("FIREWALL_EXEMPTIONS")
+                .context("missing FIREWALL_EXEMPTIONS")?,
+        )?;
+        let firewall_exemptions_v6: LpmTrie<MapData, [u8; 20], u8> = LpmTrie::try_from(
+            ebpf.take_map("FIREWALL_EXEMPTIONS_V6")
+                .context("missing FIREWALL_EXEMPTIONS_V6")?,
+        )?;
+        Ok(Self {
+            ebpf,
+            firewall_config,
+            firewall_exemptions,
+            firewall_exemptions_v6,
+        })
+    }
+
+    /// Configures the policy of the interface of `ebpf_config` and attaches the
+    /// programs to it. The policy is removed again when the XDP program could not
+    /// be attached.
+    async fn attach(
+        &mut self,
+        api_server_config: &ApiServerConfig,
+        ebpf_config: &EbpfConfig,
+        interface: &Interface,
+    ) -> Result<Attachment, anyhow::Error> {
+        // The default action and exemptions must be in place before attaching, a
+        // default deny would otherwise cut the agent off from the API server.
+        let result = self
+            .configure_policy(api_server_config, ebpf_config, interface.ifindex)
+            .await
+            .and_then(|_| {
+                self.xdp()?
+                    .attach(
+                        &interface.name,
+                        get_xdp_flags(ebpf_config.attach_mode.clone()),
+                    )
+                    .context(
+                        "failed to attach the XDP program - try setting attach_mode to \"Skb\"",
+                    )
+            });
+        let xdp_link: XdpLinkId = match result {
+            Ok(value) => value,
+            Err(error) => {
+                self.remove_policy(interface.ifindex);
+                return Err(error);
+            }
+        };
+
+        // Without the egress classifier no flow of the interface is tracked and
+        // its egress rules are not enforced, rules restricted to established flows
+        // then never match but the ingress rules keep working.
+        // The error is ignored as the clsact qdisc may already exist.
+        let _ = tc::qdisc_add_clsact(&interface.name);
+        let result = self
+            .egress()
+            .and_then(|program| Ok(program.attach(&interface.name, TcAttachType::Egress)?));
+        let (egress_link, egress_error) = match result {
+            Ok(value) => (Some(value), None),
+            Err(error) => {
+                warn!(
+                    "failed to attach the egress classifier to {}, connection tracking and egress rules are disabled: {error}",
+                    interface.name
+                );
+                (
+                    None,
+                    Some(format!("egress classifier not attached: {error}")),
+                )
+            }
+        };
+        Ok(Attachment {
+            ifindex: interface.ifindex,
+            xdp_link,
+            egress_link,
+            egress_error,
+        })
+    }
+
+    async fn configure_policy(
+        &mut self,
+        api_server_config: &ApiServerConfig,
+        ebpf_config: &EbpfConfig,
+        ifindex: u32,
+    ) -> Result<(), anyhow::Error> {
+        configure_firewall_config(ebpf_config, ifindex, &mut self.firewall_config)?;
+        configure_firewall_exemptions(
+            api_server_config,
+            ebpf_config,
+            ifindex,
+            &mut self.firewall_exemptions,
+            &mut self.firewall_exemptions_v6,
+        )
+        .await
+    }
+
+    fn remove_policy(&mut self, ifindex: u32) {
+        if let Err(error) = remove_firewall_config(ifindex, &mut self.firewall_config) {
+            debug!("failed to remove the config of {ifindex}: {:?}", error);
+        }
+        if let Err(error) = remove_firewall_exemptions(
+            ifindex,
+            &mut self.firewall_exemptions,
+            &mut self.firewall_exemptions_v6,
+        ) {
+            warn!("failed to remove the exemptions of {ifindex}: {:?}", error);
+        }
+    }
+
+    /// Detaches the programs from the interface and removes its policy. The links
+    /// are already gone along with a removed interface, the errors are ignored.
+    fn detach(&mut self, attachment: Attachment) {
+        let result = self
+            .xdp()
+            .and_then(|program| Ok(program.detach(attachment.xdp_link)?));
+        if let Err(error) = result {
+            debug!("failed to detach the XDP program: {:?}", error);
+        }
+        if let Some(egress_link) = attachment.egress_link {
+            let result = self
+                .egress()
+                .and_then(|program| Ok(program.detach(egress_link)?));
+            if let Err(error) = result {
+                debug!("failed to detach the egress classifier: {:?}", error);
+            }
+        }
+        self.remove_policy(attachment.ifindex);
+    }
+
+    fn xdp(&mut self) -> Result<&mut Xdp, anyhow::Error> {
+        Ok(self
+            .ebpf
+            .program_mut("ebpf_firewall")
+            .context("missing the XDP program")?
+            .try_into()?)
+    }
+
+    fn egress(&mut self) -> Result<&mut SchedClassifier, anyhow::Error> {
+        Ok(self
+            .ebpf
+            .program_mut("ebpf_firewall_egress")
+            .context("missing the egress classifier")?
+            .try_into()?)
+    }
+}
+
+/// Attaches to the interfaces selected by the configuration as they are added
+/// to the host and detaches from the ones removed. Link changes are followed
+/// through netlink, or by listing the interfaces every `fwr_update_duration`
+/// seconds when netlink is not available. An interface is only recorded once
+/// attached to, the ones that failed are tried again after `fwr_update_duration`
+/// seconds or on the next link change. `attached_interfaces` publishes the index
+/// of the attached interfaces by name.
+async fn watch_interfaces(
+    api: Api,
+    api_server_config: ApiServerConfig,
+    ebpf_config: EbpfConfig,
+    mut firewall: Firewall,
+    attached_interfaces: watch::Sender<BTreeMap<String, u32>>,
+    firewall_log_shipper: Shipper<FirewallLogData>,
+    lost_events: LostEvents,
+) {
+    let wait: Duration = Duration::from_secs(ebpf_config.fwr_update_duration);
+    let monitor: Option<LinkMonitor> = match LinkMonitor::new() {
+        Ok(value) => Some(value),
+        Err(error) => {
+            warn!(
+                "failed to watch the link changes, listing the interfaces every {} seconds instead: {error:?}",
+                wait.as_secs()
+            );
+            None
+        }
+    };
+    let mut attached: BTreeMap<String, (Attachment, JoinHandle<()>)> = BTreeMap::new();
+    loop {
+        let mut failed: bool = false;
+        match list_interfaces() {
+            Ok(interfaces) => {
+                let interfaces: Vec<Interface> = interfaces
+                    .into_iter()
+                    .filter(|interface| ebpf_config.selects(&interface.name))
+                    .collect();
+                let removed: Vec<String> = attached
+                    .iter()
+                    .filter(|(name, (attachment, _))| {
+                        !interfaces.iter().any(|interface| {
+                            interface.name == **name && interface.ifindex == attachment.ifindex
+                        })
+                    })
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                for name in removed {
+                    let Some((attachment, heartbeat)) = attached.remove(&name) else {
+                        continue;
+                    };
+                    info!("Detaching from {name}");
+                    heartbeat.abort();
+                    firewall.detach(attachment);
+                    spawn_heartbeat(
+                        &api,
+                        interface_heartbeat(
+                            &ebpf_config.for_interface(&name),
+                            InterfaceState::Detached,
+                        ),
+                    );
+                }
+                for interface in interfaces {
+                    if attached.contains_key(&interface.name) {
+                        continue;
+                    }
+                    info!("Attaching to {}", interface.name);
+                    let config: EbpfConfig = ebpf_config.for_interface(&interface.name);
+                    match firewall
+                        .attach(&api_server_config, &config, &interface)
+                        .await
+                    {
+                        Ok(attachment) => {
+                            let mut heartbeat: AgentHeartbeat =
+                                interface_heartbeat(&config, InterfaceState::Attached);
+                            heartbeat.error = attachment.egress_error.clone();
+                            let heartbeat = tokio::task::spawn(send_heartbeats(
+                                api.clone(),
+                                heartbeat,
+                                config.heartbeat_duration,
+                                firewall_log_shipper.clone(),
+                                lost_events.clone(),
+                            ));
+                            attached.insert(interface.name, (attachment, heartbeat));
+                        }
+                        Err(error) => {
+                            warn!(
+                                "failed to run the firewall on {}: {:?}",
+                                interface.name, error
+                            );
+                            failed = true;
+                            let mut heartbeat: AgentHeartbeat =
+                                interface_heartbeat(&config, InterfaceState::Failed);
+                            heartbeat.error = Some(format!("{:#}", error));
+                            spawn_heartbeat(&api, heartbeat);
+                        }
+                    }
+                }
+                attached_interfaces.send_if_modified(|interfaces| {
+                    let current: BTreeMap<String, u32> = attached
+                        .iter()
+                        .map(|(name, (attachment, _))| (name.clone(), attachment.ifindex))
+                        .collect();
+                    if *interfaces == current {
+                        return false;
+                    }
+                    *interfaces = current;
+                    true
+                });
+            }
+            Err(error) => {
+                warn!("failed to list the interfaces: {:?}", error);
+                failed = true;
+            }
+        }
+        match &monitor {
+            Some(monitor) => {
+                let result = if failed {
+                    tokio::time::timeout(wait, monitor.changed())
+                        .await
+                        .unwrap_or(Ok(()))
+                } else {
+                    monitor.changed().await
+                };
+                if let Err(error) = result {
+                    warn!("failed to watch the link changes: {:?}", error);
+                    tokio::time::sleep(wait).await;
+                }
+            }
+            None => tokio::time::sleep(wait).await,
+        }
+    }
 }
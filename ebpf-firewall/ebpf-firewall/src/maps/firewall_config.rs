@@ -0,0 +1,37 @@
+use anyhow::{anyhow, Error};
+use aya::maps::{HashMap, MapData};
+
+use crate::{
+    config::EbpfConfig,
+    policy::{get_default_action, FirewallConfig},
+};
+
+/// Pushes the settings of the interface read by the eBPF programs into
+/// `FIREWALL_CONFIG`, under the index of the interface.
+pub fn configure_firewall_config(
+    ebpf_config: &EbpfConfig,
+    ifindex: u32,
+    firewall_config: &mut HashMap<MapData, u32, FirewallConfig>,
+) -> Result<(), Error> {
+    let config: FirewallConfig = FirewallConfig {
+        default_action: get_default_action(ebpf_config.default_action.clone()),
+        decapsulate: ebpf_config.decapsulate as u8,
+        _padding: [0; 2],
+        log_sample_rate: ebpf_config.log_sample_rate,
+    };
+    if let Err(error) = firewall_config.insert(ifindex, config, 0) {
+        return Err(anyhow!(error.to_string()));
+    }
+    Ok(())
+}
+
+/// Removes the settings of an interface the programs were detached from.
+pub fn remove_firewall_config(
+    ifindex: u32,
+    firewall_config: &mut HashMap<MapData, u32, FirewallConfig>,
+) -> Result<(), Error> {
+    if let Err(error) = firewall_config.remove(&ifindex) {
+        return Err(anyhow!(error.to_string()));
+    }
+    Ok(())
+}
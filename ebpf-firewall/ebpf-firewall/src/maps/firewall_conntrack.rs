@@ -0,0 +1,38 @@
+use anyhow::{anyhow, Error};
+use aya::maps::{HashMap, MapData};
+use log::{debug, info};
+
+use crate::conntrack::{monotonic_now_ns, ConntrackEntry, ConntrackKey};
+
+/// Returns the flows currently tracked by the egress classifier.
+pub fn load_firewall_conntrack(
+    firewall_conntrack: &HashMap<MapData, ConntrackKey, ConntrackEntry>,
+) -> Result<Vec<(ConntrackKey, ConntrackEntry)>, Error> {
+    let mut flows: Vec<(ConntrackKey, ConntrackEntry)> = Vec::new();
+    for item in firewall_conntrack.iter() {
+        match item {
+            Ok(value) => flows.push(value),
+            Err(error) => return Err(anyhow!(error.to_string())),
+        }
+    }
+    Ok(flows)
+}
+
+/// Logs the number of tracked flows, and each flow with its idle time at debug level.
+pub fn log_firewall_conntrack(
+    firewall_conntrack: &HashMap<MapData, ConntrackKey, ConntrackEntry>,
+) -> Result<(), Error> {
+    let flows: Vec<(ConntrackKey, ConntrackEntry)> = load_firewall_conntrack(firewall_conntrack)?;
+    info!("[FIREWALL CONNTRACK] {} tracked flows", flows.len());
+    let now: u64 = monotonic_now_ns();
+    for (key, entry) in flows {
+        debug!(
+            "[FIREWALL CONNTRACK] {} out: {} in: {} idle: {}s",
+            key,
+            entry.packets_out,
+            entry.packets_in,
+            now.saturating_sub(entry.last_seen) / 1_000_000_000
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Error};
+use aya::maps::{MapData, PerCpuHashMap};
+use chrono::Utc;
+use ebpf_firewall_common::log::{DropCount, DropKey};
+
+use crate::{
+    direction::get_direction_from_u8, log::FirewallLogData, protocol::IpProtocol, rule::RuleIds,
+};
+
+/// Returns the drop counters of `FIREWALL_DROPS` summed over the CPUs.
+pub fn load_firewall_drops(
+    firewall_drops: &PerCpuHashMap<MapData, DropKey, DropCount>,
+) -> Result<HashMap<DropKey, DropCount>, Error> {
+    let mut drops: HashMap<DropKey, DropCount> = HashMap::new();
+    for item in firewall_drops.iter() {
+        match item {
+            Ok((key, values)) => {
+                let count: &mut DropCount = drops.entry(key).or_default();
+                for value in values.iter() {
+                    count.packets += value.packets;
+                    count.bytes += value.bytes;
+                }
+            }
+            Err(error) => return Err(anyhow!(error.to_string())),
+        }
+    }
+    Ok(drops)
+}
+
+/// Turns the drop counters, which only ever grow, into the drops of each window.
+#[derive(Debug)]
+pub struct DropAggregator {
+    rule_ids: RuleIds,
+    window: u64,
+    previous: HashMap<DropKey, DropCount>,
+}
+
+impl DropAggregator {
+    pub fn new(rule_ids: RuleIds, window: u64) -> Self {
+        Self {
+            rule_ids,
+            window,
+            previous: HashMap::new(),
+        }
+    }
+
+    /// Logs of the packets dropped since the previous call. A counter below its
+    /// previous value was evicted and counted again, all of it is then new.
+    pub fn collect(&mut self, drops: HashMap<DropKey, DropCount>) -> Vec<FirewallLogData> {
+        let mut logs: Vec<FirewallLogData> = Vec::new();
+        for (key, count) in drops.iter() {
+            let delta: DropCount = match self.previous.get(key) {
+                Some(previous) if previous.packets <= count.packets => DropCount {
+                    packets: count.packets - previous.packets,
+                    bytes: count.bytes.saturating_sub(previous.bytes),
+                },
+                _ => *count,
+            };
+            if delta.packets == 0 {
+                continue;
+            }
+            logs.push(FirewallLogData {
+                ip: key.source_ip().to_vec(),
+                protocol: IpProtocol::from_number(key.protocol),
+                port: None,
+                status: false,
+                timestamp: Utc::now(),
+                packets: delta.packets,
+                bytes: delta.bytes,
+                window: self.window,
+                rule: self.rule_ids.key(key.rule_id),
+                destination_ip: None,
+                destination_port: None,
+                length: None,
+                ifindex: None,
+                direction: get_direction_from_u8(key.direction),
+                icmp_type: None,
+                icmp_code: None,
+            });
+        }
+        self.previous = drops;
+        logs
+    }
+}
+
+/// Reads `FIREWALL_DROPS` and returns the logs of the packets dropped in the window.
+pub fn collect_firewall_drops(
+    firewall_drops: &PerCpuHashMap<MapData, DropKey, DropCount>,
+    aggregator: &mut DropAggregator,
+) -> Result<Vec<FirewallLogData>, Error> {
+    Ok(aggregator.collect(load_firewall_drops(firewall_drops)?))
+}
+
+#[cfg(test)]
+mod test_firewall_drops {
+    use super::*;
+    use crate::{direction::Direction, protocol::IpProtocol};
+
+    fn drop_key(ip: [u8; 4], rule_id: u32) -> DropKey {
+        let mut key: DropKey = DropKey {
+            ip: [0; 16],
+            rule_id,
+            protocol: 6,
+            ip_version: 4,
+            direction: 0,
+            _padding: [0; 1],
+        };
+        key.ip[..4].copy_from_slice(&ip);
+        key
+    }
+
+    #[test]
+    fn test_collect() {
+        let rule_ids: RuleIds = RuleIds::default();
+        let rule_id: u32 = rule_ids.id("abc");
+        let mut aggregator: DropAggregator = DropAggregator::new(rule_ids, 10);
+        let key: DropKey = drop_key([10, 0, 0, 1], rule_id);
+        let other: DropKey = drop_key([10, 0, 0, 2], 0);
+
+        let logs: Vec<FirewallLogData> = aggregator.collect(HashMap::from([(
+            key,
+            DropCount {
+                packets: 5,
+                bytes: 300,
+            },
+        )]));
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].ip, vec![10, 0, 0, 1]);
+        assert_eq!(logs[0].protocol, IpProtocol::Tcp);
+        assert_eq!((logs[0].packets, logs[0].bytes), (5, 300));
+        assert_eq!(logs[0].window, 10);
+        assert_eq!(logs[0].rule, Some("abc".to_string()));
+        assert_eq!(logs[0].direction, Direction::Ingress);
+
+        let logs: Vec<FirewallLogData> = aggregator.collect(HashMap::from([
+            (
+                key,
+                DropCount {
+                    packets: 5,
+                    bytes: 300,
+                },
+            ),
+            (
+                other,
+                DropCount {
+                    packets: 2,
+                    bytes: 120,
+                },
+            ),
+        ]));
+        assert_eq!(logs.len(), 1, "expected no log without new drops");
+        assert_eq!(logs[0].packets, 2);
+        assert_eq!(
+            logs[0].rule, None,
+            "expected no rule for the default action"
+        );
+
+        let logs: Vec<FirewallLogData> = aggregator.collect(HashMap::from([(
+            key,
+            DropCount {
+                packets: 3,
+                bytes: 180,
+            },
+        )]));
+        assert_eq!(
+            logs[0].packets, 3,
+            "expected an evicted counter to count from zero"
+        );
+    }
+}
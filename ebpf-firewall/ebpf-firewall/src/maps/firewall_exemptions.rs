@@ -0,0 +1,126 @@
+use std::net::IpAddr;
+
+use anyhow::{anyhow, Error};
+use aya::maps::{
+    lpm_trie::{Key, LpmTrie},
+    MapData,
+};
+use log::{info, warn};
+use reqwest::Url;
+
+use crate::{
+    config::{ApiServerConfig, EbpfConfig},
+    policy::parse_exemption,
+};
+
+/// Resolves the addresses of the API server so the agent never blocks its own
+/// connection to it.
+async fn api_server_addresses(api_server_config: &ApiServerConfig) -> Result<Vec<IpAddr>, Error> {
+    let url: Url = Url::parse(&api_server_config.base_url)?;
+    let host: &str = match url.host_str() {
+        Some(value) => value.trim_start_matches('[').trim_end_matches(']'),
+        None => return Err(anyhow!("api server base_url has no host")),
+    };
+    let port: u16 = url.port_or_known_default().unwrap_or(80);
+    let addresses = tokio::net::lookup_host((host, port)).await?;
+    Ok(addresses.map(|address| address.ip()).collect())
+}
+
+/// Key of an exempted prefix of an interface, the interface index in network byte
+/// order followed by the prefix. `M` is `N` plus the 4 octets of the index.
+fn exemption_key<const N: usize, const M: usize>(
+    ifindex: u32,
+    prefix_len: u8,
+    address: [u8; N],
+) -> Key<[u8; M]> {
+    let mut data: [u8; M] = [0; M];
+    data[..4].copy_from_slice(&ifindex.to_be_bytes());
+    data[4..].copy_from_slice(&address);
+    Key::new(32 + prefix_len as u32, data)
+}
+
+/// Loads the exemptions of the interface and the API server addresses into
+/// `FIREWALL_EXEMPTIONS` and `FIREWALL_EXEMPTIONS_V6`.
+pub async fn configure_firewall_exemptions(
+    api_server_config: &ApiServerConfig,
+    ebpf_config: &EbpfConfig,
+    ifindex: u32,
+    firewall_exemptions: &mut LpmTrie<MapData, [u8; 8], u8>,
+    firewall_exemptions_v6: &mut LpmTrie<MapData, [u8; 20], u8>,
+) -> Result<(), Error> {
+    let mut exemptions: Vec<(IpAddr, u8)> = Vec::new();
+    for value in &ebpf_config.exemptions {
+        exemptions.push(parse_exemption(value)?);
+    }
+    match api_server_addresses(api_server_config).await {
+        Ok(addresses) => {
+            for address in addresses {
+                let prefix_len: u8 = if address.is_ipv4() { 32 } else { 128 };
+                exemptions.push((address, prefix_len));
+            }
+        }
+        Err(error) => warn!("[FIREWALL EXEMPTIONS] api server: {}", error),
+    }
+    for (address, prefix_len) in exemptions {
+        info!(
+            "[FIREWALL EXEMPTIONS] {}/{} on {}",
+            address,
+            prefix_len,
+            ebpf_config.interface.clone().unwrap_or_default()
+        );
+        let result = match address {
+            IpAddr::V4(address) => firewall_exemptions.insert(
+                &exemption_key(ifindex, prefix_len, address.octets()),
+                1,
+                0,
+            ),
+            IpAddr::V6(address) => firewall_exemptions_v6.insert(
+                &exemption_key(ifindex, prefix_len, address.octets()),
+                1,
+                0,
+            ),
+        };
+        if let Err(error) = result {
+            warn!("[FIREWALL EXEMPTIONS] {}", error);
+        }
+    }
+    Ok(())
+}
+
+/// Removes the exemptions of an interface the programs were detached from.
+pub fn remove_firewall_exemptions(
+    ifindex: u32,
+    firewall_exemptions: &mut LpmTrie<MapData, [u8; 8], u8>,
+    firewall_exemptions_v6: &mut LpmTrie<MapData, [u8; 20], u8>,
+) -> Result<(), Error> {
+    let interface: [u8; 4] = ifindex.to_be_bytes();
+    let keys: Vec<Key<[u8; 8]>> = firewall_exemptions
+        .keys()
+        .filter_map(|key| key.ok())
+        .filter(|key| key.data()[..4] == interface)
+        .collect();
+    for key in keys {
+        firewall_exemptions.remove(&key)?;
+    }
+    let keys: Vec<Key<[u8; 20]>> = firewall_exemptions_v6
+        .keys()
+        .filter_map(|key| key.ok())
+        .filter(|key| key.data()[..4] == interface)
+        .collect();
+    for key in keys {
+        firewall_exemptions_v6.remove(&key)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_firewall_exemptions {
+    use super::*;
+
+    #[test]
+    fn test_exemption_key() {
+        let key: Key<[u8; 8]> = exemption_key(3, 24, [192, 168, 1, 0]);
+        assert_eq!(key.prefix_len(), 56);
+        assert_eq!(key.data(), [0, 0, 0, 3, 192, 168, 1, 0]);
+    }
+}
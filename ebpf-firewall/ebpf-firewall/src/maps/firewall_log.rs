@@ -1,61 +1,176 @@
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
 
 use anyhow::{anyhow, Error};
 use aya::{
-    maps::{AsyncPerfEventArray, Map},
+    maps::{AsyncPerfEventArray, Map, MapData, PerCpuArray, RingBuf},
     util::online_cpus,
+    Ebpf,
 };
 use bytes::BytesMut;
+use chrono::{DateTime, TimeDelta, Utc};
+use ebpf_firewall_common::log::FirewallLog;
 use log::warn;
+use log_shipper::Shipper;
+use tokio::io::unix::AsyncFd;
 
 use crate::{
-    api::Api,
-    log::{FirewallLog, FirewallLogData},
-    protocol::{get_protocol_from_u8, IpProtocol},
+    agent::LostEvents, conntrack::monotonic_now_ns, direction::get_direction_from_u8,
+    log::FirewallLogData, protocol::IpProtocol, rule::RuleIds,
 };
 
-pub async fn configure_firewall_log(api: &Api, firewall_log_map: Map) -> Result<(), Error> {
-    let mut perf_array = AsyncPerfEventArray::try_from(firewall_log_map).unwrap();
-    let a_api: Arc<Api> = Arc::new(api.to_owned());
-    for cpu_id in online_cpus().unwrap() {
-        let mut buf = match perf_array.open(cpu_id, None) {
-            Ok(value) => value,
-            Err(error) => return Err(anyhow!(error)),
-        };
+fn firewall_log_data(bytes: &[u8], rule_ids: &RuleIds) -> Option<FirewallLogData> {
+    if bytes.len() < size_of::<FirewallLog>() {
+        return None;
+    }
+    let info = unsafe { (bytes.as_ptr() as *const FirewallLog).read_unaligned() };
+    let protocol = IpProtocol::from_number(info.protocol);
+    let has_ports: bool = protocol == IpProtocol::Tcp || protocol == IpProtocol::Udp;
+    let is_icmp: bool = protocol == IpProtocol::Icmp;
+    // The event carries the monotonic time of the packet, its age gives the wall time.
+    let age: u64 = monotonic_now_ns().saturating_sub(info.timestamp);
+    let timestamp: DateTime<Utc> = Utc::now() - TimeDelta::nanoseconds(age as i64);
+    Some(FirewallLogData {
+        ip: info.source_ip().to_vec(),
+        port: has_ports.then_some(info.port),
+        protocol,
+        status: info.status == 1,
+        timestamp,
+        packets: 1,
+        bytes: info.length as u64,
+        window: 0,
+        rule: rule_ids.key(info.rule_id),
+        destination_ip: Some(info.destination_ip().to_vec()),
+        destination_port: has_ports.then_some(info.destination_port),
+        length: Some(info.length),
+        ifindex: Some(info.ifindex),
+        direction: get_direction_from_u8(info.direction),
+        icmp_type: is_icmp.then_some(info.icmp_type),
+        icmp_code: is_icmp.then_some(info.icmp_code),
+    })
+}
 
-        let shared_api = a_api.clone();
-        tokio::task::spawn(async move {
-            let mut buffers: Vec<_> = (0..10).map(|_| BytesMut::with_capacity(1024)).collect();
-
-            loop {
-                match buf.read_events(&mut buffers).await {
-                    Ok(events) => {
-                        for i in 0..events.read {
-                            let buf = &mut buffers[i];
-                            let ptr = buf.as_ptr() as *const FirewallLog;
-                            let info = unsafe { ptr.read_unaligned() };
-                            let protocol = get_protocol_from_u8(info.protocol);
-                            let data: FirewallLogData = FirewallLogData {
-                                ip: info.ip,
-                                port: if protocol == IpProtocol::Icmp {
-                                    None
-                                } else {
-                                    Some(info.port)
-                                },
-                                protocol: protocol,
-                                status: if info.status == 1 { true } else { false },
-                            };
-                            if let Err(error) = shared_api.send_firewall_log(data).await {
-                                warn!("[FIREWALL LOG]: {:?}", error.to_string());
-                            }
+/// Ships the logs of `FIREWALL_LOG`, read by a single task from the ring buffer or
+/// by one task per CPU from the perf event array the older kernels fall back to.
+/// These are the sampled drops and the packets of rules with `log` set, the
+/// other drops are logged by `DropAggregator`.
+pub fn configure_firewall_log(
+    shipper: &Shipper<FirewallLogData>,
+    rule_ids: &RuleIds,
+    ebpf: &mut Ebpf,
+) -> Result<LostEvents, Error> {
+    match ebpf.take_map("FIREWALL_LOG") {
+        Some(map @ Map::RingBuf(_)) => {
+            let lost: PerCpuArray<MapData, u64> = match ebpf.take_map("FIREWALL_LOG_LOST") {
+                Some(map) => PerCpuArray::try_from(map)?,
+                None => return Err(anyhow!("FIREWALL_LOG_LOST map not found")),
+            };
+            let mut ring_buf = AsyncFd::new(RingBuf::try_from(map)?)?;
+            let shipper: Shipper<FirewallLogData> = shipper.clone();
+            let rule_ids: RuleIds = rule_ids.clone();
+            tokio::task::spawn(async move {
+                loop {
+                    let mut guard = match ring_buf.readable_mut().await {
+                        Ok(value) => value,
+                        Err(error) => {
+                            warn!("[FIREWALL LOG] {:?}", error);
+                            return;
+                        }
+                    };
+                    let ring_buf = guard.get_inner_mut();
+                    while let Some(item) = ring_buf.next() {
+                        if let Some(data) = firewall_log_data(&item, &rule_ids) {
+                            shipper.send(data);
                         }
                     }
-                    Err(error) => {
-                        warn!("[FIREWALL LOG] {:?}", error);
+                    guard.clear_ready();
+                }
+            });
+            Ok(LostEvents::RingBuf(Arc::new(lost)))
+        }
+        Some(map @ Map::PerfEventArray(_)) => {
+            let lost: Arc<AtomicU64> = Arc::new(AtomicU64::new(0));
+            let mut perf_array = AsyncPerfEventArray::try_from(map)?;
+            for cpu_id in online_cpus().map_err(|(_, error)| error)? {
+                let mut buf = perf_array.open(cpu_id, None)?;
+                let shipper: Shipper<FirewallLogData> = shipper.clone();
+                let lost: Arc<AtomicU64> = lost.clone();
+                let rule_ids: RuleIds = rule_ids.clone();
+                tokio::task::spawn(async move {
+                    let mut buffers: Vec<_> =
+                        (0..10).map(|_| BytesMut::with_capacity(1024)).collect();
+                    loop {
+                        match buf.read_events(&mut buffers).await {
+                            Ok(events) => {
+                                lost.fetch_add(events.lost as u64, Ordering::Relaxed);
+                                for buf in buffers.iter().take(events.read) {
+                                    if let Some(data) = firewall_log_data(buf, &rule_ids) {
+                                        shipper.send(data);
+                                    }
+                                }
+                            }
+                            Err(error) => {
+                                warn!("[FIREWALL LOG] {:?}", error);
+                            }
+                        };
                     }
-                };
+                });
             }
-        });
+            Ok(LostEvents::PerfEventArray(lost))
+        }
+        _ => Err(anyhow!("FIREWALL_LOG map not found")),
+    }
+}
+
+#[cfg(test)]
+mod test_firewall_log {
+    use super::*;
+    use crate::direction::Direction;
+
+    #[test]
+    fn test_firewall_log_data() {
+        let rule_ids: RuleIds = RuleIds::default();
+        let rule_id: u32 = rule_ids.id("abc");
+        let mut log: FirewallLog = FirewallLog {
+            ip: [0; 16],
+            destination_ip: [0; 16],
+            timestamp: monotonic_now_ns(),
+            rule_id,
+            length: 74,
+            ifindex: 2,
+            port: 51000,
+            destination_port: 443,
+            protocol: 6,
+            status: 1,
+            ip_version: 4,
+            direction: 0,
+            icmp_type: 0,
+            icmp_code: 0,
+            _padding: [0; 2],
+        };
+        log.ip[..4].copy_from_slice(&[10, 0, 0, 1]);
+        log.destination_ip[..4].copy_from_slice(&[10, 0, 0, 254]);
+
+        let data: FirewallLogData = firewall_log_data(bytemuck::bytes_of(&log), &rule_ids).unwrap();
+        assert_eq!(data.ip, vec![10, 0, 0, 1]);
+        assert_eq!(data.destination_ip, Some(vec![10, 0, 0, 254]));
+        assert_eq!((data.port, data.destination_port), (Some(51000), Some(443)));
+        assert!(data.status, "expected an allowed packet");
+        assert_eq!(data.rule, Some("abc".to_string()));
+        assert_eq!((data.length, data.ifindex), (Some(74), Some(2)));
+        assert!((Utc::now() - data.timestamp).num_seconds() < 1);
+        assert_eq!(data.direction, Direction::Ingress);
+        assert_eq!((data.icmp_type, data.icmp_code), (None, None));
+
+        log.protocol = 1;
+        log.direction = 1;
+        (log.icmp_type, log.icmp_code) = (3, 4);
+        let data: FirewallLogData = firewall_log_data(bytemuck::bytes_of(&log), &rule_ids).unwrap();
+        assert_eq!((data.port, data.destination_port), (None, None));
+        assert_eq!(data.direction, Direction::Egress);
+        assert_eq!((data.icmp_type, data.icmp_code), (Some(3), Some(4)));
+        assert!(firewall_log_data(&[0; 8], &rule_ids).is_none());
     }
-    Ok(())
 }
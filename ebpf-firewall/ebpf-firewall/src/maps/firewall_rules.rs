@@ -1,52 +1,856 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+    os::fd::{AsFd, OwnedFd},
+};
+
 use anyhow::{anyhow, Error};
-use aya::maps::{
-    lpm_trie::{Key, LpmTrie},
-    MapData,
+use aya::{
+    maps::{
+        lpm_trie::{Key, LpmTrie},
+        Array, IterableMap, Map, MapData, PerCpuHashMap,
+    },
+    Ebpf,
+};
+use ebpf_firewall_common::{
+    rule::{Rule, RuleChain},
+    DEFAULT_RULE_ID, MAX_RULES_PER_CHAIN, MAX_RULE_CHAINS, MAX_RULE_CHAIN_DEPTH, NO_PARENT_CHAIN,
 };
 use log::{info, warn};
 
 use crate::{
-    api::Api,
+    connection_state::get_connection_state,
+    direction::get_direction,
     protocol::get_protocol,
-    rule::{FirewallRuleData, Rule},
+    rule::{FirewallRuleData, RateLimit, RuleIds},
+    rule_action::{get_rule_action, RuleAction},
+    stats::TrafficStats,
 };
 
-pub async fn configure_firewall_rules(
-    api: &Api,
-    layer: u8,
-    firewall_rules: &mut LpmTrie<&mut MapData, [u8; 4], Rule>,
-) -> Result<(), Error> {
-    let data: Vec<FirewallRuleData> = match api.load_firewall_rules(layer).await {
-        Ok(value) => value,
-        Err(error) => return Err(anyhow!(error.to_string())),
+/// Clears the host bits of `ip` beyond the prefix length.
+fn network<const N: usize>(ip: [u8; N], cidr: u16) -> [u8; N] {
+    let mut network: [u8; N] = [0; N];
+    let mut remaining: u16 = cidr;
+    for i in 0..N {
+        if remaining == 0 {
+            break;
+        }
+        let bits: u16 = remaining.min(8);
+        network[i] = ip[i] & (0xffu8 << (8 - bits));
+        remaining -= bits;
+    }
+    network
+}
+
+/// Converts the rule data into the rule evaluated by the XDP program.
+fn to_rule<const N: usize>(item: FirewallRuleData, rule_ids: &RuleIds) -> Option<Rule> {
+    let mut destination_ip: [u8; 16] = [0; 16];
+    let mut destination_prefix_len: u8 = 0;
+    if let Some(value) = item.destination_ip {
+        if value.len() != N {
+            warn!("[FIREWALL RULES WARN] destination ip and ip address family differ");
+            return None;
+        }
+        destination_ip[..N].copy_from_slice(&value);
+        destination_prefix_len = item.destination_cidr.unwrap_or(N as u16 * 8) as u8;
+    }
+    let rate_limit: RateLimit = match (&item.action, item.rate_limit) {
+        (RuleAction::Filter, _) => RateLimit {
+            pps: None,
+            bps: None,
+            burst_ms: 0,
+            prefix_len: None,
+        },
+        (_, Some(rate_limit)) => rate_limit,
+        (action, None) => {
+            warn!("[FIREWALL RULES WARN] {} rule without rate limit", action);
+            return None;
+        }
+    };
+    let Some(protocol) = get_protocol(item.protocol.clone()) else {
+        warn!("[FIREWALL RULES WARN] {} protocol rule", item.protocol);
+        return None;
+    };
+    Some(Rule {
+        bps: rate_limit.bps.unwrap_or(0),
+        burst_ns: rate_limit.burst_ms as u64 * 1_000_000,
+        destination_ip,
+        id: item
+            .id
+            .map(|id| rule_ids.id(&id.key()))
+            .unwrap_or(DEFAULT_RULE_ID),
+        pps: rate_limit.pps.unwrap_or(0),
+        ifindex: 0,
+        from_port: item.from_port.unwrap_or(0),
+        to_port: item.to_port.unwrap_or(0),
+        destination_from_port: item.destination_from_port.unwrap_or(0),
+        destination_to_port: item.destination_to_port.unwrap_or(0),
+        vlan_id: item.vlan_id.unwrap_or(0),
+        status: item.status as u8,
+        protocol,
+        direction: get_direction(item.direction),
+        destination_prefix_len,
+        connection_state: get_connection_state(item.connection_state),
+        log: item.log as u8,
+        action: get_rule_action(item.action),
+        rate_prefix_len: rate_limit
+            .prefix_len
+            .map_or(N as u8 * 8, |prefix_len| prefix_len.min(N as u8 * 8)),
+        icmp_type: item.icmp_type.unwrap_or(0),
+        icmp_code: item.icmp_code.unwrap_or(0),
+        has_from_port: item.from_port.is_some() as u8,
+        has_to_port: item.to_port.is_some() as u8,
+        has_destination_from_port: item.destination_from_port.is_some() as u8,
+        has_destination_to_port: item.destination_to_port.is_some() as u8,
+        has_vlan_id: item.vlan_id.is_some() as u8,
+        has_icmp_type: item.icmp_type.is_some() as u8,
+        has_icmp_code: item.icmp_code.is_some() as u8,
+        _padding: [0; 1],
+    })
+}
+
+/// The rules evaluated for the rule data, one per attached interface of a rule
+/// restricted to interfaces. A rule of interfaces none of which is attached has none.
+fn to_rules<const N: usize>(
+    item: FirewallRuleData,
+    rule_ids: &RuleIds,
+    interfaces: &BTreeMap<String, u32>,
+) -> Vec<Rule> {
+    let names: Vec<String> = item.interfaces.clone();
+    let Some(rule) = to_rule::<N>(item, rule_ids) else {
+        return Vec::new();
     };
+    if names.is_empty() {
+        return vec![rule];
+    }
+    names
+        .iter()
+        .filter_map(|name| interfaces.get(name))
+        .map(|ifindex| Rule {
+            ifindex: *ifindex,
+            ..rule
+        })
+        .collect()
+}
+
+/// Formats a prefix as `10.0.0.0/8` or `2001:db8::/32`.
+fn prefix_to_string<const N: usize>(ip: [u8; N], cidr: u16) -> String {
+    let address: Option<IpAddr> = match N {
+        4 => <[u8; 4]>::try_from(&ip[..]).ok().map(IpAddr::from),
+        _ => <[u8; 16]>::try_from(&ip[..]).ok().map(IpAddr::from),
+    };
+    match address {
+        Some(address) => format!("{}/{}", address, cidr),
+        None => format!("{:?}/{}", ip, cidr),
+    }
+}
+
+/// Prefix length and network of a rule chain.
+type Prefix<const N: usize> = (u16, [u8; N]);
+
+/// Rule chains of an address family with the trie key of their prefix and the
+/// longest prefix containing it. `RuleChain::parent` is only set once the chains
+/// have an index, see `diff_rule_chains`.
+type RuleChains<const N: usize> = Vec<(Key<[u8; N]>, Option<Prefix<N>>, RuleChain)>;
 
-    let mut remove_keys: Vec<Key<[u8; 4]>> = Vec::new();
-    for i in firewall_rules.iter() {
-        if let Ok(item) = i {
-            remove_keys.push(item.0);
+/// Groups the rules of the given address family by prefix, each group sorted by priority.
+/// The chain of a prefix holds its own rules, the XDP program goes on with the chains
+/// of the shorter prefixes containing it, longest first. The chains are sorted by
+/// prefix length, a chain comes after its parent. `interfaces` holds the index of
+/// the attached interfaces by name. A prefix with more than `MAX_RULES_PER_CHAIN`
+/// rules, or nested in more than `MAX_RULE_CHAIN_DEPTH` prefixes, rejects the whole
+/// rule set, leaving out some of them would let traffic skip their rules.
+fn build_rule_chains<const N: usize>(
+    data: Vec<FirewallRuleData>,
+    rule_ids: &RuleIds,
+    interfaces: &BTreeMap<String, u32>,
+) -> Result<RuleChains<N>, Error> {
+    let mut groups: BTreeMap<Prefix<N>, Vec<FirewallRuleData>> = BTreeMap::new();
+    for item in data {
+        let ip: [u8; N] = match item.ip.as_slice().try_into() {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        groups
+            .entry((item.cidr, network(ip, item.cidr)))
+            .or_default()
+            .push(item);
+    }
+
+    let mut group_rules: BTreeMap<Prefix<N>, Vec<Rule>> = BTreeMap::new();
+    for (prefix, mut items) in groups {
+        items.sort_by_key(|item| item.priority);
+        group_rules.insert(
+            prefix,
+            items
+                .into_iter()
+                .flat_map(|item| to_rules::<N>(item, rule_ids, interfaces))
+                .collect(),
+        );
+    }
+
+    let mut chains: RuleChains<N> = Vec::new();
+    for (&(cidr, ip), rules) in &group_rules {
+        if rules.len() > MAX_RULES_PER_CHAIN {
+            return Err(anyhow!(
+                "{} has {} rules, at most {} are supported",
+                prefix_to_string(ip, cidr),
+                rules.len(),
+                MAX_RULES_PER_CHAIN
+            ));
+        }
+        let parents: Vec<Prefix<N>> = group_rules
+            .keys()
+            .rev()
+            .filter(|(outer_cidr, outer_ip)| {
+                *outer_cidr < cidr && network(ip, *outer_cidr) == *outer_ip
+            })
+            .copied()
+            .collect();
+        if parents.len() >= MAX_RULE_CHAIN_DEPTH {
+            return Err(anyhow!(
+                "{} is nested in {} prefixes, at most {} chains are evaluated for a packet",
+                prefix_to_string(ip, cidr),
+                parents.len(),
+                MAX_RULE_CHAIN_DEPTH
+            ));
         }
+        let mut chain: RuleChain = RuleChain::default();
+        for rule in rules {
+            chain.rules[chain.len as usize] = *rule;
+            chain.len += 1;
+        }
+        chains.push((Key::new(cidr as u32, ip), parents.first().copied(), chain));
+    }
+    Ok(chains)
+}
+
+/// Prefixes applied by the previous sync of one address family, with the index
+/// of their rule chain in `FIREWALL_RULE_CHAINS`/`FIREWALL_RULE_CHAINS_V6`.
+#[derive(Clone, Debug, Default)]
+pub struct RuleSnapshot<const N: usize> {
+    chains: BTreeMap<Prefix<N>, (u32, RuleChain)>,
+}
+
+impl<const N: usize> RuleSnapshot<N> {
+    /// Prefix lengths used by the applied rules.
+    pub fn cidrs(&self) -> BTreeSet<u16> {
+        self.chains.keys().map(|(cidr, _)| *cidr).collect()
     }
+}
 
-    info!("[FIREWALL RULES] Updating Firewall Rules");
-    if remove_keys.len() > 0 {
-        for key in remove_keys {
-            if let Err(error) = firewall_rules.remove(&key) {
-                warn!("[FIREWALL RULES WARN] {:?}", error);
+/// Map updates needed to go from a snapshot to the new rules, and the snapshot
+/// once they are applied.
+struct RuleDiff<const N: usize> {
+    upserts: Vec<(Key<[u8; N]>, u32, RuleChain)>,
+    removals: Vec<Key<[u8; N]>>,
+    snapshot: RuleSnapshot<N>,
+}
+
+/// Lowest chain index used neither by the previous snapshot nor by this sync.
+fn allocate_index(used: &mut BTreeSet<u32>) -> Option<u32> {
+    let index: u32 = (0..MAX_RULE_CHAINS).find(|index| !used.contains(index))?;
+    used.insert(index);
+    Some(index)
+}
+
+/// Compares the new chains with the snapshot. Unchanged prefixes are left alone,
+/// a changed chain is written to a free index so the trie entry is switched to it
+/// in a single update and the XDP program never sees a partially written chain.
+/// A chain whose parent moves to another index is changed too, the parents come
+/// first in `chains` and in the upserts.
+fn diff_rule_chains<const N: usize>(
+    previous: &RuleSnapshot<N>,
+    chains: RuleChains<N>,
+) -> RuleDiff<N> {
+    let mut used: BTreeSet<u32> = previous.chains.values().map(|(index, _)| *index).collect();
+    let mut diff: RuleDiff<N> = RuleDiff {
+        upserts: Vec::new(),
+        removals: Vec::new(),
+        snapshot: RuleSnapshot::default(),
+    };
+    for (key, parent, mut chain) in chains {
+        let prefix: Prefix<N> = (key.prefix_len() as u16, key.data());
+        chain.parent = parent
+            .and_then(|parent| diff.snapshot.chains.get(&parent))
+            .map_or(NO_PARENT_CHAIN, |(index, _)| *index);
+        let index: u32 = match previous.chains.get(&prefix) {
+            Some((index, previous_chain)) if *previous_chain == chain => {
+                diff.snapshot.chains.insert(prefix, (*index, chain));
+                continue;
             }
+            // Without a spare index the chain is overwritten in place.
+            Some((index, _)) => allocate_index(&mut used).unwrap_or(*index),
+            None => match allocate_index(&mut used) {
+                Some(index) => index,
+                None => {
+                    warn!(
+                        "[FIREWALL RULES WARN] only the first {} prefixes are applied",
+                        MAX_RULE_CHAINS
+                    );
+                    continue;
+                }
+            },
+        };
+        diff.upserts.push((key, index, chain));
+        diff.snapshot.chains.insert(prefix, (index, chain));
+    }
+    for (cidr, ip) in previous.chains.keys() {
+        if !diff.snapshot.chains.contains_key(&(*cidr, *ip)) {
+            diff.removals.push(Key::new(*cidr as u32, *ip));
         }
     }
-    for item in data {
-        let key = Key::new(item.cidr as u32, item.ip);
-        let rule: Rule = Rule {
-            from_port: item.from_port,
-            to_port: item.to_port,
-            status: item.status,
-            protocol: get_protocol(item.protocol),
+    diff
+}
+
+/// Synchronises the trie and rule chains of the address family with the chains
+/// built from the rules, `N` is 4 for `FIREWALL_RULES` and 16 for `FIREWALL_RULES_V6`.
+/// Only the prefixes that changed since `snapshot` are written.
+pub fn configure_firewall_rules<const N: usize>(
+    chains: RuleChains<N>,
+    snapshot: &mut RuleSnapshot<N>,
+    firewall_rules: &mut LpmTrie<MapData, [u8; N], u32>,
+    firewall_rule_chains: &mut Array<MapData, RuleChain>,
+) -> Result<(), Error> {
+    let mut diff: RuleDiff<N> = diff_rule_chains(snapshot, chains);
+    if diff.upserts.is_empty() && diff.removals.is_empty() {
+        return Ok(());
+    }
+    info!(
+        "[FIREWALL RULES] Updating Firewall Rules, {} prefixes set, {} removed",
+        diff.upserts.len(),
+        diff.removals.len()
+    );
+
+    // Indexes of the chains left unwritten, their children are not written either.
+    let mut failed: BTreeSet<u32> = BTreeSet::new();
+    for (key, index, chain) in diff.upserts {
+        let prefix: Prefix<N> = (key.prefix_len() as u16, key.data());
+        let result: Result<(), Error> = if failed.contains(&chain.parent) {
+            Err(anyhow!(
+                "the chain of the prefix containing {} was not written",
+                prefix_to_string(key.data(), prefix.0)
+            ))
+        } else {
+            firewall_rule_chains
+                .set(index, chain, 0)
+                .and_then(|_| firewall_rules.insert(&key, index, 0))
+                .map_err(Error::from)
         };
-        if let Err(error) = firewall_rules.insert(&key, &rule, 0) {
+        if let Err(error) = result {
+            warn!("[FIREWALL RULES WARN] {:?}", error);
+            failed.insert(index);
+            // Keep what is still in the maps so the next sync retries.
+            match snapshot.chains.get(&prefix) {
+                Some(previous) => diff.snapshot.chains.insert(prefix, *previous),
+                None => diff.snapshot.chains.remove(&prefix),
+            };
+        }
+    }
+    for key in diff.removals {
+        if let Err(error) = firewall_rules.remove(&key) {
             warn!("[FIREWALL RULES WARN] {:?}", error);
+            let prefix: Prefix<N> = (key.prefix_len() as u16, key.data());
+            if let Some(previous) = snapshot.chains.get(&prefix) {
+                diff.snapshot.chains.insert(prefix, *previous);
+            }
         }
     }
+
+    *snapshot = diff.snapshot;
     Ok(())
 }
+
+/// Rule maps of both address families with the snapshot of their last sync.
+/// `rule_stats` is a second handle on `FIREWALL_RULE_STATS`, the counters of the
+/// removed rules are dropped with their ids.
+pub struct FirewallRuleMaps {
+    rule_ids: RuleIds,
+    rule_stats: PerCpuHashMap<MapData, u32, TrafficStats>,
+    firewall_rules: LpmTrie<MapData, [u8; 4], u32>,
+    firewall_rule_chains: Array<MapData, RuleChain>,
+    snapshot: RuleSnapshot<4>,
+    firewall_rules_v6: LpmTrie<MapData, [u8; 16], u32>,
+    firewall_rule_chains_v6: Array<MapData, RuleChain>,
+    snapshot_v6: RuleSnapshot<16>,
+}
+
+fn take_map(ebpf: &mut Ebpf, name: &str) -> Result<Map, Error> {
+    ebpf.take_map(name)
+        .ok_or_else(|| anyhow!("map {} not found", name))
+}
+
+/// Opens `FIREWALL_RULE_STATS` again, the map itself is left in `ebpf` for
+/// `FirewallStatsMaps`.
+fn rule_stats_handle(ebpf: &Ebpf) -> Result<PerCpuHashMap<MapData, u32, TrafficStats>, Error> {
+    let map: &Map = ebpf
+        .map("FIREWALL_RULE_STATS")
+        .ok_or_else(|| anyhow!("map FIREWALL_RULE_STATS not found"))?;
+    let rule_stats: PerCpuHashMap<&MapData, u32, TrafficStats> = PerCpuHashMap::try_from(map)?;
+    let fd: OwnedFd = rule_stats.map().fd().as_fd().try_clone_to_owned()?;
+    Ok(PerCpuHashMap::try_from(Map::PerCpuLruHashMap(
+        MapData::from_fd(fd)?,
+    ))?)
+}
+
+impl FirewallRuleMaps {
+    pub fn new(ebpf: &mut Ebpf, rule_ids: RuleIds) -> Result<Self, Error> {
+        Ok(Self {
+            rule_ids,
+            rule_stats: rule_stats_handle(ebpf)?,
+            firewall_rules: LpmTrie::try_from(take_map(ebpf, "FIREWALL_RULES")?)?,
+            firewall_rule_chains: Array::try_from(take_map(ebpf, "FIREWALL_RULE_CHAINS")?)?,
+            snapshot: RuleSnapshot::default(),
+            firewall_rules_v6: LpmTrie::try_from(take_map(ebpf, "FIREWALL_RULES_V6")?)?,
+            firewall_rule_chains_v6: Array::try_from(take_map(ebpf, "FIREWALL_RULE_CHAINS_V6")?)?,
+            snapshot_v6: RuleSnapshot::default(),
+        })
+    }
+
+    /// Applies the rules of both address families to the attached `interfaces`,
+    /// indexed by name. A rejected rule set leaves the maps of both families as
+    /// they were. The ids and counters of the rules no longer in the set are
+    /// released once it is applied.
+    pub fn configure(
+        &mut self,
+        data: Vec<FirewallRuleData>,
+        interfaces: &BTreeMap<String, u32>,
+    ) -> Result<(), Error> {
+        let keys: BTreeSet<String> = data
+            .iter()
+            .filter_map(|item| item.id.as_ref().map(|id| id.key()))
+            .collect();
+        let chains: RuleChains<4> =
+            build_rule_chains::<4>(data.clone(), &self.rule_ids, interfaces)?;
+        let chains_v6: RuleChains<16> = build_rule_chains::<16>(data, &self.rule_ids, interfaces)?;
+        configure_firewall_rules(
+            chains,
+            &mut self.snapshot,
+            &mut self.firewall_rules,
+            &mut self.firewall_rule_chains,
+        )?;
+        configure_firewall_rules(
+            chains_v6,
+            &mut self.snapshot_v6,
+            &mut self.firewall_rules_v6,
+            &mut self.firewall_rule_chains_v6,
+        )?;
+        for id in self.rule_ids.retain(&keys) {
+            // Missing when the rule matched no packet.
+            let _ = self.rule_stats.remove(&id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_firewall_rules {
+    use ebpf_firewall_common::{
+        parse::{IP_PROTO_ICMP, IP_PROTO_TCP, IP_PROTO_UDP},
+        RULE_PROTOCOL_ANY,
+    };
+
+    use super::*;
+    use crate::{connection_state::ConnectionState, direction::Direction, protocol::IpProtocol};
+
+    fn rule_data(ip: Vec<u8>, cidr: u16, priority: u32, protocol: IpProtocol) -> FirewallRuleData {
+        FirewallRuleData {
+            id: None,
+            ip,
+            protocol,
+            cidr,
+            from_port: None,
+            to_port: None,
+            status: false,
+            priority,
+            direction: Direction::Ingress,
+            destination_ip: None,
+            destination_cidr: None,
+            destination_from_port: None,
+            destination_to_port: None,
+            connection_state: ConnectionState::Any,
+            vlan_id: None,
+            icmp_type: None,
+            icmp_code: None,
+            log: false,
+            action: RuleAction::Filter,
+            rate_limit: None,
+            interfaces: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_build_rule_chains() {
+        let data: Vec<FirewallRuleData> = vec![
+            rule_data(vec![192, 168, 1, 0], 24, 20, IpProtocol::Udp),
+            rule_data(vec![192, 168, 1, 7], 24, 10, IpProtocol::Tcp),
+            rule_data(vec![10, 0, 0, 0], 8, 0, IpProtocol::Icmp),
+            rule_data(vec![0; 16], 0, 0, IpProtocol::Tcp),
+        ];
+        let chains = build_rule_chains::<4>(data, &RuleIds::default(), &BTreeMap::new()).unwrap();
+        assert_eq!(chains.len(), 2, "expected one chain per IPv4 prefix");
+
+        let (key, _, chain) = &chains[1];
+        assert_eq!(key.prefix_len(), 24);
+        assert_eq!(key.data(), [192, 168, 1, 0]);
+        assert_eq!(chain.len, 2);
+        assert_eq!(chain.rules[0].protocol, IP_PROTO_TCP);
+        assert_eq!(chain.rules[1].protocol, IP_PROTO_UDP);
+    }
+
+    #[test]
+    fn test_build_rule_chains_shorter_prefixes() {
+        let data: Vec<FirewallRuleData> = vec![
+            rule_data(vec![0, 0, 0, 0], 0, 0, IpProtocol::Icmp),
+            rule_data(vec![10, 0, 0, 0], 8, 0, IpProtocol::Udp),
+            rule_data(vec![10, 1, 0, 0], 16, 0, IpProtocol::Tcp),
+            rule_data(vec![172, 16, 0, 0], 12, 0, IpProtocol::Tcp),
+        ];
+        let chains = build_rule_chains::<4>(data, &RuleIds::default(), &BTreeMap::new()).unwrap();
+        let chain = |prefix_len: u32, ip: [u8; 4]| -> (Option<Prefix<4>>, Vec<u8>) {
+            let (_, parent, chain) = chains
+                .iter()
+                .find(|(key, _, _)| key.prefix_len() == prefix_len && key.data() == ip)
+                .unwrap();
+            let protocols: Vec<u8> = chain.rules[..chain.len as usize]
+                .iter()
+                .map(|rule| rule.protocol)
+                .collect();
+            (*parent, protocols)
+        };
+        assert_eq!(
+            chain(16, [10, 1, 0, 0]),
+            (Some((8, [10, 0, 0, 0])), vec![IP_PROTO_TCP]),
+            "expected the own rules of the prefix and the longest prefix containing it"
+        );
+        assert_eq!(
+            chain(8, [10, 0, 0, 0]),
+            (Some((0, [0, 0, 0, 0])), vec![IP_PROTO_UDP])
+        );
+        assert_eq!(
+            chain(12, [172, 16, 0, 0]),
+            (Some((0, [0, 0, 0, 0])), vec![IP_PROTO_TCP])
+        );
+        assert_eq!(chain(0, [0, 0, 0, 0]), (None, vec![IP_PROTO_ICMP]));
+        let prefix_lens: Vec<u32> = chains.iter().map(|(key, _, _)| key.prefix_len()).collect();
+        assert_eq!(
+            prefix_lens,
+            vec![0, 8, 12, 16],
+            "expected the parents before their children"
+        );
+    }
+
+    #[test]
+    fn test_build_rule_chains_too_many_rules() {
+        let mut data: Vec<FirewallRuleData> = (0..10)
+            .map(|priority| rule_data(vec![0, 0, 0, 0], 0, priority, IpProtocol::Tcp))
+            .collect();
+        data.extend(
+            (0..16).map(|priority| rule_data(vec![192, 168, 1, 0], 24, priority, IpProtocol::Udp)),
+        );
+        let chains = build_rule_chains::<4>(data.clone(), &RuleIds::default(), &BTreeMap::new());
+        assert!(
+            chains.is_ok(),
+            "expected the rules of the containing prefixes not to count: {:?}",
+            chains.err()
+        );
+        let (_, _, chain) = &chains.unwrap()[1];
+        assert_eq!(chain.len as usize, MAX_RULES_PER_CHAIN);
+
+        data.push(rule_data(vec![192, 168, 1, 0], 24, 16, IpProtocol::Udp));
+        let chains = build_rule_chains::<4>(data, &RuleIds::default(), &BTreeMap::new());
+        let error = chains.err();
+        assert!(
+            error.is_some(),
+            "expected the rules beyond MAX_RULES_PER_CHAIN to reject the rule set"
+        );
+        assert!(error.unwrap().to_string().contains("192.168.1.0/24"));
+    }
+
+    #[test]
+    fn test_build_rule_chains_too_deep() {
+        let mut data: Vec<FirewallRuleData> = (0..MAX_RULE_CHAIN_DEPTH as u16)
+            .map(|cidr| rule_data(vec![0, 0, 0, 0], cidr, 0, IpProtocol::Tcp))
+            .collect();
+        let chains = build_rule_chains::<4>(data.clone(), &RuleIds::default(), &BTreeMap::new());
+        assert!(chains.is_ok(), "{:?}", chains.err());
+
+        data.push(rule_data(
+            vec![0, 0, 0, 0],
+            MAX_RULE_CHAIN_DEPTH as u16,
+            0,
+            IpProtocol::Tcp,
+        ));
+        let chains = build_rule_chains::<4>(data, &RuleIds::default(), &BTreeMap::new());
+        assert!(
+            chains.is_err(),
+            "expected more nested prefixes than MAX_RULE_CHAIN_DEPTH to reject the rule set"
+        );
+    }
+
+    #[test]
+    fn test_build_rule_chains_direction() {
+        let mut egress: FirewallRuleData = rule_data(vec![203, 0, 113, 0], 24, 0, IpProtocol::Tcp);
+        egress.direction = Direction::Egress;
+        let data: Vec<FirewallRuleData> = vec![
+            egress,
+            rule_data(vec![203, 0, 113, 0], 24, 10, IpProtocol::Tcp),
+        ];
+        let chains = build_rule_chains::<4>(data, &RuleIds::default(), &BTreeMap::new()).unwrap();
+        assert_eq!(
+            chains.len(),
+            1,
+            "expected the egress rules to share the chain of their prefix"
+        );
+        assert_eq!(chains[0].2.len, 2);
+        assert_eq!(chains[0].2.rules[0].direction, 1);
+        assert_eq!(chains[0].2.rules[1].direction, 0);
+    }
+
+    #[test]
+    fn test_build_rule_chains_interfaces() {
+        let mut scoped: FirewallRuleData = rule_data(vec![203, 0, 113, 0], 24, 0, IpProtocol::Tcp);
+        scoped.interfaces = vec!["eth0".to_string(), "eth1".to_string(), "eth2".to_string()];
+        let mut detached: FirewallRuleData =
+            rule_data(vec![203, 0, 113, 0], 24, 5, IpProtocol::Tcp);
+        detached.interfaces = vec!["eth3".to_string()];
+        let data: Vec<FirewallRuleData> = vec![
+            scoped,
+            detached,
+            rule_data(vec![203, 0, 113, 0], 24, 10, IpProtocol::Udp),
+        ];
+        let interfaces: BTreeMap<String, u32> =
+            BTreeMap::from([("eth0".to_string(), 2), ("eth2".to_string(), 4)]);
+        let chains = build_rule_chains::<4>(data, &RuleIds::default(), &interfaces).unwrap();
+        let chain: &RuleChain = &chains[0].2;
+        assert_eq!(
+            chain.len, 3,
+            "expected a rule per attached interface and none for detached ones"
+        );
+        assert_eq!(chain.rules[0].ifindex, 2);
+        assert_eq!(chain.rules[1].ifindex, 4);
+        assert_eq!(
+            (chain.rules[2].ifindex, chain.rules[2].protocol),
+            (0, IP_PROTO_UDP)
+        );
+    }
+
+    #[test]
+    fn test_to_rule_connection_state() {
+        let rule_ids: RuleIds = RuleIds::default();
+        let mut data: FirewallRuleData = rule_data(vec![10, 0, 0, 0], 8, 0, IpProtocol::Tcp);
+        let rule: Rule = to_rule::<4>(data.clone(), &rule_ids).unwrap();
+        assert_eq!(rule.connection_state, 0, "rules match any state by default");
+
+        data.connection_state = ConnectionState::Established;
+        let rule: Rule = to_rule::<4>(data, &rule_ids).unwrap();
+        assert_eq!(rule.connection_state, 2);
+    }
+
+    #[test]
+    fn test_to_rule_vlan_id() {
+        let rule_ids: RuleIds = RuleIds::default();
+        let mut data: FirewallRuleData = rule_data(vec![10, 0, 0, 0], 8, 0, IpProtocol::Tcp);
+        let rule: Rule = to_rule::<4>(data.clone(), &rule_ids).unwrap();
+        assert_eq!(rule.vlan_id(), None, "rules match any VLAN by default");
+
+        data.vlan_id = Some(100);
+        let rule: Rule = to_rule::<4>(data, &rule_ids).unwrap();
+        assert_eq!(rule.vlan_id(), Some(100));
+    }
+
+    #[test]
+    fn test_to_rule_icmp() {
+        let rule_ids: RuleIds = RuleIds::default();
+        let mut data: FirewallRuleData = rule_data(vec![10, 0, 0, 0], 8, 0, IpProtocol::Icmp);
+        data.icmp_type = Some(3);
+        data.icmp_code = Some(4);
+        let rule: Rule = to_rule::<4>(data, &rule_ids).unwrap();
+        assert_eq!((rule.icmp_type(), rule.icmp_code()), (Some(3), Some(4)));
+    }
+
+    #[test]
+    fn test_to_rule_protocol() {
+        let rule_ids: RuleIds = RuleIds::default();
+        let any: FirewallRuleData = rule_data(vec![10, 0, 0, 0], 8, 0, IpProtocol::Any);
+        assert_eq!(
+            to_rule::<4>(any, &rule_ids).unwrap().protocol,
+            RULE_PROTOCOL_ANY
+        );
+        let gre: FirewallRuleData = rule_data(vec![10, 0, 0, 0], 8, 0, IpProtocol::Other(47));
+        assert_eq!(to_rule::<4>(gre, &rule_ids).unwrap().protocol, 47);
+        let undefined: FirewallRuleData = rule_data(vec![10, 0, 0, 0], 8, 0, IpProtocol::Undefined);
+        assert!(
+            to_rule::<4>(undefined, &rule_ids).is_none(),
+            "expected a rule without protocol to be skipped"
+        );
+    }
+
+    #[test]
+    fn test_to_rule_rate_limit() {
+        let rule_ids: RuleIds = RuleIds::default();
+        let mut data: FirewallRuleData = rule_data(vec![0, 0, 0, 0], 0, 0, IpProtocol::Tcp);
+        data.action = RuleAction::SynFlood;
+        assert!(
+            to_rule::<4>(data.clone(), &rule_ids).is_none(),
+            "expected a rate limit rule without limits to be skipped"
+        );
+
+        data.rate_limit = Some(RateLimit {
+            pps: Some(100),
+            bps: None,
+            burst_ms: 500,
+            prefix_len: Some(24),
+        });
+        let rule: Rule = to_rule::<4>(data.clone(), &rule_ids).unwrap();
+        assert_eq!(rule.action, 2);
+        assert_eq!((rule.pps, rule.bps), (100, 0));
+        assert_eq!(rule.burst_ns, 500_000_000);
+        assert_eq!(rule.rate_prefix_len, 24);
+
+        data.rate_limit.as_mut().unwrap().prefix_len = None;
+        let rule: Rule = to_rule::<16>(data, &rule_ids).unwrap();
+        assert_eq!(
+            rule.rate_prefix_len, 128,
+            "expected the full address by default"
+        );
+    }
+
+    #[test]
+    fn test_rule_ids() {
+        let rule_ids: RuleIds = RuleIds::default();
+        let mut data: FirewallRuleData = rule_data(vec![10, 0, 0, 0], 8, 0, IpProtocol::Tcp);
+        let rule: Rule = to_rule::<4>(data.clone(), &rule_ids).unwrap();
+        assert_eq!(
+            rule.id, DEFAULT_RULE_ID,
+            "rules without a record have no id"
+        );
+
+        data.id =
+            serde_json::from_str(r#"{"tb": "firewall_rule", "id": {"String": "abc"}}"#).unwrap();
+        let rule: Rule = to_rule::<4>(data.clone(), &rule_ids).unwrap();
+        assert_eq!(rule.id, 1);
+        assert_eq!(rule_ids.key(rule.id), Some("abc".to_string()));
+        assert_eq!(rule_ids.id("def"), 2);
+        assert_eq!(
+            to_rule::<4>(data, &rule_ids).unwrap().id,
+            1,
+            "expected the id to be kept across syncs"
+        );
+        assert_eq!(rule_ids.key(DEFAULT_RULE_ID), None);
+
+        let keys: BTreeSet<String> = BTreeSet::from(["def".to_string()]);
+        assert_eq!(rule_ids.retain(&keys), vec![1]);
+        assert_eq!(rule_ids.key(1), None);
+        assert_eq!(rule_ids.key(2), Some("def".to_string()));
+        assert_eq!(rule_ids.id("abc"), 3, "expected the ids not to be reused");
+    }
+
+    #[test]
+    fn test_diff_rule_chains() {
+        let data: Vec<FirewallRuleData> = vec![
+            rule_data(vec![192, 168, 1, 0], 24, 0, IpProtocol::Udp),
+            rule_data(vec![10, 0, 0, 0], 8, 0, IpProtocol::Icmp),
+        ];
+        let diff = diff_rule_chains(
+            &RuleSnapshot::default(),
+            build_rule_chains::<4>(data, &RuleIds::default(), &BTreeMap::new()).unwrap(),
+        );
+        assert_eq!(diff.upserts.len(), 2);
+        assert!(diff.removals.is_empty());
+        assert_eq!(diff.snapshot.cidrs(), BTreeSet::from([8, 24]));
+        let snapshot: RuleSnapshot<4> = diff.snapshot;
+
+        let diff = diff_rule_chains(
+            &snapshot,
+            build_rule_chains::<4>(
+                vec![
+                    rule_data(vec![10, 0, 0, 0], 8, 0, IpProtocol::Icmp),
+                    rule_data(vec![192, 168, 1, 0], 24, 0, IpProtocol::Udp),
+                ],
+                &RuleIds::default(),
+                &BTreeMap::new(),
+            )
+            .unwrap(),
+        );
+        assert!(
+            diff.upserts.is_empty(),
+            "unchanged rules must not be rewritten"
+        );
+        assert!(diff.removals.is_empty());
+
+        let diff = diff_rule_chains(
+            &snapshot,
+            build_rule_chains::<4>(
+                vec![rule_data(vec![10, 0, 0, 0], 8, 0, IpProtocol::Tcp)],
+                &RuleIds::default(),
+                &BTreeMap::new(),
+            )
+            .unwrap(),
+        );
+        assert_eq!(diff.upserts.len(), 1);
+        let (key, index, _) = &diff.upserts[0];
+        assert_eq!(key.prefix_len(), 8);
+        let (previous_index, _) = snapshot.chains[&(8, [10, 0, 0, 0])];
+        assert!(
+            *index != previous_index && *index != snapshot.chains[&(24, [192, 168, 1, 0])].0,
+            "a changed chain must be written to a free index"
+        );
+        assert_eq!(diff.removals.len(), 1);
+        assert_eq!(diff.removals[0].data(), [192, 168, 1, 0]);
+        assert_eq!(diff.snapshot.cidrs(), BTreeSet::from([8]));
+    }
+
+    #[test]
+    fn test_diff_rule_chains_parents() {
+        let data = |protocol: IpProtocol| -> RuleChains<4> {
+            build_rule_chains::<4>(
+                vec![
+                    rule_data(vec![10, 0, 0, 0], 8, 0, protocol),
+                    rule_data(vec![10, 1, 0, 0], 16, 0, IpProtocol::Udp),
+                    rule_data(vec![192, 168, 0, 0], 16, 0, IpProtocol::Udp),
+                ],
+                &RuleIds::default(),
+                &BTreeMap::new(),
+            )
+            .unwrap()
+        };
+        let snapshot: RuleSnapshot<4> =
+            diff_rule_chains(&RuleSnapshot::default(), data(IpProtocol::Tcp)).snapshot;
+        let (parent_index, _) = snapshot.chains[&(8, [10, 0, 0, 0])];
+        assert_eq!(
+            snapshot.chains[&(16, [10, 1, 0, 0])].1.parent,
+            parent_index,
+            "expected the index of the chain of the containing prefix"
+        );
+        assert_eq!(
+            snapshot.chains[&(16, [192, 168, 0, 0])].1.parent,
+            NO_PARENT_CHAIN
+        );
+
+        let diff = diff_rule_chains(&snapshot, data(IpProtocol::Icmp));
+        let upserts: Vec<(u32, u32)> = diff
+            .upserts
+            .iter()
+            .map(|(key, index, _)| (key.prefix_len(), *index))
+            .collect();
+        assert_eq!(
+            upserts
+                .iter()
+                .map(|(prefix_len, _)| *prefix_len)
+                .collect::<Vec<u32>>(),
+            vec![8, 16],
+            "expected the child of a moved chain to be rewritten after it"
+        );
+        let (_, child) = diff.snapshot.chains[&(16, [10, 1, 0, 0])];
+        assert_eq!(child.parent, upserts[0].1);
+    }
+
+    #[test]
+    fn test_network() {
+        assert_eq!(network([192, 168, 1, 7], 24), [192, 168, 1, 0]);
+        assert_eq!(network([10, 255, 1, 7], 12), [10, 240, 0, 0]);
+        assert_eq!(network([10, 255, 1, 7], 0), [0, 0, 0, 0]);
+        assert_eq!(network([10, 255, 1, 7], 32), [10, 255, 1, 7]);
+    }
+}
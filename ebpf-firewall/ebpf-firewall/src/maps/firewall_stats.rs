@@ -0,0 +1,139 @@
+use anyhow::{anyhow, Error};
+use aya::{
+    maps::{MapData, PerCpuArray, PerCpuHashMap, PerCpuValues},
+    Ebpf,
+};
+
+use crate::{
+    protocol::IpProtocol,
+    rule::RuleIds,
+    stats::{ProtocolStats, RuleStats, TrafficStats},
+};
+
+fn sum(values: &PerCpuValues<TrafficStats>) -> TrafficStats {
+    let mut traffic: TrafficStats = TrafficStats::default();
+    for value in values.iter() {
+        traffic.add(value);
+    }
+    traffic
+}
+
+/// Groups the traffic of the protocol numbers by protocol. Protocols without
+/// traffic are left out.
+fn group_protocol_stats(traffic: impl Iterator<Item = (u8, TrafficStats)>) -> Vec<ProtocolStats> {
+    let mut stats: Vec<ProtocolStats> = Vec::new();
+    for (protocol, traffic) in traffic {
+        if traffic == TrafficStats::default() {
+            continue;
+        }
+        let protocol = IpProtocol::from_number(protocol);
+        match stats.iter_mut().find(|item| item.protocol == protocol) {
+            Some(item) => item.traffic.add(&traffic),
+            None => stats.push(ProtocolStats { protocol, traffic }),
+        }
+    }
+    stats
+}
+
+/// Traffic counters of the rules, protocols and parse errors, summed over the
+/// CPUs when read.
+pub struct FirewallStatsMaps {
+    rule_ids: RuleIds,
+    rule_stats: PerCpuHashMap<MapData, u32, TrafficStats>,
+    protocol_stats: PerCpuArray<MapData, TrafficStats>,
+    parse_errors: PerCpuArray<MapData, TrafficStats>,
+}
+
+impl FirewallStatsMaps {
+    pub fn new(ebpf: &mut Ebpf, rule_ids: RuleIds) -> Result<Self, Error> {
+        let Some(rule_stats) = ebpf.take_map("FIREWALL_RULE_STATS") else {
+            return Err(anyhow!("FIREWALL_RULE_STATS map not found"));
+        };
+        let Some(protocol_stats) = ebpf.take_map("FIREWALL_PROTOCOL_STATS") else {
+            return Err(anyhow!("FIREWALL_PROTOCOL_STATS map not found"));
+        };
+        let Some(parse_errors) = ebpf.take_map("FIREWALL_PARSE_ERRORS") else {
+            return Err(anyhow!("FIREWALL_PARSE_ERRORS map not found"));
+        };
+        Ok(Self {
+            rule_ids,
+            rule_stats: PerCpuHashMap::try_from(rule_stats)?,
+            protocol_stats: PerCpuArray::try_from(protocol_stats)?,
+            parse_errors: PerCpuArray::try_from(parse_errors)?,
+        })
+    }
+
+    /// Traffic of each rule matched since the agent started and of the default action.
+    pub fn rule_stats(&self) -> Result<Vec<RuleStats>, Error> {
+        let mut stats: Vec<RuleStats> = Vec::new();
+        for item in self.rule_stats.iter() {
+            match item {
+                Ok((rule_id, values)) => stats.push(RuleStats {
+                    rule: self.rule_ids.key(rule_id),
+                    traffic: sum(&values),
+                }),
+                Err(error) => return Err(anyhow!(error.to_string())),
+            }
+        }
+        Ok(stats)
+    }
+
+    pub fn protocol_stats(&self) -> Result<Vec<ProtocolStats>, Error> {
+        let mut traffic: Vec<(u8, TrafficStats)> = Vec::new();
+        for protocol in 0..=u8::MAX {
+            let values = self.protocol_stats.get(&(protocol as u32), 0)?;
+            traffic.push((protocol, sum(&values)));
+        }
+        Ok(group_protocol_stats(traffic.into_iter()))
+    }
+
+    /// Traffic of the packets the parser rejected, given the default action.
+    pub fn parse_errors(&self) -> Result<TrafficStats, Error> {
+        Ok(sum(&self.parse_errors.get(&0, 0)?))
+    }
+}
+
+#[cfg(test)]
+mod test_firewall_stats {
+    use super::*;
+    use crate::protocol::IpProtocol;
+
+    fn traffic(passed_packets: u64, dropped_packets: u64) -> TrafficStats {
+        TrafficStats {
+            passed_packets,
+            passed_bytes: passed_packets * 100,
+            dropped_packets,
+            dropped_bytes: dropped_packets * 100,
+        }
+    }
+
+    #[test]
+    fn test_group_protocol_stats() {
+        let stats: Vec<ProtocolStats> = group_protocol_stats(
+            vec![
+                (1, traffic(0, 0)),
+                (6, traffic(10, 2)),
+                (17, traffic(5, 0)),
+                (47, traffic(1, 1)),
+                (50, traffic(0, 3)),
+            ]
+            .into_iter(),
+        );
+        assert_eq!(
+            stats.len(),
+            4,
+            "expected protocols without traffic to be left out"
+        );
+        assert_eq!(stats[0].protocol, IpProtocol::Tcp);
+        assert_eq!(stats[0].traffic, traffic(10, 2));
+        assert_eq!(stats[1].protocol, IpProtocol::Udp);
+        assert_eq!(stats[2].protocol, IpProtocol::Other(47));
+        assert_eq!(stats[2].traffic, traffic(1, 1));
+        assert_eq!(
+            stats[3].protocol,
+            IpProtocol::Other(50),
+            "expected the protocols without a variant to be reported by number"
+        );
+        assert_eq!(stats[3].traffic, traffic(0, 3));
+    }
+}
@@ -1,7 +1,15 @@
-pub mod firewall_cidrs;
+pub mod firewall_config;
+pub mod firewall_conntrack;
+pub mod firewall_drops;
+pub mod firewall_exemptions;
 pub mod firewall_log;
 pub mod firewall_rules;
+pub mod firewall_stats;
 
-pub use firewall_cidrs::configure_firewall_cidrs;
+pub use firewall_config::{configure_firewall_config, remove_firewall_config};
+pub use firewall_conntrack::{load_firewall_conntrack, log_firewall_conntrack};
+pub use firewall_drops::{collect_firewall_drops, load_firewall_drops, DropAggregator};
+pub use firewall_exemptions::{configure_firewall_exemptions, remove_firewall_exemptions};
 pub use firewall_log::configure_firewall_log;
-pub use firewall_rules::configure_firewall_rules;
+pub use firewall_rules::{configure_firewall_rules, FirewallRuleMaps};
+pub use firewall_stats::FirewallStatsMaps;
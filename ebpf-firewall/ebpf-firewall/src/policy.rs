@@ -0,0 +1,80 @@
+use std::net::IpAddr;
+
+use anyhow::anyhow;
+pub use ebpf_firewall_common::config::FirewallConfig;
+use ebpf_firewall_common::{DEFAULT_ACTION_ALLOW, DEFAULT_ACTION_DENY};
+use serde::{Deserialize, Serialize};
+
+/// Action applied to packets not matched by any rule.
+/// `Deny` turns the firewall into a whitelist of the allow rules.
+#[derive(Serialize, PartialEq, Eq, Deserialize, Clone, Debug, Default)]
+pub enum DefaultAction {
+    #[default]
+    Allow,
+    Deny,
+}
+
+impl std::fmt::Display for DefaultAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Allow => write!(f, "Allow"),
+            Self::Deny => write!(f, "Deny"),
+        }
+    }
+}
+
+pub fn get_default_action(action: DefaultAction) -> u8 {
+    match action {
+        DefaultAction::Allow => DEFAULT_ACTION_ALLOW,
+        DefaultAction::Deny => DEFAULT_ACTION_DENY,
+    }
+}
+
+/// Parses an exemption such as `10.0.0.0/8`, `2001:db8::1` or `192.168.1.10`.
+/// A missing prefix length means the single address.
+pub fn parse_exemption(value: &str) -> Result<(IpAddr, u8), anyhow::Error> {
+    let (address, prefix_len) = match value.split_once('/') {
+        Some((address, prefix_len)) => (address, Some(prefix_len)),
+        None => (value, None),
+    };
+    let address: IpAddr = address
+        .trim()
+        .parse()
+        .map_err(|error| anyhow!("invalid exemption {}: {}", value, error))?;
+    let max_prefix_len: u8 = if address.is_ipv4() { 32 } else { 128 };
+    let prefix_len: u8 = match prefix_len {
+        Some(prefix_len) => prefix_len
+            .trim()
+            .parse()
+            .map_err(|error| anyhow!("invalid exemption {}: {}", value, error))?,
+        None => max_prefix_len,
+    };
+    if prefix_len > max_prefix_len {
+        return Err(anyhow!(
+            "invalid exemption {}: prefix length must not be greater than {}",
+            value,
+            max_prefix_len
+        ));
+    }
+    Ok((address, prefix_len))
+}
+
+#[cfg(test)]
+mod test_policy {
+    use super::*;
+
+    #[test]
+    fn test_parse_exemption() {
+        let result = parse_exemption("10.0.0.0/8");
+        assert!(result.is_ok(), "{:?}", result.err());
+        assert_eq!(result.unwrap(), ("10.0.0.0".parse().unwrap(), 8));
+
+        let result = parse_exemption("2001:db8::1");
+        assert!(result.is_ok(), "{:?}", result.err());
+        assert_eq!(result.unwrap(), ("2001:db8::1".parse().unwrap(), 128));
+
+        assert!(parse_exemption("10.0.0.0/33").is_err());
+        assert!(parse_exemption("10.0.0/8").is_err());
+        assert!(parse_exemption("10.0.0.0/x").is_err());
+    }
+}
@@ -1,24 +1,159 @@
-use aya::Pod;
-use network_types::ip::IpProto;
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+
 use serde::Deserialize;
+use serde_json::Value;
 
-use crate::protocol::IpProtocol;
-#[repr(C)]
-#[derive(Clone, Copy, Debug)]
-pub struct Rule {
-    pub from_port: Option<u16>,
-    pub to_port: Option<u16>,
-    pub status: bool,
-    pub protocol: IpProto,
+use crate::{
+    connection_state::ConnectionState, direction::Direction, protocol::IpProtocol,
+    rule_action::RuleAction,
+};
+/// Record id of a rule as serialized by the API server, such as
+/// `{"tb": "firewall_rule", "id": {"String": "<key>"}}`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RecordId {
+    pub tb: String,
+    pub id: Value,
+}
+
+impl RecordId {
+    /// Key of the record, as used in the rule routes of the API server.
+    pub fn key(&self) -> String {
+        match self.id.as_object().and_then(|key| key.values().next()) {
+            Some(Value::String(key)) => key.clone(),
+            Some(key) => key.to_string(),
+            None => self.id.to_string(),
+        }
+    }
+}
+
+/// Numeric ids of the rule records in the XDP program, starting at 1. Ids are
+/// kept while the rule is applied so the counters and logs of a rule keep pointing
+/// to its record across syncs. Ids are not reused, a late log of a removed rule
+/// doesn't point to another record.
+#[derive(Clone, Debug, Default)]
+pub struct RuleIds {
+    table: Arc<Mutex<RuleIdTable>>,
+}
+
+#[derive(Debug, Default)]
+struct RuleIdTable {
+    ids: HashMap<String, u32>,
+    keys: HashMap<u32, String>,
+    last_id: u32,
+}
+
+impl RuleIds {
+    /// Id of the rule record, assigned on first use.
+    pub fn id(&self, key: &str) -> u32 {
+        let mut table = self.table.lock().unwrap();
+        if let Some(id) = table.ids.get(key) {
+            return *id;
+        }
+        table.last_id += 1;
+        let id: u32 = table.last_id;
+        table.ids.insert(key.to_string(), id);
+        table.keys.insert(id, key.to_string());
+        id
+    }
+
+    /// Key of the rule record with the id, `None` for the default action.
+    pub fn key(&self, id: u32) -> Option<String> {
+        self.table.lock().unwrap().keys.get(&id).cloned()
+    }
+
+    /// Forgets the records missing from `keys`, returns their ids.
+    pub fn retain(&self, keys: &BTreeSet<String>) -> Vec<u32> {
+        let mut table = self.table.lock().unwrap();
+        let removed: Vec<u32> = table
+            .ids
+            .iter()
+            .filter(|(key, _)| !keys.contains(*key))
+            .map(|(_, id)| *id)
+            .collect();
+        table.ids.retain(|key, _| keys.contains(key));
+        for id in &removed {
+            table.keys.remove(id);
+        }
+        removed
+    }
 }
-unsafe impl Pod for Rule {}
 
+/// `ip` holds 4 octets for an IPv4 rule and 16 octets for an IPv6 rule.
+/// `ip`/`cidr` and `from_port`/`to_port` match the remote end, the source of
+/// `Ingress` and the destination of `Egress` packets, the `destination_*` fields
+/// optionally match the local address and port.
+/// Rules of the same prefix are evaluated by ascending `priority`.
+/// `connection_state` restricts the rule to new flows or replies to local flows.
+/// `vlan_id` restricts the rule to the frames of a VLAN, by their outermost tag.
+/// `icmp_type` and `icmp_code` restrict `Icmp` rules to a message type and code.
+/// `log` has every packet the rule matches logged, allowed ones included.
+/// `interfaces` restricts the rule to the named interfaces, empty means all of them.
+/// `rate_limit` holds the limits of the `RateLimit` and `SynFlood` actions.
 #[derive(Clone, Debug, Deserialize)]
 pub struct FirewallRuleData {
-    pub ip: [u8; 4],
+    #[serde(default)]
+    pub id: Option<RecordId>,
+    pub ip: Vec<u8>,
     pub protocol: IpProtocol,
     pub cidr: u16,
     pub from_port: Option<u16>,
     pub to_port: Option<u16>,
     pub status: bool,
+    #[serde(default)]
+    pub priority: u32,
+    #[serde(default)]
+    pub direction: Direction,
+    #[serde(default)]
+    pub destination_ip: Option<Vec<u8>>,
+    #[serde(default)]
+    pub destination_cidr: Option<u16>,
+    #[serde(default)]
+    pub destination_from_port: Option<u16>,
+    #[serde(default)]
+    pub destination_to_port: Option<u16>,
+    #[serde(default)]
+    pub connection_state: ConnectionState,
+    #[serde(default)]
+    pub vlan_id: Option<u16>,
+    #[serde(default)]
+    pub icmp_type: Option<u8>,
+    #[serde(default)]
+    pub icmp_code: Option<u8>,
+    #[serde(default)]
+    pub log: bool,
+    #[serde(default)]
+    pub action: RuleAction,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    #[serde(default)]
+    pub interfaces: Vec<String>,
+}
+
+/// Limits applied to each source prefix of `prefix_len` bits, the full address
+/// when not set. `pps` is in packets and `bps` in bits per second, a source may
+/// send a burst of `burst_ms` milliseconds of traffic at the full rate at once.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RateLimit {
+    #[serde(default)]
+    pub pps: Option<u32>,
+    #[serde(default)]
+    pub bps: Option<u64>,
+    #[serde(default = "default_burst_ms")]
+    pub burst_ms: u32,
+    #[serde(default)]
+    pub prefix_len: Option<u8>,
+}
+
+fn default_burst_ms() -> u32 {
+    1000
+}
+
+/// Rules of a layer at a rule set version, as sent by `/firewall-rule/stream/{layer}`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct FirewallRuleSet {
+    pub version: u64,
+    pub rules: Vec<FirewallRuleData>,
 }
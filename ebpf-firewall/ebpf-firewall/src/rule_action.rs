@@ -0,0 +1,31 @@
+use ebpf_firewall_common::{RULE_ACTION_FILTER, RULE_ACTION_RATE_LIMIT, RULE_ACTION_SYN_FLOOD};
+use serde::{Deserialize, Serialize};
+
+/// What a rule does with the matched packets. `Filter` passes or drops them by
+/// `status`, `RateLimit` drops the traffic of a source prefix beyond the limit
+/// and `SynFlood` only limits the TCP packets opening a connection.
+#[derive(Serialize, PartialEq, Eq, Deserialize, Clone, Debug, Default)]
+pub enum RuleAction {
+    #[default]
+    Filter,
+    RateLimit,
+    SynFlood,
+}
+
+impl std::fmt::Display for RuleAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Filter => write!(f, "Filter"),
+            Self::RateLimit => write!(f, "RateLimit"),
+            Self::SynFlood => write!(f, "SynFlood"),
+        }
+    }
+}
+
+pub fn get_rule_action(action: RuleAction) -> u8 {
+    match action {
+        RuleAction::Filter => RULE_ACTION_FILTER,
+        RuleAction::RateLimit => RULE_ACTION_RATE_LIMIT,
+        RuleAction::SynFlood => RULE_ACTION_SYN_FLOOD,
+    }
+}
@@ -0,0 +1,29 @@
+pub use ebpf_firewall_common::stats::TrafficStats;
+use serde::Serialize;
+
+use crate::protocol::IpProtocol;
+
+/// Traffic of a rule, `rule` is the key of its record and `None` for the default action.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RuleStats {
+    pub rule: Option<String>,
+    pub traffic: TrafficStats,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ProtocolStats {
+    pub protocol: IpProtocol,
+    pub traffic: TrafficStats,
+}
+
+/// Traffic counters since the agent started, reported to the API server which
+/// keeps the latest report of each agent and interface. `parse_errors` is the
+/// traffic of the packets the parser rejected, given the default action.
+#[derive(Clone, Debug, Serialize)]
+pub struct FirewallStatsReport {
+    pub agent: String,
+    pub interface: Option<String>,
+    pub rules: Vec<RuleStats>,
+    pub protocols: Vec<ProtocolStats>,
+    pub parse_errors: TrafficStats,
+}
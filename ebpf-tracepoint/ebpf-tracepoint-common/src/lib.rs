@@ -3,3 +3,6 @@
 pub static ARGV_LEN: usize = 32;
 pub static ARGV_OFFSET: usize = 4;
 pub static COMMAND_LEN: usize = 64;
+/// Size of the ring buffer carrying the events to the agent, a power of 2 multiple
+/// of the page size.
+pub const EVENTS_RING_BUF_SIZE: u32 = 1 << 20;
@@ -0,0 +1,11 @@
+use aya_ebpf::{macros::map, maps::perf::PerfEventArray, EbpfContext};
+
+use crate::CommandInfo;
+
+/// Lost events are reported to the reader of each CPU buffer.
+#[map]
+static COMMAND_EVENTS: PerfEventArray<CommandInfo> = PerfEventArray::new(0);
+
+pub fn output<C: EbpfContext>(ctx: &C, info: &CommandInfo) {
+    COMMAND_EVENTS.output(ctx, info, 0);
+}
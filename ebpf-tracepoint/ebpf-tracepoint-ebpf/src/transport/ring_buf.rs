@@ -0,0 +1,23 @@
+use aya_ebpf::{
+    macros::map,
+    maps::{PerCpuArray, RingBuf},
+    EbpfContext,
+};
+use ebpf_tracepoint_common::EVENTS_RING_BUF_SIZE;
+
+use crate::CommandInfo;
+
+#[map]
+static COMMAND_EVENTS: RingBuf = RingBuf::with_byte_size(EVENTS_RING_BUF_SIZE, 0);
+
+/// Number of events that did not fit in `COMMAND_EVENTS`, per CPU.
+#[map]
+static COMMAND_EVENTS_LOST: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+pub fn output<C: EbpfContext>(_ctx: &C, info: &CommandInfo) {
+    if COMMAND_EVENTS.output(info, 0).is_err() {
+        if let Some(lost) = COMMAND_EVENTS_LOST.get_ptr_mut(0) {
+            unsafe { *lost += 1 };
+        }
+    }
+}
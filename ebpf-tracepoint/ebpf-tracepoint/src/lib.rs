@@ -1,5 +1,8 @@
 use std::{fs::File, io::Read};
 
+pub use agent_common::{kernel_release, AgentHeartbeat, AgentKind, AgentMetrics, LostEvents};
+use chrono::{DateTime, Utc};
+use log_shipper::{Shipper, ShipperConfig};
 use reqwest;
 use serde::{Deserialize, Serialize};
 use toml;
@@ -12,18 +15,67 @@ pub struct CommandExecutionRequestForm {
     pub pid: u32,
     pub gid: u32,
     pub uid: u32,
+    /// Time the event was read, kept while the event waits in the spool.
+    pub timestamp: DateTime<Utc>,
 }
 
-pub async fn send_log(base_url: String, data: CommandExecutionRequestForm) -> Result<(), String> {
-    let url: String = format!("{}/command-execution/log", base_url);
+/// Starts shipping command executions to the bulk endpoint in batches.
+pub fn ship_logs(
+    base_url: String,
+    config: ShipperConfig,
+) -> Result<Shipper<CommandExecutionRequestForm>, String> {
+    let url: String = format!("{}/command-execution/bulk", base_url);
+    match Shipper::spawn("command-execution", url, config) {
+        Ok(shipper) => Ok(shipper),
+        Err(error) => Err(format!("[SHIPPER ERROR] ship_logs: {}", error)),
+    }
+}
+pub async fn send_heartbeat(base_url: String, data: AgentHeartbeat) -> Result<(), String> {
+    let url: String = format!("{}/agent/heartbeat", base_url);
     match reqwest::Client::builder().build() {
         Ok(client) => match client.post(url).json(&data).send().await {
-            Ok(_) => Ok(()),
-            Err(error) => Err(format!("[REQUEST ERROR] send_log: {}", error.to_string())),
+            Ok(response) => match response.error_for_status() {
+                Ok(_) => Ok(()),
+                Err(error) => Err(format!("[REQUEST ERROR] send_heartbeat: {}", error)),
+            },
+            Err(error) => Err(format!("[REQUEST ERROR] send_heartbeat: {}", error)),
         },
-        Err(error) => Err(format!("[REQUEST ERROR] send_log: {}", error.to_string())),
+        Err(error) => Err(format!("[REQUEST ERROR] send_heartbeat: {}", error)),
     }
 }
+
+/// `name` is the name the agent registers with, the host name when not set.
+/// `heartbeat_duration` is the number of seconds between heartbeats.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AgentConfig {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default = "default_heartbeat_duration")]
+    pub heartbeat_duration: u64,
+}
+
+fn default_heartbeat_duration() -> u64 {
+    30
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            name: None,
+            heartbeat_duration: default_heartbeat_duration(),
+        }
+    }
+}
+
+impl AgentConfig {
+    pub fn hostname(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => agent_common::hostname(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct ApiServerConfig {
     pub base_url: String,
@@ -31,6 +83,10 @@ pub struct ApiServerConfig {
 #[derive(Debug, Clone, Deserialize)]
 pub struct AppConfig {
     pub api_server: ApiServerConfig,
+    #[serde(default)]
+    pub agent: AgentConfig,
+    #[serde(default)]
+    pub shipper: ShipperConfig,
 }
 
 impl AppConfig {
@@ -62,3 +118,39 @@ impl AppConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod test_lib {
+    use super::*;
+
+    #[test]
+    fn test_app_config() {
+        let config: AppConfig = toml::from_str(
+            r#"
+            [api_server]
+            base_url = "http://localhost:8080"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.api_server.base_url, "http://localhost:8080");
+        assert_eq!(config.agent.name, None);
+        assert_eq!(config.agent.heartbeat_duration, 30);
+
+        let config: AppConfig = toml::from_str(
+            r#"
+            [api_server]
+            base_url = "http://localhost:8080"
+
+            [agent]
+            name = "build-01"
+            heartbeat_duration = 10
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.agent.hostname(), "build-01");
+        assert_eq!(config.agent.heartbeat_duration, 10);
+
+        assert!(toml::from_str::<AppConfig>("[agent]").is_err());
+        assert!(AppConfig::load(Some("missing.toml".to_string())).is_err());
+    }
+}
@@ -1,13 +1,32 @@
 use aya::programs::TracePoint;
 #[rustfmt::skip]
-use log::{debug, warn, error};
-use aya::{maps::perf::AsyncPerfEventArray, util::online_cpus, Pod};
+use log::{debug, warn};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use aya::{
+    maps::{
+        perf::{AsyncPerfEventArray, Events},
+        Map, MapData, PerCpuArray, RingBuf,
+    },
+    util::online_cpus,
+    Pod,
+};
 use bytemuck::{Pod as BPod, Zeroable};
 use bytes::BytesMut;
+use chrono::Utc;
 use clap::Parser;
-use ebpf_tracepoint::{send_log, ApiServerConfig, AppConfig, CommandExecutionRequestForm};
+use ebpf_tracepoint::{
+    kernel_release, send_heartbeat, ship_logs, AgentConfig, AgentHeartbeat, AgentKind,
+    AgentMetrics, ApiServerConfig, AppConfig, CommandExecutionRequestForm, LostEvents,
+};
 use ebpf_tracepoint_common::{ARGV_LEN, ARGV_OFFSET};
-use tokio::signal;
+use tokio::{io::unix::AsyncFd, signal};
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -28,6 +47,59 @@ pub struct CommandInfo {
     pub uid: u32,
 }
 unsafe impl Pod for CommandInfo {}
+
+fn command_execution(bytes: &[u8]) -> Option<CommandExecutionRequestForm> {
+    if bytes.len() < std::mem::size_of::<CommandInfo>() {
+        return None;
+    }
+    let ptr = bytes.as_ptr() as *const CommandInfo;
+    let info = unsafe { ptr.read_unaligned() };
+    let command_str = String::from_utf8_lossy(&info.command[..info.command_len]);
+    let mut argsv_str: String = "".to_string();
+    for i in 0..4 {
+        let argv_len: usize = info.argvs_offset[i as usize];
+        if argv_len == 0 {
+            break;
+        }
+        let argv_buf: [u8; ARGV_LEN] = info.argvs[i as usize];
+        argsv_str.push_str(&String::from_utf8_lossy(&argv_buf[..argv_len]));
+        argsv_str.push_str(" ");
+    }
+    debug!(
+        "Command: {} {} | pid: {} | gid: {} | tgid: {} | uid: {}",
+        command_str,
+        argsv_str.trim_end(),
+        info.pid,
+        info.gid,
+        info.tgid,
+        info.uid
+    );
+    Some(CommandExecutionRequestForm {
+        command: command_str.to_string(),
+        args: argsv_str.trim_end().to_string(),
+        tgid: info.tgid,
+        gid: info.gid,
+        pid: info.pid,
+        uid: info.uid,
+        timestamp: Utc::now(),
+    })
+}
+
+/// Command executions of the `events` read into `buffers` from a perf buffer, the
+/// events the buffer lost are added to `lost`.
+fn perf_command_executions(
+    buffers: &[BytesMut],
+    events: Events,
+    lost: &AtomicU64,
+) -> Vec<CommandExecutionRequestForm> {
+    lost.fetch_add(events.lost as u64, Ordering::Relaxed);
+    buffers
+        .iter()
+        .take(events.read)
+        .filter_map(|buf| command_execution(buf))
+        .collect()
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
@@ -39,6 +111,11 @@ async fn main() -> anyhow::Result<()> {
         Err(error) => panic!("{}", error),
     };
     let api_server_config: ApiServerConfig = app_config.api_server;
+    let agent_config: AgentConfig = app_config.agent;
+    let shipper = match ship_logs(api_server_config.base_url.clone(), app_config.shipper) {
+        Ok(shipper) => shipper,
+        Err(error) => panic!("{}", error),
+    };
 
     // Bump the memlock rlimit. This is needed for older kernels that don't use the
     // new memcg based accounting, see https://lwn.net/Articles/837122/
@@ -55,10 +132,24 @@ async fn main() -> anyhow::Result<()> {
     // runtime. This approach is recommended for most real-world use cases. If you would
     // like to specify the eBPF program at runtime rather than at compile-time, you can
     // reach for `Bpf::load_file` instead.
-    let mut ebpf = aya::Ebpf::load(aya::include_bytes_aligned!(concat!(
+    // Kernels before 5.8 have no BPF ring buffer, the build sending the events through
+    // a perf event array is loaded instead.
+    let mut ebpf = match aya::Ebpf::load(aya::include_bytes_aligned!(concat!(
         env!("OUT_DIR"),
         "/ebpf-tracepoint"
-    )))?;
+    ))) {
+        Ok(ebpf) => ebpf,
+        Err(error) => {
+            warn!(
+                "failed to load the ring buffer build, falling back to perf events: {}",
+                error
+            );
+            aya::Ebpf::load(aya::include_bytes_aligned!(concat!(
+                env!("OUT_DIR"),
+                "/ebpf-tracepoint-perf"
+            )))?
+        }
+    };
     if let Err(e) = aya_log::EbpfLogger::init(&mut ebpf) {
         // This can happen if you remove all log statements from your eBPF program.
         warn!("failed to initialize eBPF logger: {}", e);
@@ -67,70 +158,95 @@ async fn main() -> anyhow::Result<()> {
     program.load()?;
     program.attach("syscalls", "sys_enter_execve")?;
 
-    let mut perf_command_events =
-        AsyncPerfEventArray::try_from(ebpf.take_map("COMMAND_EVENTS").unwrap())?;
-
-    for cpu_id in online_cpus().map_err(|(_, error)| error)? {
-        let mut buf = perf_command_events.open(cpu_id, None)?;
-
-        let api_base_url = api_server_config.base_url.clone();
-        tokio::task::spawn(async move {
-            let mut buffers = (0..10)
-                .map(|_| BytesMut::with_capacity(1024))
-                .collect::<Vec<_>>();
-
-            loop {
-                match buf.read_events(&mut buffers).await {
-                    Ok(events) => {
-                        for i in 0..events.read {
-                            let buf = &mut buffers[i];
-                            let ptr = buf.as_ptr() as *const CommandInfo;
-                            let info = unsafe { ptr.read_unaligned() };
-                            let command_str =
-                                String::from_utf8_lossy(&info.command[..info.command_len]);
-                            let mut argsv_str: String = "".to_string();
-                            for i in 0..4 {
-                                let argv_len: usize = info.argvs_offset[i as usize];
-                                if argv_len == 0 {
-                                    break;
+    let lost_events: LostEvents = match ebpf.take_map("COMMAND_EVENTS") {
+        Some(map @ Map::RingBuf(_)) => {
+            let lost: PerCpuArray<MapData, u64> =
+                PerCpuArray::try_from(ebpf.take_map("COMMAND_EVENTS_LOST").unwrap())?;
+            let mut ring_buf = AsyncFd::new(RingBuf::try_from(map)?)?;
+            let shipper = shipper.clone();
+            tokio::task::spawn(async move {
+                loop {
+                    let mut guard = match ring_buf.readable_mut().await {
+                        Ok(guard) => guard,
+                        Err(err) => {
+                            debug!("panic: {:?}", err);
+                            return;
+                        }
+                    };
+                    let ring_buf = guard.get_inner_mut();
+                    while let Some(item) = ring_buf.next() {
+                        if let Some(form) = command_execution(&item) {
+                            shipper.send(form);
+                        }
+                    }
+                    guard.clear_ready();
+                }
+            });
+            LostEvents::RingBuf(Arc::new(lost))
+        }
+        Some(map) => {
+            let lost: Arc<AtomicU64> = Arc::new(AtomicU64::new(0));
+            let mut perf_command_events = AsyncPerfEventArray::try_from(map)?;
+            for cpu_id in online_cpus().map_err(|(_, error)| error)? {
+                let mut buf = perf_command_events.open(cpu_id, None)?;
+
+                let shipper = shipper.clone();
+                let lost = lost.clone();
+                tokio::task::spawn(async move {
+                    let mut buffers = (0..10)
+                        .map(|_| BytesMut::with_capacity(1024))
+                        .collect::<Vec<_>>();
+
+                    loop {
+                        match buf.read_events(&mut buffers).await {
+                            Ok(events) => {
+                                for form in perf_command_executions(&buffers, events, &lost) {
+                                    shipper.send(form);
                                 }
-                                let argv_buf: [u8; ARGV_LEN] = info.argvs[i as usize];
-                                argsv_str.push_str(&String::from_utf8_lossy(&argv_buf[..argv_len]));
-                                argsv_str.push_str(" ");
                             }
-                            debug!(
-                                "Command: {} {} | pid: {} | gid: {} | tgid: {} | uid: {}",
-                                command_str,
-                                argsv_str.trim_end(),
-                                info.pid,
-                                info.gid,
-                                info.tgid,
-                                info.uid
-                            );
-                            if let Err(error) = send_log(
-                                api_base_url.clone(),
-                                CommandExecutionRequestForm {
-                                    command: command_str.to_string(),
-                                    args: argsv_str.trim_end().to_string(),
-                                    tgid: info.tgid,
-                                    gid: info.gid,
-                                    pid: info.pid,
-                                    uid: info.uid,
-                                },
-                            )
-                            .await
-                            {
-                                error!("[COMMAND EXECUTION REQUEST ERROR] send_log: {}", error);
+                            Err(err) => {
+                                debug!("panic: {:?}", err);
                             }
                         }
                     }
-                    Err(err) => {
-                        debug!("panic: {:?}", err);
-                    }
-                }
+                });
             }
-        });
-    }
+            LostEvents::PerfEventArray(lost)
+        }
+        None => return Err(anyhow::anyhow!("COMMAND_EVENTS map not found")),
+    };
+
+    let mut heartbeat: AgentHeartbeat = AgentHeartbeat {
+        hostname: agent_config.hostname(),
+        kind: AgentKind::Tracepoint,
+        interface: None,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        kernel: kernel_release(),
+        attach_mode: None,
+        metrics: None,
+        state: None,
+        error: None,
+    };
+    let api_base_url = api_server_config.base_url.clone();
+    tokio::task::spawn(async move {
+        loop {
+            let stats = shipper.stats();
+            heartbeat.metrics = Some(AgentMetrics {
+                transport: lost_events.transport().to_string(),
+                events_lost: lost_events.get().unwrap_or_else(|error| {
+                    warn!("{}", error);
+                    0
+                }),
+                events_shipped: stats.shipped,
+                events_dropped: stats.dropped,
+                events_spooled: stats.spooled,
+            });
+            if let Err(error) = send_heartbeat(api_base_url.clone(), heartbeat.clone()).await {
+                warn!("{}", error);
+            }
+            tokio::time::sleep(Duration::from_secs(agent_config.heartbeat_duration)).await;
+        }
+    });
 
     let ctrl_c = signal::ctrl_c();
     println!("Waiting for Ctrl-C...");
@@ -139,3 +255,88 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test_main {
+    use super::*;
+
+    fn command_info(command: &str, args: &[&str]) -> CommandInfo {
+        let mut info: CommandInfo = CommandInfo::zeroed();
+        info.command[..command.len()].copy_from_slice(command.as_bytes());
+        info.command_len = command.len();
+        for (i, arg) in args.iter().enumerate() {
+            info.argvs[i][..arg.len()].copy_from_slice(arg.as_bytes());
+            info.argvs_offset[i] = arg.len();
+        }
+        info.tgid = 100;
+        info.pid = 101;
+        info.gid = 1000;
+        info.uid = 1001;
+        info
+    }
+
+    #[test]
+    fn test_command_execution() {
+        let info: CommandInfo = command_info("/usr/bin/ls", &["ls", "-l", "/tmp"]);
+        let form: CommandExecutionRequestForm =
+            command_execution(bytemuck::bytes_of(&info)).unwrap();
+        assert_eq!(form.command, "/usr/bin/ls");
+        assert_eq!(form.args, "ls -l /tmp");
+        assert_eq!((form.tgid, form.pid), (100, 101));
+        assert_eq!((form.gid, form.uid), (1000, 1001));
+        assert!((Utc::now() - form.timestamp).num_seconds() < 1);
+
+        let info: CommandInfo = command_info("/usr/bin/id", &[]);
+        let form: CommandExecutionRequestForm =
+            command_execution(bytemuck::bytes_of(&info)).unwrap();
+        assert_eq!(form.args, "", "expected no arguments");
+
+        let mut info: CommandInfo = command_info("/usr/bin/echo", &["echo", "a", "b"]);
+        info.argvs_offset[1] = 0;
+        let form: CommandExecutionRequestForm =
+            command_execution(bytemuck::bytes_of(&info)).unwrap();
+        assert_eq!(
+            form.args, "echo",
+            "expected the arguments to end at the first empty one"
+        );
+
+        let mut info: CommandInfo = command_info("/usr/bin/cat", &["cat"]);
+        info.command[9] = 0xff;
+        let form: CommandExecutionRequestForm =
+            command_execution(bytemuck::bytes_of(&info)).unwrap();
+        assert_eq!(form.command, "/usr/bin/\u{fffd}at");
+    }
+
+    #[test]
+    fn test_command_execution_truncated() {
+        let info: CommandInfo = command_info("/usr/bin/ls", &["ls"]);
+        let bytes: &[u8] = bytemuck::bytes_of(&info);
+        assert!(command_execution(&bytes[..bytes.len() - 1]).is_none());
+        assert!(command_execution(&[]).is_none());
+    }
+
+    #[test]
+    fn test_perf_command_executions() {
+        let info: CommandInfo = command_info("/usr/bin/ls", &["ls"]);
+        let buffers: Vec<BytesMut> = vec![
+            BytesMut::from(bytemuck::bytes_of(&info)),
+            BytesMut::from(&b"short"[..]),
+            BytesMut::from(bytemuck::bytes_of(&info)),
+        ];
+        let lost: Arc<AtomicU64> = Arc::new(AtomicU64::new(0));
+        let lost_events: LostEvents = LostEvents::PerfEventArray(lost.clone());
+
+        let forms = perf_command_executions(&buffers, Events { read: 2, lost: 3 }, &lost);
+        assert_eq!(forms.len(), 1, "expected the short event to be skipped");
+        assert_eq!(forms[0].command, "/usr/bin/ls");
+        let forms = perf_command_executions(&buffers, Events { read: 3, lost: 2 }, &lost);
+        assert_eq!(forms.len(), 2);
+
+        assert_eq!(
+            lost_events.get().unwrap(),
+            5,
+            "expected the lost events to add up"
+        );
+        assert_eq!(lost_events.transport(), "PerfEventArray");
+    }
+}
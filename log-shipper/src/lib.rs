@@ -0,0 +1,470 @@
+//! Ships agent events to the bulk ingestion endpoints of the API server.
+//!
+//! Events are queued in memory and posted as JSON arrays of up to `batch_size`
+//! events. Batches that can't be delivered are spooled to disk and retried with
+//! an exponential backoff, oldest first, so events keep their order across an
+//! outage of the API server and across agent restarts.
+
+mod spool;
+
+use std::{
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use log::warn;
+use reqwest::{header::CONTENT_TYPE, StatusCode};
+use serde::{Deserialize, Serialize};
+pub use spool::Spool;
+use tokio::{sync::mpsc, time::Instant};
+
+/// First wait after a failed delivery, doubled on every further failure.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ShipperConfig {
+    /// Events per request.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Milliseconds a batch waits for more events before it is sent.
+    #[serde(default = "default_flush_interval")]
+    pub flush_interval: u64,
+    /// Events queued in memory, further events are dropped until there is room.
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+    /// Directory of the spools, each shipper uses a sub-directory of its own.
+    #[serde(default = "default_spool_path")]
+    pub spool_path: String,
+    /// Bytes a spool may hold, the oldest batches are dropped beyond it.
+    #[serde(default = "default_spool_size")]
+    pub spool_size: u64,
+    /// Longest wait in seconds between two delivery attempts.
+    #[serde(default = "default_max_backoff")]
+    pub max_backoff: u64,
+}
+
+fn default_batch_size() -> usize {
+    500
+}
+
+fn default_flush_interval() -> u64 {
+    1000
+}
+
+fn default_queue_size() -> usize {
+    50_000
+}
+
+fn default_spool_path() -> String {
+    "spool".to_string()
+}
+
+fn default_spool_size() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_max_backoff() -> u64 {
+    60
+}
+
+impl Default for ShipperConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: default_batch_size(),
+            flush_interval: default_flush_interval(),
+            queue_size: default_queue_size(),
+            spool_path: default_spool_path(),
+            spool_size: default_spool_size(),
+            max_backoff: default_max_backoff(),
+        }
+    }
+}
+
+/// Event counts of a shipper. `dropped` counts the events lost to a full queue,
+/// a full spool or a batch rejected by the API server, `spooled` the events
+/// currently waiting on disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShipperStats {
+    pub shipped: u64,
+    pub dropped: u64,
+    pub spooled: u64,
+}
+
+#[derive(Debug, Default)]
+struct ShipperCounters {
+    shipped: AtomicU64,
+    dropped: AtomicU64,
+    spooled: AtomicU64,
+}
+
+/// Handle of a running shipper, cheap to clone.
+#[derive(Debug)]
+pub struct Shipper<T> {
+    sender: mpsc::Sender<T>,
+    counters: Arc<ShipperCounters>,
+}
+
+impl<T> Clone for Shipper<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            counters: self.counters.clone(),
+        }
+    }
+}
+
+impl<T: Serialize + Send + 'static> Shipper<T> {
+    /// Starts shipping to `url`, spooling to `<spool_path>/<name>`. Batches left in
+    /// the spool by a previous run are sent first. Must be called within a tokio
+    /// runtime.
+    pub fn spawn(name: &str, url: String, config: ShipperConfig) -> io::Result<Self> {
+        let spool: Spool =
+            Spool::open(Path::new(&config.spool_path).join(name), config.spool_size)?;
+        let client: reqwest::Client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(io::Error::other)?;
+        let counters: Arc<ShipperCounters> = Arc::new(ShipperCounters::default());
+        counters
+            .spooled
+            .store(spool.events() as u64, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel::<T>(config.queue_size.max(1));
+        let worker: Worker = Worker {
+            name: name.to_string(),
+            url,
+            client,
+            batch_size: config.batch_size.max(1),
+            flush_interval: Duration::from_millis(config.flush_interval),
+            max_backoff: Duration::from_secs(config.max_backoff),
+            backoff: Duration::ZERO,
+            retry_at: None,
+            spool,
+            counters: counters.clone(),
+        };
+        tokio::task::spawn(worker.run(receiver));
+        Ok(Self { sender, counters })
+    }
+
+    /// Queues an event, it is dropped and counted when the queue is full.
+    pub fn send(&self, event: T) {
+        if self.sender.try_send(event).is_err() {
+            self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> ShipperStats {
+        ShipperStats {
+            shipped: self.counters.shipped.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+            spooled: self.counters.spooled.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Outcome of posting a batch. A rejected batch is malformed in the eyes of the
+/// API server and would never be accepted, a failed one is retried.
+enum Delivery {
+    Delivered,
+    Rejected(String),
+    Failed(String),
+}
+
+struct Worker {
+    name: String,
+    url: String,
+    client: reqwest::Client,
+    batch_size: usize,
+    flush_interval: Duration,
+    max_backoff: Duration,
+    backoff: Duration,
+    retry_at: Option<Instant>,
+    spool: Spool,
+    counters: Arc<ShipperCounters>,
+}
+
+impl Worker {
+    async fn run<T: Serialize>(mut self, mut receiver: mpsc::Receiver<T>) {
+        let mut batch: Vec<T> = Vec::with_capacity(self.batch_size);
+        let mut closed: bool = false;
+        while !closed {
+            let deadline: Instant = Instant::now() + self.flush_interval;
+            while batch.len() < self.batch_size {
+                match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                    Ok(Some(event)) => batch.push(event),
+                    Ok(None) => {
+                        closed = true;
+                        break;
+                    }
+                    Err(_) => break,
+                }
+            }
+            if !batch.is_empty() {
+                let events: usize = batch.len();
+                match serde_json::to_vec(&batch) {
+                    Ok(body) => self.ship(body, events).await,
+                    Err(error) => {
+                        warn!("[LOG SHIPPER] {}: {}", self.name, error);
+                        self.dropped(events);
+                    }
+                }
+                batch.clear();
+            }
+            self.drain().await;
+        }
+    }
+
+    /// Posts the batch unless older batches are waiting, spools it otherwise or when
+    /// the delivery fails.
+    async fn ship(&mut self, body: Vec<u8>, events: usize) {
+        if self.spool.is_empty() && self.retry_at.is_none() {
+            match self.post(&body).await {
+                Delivery::Delivered => {
+                    self.counters
+                        .shipped
+                        .fetch_add(events as u64, Ordering::Relaxed);
+                    return;
+                }
+                Delivery::Rejected(error) => {
+                    warn!("[LOG SHIPPER] {}: {}", self.name, error);
+                    self.dropped(events);
+                    return;
+                }
+                Delivery::Failed(error) => {
+                    warn!("[LOG SHIPPER] {}: {}, spooling", self.name, error);
+                    self.back_off();
+                }
+            }
+        }
+        match self.spool.push(&body, events) {
+            Ok(evicted) => {
+                self.counters
+                    .spooled
+                    .fetch_add(events as u64, Ordering::Relaxed);
+                if evicted > 0 {
+                    self.counters
+                        .spooled
+                        .fetch_sub(evicted as u64, Ordering::Relaxed);
+                    self.dropped(evicted);
+                }
+            }
+            Err(error) => {
+                warn!("[LOG SHIPPER] {}: failed to spool: {}", self.name, error);
+                self.dropped(events);
+            }
+        }
+    }
+
+    /// Posts the spooled batches oldest first, until one fails.
+    async fn drain(&mut self) {
+        if self
+            .retry_at
+            .is_some_and(|retry_at| Instant::now() < retry_at)
+        {
+            return;
+        }
+        loop {
+            let body: Vec<u8> = match self.spool.front() {
+                Ok(Some(body)) => body,
+                Ok(None) => break,
+                Err(error) => {
+                    warn!("[LOG SHIPPER] {}: unreadable batch: {}", self.name, error);
+                    let events: usize = self.pop_spooled();
+                    self.dropped(events);
+                    continue;
+                }
+            };
+            match self.post(&body).await {
+                Delivery::Delivered => {
+                    let events: usize = self.pop_spooled();
+                    self.counters
+                        .shipped
+                        .fetch_add(events as u64, Ordering::Relaxed);
+                }
+                Delivery::Rejected(error) => {
+                    warn!("[LOG SHIPPER] {}: {}", self.name, error);
+                    let events: usize = self.pop_spooled();
+                    self.dropped(events);
+                }
+                Delivery::Failed(error) => {
+                    warn!("[LOG SHIPPER] {}: {}", self.name, error);
+                    self.back_off();
+                    return;
+                }
+            }
+        }
+        self.backoff = Duration::ZERO;
+        self.retry_at = None;
+    }
+
+    async fn post(&self, body: &[u8]) -> Delivery {
+        let response = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_vec())
+            .send()
+            .await;
+        match response {
+            Ok(response) => {
+                let status: StatusCode = response.status();
+                if status.is_success() {
+                    Delivery::Delivered
+                } else if status.is_client_error()
+                    && status != StatusCode::REQUEST_TIMEOUT
+                    && status != StatusCode::TOO_MANY_REQUESTS
+                {
+                    Delivery::Rejected(format!("the batch was rejected with {}", status))
+                } else {
+                    Delivery::Failed(format!("the batch was not accepted: {}", status))
+                }
+            }
+            Err(error) => Delivery::Failed(error.to_string()),
+        }
+    }
+
+    fn back_off(&mut self) {
+        self.backoff = if self.backoff.is_zero() {
+            MIN_BACKOFF
+        } else {
+            self.backoff * 2
+        }
+        .min(self.max_backoff);
+        self.retry_at = Some(Instant::now() + self.backoff);
+    }
+
+    /// Removes the oldest spooled batch, returns its number of events.
+    fn pop_spooled(&mut self) -> usize {
+        match self.spool.pop_front() {
+            Ok(events) => {
+                self.counters
+                    .spooled
+                    .fetch_sub(events as u64, Ordering::Relaxed);
+                events
+            }
+            Err(error) => {
+                warn!("[LOG SHIPPER] {}: {}", self.name, error);
+                0
+            }
+        }
+    }
+
+    fn dropped(&self, events: usize) {
+        self.counters
+            .dropped
+            .fetch_add(events as u64, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test_shipper {
+    use std::net::SocketAddr;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Answers every request with a 200 and forwards its body.
+    async fn serve(listener: TcpListener, bodies: mpsc::UnboundedSender<String>) {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request: Vec<u8> = Vec::new();
+            let mut chunk: [u8; 4096] = [0; 4096];
+            let body: String = loop {
+                let read: usize = socket.read(&mut chunk).await.unwrap();
+                request.extend_from_slice(&chunk[..read]);
+                let Some(end) = request.windows(4).position(|value| value == b"\r\n\r\n") else {
+                    continue;
+                };
+                let headers: String = String::from_utf8_lossy(&request[..end]).to_lowercase();
+                let length: usize = headers
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .map(|value| value.trim().parse().unwrap())
+                    .unwrap_or(0);
+                if request.len() >= end + 4 + length {
+                    break String::from_utf8_lossy(&request[end + 4..end + 4 + length]).to_string();
+                }
+            };
+            bodies.send(body).unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                .await
+                .unwrap();
+        }
+    }
+
+    async fn wait_for(shipper: &Shipper<u32>, stats: ShipperStats) {
+        for _ in 0..500 {
+            if shipper.stats() == stats {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("expected {:?}, got {:?}", stats, shipper.stats());
+    }
+
+    #[tokio::test]
+    async fn test_ship_after_outage() {
+        let spool_path = std::env::temp_dir().join("log-shipper-outage");
+        let _ = std::fs::remove_dir_all(&spool_path);
+        // Nothing listens on the port until the outage is over.
+        let address: SocketAddr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config: ShipperConfig = ShipperConfig {
+            batch_size: 2,
+            flush_interval: 20,
+            spool_path: spool_path.to_string_lossy().to_string(),
+            max_backoff: 0,
+            ..Default::default()
+        };
+        let shipper: Shipper<u32> =
+            Shipper::spawn("events", format!("http://{}/bulk", address), config).unwrap();
+        for event in 1..=3 {
+            shipper.send(event);
+        }
+        wait_for(
+            &shipper,
+            ShipperStats {
+                shipped: 0,
+                dropped: 0,
+                spooled: 3,
+            },
+        )
+        .await;
+
+        let (sender, mut bodies) = mpsc::unbounded_channel::<String>();
+        tokio::task::spawn(serve(TcpListener::bind(address).await.unwrap(), sender));
+        shipper.send(4);
+        wait_for(
+            &shipper,
+            ShipperStats {
+                shipped: 4,
+                dropped: 0,
+                spooled: 0,
+            },
+        )
+        .await;
+        let mut received: Vec<String> = Vec::new();
+        while let Ok(body) = bodies.try_recv() {
+            received.push(body);
+        }
+        assert_eq!(
+            received,
+            vec!["[1,2]", "[3]", "[4]"],
+            "expected the order to be kept"
+        );
+        std::fs::remove_dir_all(&spool_path).unwrap();
+    }
+}
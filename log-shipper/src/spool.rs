@@ -0,0 +1,197 @@
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// A spooled batch, stored as `<sequence>_<events>.json`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SpooledBatch {
+    sequence: u64,
+    events: usize,
+    size: u64,
+}
+
+impl SpooledBatch {
+    fn file_name(&self) -> String {
+        format!("{:020}_{}.json", self.sequence, self.events)
+    }
+
+    fn parse(file_name: &str, size: u64) -> Option<Self> {
+        let (sequence, events) = file_name.strip_suffix(".json")?.split_once('_')?;
+        Some(Self {
+            sequence: sequence.parse().ok()?,
+            events: events.parse().ok()?,
+            size,
+        })
+    }
+}
+
+/// Bounded on-disk queue of request bodies, one file per batch. Batches left over
+/// from a previous run are picked up again, the oldest ones are dropped once the
+/// spool grows beyond `max_size` bytes.
+#[derive(Debug)]
+pub struct Spool {
+    path: PathBuf,
+    max_size: u64,
+    size: u64,
+    batches: VecDeque<SpooledBatch>,
+}
+
+impl Spool {
+    pub fn open(path: impl AsRef<Path>, max_size: u64) -> io::Result<Self> {
+        let path: PathBuf = path.as_ref().to_path_buf();
+        fs::create_dir_all(&path)?;
+        let mut batches: Vec<SpooledBatch> = Vec::new();
+        for entry in fs::read_dir(&path)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let Some(file_name) = file_name.to_str() else {
+                continue;
+            };
+            if file_name.ends_with(".tmp") {
+                fs::remove_file(entry.path())?;
+                continue;
+            }
+            if let Some(batch) = SpooledBatch::parse(file_name, entry.metadata()?.len()) {
+                batches.push(batch);
+            }
+        }
+        batches.sort_by_key(|batch| batch.sequence);
+        let size: u64 = batches.iter().map(|batch| batch.size).sum();
+        let mut spool: Spool = Spool {
+            path,
+            max_size,
+            size,
+            batches: batches.into(),
+        };
+        spool.evict()?;
+        Ok(spool)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Number of spooled batches.
+    pub fn len(&self) -> usize {
+        self.batches.len()
+    }
+
+    /// Number of events in the spooled batches.
+    pub fn events(&self) -> usize {
+        self.batches.iter().map(|batch| batch.events).sum()
+    }
+
+    /// Size of the spooled batches in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Appends a batch of `events` events, returns the number of events dropped to
+    /// stay within the size limit.
+    pub fn push(&mut self, body: &[u8], events: usize) -> io::Result<usize> {
+        if body.len() as u64 > self.max_size {
+            return Err(io::Error::other("the batch is larger than the spool"));
+        }
+        let batch: SpooledBatch = SpooledBatch {
+            sequence: self.batches.back().map_or(0, |batch| batch.sequence + 1),
+            events,
+            size: body.len() as u64,
+        };
+        // Written under a temporary name first so a crash never leaves a partial batch.
+        let temporary: PathBuf = self.path.join(format!("{}.tmp", batch.file_name()));
+        fs::write(&temporary, body)?;
+        fs::rename(&temporary, self.path.join(batch.file_name()))?;
+        self.size += batch.size;
+        self.batches.push_back(batch);
+        self.evict()
+    }
+
+    /// The oldest batch, it stays spooled until `pop_front` is called.
+    pub fn front(&self) -> io::Result<Option<Vec<u8>>> {
+        match self.batches.front() {
+            Some(batch) => Ok(Some(fs::read(self.path.join(batch.file_name()))?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes the oldest batch, returns its number of events.
+    pub fn pop_front(&mut self) -> io::Result<usize> {
+        match self.batches.pop_front() {
+            Some(batch) => {
+                self.size -= batch.size;
+                fs::remove_file(self.path.join(batch.file_name()))?;
+                Ok(batch.events)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn evict(&mut self) -> io::Result<usize> {
+        let mut dropped: usize = 0;
+        while self.size > self.max_size {
+            dropped += self.pop_front()?;
+        }
+        Ok(dropped)
+    }
+}
+
+#[cfg(test)]
+mod test_spool {
+    use super::*;
+
+    fn spool_path(name: &str) -> PathBuf {
+        let path: PathBuf = std::env::temp_dir().join(format!("log-shipper-{}", name));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    #[test]
+    fn test_push_and_pop() {
+        let path: PathBuf = spool_path("push-and-pop");
+        let mut spool: Spool = Spool::open(&path, 1024).unwrap();
+        assert!(spool.is_empty());
+        assert_eq!(spool.push(b"[1,2]", 2).unwrap(), 0);
+        assert_eq!(spool.push(b"[3]", 1).unwrap(), 0);
+        assert_eq!(spool.len(), 2);
+        assert_eq!(spool.events(), 3);
+        assert_eq!(spool.size(), 8);
+        assert_eq!(spool.front().unwrap(), Some(b"[1,2]".to_vec()));
+        assert_eq!(spool.pop_front().unwrap(), 2);
+        assert_eq!(spool.front().unwrap(), Some(b"[3]".to_vec()));
+
+        let mut spool: Spool = Spool::open(&path, 1024).unwrap();
+        assert_eq!(spool.len(), 1, "expected the batch to survive a reopen");
+        assert_eq!(spool.push(b"[4]", 1).unwrap(), 0);
+        assert_eq!(spool.pop_front().unwrap(), 1);
+        assert_eq!(spool.front().unwrap(), Some(b"[4]".to_vec()));
+        assert_eq!(spool.pop_front().unwrap(), 1);
+        assert!(spool.is_empty());
+        assert_eq!(spool.size(), 0);
+        assert_eq!(spool.front().unwrap(), None);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let path: PathBuf = spool_path("size-limit");
+        let mut spool: Spool = Spool::open(&path, 10).unwrap();
+        assert_eq!(spool.push(b"[1,2,3]", 3).unwrap(), 0);
+        assert_eq!(
+            spool.push(b"[4,5]", 2).unwrap(),
+            3,
+            "expected the oldest batch to go"
+        );
+        assert_eq!(spool.front().unwrap(), Some(b"[4,5]".to_vec()));
+        assert!(spool.push(b"[6,7,8,9,10]", 5).is_err());
+        assert_eq!(spool.len(), 1);
+
+        let spool: Spool = Spool::open(&path, 4).unwrap();
+        assert!(
+            spool.is_empty(),
+            "expected a smaller limit to apply on open"
+        );
+        fs::remove_dir_all(&path).unwrap();
+    }
+}